@@ -0,0 +1,19 @@
+#!/bin/sh
+# Download the RFC 8251 Opus test vectors and print the directory holding
+# them, for OPUS_TEST_VECTORS.
+#
+# Usage: scripts/fetch-test-vectors.sh [directory]
+#
+# The vectors are extracted to <directory>/opus_newvectors, by default under
+# target/. Nothing is downloaded if they are already there.
+set -eu
+
+URL=https://opus-codec.org/docs/opus_testvectors-rfc8251.tar.gz
+DIR=${1:-"$(dirname "$0")/../target"}
+VECTORS="$DIR/opus_newvectors"
+
+if [ ! -f "$VECTORS/testvector12.bit" ]; then
+	mkdir -p "$DIR"
+	curl -fsSL "$URL" | tar -xz -C "$DIR"
+fi
+cd "$VECTORS" && pwd
@@ -0,0 +1,75 @@
+//! Check the linked libopus against the official test vectors.
+//!
+//! Usage: `opus-rs-conformance <vector directory>`
+//!
+//! The directory should contain `testvectorNN.bit` files with their
+//! `testvectorNN.dec` references, and optionally `testvectorNNm.dec`.
+
+extern crate opus;
+
+use opus::conformance;
+use std::path::Path;
+use std::process::exit;
+
+fn main() {
+	let args: Vec<String> = std::env::args().collect();
+	if args.len() != 2 {
+		eprintln!("Usage: {} <vector directory>", args[0]);
+		exit(2);
+	}
+	let dir = Path::new(&args[1]);
+
+	let mut vectors: Vec<_> = match std::fs::read_dir(dir) {
+		Ok(entries) => entries
+			.filter_map(|entry| entry.ok())
+			.map(|entry| entry.path())
+			.filter(|path| path.extension().is_some_and(|ext| ext == "bit"))
+			.collect(),
+		Err(err) => {
+			eprintln!("{}: {}", dir.display(), err);
+			exit(2);
+		}
+	};
+	vectors.sort();
+	if vectors.is_empty() {
+		eprintln!("{}: no .bit files found", dir.display());
+		exit(2);
+	}
+
+	let mut failures = 0;
+	for bit in &vectors {
+		let stem = bit.file_stem().unwrap().to_string_lossy();
+		let dec = bit.with_extension("dec");
+		let alternate = dir.join(format!("{}m.dec", stem));
+		let alternate = if alternate.exists() { Some(alternate.as_path()) } else { None };
+		match conformance::run_vector(bit, &dec, alternate) {
+			Ok(results) => {
+				for result in results {
+					println!(
+						"{} {} Hz {:?}{}: {} (quality {:.1} %, error {:.6})",
+						stem,
+						result.sample_rate,
+						result.channels,
+						if result.multistream { " multistream" } else { "" },
+						if result.comparison.passes() { "PASS" } else { "FAIL" },
+						result.comparison.quality,
+						result.comparison.error
+					);
+					if !result.comparison.passes() {
+						failures += 1;
+					}
+				}
+			}
+			Err(err) => {
+				println!("{}: FAIL ({})", stem, err);
+				failures += 1;
+			}
+		}
+	}
+
+	if failures > 0 {
+		println!("{} checks failed", failures);
+		exit(1);
+	}
+	println!("All {} test vectors pass", vectors.len());
+}
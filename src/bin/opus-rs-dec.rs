@@ -0,0 +1,568 @@
+//! Decode an Ogg Opus or `opus_demo` bitstream file to WAV or raw PCM.
+//!
+//! Usage: `opus-rs-dec [options] <input> <output>`
+//!
+//! Either path may be `-` for standard input or output. Packets can be
+//! dropped at random before decoding, to hear how the stream holds up under
+//! loss with concealment and forward error correction.
+
+extern crate opus;
+
+use opus::ogg::OggOpusReader;
+use opus::opus_demo::DemoBitstreamReader;
+use opus::opus_head::OpusHead;
+use opus::pcm::{PcmFormat, SampleFormat, StreamLayout, WavWriter};
+use opus::resample::{self, Resampler};
+use opus::simulate::{Impairments, LossModel, Network};
+use opus::{packet, Channels, SoftClip};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Cursor, Read, Stdout, Write};
+use std::process::exit;
+
+const USAGE: &str = "\
+Usage: opus-rs-dec [options] <input> <output>
+
+Decodes an Ogg Opus file, or a bitstream in the opus_demo format, to WAV or
+raw PCM. Either path may be - for standard input or output.
+
+Decoding options:
+  --rate <hz>            Output sample rate (default: 48000)
+  --gain <dB>            Gain added to the header's output gain (default: 0)
+  --soft-clip            Soft-clip the output to avoid harsh clipping
+  --channels <n>         Channels of an opus_demo input, 1 or 2 (default: 2)
+
+Loss simulation:
+  --packet-loss <0-100>  Percentage of packets to drop at random (default: 0)
+  --seed <n>             Seed for choosing the dropped packets (default: 1)
+  --fec                  Recover dropped packets from the next packet's
+                         forward error correction data, where present
+
+Output options:
+  --float                Write 32-bit floating point samples (default: 16-bit)
+  --raw                  Write headerless little-endian PCM instead of WAV
+  --quiet                Don't print a summary
+  -h, --help             Show this help";
+
+struct Options {
+	input: String,
+	output: String,
+	rate: u32,
+	gain: f64,
+	soft_clip: bool,
+	channels: u8,
+	packet_loss: f64,
+	seed: u64,
+	fec: bool,
+	float: bool,
+	raw: bool,
+	quiet: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+	eprintln!("opus-rs-dec: {}", message);
+	eprintln!("Try 'opus-rs-dec --help' for more information.");
+	exit(2);
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> T {
+	match value.parse() {
+		Ok(value) => value,
+		Err(_) => usage_error(&format!("invalid value for {}: {}", flag, value)),
+	}
+}
+
+fn parse_args(args: &[String]) -> Options {
+	let mut options = Options {
+		input: String::new(),
+		output: String::new(),
+		rate: 48000,
+		gain: 0.0,
+		soft_clip: false,
+		channels: 2,
+		packet_loss: 0.0,
+		seed: 1,
+		fec: false,
+		float: false,
+		raw: false,
+		quiet: false,
+	};
+	let mut paths = Vec::new();
+	let mut iter = args.iter();
+	while let Some(arg) = iter.next() {
+		let mut value = || match iter.next() {
+			Some(value) => value.as_str(),
+			None => usage_error(&format!("{} requires a value", arg)),
+		};
+		match arg.as_str() {
+			"-h" | "--help" => {
+				println!("{}", USAGE);
+				exit(0);
+			}
+			"--rate" => {
+				options.rate = parse(arg, value());
+				if !(1000..=192_000).contains(&options.rate) {
+					usage_error("sample rate out of range");
+				}
+			}
+			"--gain" => {
+				options.gain = parse(arg, value());
+				if !(-128.0..128.0).contains(&options.gain) {
+					usage_error("gain out of range");
+				}
+			}
+			"--soft-clip" => options.soft_clip = true,
+			"--channels" => {
+				options.channels = parse(arg, value());
+				if !(1..=2).contains(&options.channels) {
+					usage_error("opus_demo input must have 1 or 2 channels");
+				}
+			}
+			"--packet-loss" => {
+				options.packet_loss = parse(arg, value());
+				if !(0.0..=100.0).contains(&options.packet_loss) {
+					usage_error("packet loss out of range");
+				}
+			}
+			"--seed" => options.seed = parse(arg, value()),
+			"--fec" => options.fec = true,
+			"--float" => options.float = true,
+			"--raw" => options.raw = true,
+			"--quiet" => options.quiet = true,
+			"-" => paths.push(arg.clone()),
+			flag if flag.starts_with('-') => usage_error(&format!("unknown option: {}", flag)),
+			_ => paths.push(arg.clone()),
+		}
+	}
+	if paths.len() != 2 {
+		usage_error("expected an input and an output path");
+	}
+	options.output = paths.pop().unwrap();
+	options.input = paths.pop().unwrap();
+	options
+}
+
+fn main() {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	let options = parse_args(&args);
+	if let Err(err) = run(&options) {
+		eprintln!("opus-rs-dec: {}", err);
+		exit(1);
+	}
+}
+
+/// The packets of the input, from either container.
+enum Reader {
+	Ogg(OggOpusReader<Box<dyn Read>>),
+	Demo(DemoBitstreamReader<Box<dyn Read>>),
+}
+
+/// A packet of the input, as it comes out of the simulated network.
+struct Packet {
+	/// The packet, left empty for a lost packet of an `opus_demo` file.
+	data: Vec<u8>,
+	/// The granule position of the page the packet ends, if any.
+	granule: Option<u64>,
+	/// Whether the packet survived the network.
+	received: bool,
+}
+
+/// The input file, read a packet at a time.
+struct Input {
+	head: OpusHead,
+	reader: Reader,
+	network: Option<Network>,
+}
+
+impl Input {
+	fn open(options: &Options) -> Result<Input, Box<dyn std::error::Error>> {
+		let mut input: Box<dyn Read> = if options.input == "-" {
+			Box::new(io::stdin())
+		} else {
+			let file =
+				File::open(&options.input).map_err(|err| format!("{}: {}", options.input, err))?;
+			Box::new(file)
+		};
+		let mut magic = Vec::new();
+		input.by_ref().take(4).read_to_end(&mut magic)?;
+		let is_ogg = magic == b"OggS";
+		let input: Box<dyn Read> = Box::new(BufReader::new(Cursor::new(magic).chain(input)));
+
+		let (head, reader) = if is_ogg {
+			let reader = OggOpusReader::new(input)?;
+			(reader.head().clone(), Reader::Ogg(reader))
+		} else {
+			let channels = if options.channels == 1 { Channels::Mono } else { Channels::Stereo };
+			(OpusHead::new(channels, 0, 48000), Reader::Demo(DemoBitstreamReader::new(input)))
+		};
+
+		// drop packets as chosen by the simulated network
+		let network = if options.packet_loss > 0.0 {
+			Some(Network::new(&Impairments {
+				loss: LossModel::Bernoulli { loss: options.packet_loss / 100.0 },
+				seed: options.seed,
+				..Impairments::default()
+			}))
+		} else {
+			None
+		};
+		Ok(Input { head, reader, network })
+	}
+
+	fn read_packet(&mut self) -> opus::Result<Option<Packet>> {
+		let (data, granule) = match self.reader {
+			Reader::Ogg(ref mut reader) => match reader.read_packet()? {
+				Some(packet) => (packet.data, packet.granule_position),
+				None => return Ok(None),
+			},
+			Reader::Demo(ref mut reader) => match reader.read_packet()? {
+				Some(packet) => (packet.data, None),
+				None => return Ok(None),
+			},
+		};
+		let mut received = !data.is_empty();
+		if let Some(ref mut network) = self.network {
+			received &= !network.send(&data).is_empty();
+		}
+		Ok(Some(Packet { data, granule, received }))
+	}
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+	let mut input = Input::open(options)?;
+	let head = input.head.clone();
+	let channels = head.channels as usize;
+
+	// unsupported rates are decoded at the next supported rate up
+	let decode_rate = resample::codec_rate(options.rate);
+	let mut decoder = head.decoder(decode_rate)?;
+	if options.gain != 0.0 {
+		let gain = head.output_gain as f64 + options.gain * 256.0;
+		decoder.set_gain(gain.round().clamp(-32768.0, 32767.0) as i32)?;
+	}
+	let ogg = matches!(input.reader, Reader::Ogg(_));
+	let mut output = Output::new(options, &head, decode_rate, ogg)?;
+
+	let mut stats = Stats::default();
+	let mut pcm = Vec::new();
+	// dropped packets are concealed for their own duration, and the lost
+	// packets of an opus_demo file for that of the last packet
+	let mut duration = decode_rate as usize / 50;
+	// the next packet is read ahead for its forward error correction data
+	let mut next = input.read_packet()?;
+	while let Some(packet) = next {
+		next = input.read_packet()?;
+		stats.packets += 1;
+		if !packet.data.is_empty() {
+			duration = packet::get_nb_samples(&packet.data, decode_rate)?;
+		}
+		pcm.clear();
+		pcm.resize(duration * channels, 0.0f32);
+		if packet.received {
+			match decoder.decode_float(&packet.data, &mut pcm, false) {
+				Ok(_) => {}
+				// packets which fail to decode are concealed instead
+				Err(_) => {
+					decoder.decode_float(&[], &mut pcm, false)?;
+					stats.errors += 1;
+				}
+			}
+		} else if let Some(next) = next.as_ref().filter(|next| options.fec && next.received) {
+			match decoder.decode_float(&next.data, &mut pcm, true) {
+				Ok(_) => stats.fec += 1,
+				// as are packets whose recovery data fails to decode
+				Err(_) => {
+					decoder.decode_float(&[], &mut pcm, false)?;
+					stats.errors += 1;
+				}
+			}
+		} else {
+			decoder.decode_float(&[], &mut pcm, false)?;
+			stats.concealed += 1;
+		}
+		output.write(&pcm, packet.granule)?;
+	}
+	let frames = output.finish()?;
+
+	if !options.quiet {
+		eprintln!(
+			"Decoded {:.2} s of {}-channel audio from {} packets: {} lost, {} recovered with FEC, {} concealed, {} failed to decode",
+			frames as f64 / decode_rate as f64,
+			channels,
+			stats.packets,
+			stats.fec + stats.concealed,
+			stats.fec,
+			stats.concealed,
+			stats.errors
+		);
+	}
+	Ok(())
+}
+
+#[derive(Default)]
+struct Stats {
+	packets: usize,
+	fec: usize,
+	concealed: usize,
+	errors: usize,
+}
+
+/// Trims and resamples the decoded audio as it arrives.
+struct Output {
+	channels: usize,
+	decode_rate: u32,
+	pre_skip: u64,
+	/// Frames of the pre-skip still to drop.
+	skip: usize,
+	/// Whether the input gives the end of the audio, so that the padding
+	/// after it can be trimmed.
+	trim_end: bool,
+	/// The audio of the last page, held back until the next packet shows
+	/// that it isn't the final page, whose end may be padding.
+	held: Vec<f32>,
+	/// The granule position of the last page, once it's complete.
+	granule: Option<u64>,
+	/// Frames passed on after trimming, at the decoding rate.
+	frames: u64,
+	/// The resampler, with the frames of its delay still to drop.
+	resampler: Option<(Resampler, usize)>,
+	writer: Writer,
+}
+
+impl Output {
+	fn new(
+		options: &Options,
+		head: &OpusHead,
+		decode_rate: u32,
+		trim_end: bool,
+	) -> Result<Output, Box<dyn std::error::Error>> {
+		let channels = head.channels as usize;
+		let resampler = if decode_rate != options.rate {
+			let resampler = Resampler::new(decode_rate, options.rate, channels)?;
+			let delay = resampler.delay() * options.rate as usize / decode_rate as usize;
+			Some((resampler, delay))
+		} else {
+			None
+		};
+		Ok(Output {
+			channels,
+			decode_rate,
+			pre_skip: head.pre_skip as u64,
+			skip: head.pre_skip as usize * decode_rate as usize / 48000,
+			trim_end,
+			held: Vec::new(),
+			granule: None,
+			frames: 0,
+			resampler,
+			writer: Writer::new(options, head)?,
+		})
+	}
+
+	/// Pass on a packet's audio, with the granule position of the page it
+	/// ends, if any.
+	fn write(&mut self, pcm: &[f32], granule: Option<u64>) -> opus::Result<()> {
+		// a page followed by more audio isn't the last, so it's all kept
+		if self.granule.take().is_some() {
+			let held = std::mem::take(&mut self.held);
+			self.pass_on(&held)?;
+			self.held = held;
+			self.held.clear();
+		}
+
+		let skip = self.skip.min(pcm.len() / self.channels);
+		self.skip -= skip;
+		let pcm = &pcm[skip * self.channels..];
+		if self.trim_end {
+			self.held.extend_from_slice(pcm);
+			self.granule = granule;
+			Ok(())
+		} else {
+			self.pass_on(pcm)
+		}
+	}
+
+	/// Trim the padding from the end of the last page and flush the
+	/// resampler, returning the number of frames passed on at the decoding
+	/// rate.
+	fn finish(mut self) -> opus::Result<u64> {
+		let mut held = std::mem::take(&mut self.held);
+		if let Some(granule) = self.granule {
+			let length = granule.saturating_sub(self.pre_skip) * self.decode_rate as u64 / 48000;
+			let frames = length.saturating_sub(self.frames) as usize;
+			held.truncate(frames * self.channels);
+		}
+		self.pass_on(&held)?;
+
+		if let Some((ref mut resampler, _)) = self.resampler {
+			let input_rate = self.decode_rate as u64;
+			let output_rate = self.writer.rate as u64;
+			let length = self.frames * output_rate / input_rate;
+			let silence = vec![0.0f32; resampler.delay() * self.channels];
+			let output = resampler.process(&silence);
+			let frames = length.saturating_sub(self.writer.frames) as usize;
+			let end = (frames * self.channels).min(output.len());
+			self.writer.write(&output[..end])?;
+		}
+		self.writer.finish()?;
+		Ok(self.frames)
+	}
+
+	/// Resample audio at the decoding rate, dropping the resampler's delay,
+	/// and write it.
+	fn pass_on(&mut self, pcm: &[f32]) -> opus::Result<()> {
+		self.frames += (pcm.len() / self.channels) as u64;
+		match self.resampler {
+			Some((ref mut resampler, ref mut delay)) => {
+				let output = resampler.process(pcm);
+				let skip = (*delay).min(output.len() / self.channels);
+				*delay -= skip;
+				self.writer.write(&output[skip * self.channels..])
+			}
+			None => self.writer.write(pcm),
+		}
+	}
+}
+
+/// Where the audio is written.
+enum Sink {
+	Wav(WavWriter<BufWriter<File>>),
+	/// A WAV file on standard output, whose header can't be filled in.
+	StreamingWav(WavWriter<BufWriter<Stdout>>),
+	Raw(Box<dyn Write>),
+}
+
+/// Soft-clips, reorders and writes the audio at the output rate.
+struct Writer {
+	channels: usize,
+	rate: u32,
+	float: bool,
+	/// The soft clippers, one for the whole signal when it has at most two
+	/// channels and one per channel otherwise.
+	clips: Vec<SoftClip>,
+	/// The position in a WAV file of each channel in Vorbis order, when it
+	/// needs reordering.
+	order: Option<Vec<usize>>,
+	sink: Sink,
+	buffer: Vec<f32>,
+	bytes: Vec<u8>,
+	/// Frames written.
+	frames: u64,
+}
+
+impl Writer {
+	fn new(options: &Options, head: &OpusHead) -> Result<Writer, Box<dyn std::error::Error>> {
+		let channels = head.channels as usize;
+		let format = PcmFormat {
+			channels: channels as u16,
+			sample_rate: options.rate,
+			sample_format: if options.float { SampleFormat::F32 } else { SampleFormat::I16 },
+			channel_mask: None,
+		};
+		let clips = if !options.soft_clip {
+			Vec::new()
+		} else if channels <= 2 {
+			let layout = if channels == 1 { Channels::Mono } else { Channels::Stereo };
+			vec![SoftClip::new(layout)]
+		} else {
+			// the soft clipper handles at most two channels at a time
+			(0..channels).map(|_| SoftClip::new(Channels::Mono)).collect()
+		};
+		// Vorbis order is reordered to the WAV speaker order
+		let order = if head.mapping_family == 1 && channels > 2 && !options.raw {
+			Some(StreamLayout::with_family(&format, 1)?.order)
+		} else {
+			None
+		};
+
+		let sink = if options.output == "-" {
+			let stdout = BufWriter::new(io::stdout());
+			if options.raw {
+				Sink::Raw(Box::new(stdout))
+			} else {
+				Sink::StreamingWav(WavWriter::streaming(stdout, &format)?)
+			}
+		} else {
+			let file = File::create(&options.output)
+				.map_err(|err| format!("{}: {}", options.output, err))?;
+			let file = BufWriter::new(file);
+			if options.raw {
+				Sink::Raw(Box::new(file))
+			} else {
+				Sink::Wav(WavWriter::new(file, &format)?)
+			}
+		};
+
+		Ok(Writer {
+			channels,
+			rate: options.rate,
+			float: options.float,
+			clips,
+			order,
+			sink,
+			buffer: Vec::new(),
+			bytes: Vec::new(),
+			frames: 0,
+		})
+	}
+
+	fn write(&mut self, pcm: &[f32]) -> opus::Result<()> {
+		let channels = self.channels;
+		self.frames += (pcm.len() / channels) as u64;
+		self.buffer.clear();
+		self.buffer.extend_from_slice(pcm);
+
+		if let [ref mut clip] = self.clips[..] {
+			clip.apply(&mut self.buffer);
+		} else if !self.clips.is_empty() {
+			let mut channel = Vec::with_capacity(pcm.len() / channels);
+			for (c, clip) in self.clips.iter_mut().enumerate() {
+				channel.clear();
+				channel.extend(self.buffer.iter().skip(c).step_by(channels));
+				clip.apply(&mut channel);
+				for (sample, &clipped) in
+					self.buffer.iter_mut().skip(c).step_by(channels).zip(&channel)
+				{
+					*sample = clipped;
+				}
+			}
+		}
+
+		if let Some(ref order) = self.order {
+			let mut frame = vec![0.0; channels];
+			for chunk in self.buffer.chunks_mut(channels) {
+				for (i, &file) in order.iter().enumerate() {
+					frame[file] = chunk[i];
+				}
+				chunk.copy_from_slice(&frame);
+			}
+		}
+
+		match self.sink {
+			Sink::Wav(ref mut writer) => writer.write(&self.buffer),
+			Sink::StreamingWav(ref mut writer) => writer.write(&self.buffer),
+			Sink::Raw(ref mut writer) => {
+				self.bytes.clear();
+				for &sample in &self.buffer {
+					if self.float {
+						self.bytes.extend_from_slice(&sample.to_le_bytes());
+					} else {
+						let sample = (sample * 32768.0).round().clamp(-32768.0, 32767.0) as i16;
+						self.bytes.extend_from_slice(&sample.to_le_bytes());
+					}
+				}
+				writer.write_all(&self.bytes)?;
+				Ok(())
+			}
+		}
+	}
+
+	fn finish(self) -> opus::Result<()> {
+		match self.sink {
+			Sink::Wav(writer) => writer.finish()?.flush()?,
+			Sink::StreamingWav(writer) => {
+				writer.finish_streaming()?;
+			}
+			Sink::Raw(mut writer) => writer.flush()?,
+		}
+		Ok(())
+	}
+}
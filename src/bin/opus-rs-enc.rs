@@ -0,0 +1,335 @@
+//! Encode WAV, CAF or raw PCM audio to an Ogg Opus file.
+//!
+//! Usage: `opus-rs-enc [options] <input> <output>`
+//!
+//! Either path may be `-` for standard input or output. Run with `--help`
+//! for the options, which map directly onto the encoder's settings.
+
+extern crate opus;
+
+use opus::ogg::{OggOpusWriter, OpusTags};
+use opus::pcm::{PcmFormat, PcmReader, SampleFormat, StreamLayout};
+use opus::resample::{self, Resampler};
+use opus::{Application, Bitrate, BufferedEncoder, FrameSize, MSEncoder};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::process::exit;
+
+const USAGE: &str = "\
+Usage: opus-rs-enc [options] <input> <output>
+
+Encodes a WAV, RF64, CAF or raw PCM file to Ogg Opus. Either path may be -
+for standard input or output.
+
+Encoding options:
+  --bitrate <kbit/s>     Target bitrate (default: chosen by the encoder)
+  --vbr                  Use variable bitrate (default)
+  --cvbr                 Use constrained variable bitrate
+  --hard-cbr             Use constant bitrate
+  --comp <0-10>          Encoder complexity (default: 10)
+  --framesize <ms>       Frame duration: 2.5, 5, 10, 20, 40, 60, 80, 100 or
+                         120 (default: 20)
+  --application <name>   audio, voip or lowdelay (default: audio)
+  --fec                  Enable inband forward error correction
+  --expect-loss <0-100>  Expected packet loss percentage (default: 0)
+  --dtx                  Enable discontinuous transmission
+  --mapping-family <n>   Channel mapping family: 0, 1 or 255 (default: 0 for
+                         mono and stereo, 1 for surround layouts, otherwise 255)
+
+Input options:
+  --raw                  Read headerless PCM
+  --raw-bits <n>         Bits per raw sample: 8, 16, 24 or 32 (default: 16)
+  --raw-float            Raw samples are floating point (32 or 64 bits)
+  --raw-rate <hz>        Raw sample rate (default: 48000)
+  --raw-chan <n>         Raw channel count (default: 2)
+  --raw-endianness <n>   0 for little-endian, 1 for big-endian (default: 0)
+
+Output options:
+  --comment <key=value>  Add a comment to the tags
+  --title, --artist, --album, --date, --genre <value>
+                         Add the corresponding comment
+  --serial <n>           Ogg stream serial number (default: 1)
+  --quiet                Don't print a summary
+  -h, --help             Show this help";
+
+struct Options {
+	input: String,
+	output: String,
+	bitrate: Bitrate,
+	vbr: bool,
+	constrained: bool,
+	complexity: i32,
+	frame_size: FrameSize,
+	application: Application,
+	fec: bool,
+	expect_loss: i32,
+	dtx: bool,
+	mapping_family: Option<u8>,
+	raw: bool,
+	raw_bits: u16,
+	raw_float: bool,
+	raw_rate: u32,
+	raw_channels: u16,
+	raw_big_endian: bool,
+	comments: Vec<(String, String)>,
+	serial: u32,
+	quiet: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+	eprintln!("opus-rs-enc: {}", message);
+	eprintln!("Try 'opus-rs-enc --help' for more information.");
+	exit(2);
+}
+
+fn parse<T: std::str::FromStr>(flag: &str, value: &str) -> T {
+	match value.parse() {
+		Ok(value) => value,
+		Err(_) => usage_error(&format!("invalid value for {}: {}", flag, value)),
+	}
+}
+
+fn parse_args(args: &[String]) -> Options {
+	let mut options = Options {
+		input: String::new(),
+		output: String::new(),
+		bitrate: Bitrate::Auto,
+		vbr: true,
+		constrained: false,
+		complexity: 10,
+		frame_size: FrameSize::Ms20,
+		application: Application::Audio,
+		fec: false,
+		expect_loss: 0,
+		dtx: false,
+		mapping_family: None,
+		raw: false,
+		raw_bits: 16,
+		raw_float: false,
+		raw_rate: 48000,
+		raw_channels: 2,
+		raw_big_endian: false,
+		comments: Vec::new(),
+		serial: 1,
+		quiet: false,
+	};
+	let mut paths = Vec::new();
+	let mut iter = args.iter();
+	while let Some(arg) = iter.next() {
+		let mut value = || match iter.next() {
+			Some(value) => value.as_str(),
+			None => usage_error(&format!("{} requires a value", arg)),
+		};
+		match arg.as_str() {
+			"-h" | "--help" => {
+				println!("{}", USAGE);
+				exit(0);
+			}
+			"--bitrate" => {
+				let kbps: f64 = parse(arg, value());
+				if !(0.5..=2048.0).contains(&kbps) {
+					usage_error("bitrate out of range");
+				}
+				options.bitrate = Bitrate::Bits((kbps * 1000.0).round() as i32);
+			}
+			"--vbr" => {
+				options.vbr = true;
+				options.constrained = false;
+			}
+			"--cvbr" => {
+				options.vbr = true;
+				options.constrained = true;
+			}
+			"--hard-cbr" => options.vbr = false,
+			"--comp" => options.complexity = parse(arg, value()),
+			"--framesize" => {
+				let value = value();
+				options.frame_size = parse(arg, value);
+				if options.frame_size == FrameSize::Arg {
+					usage_error(&format!("invalid value for {}: {}", arg, value));
+				}
+			}
+			"--application" => options.application = parse(arg, value()),
+			"--fec" => options.fec = true,
+			"--expect-loss" => options.expect_loss = parse(arg, value()),
+			"--dtx" => options.dtx = true,
+			"--mapping-family" => options.mapping_family = Some(parse(arg, value())),
+			"--raw" => options.raw = true,
+			"--raw-bits" => options.raw_bits = parse(arg, value()),
+			"--raw-float" => options.raw_float = true,
+			"--raw-rate" => options.raw_rate = parse(arg, value()),
+			"--raw-chan" => options.raw_channels = parse(arg, value()),
+			"--raw-endianness" => {
+				options.raw_big_endian = match value() {
+					"0" => false,
+					"1" => true,
+					other => usage_error(&format!("invalid endianness: {}", other)),
+				}
+			}
+			"--comment" => {
+				let comment = value();
+				match comment.find('=') {
+					Some(at) if at > 0 => options
+						.comments
+						.push((comment[..at].to_owned(), comment[at + 1..].to_owned())),
+					_ => usage_error(&format!("invalid comment: {}", comment)),
+				}
+			}
+			"--title" | "--artist" | "--album" | "--date" | "--genre" => {
+				let key = arg[2..].to_ascii_uppercase();
+				options.comments.push((key, value().to_owned()));
+			}
+			"--serial" => options.serial = parse(arg, value()),
+			"--quiet" => options.quiet = true,
+			"-" => paths.push(arg.clone()),
+			flag if flag.starts_with('-') => usage_error(&format!("unknown option: {}", flag)),
+			_ => paths.push(arg.clone()),
+		}
+	}
+	if paths.len() != 2 {
+		usage_error("expected an input and an output path");
+	}
+	options.output = paths.pop().unwrap();
+	options.input = paths.pop().unwrap();
+	options
+}
+
+fn main() {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	let options = parse_args(&args);
+	if let Err(err) = run(&options) {
+		eprintln!("opus-rs-enc: {}", err);
+		exit(1);
+	}
+}
+
+fn run(options: &Options) -> Result<(), Box<dyn std::error::Error>> {
+	let input: Box<dyn Read> = if options.input == "-" {
+		Box::new(io::stdin())
+	} else {
+		Box::new(File::open(&options.input).map_err(|err| format!("{}: {}", options.input, err))?)
+	};
+	let input = BufReader::new(input);
+	let mut reader = if options.raw {
+		let sample_format = match (options.raw_bits, options.raw_float) {
+			(8, false) => SampleFormat::U8,
+			(16, false) => SampleFormat::I16,
+			(24, false) => SampleFormat::I24,
+			(32, false) => SampleFormat::I32,
+			(32, true) => SampleFormat::F32,
+			(64, true) => SampleFormat::F64,
+			_ => return Err("unsupported raw sample format".into()),
+		};
+		let format = PcmFormat {
+			channels: options.raw_channels,
+			sample_rate: options.raw_rate,
+			sample_format,
+			channel_mask: None,
+		};
+		PcmReader::raw(input, &format, options.raw_big_endian)?
+	} else {
+		PcmReader::new(input)?
+	};
+	let format = *reader.format();
+	let channels = format.channels as usize;
+
+	let layout = match options.mapping_family {
+		Some(family) => StreamLayout::with_family(&format, family)?,
+		None => StreamLayout::for_format(&format)?,
+	};
+	reader.reorder(&layout)?;
+
+	// unsupported rates are resampled to the next supported rate up
+	let codec_rate = resample::codec_rate(format.sample_rate);
+	let mut resampler = if codec_rate == format.sample_rate {
+		None
+	} else {
+		Some(Resampler::new(format.sample_rate, codec_rate, channels)?)
+	};
+	let mut encoder = layout.encoder(codec_rate, options.application)?;
+	configure(&mut encoder, options)?;
+	let mut head = layout.head(&mut encoder)?;
+	let mut encoder = BufferedEncoder::<f32, MSEncoder>::new(encoder, options.frame_size)?;
+	let resampler_delay = resampler.as_ref().map_or(0, |r| r.delay() as u64);
+	let pre_skip = head.pre_skip as u64 + resampler_delay * 48000 / format.sample_rate as u64;
+	head.pre_skip = pre_skip.min(u16::MAX as u64) as u16;
+	head.input_sample_rate = format.sample_rate;
+
+	let mut tags = OpusTags::new();
+	tags.add("ENCODER", &format!("opus-rs-enc {}", env!("CARGO_PKG_VERSION")));
+	for (key, value) in &options.comments {
+		tags.add(key, value);
+	}
+
+	let output: Box<dyn Write> = if options.output == "-" {
+		Box::new(io::stdout())
+	} else {
+		Box::new(
+			File::create(&options.output).map_err(|err| format!("{}: {}", options.output, err))?,
+		)
+	};
+	let mut ogg = OggOpusWriter::new(BufWriter::new(output), &head, &tags, options.serial)?;
+
+	let mut buffer = vec![0.0f32; 4096 * channels];
+	let mut packets = Vec::new();
+	let mut frames = 0u64;
+	let mut bytes = 0u64;
+	let mut count = 0u64;
+	let mut write = |packets: &mut Vec<Vec<u8>>, ogg: &mut OggOpusWriter<_>| -> opus::Result<()> {
+		for packet in packets.drain(..) {
+			ogg.write_packet(&packet)?;
+			bytes += packet.len() as u64;
+			count += 1;
+		}
+		Ok(())
+	};
+
+	loop {
+		let read = reader.read(&mut buffer)?;
+		if read == 0 {
+			break;
+		}
+		frames += read as u64;
+		let input = &buffer[..read * channels];
+		match resampler {
+			Some(ref mut resampler) => encoder.push(resampler.process(input), &mut packets)?,
+			None => encoder.push(input, &mut packets)?,
+		}
+		write(&mut packets, &mut ogg)?;
+	}
+
+	// flush the delayed end of the input out of the resampler, then pad with
+	// silence until the encoder's lookahead is covered
+	if let Some(ref mut resampler) = resampler {
+		let silence = vec![0.0f32; (resampler.delay() + 1) * channels];
+		encoder.push(resampler.process(&silence), &mut packets)?;
+	}
+	encoder.finish(&mut packets)?;
+	write(&mut packets, &mut ogg)?;
+	let length = (frames * 48000 + format.sample_rate as u64 / 2) / format.sample_rate as u64;
+	ogg.finish_with_length(length)?.flush()?;
+
+	if !options.quiet {
+		let seconds = length as f64 / 48000.0;
+		eprintln!(
+			"Encoded {:.2} s of {}-channel audio in {} packets, {:.1} kbit/s",
+			seconds,
+			channels,
+			count,
+			if seconds > 0.0 { bytes as f64 * 8.0 / seconds / 1000.0 } else { 0.0 }
+		);
+	}
+	Ok(())
+}
+
+fn configure(encoder: &mut MSEncoder, options: &Options) -> opus::Result<()> {
+	encoder.set_bitrate(options.bitrate)?;
+	encoder.set_vbr(options.vbr)?;
+	encoder.set_vbr_constraint(options.constrained)?;
+	encoder.set_complexity(options.complexity)?;
+	encoder.set_expert_frame_duration(options.frame_size)?;
+	encoder.set_inband_fec(options.fec)?;
+	encoder.set_packet_loss_perc(options.expect_loss)?;
+	encoder.set_dtx(options.dtx)?;
+	Ok(())
+}
@@ -0,0 +1,103 @@
+//! Describe the packets of an Ogg Opus, WebM or `opus_demo` bitstream file.
+//!
+//! Usage: `opus-rs-info [options] <input>`
+//!
+//! The input may be `-` for standard input. The report covers the stream's
+//! header, histograms of the packets' TOC fields and sizes, the bitrate over
+//! time, and any packets breaking the framing rules of RFC 6716.
+
+extern crate opus;
+extern crate serde_json;
+
+use opus::inspect;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::process::exit;
+
+const USAGE: &str = "\
+Usage: opus-rs-info [options] <input>
+
+Describes the packets of an Ogg Opus, WebM or opus_demo bitstream file. The
+input may be - for standard input.
+
+Options:
+  --packets              List every packet
+  --json                 Write the report as JSON, always including every
+                         packet
+  -h, --help             Show this help
+
+The exit status is 3 if any packet breaks the framing rules of RFC 6716.";
+
+struct Options {
+	input: String,
+	packets: bool,
+	json: bool,
+}
+
+fn usage_error(message: &str) -> ! {
+	eprintln!("opus-rs-info: {}", message);
+	eprintln!("Try 'opus-rs-info --help' for more information.");
+	exit(2);
+}
+
+fn parse_args(args: &[String]) -> Options {
+	let mut options = Options {
+		input: String::new(),
+		packets: false,
+		json: false,
+	};
+	let mut paths = Vec::new();
+	for arg in args {
+		match arg.as_str() {
+			"-h" | "--help" => {
+				println!("{}", USAGE);
+				exit(0);
+			}
+			"--packets" => options.packets = true,
+			"--json" => options.json = true,
+			"-" => paths.push(arg.clone()),
+			flag if flag.starts_with('-') => usage_error(&format!("unknown option: {}", flag)),
+			_ => paths.push(arg.clone()),
+		}
+	}
+	if paths.len() != 1 {
+		usage_error("expected an input path");
+	}
+	options.input = paths.pop().unwrap();
+	options
+}
+
+fn main() {
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	let options = parse_args(&args);
+	match run(&options) {
+		Ok(true) => {}
+		Ok(false) => exit(3),
+		Err(err) => {
+			eprintln!("opus-rs-info: {}", err);
+			exit(1);
+		}
+	}
+}
+
+/// Print the report, returning whether the stream is valid.
+fn run(options: &Options) -> Result<bool, Box<dyn std::error::Error>> {
+	let input: Box<dyn Read> = if options.input == "-" {
+		Box::new(io::stdin())
+	} else {
+		Box::new(File::open(&options.input).map_err(|err| format!("{}: {}", options.input, err))?)
+	};
+	let report = inspect::inspect(BufReader::new(input))?;
+
+	if options.json {
+		println!("{}", serde_json::to_string_pretty(&report)?);
+	} else {
+		if options.packets {
+			for packet in &report.packets {
+				println!("{}", packet);
+			}
+		}
+		print!("{}", report);
+	}
+	Ok(report.is_valid())
+}
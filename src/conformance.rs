@@ -0,0 +1,459 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Conformance testing against the official Opus test vectors.
+//!
+//! The test vectors from RFC 6716, as updated by RFC 8251, are `.bit` files
+//! in the `opus_demo` bitstream format, which gives the encoder's final range
+//! for each packet. Each has a reference decoding in a `.dec` file of 16-bit
+//! little-endian stereo PCM at 48 kHz, and RFC 8251 adds a second reference
+//! ending in `m.dec` which is also acceptable.
+//!
+//! A decoder is conformant if, at every sample rate and channel count, its
+//! final ranges match the encoder's and its output is close enough to either
+//! reference according to `compare`, a port of libopus's `opus_compare`.
+
+use super::opus_demo::DemoBitstreamReader;
+use super::{Channels, Decoder, Error, ErrorKind, MSDecoder, Result};
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// The sample rates at which every test vector is decoded.
+pub const RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+
+/// The outcome of comparing decoded audio against a reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Comparison {
+	/// The internal weighted error.
+	pub error: f64,
+	/// The Opus quality metric, as a percentage. Negative values fail.
+	pub quality: f64,
+}
+
+impl Comparison {
+	/// Check whether the quality is high enough to be conformant.
+	pub fn passes(&self) -> bool {
+		self.quality >= 0.0
+	}
+}
+
+/// The result of decoding a test vector with one configuration.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VectorResult {
+	/// The sample rate decoded at.
+	pub sample_rate: u32,
+	/// The number of channels decoded.
+	pub channels: Channels,
+	/// Whether the vector was decoded by an `MSDecoder` with mapping family
+	/// 0, rather than a `Decoder`.
+	pub multistream: bool,
+	/// The comparison against the better-matching reference.
+	pub comparison: Comparison,
+}
+
+/// Decode a test vector bitstream, checking every packet's final range.
+///
+/// Packets of length zero are treated as lost and concealed.
+pub fn decode<R: Read>(bitstream: R, sample_rate: u32, channels: Channels) -> Result<Vec<i16>> {
+	decode_with(bitstream, Decoder::new(sample_rate, channels)?, sample_rate, channels)
+}
+
+/// Decode a test vector bitstream with an `MSDecoder`, as a single stream
+/// with mapping family 0, checking every packet's final range.
+pub fn decode_multistream<R: Read>(
+	bitstream: R,
+	sample_rate: u32,
+	channels: Channels,
+) -> Result<Vec<i16>> {
+	let coupled = channels as u8 - 1;
+	let mapping = [0, 1];
+	let decoder = MSDecoder::new(sample_rate, 1, coupled, &mapping[..channels as usize])?;
+	decode_with(bitstream, decoder, sample_rate, channels)
+}
+
+/// The parts of `Decoder` and `MSDecoder` needed to decode a test vector.
+trait VectorDecoder {
+	fn decode(&mut self, input: &[u8], output: &mut [i16]) -> Result<usize>;
+	fn get_final_range(&mut self) -> Result<u32>;
+	fn get_last_packet_duration(&mut self) -> Result<u32>;
+}
+
+impl VectorDecoder for Decoder {
+	fn decode(&mut self, input: &[u8], output: &mut [i16]) -> Result<usize> {
+		Decoder::decode(self, input, output, false)
+	}
+
+	fn get_final_range(&mut self) -> Result<u32> {
+		Decoder::get_final_range(self)
+	}
+
+	fn get_last_packet_duration(&mut self) -> Result<u32> {
+		Decoder::get_last_packet_duration(self)
+	}
+}
+
+impl VectorDecoder for MSDecoder {
+	fn decode(&mut self, input: &[u8], output: &mut [i16]) -> Result<usize> {
+		MSDecoder::decode(self, input, output, false)
+	}
+
+	fn get_final_range(&mut self) -> Result<u32> {
+		MSDecoder::get_final_range(self)
+	}
+
+	fn get_last_packet_duration(&mut self) -> Result<u32> {
+		MSDecoder::get_last_packet_duration(self)
+	}
+}
+
+fn decode_with<R: Read, D: VectorDecoder>(
+	bitstream: R,
+	mut decoder: D,
+	sample_rate: u32,
+	channels: Channels,
+) -> Result<Vec<i16>> {
+	let mut reader = DemoBitstreamReader::new(BufReader::new(bitstream));
+	let channels = channels as usize;
+	let mut buffer = vec![0i16; sample_rate as usize * 120 / 1000 * channels];
+	let mut output = Vec::new();
+	let mut data = Vec::new();
+	let mut packet = 0;
+	while let Some(expected) = reader.read_packet_into(&mut data)? {
+		let samples = if data.is_empty() {
+			let duration = decoder.get_last_packet_duration()? as usize;
+			decoder.decode(&[], &mut buffer[..duration * channels])?
+		} else {
+			let samples = decoder.decode(&data, &mut buffer)?;
+			let actual = decoder.get_final_range()?;
+			if expected != actual {
+				return Err(Error::new(
+					"conformance::decode",
+					ErrorKind::RangeMismatch { packet, expected, actual },
+				));
+			}
+			samples
+		};
+		output.extend_from_slice(&buffer[..samples * channels]);
+		packet += 1;
+	}
+	Ok(output)
+}
+
+/// Read 16-bit little-endian PCM, such as a `.dec` file.
+pub fn read_pcm16<R: Read>(mut reader: R) -> Result<Vec<i16>> {
+	let mut bytes = Vec::new();
+	reader.read_to_end(&mut bytes)?;
+	Ok(bytes.chunks_exact(2).map(|pair| i16::from_le_bytes([pair[0], pair[1]])).collect())
+}
+
+/// Decode a test vector at every sample rate and channel count, and compare
+/// the output against its references.
+///
+/// As in libopus's `run_vectors.sh`, output only needs to match one of the
+/// references. The stereo decodes are repeated with a family 0 `MSDecoder`.
+pub fn run_vector(bit: &Path, dec: &Path, alternate: Option<&Path>) -> Result<Vec<VectorResult>> {
+	let mut references = vec![read_pcm16(File::open(dec)?)?];
+	if let Some(path) = alternate {
+		references.push(read_pcm16(File::open(path)?)?);
+	}
+
+	let configs = [(Channels::Stereo, false), (Channels::Mono, false), (Channels::Stereo, true)];
+	let mut results = Vec::new();
+	for &(channels, multistream) in configs.iter() {
+		for &sample_rate in RATES.iter().rev() {
+			let output = if multistream {
+				decode_multistream(File::open(bit)?, sample_rate, channels)?
+			} else {
+				decode(File::open(bit)?, sample_rate, channels)?
+			};
+			let mut comparison = compare(&references[0], &output, channels, sample_rate)?;
+			for reference in &references[1..] {
+				let other = compare(reference, &output, channels, sample_rate)?;
+				if other.quality > comparison.quality {
+					comparison = other;
+				}
+			}
+			results.push(VectorResult {
+				sample_rate,
+				channels,
+				multistream,
+				comparison,
+			});
+		}
+	}
+	Ok(results)
+}
+
+/// The number of bands compared at 48 kHz.
+const NBANDS: usize = 21;
+
+/// The number of frequency bins compared at 48 kHz.
+const NFREQS: usize = 240;
+
+/// Bands on which the pseudo-NMR is computed, derived from the Bark scale as
+/// in CELT.
+const BANDS: [usize; NBANDS + 1] =
+	[0, 2, 4, 6, 8, 10, 12, 14, 16, 20, 24, 28, 32, 40, 48, 56, 68, 80, 96, 120, 156, 200];
+
+const TEST_WIN_SIZE: usize = 480;
+const TEST_WIN_STEP: usize = 120;
+
+/// Compare decoded audio against a reference using the Opus quality metric.
+///
+/// This is a port of libopus's `opus_compare`. The reference is always
+/// stereo at 48 kHz, and is mixed down for mono comparisons. The output is
+/// interleaved with the given channel count at `sample_rate`.
+pub fn compare(
+	reference: &[i16],
+	output: &[i16],
+	channels: Channels,
+	sample_rate: u32,
+) -> Result<Comparison> {
+	let nchannels = channels as usize;
+	let x: Vec<f32> = match channels {
+		Channels::Stereo => reference.iter().map(|&s| s as f32).collect(),
+		Channels::Mono => reference
+			.chunks_exact(2)
+			.map(|pair| (0.5 * (pair[0] as f64 + pair[1] as f64)) as f32)
+			.collect(),
+	};
+	let ylength = output.len() / nchannels;
+	let y: Vec<f32> = output[..ylength * nchannels].iter().map(|&s| s as f32).collect();
+	compare_f32(&x, &y, nchannels, sample_rate)
+}
+
+/// The core of `compare`, for a reference at 48 kHz and output at
+/// `sample_rate` with the same channel count, both scaled to 16-bit range.
+pub(crate) fn compare_f32(
+	x: &[f32],
+	y: &[f32],
+	nchannels: usize,
+	sample_rate: u32,
+) -> Result<Comparison> {
+	let (ybands, downsample) = match sample_rate {
+		8000 => (13, 6),
+		12000 => (15, 4),
+		16000 => (17, 3),
+		24000 => (19, 2),
+		48000 => (NBANDS, 1),
+		_ => {
+			return Err(Error::new(
+				"conformance::compare",
+				ErrorKind::UnsupportedSampleRate(sample_rate),
+			))
+		}
+	};
+	let yfreqs = NFREQS / downsample;
+	let xlength = x.len() / nchannels;
+	let ylength = y.len() / nchannels;
+	if xlength != ylength * downsample {
+		return Err(Error::bad_arg("conformance::compare").with_context("sample counts differ"));
+	}
+	if xlength < TEST_WIN_SIZE {
+		return Err(Error::bad_arg("conformance::compare").with_context("insufficient sample data"));
+	}
+
+	let nframes = (xlength - TEST_WIN_SIZE + TEST_WIN_STEP) / TEST_WIN_STEP;
+	let mut xb = vec![0.0f32; nframes * NBANDS * nchannels];
+	let mut xs = vec![0.0f32; nframes * NFREQS * nchannels];
+	let mut ys = vec![0.0f32; nframes * yfreqs * nchannels];
+	// compute the per-band spectral energy of the original signal and the
+	// error
+	band_energy(
+		Some(&mut xb),
+		&mut xs,
+		&BANDS,
+		NBANDS,
+		x,
+		nchannels,
+		nframes,
+		TEST_WIN_SIZE,
+		TEST_WIN_STEP,
+		1,
+	);
+	band_energy(
+		None,
+		&mut ys,
+		&BANDS,
+		ybands,
+		y,
+		nchannels,
+		nframes,
+		TEST_WIN_SIZE / downsample,
+		TEST_WIN_STEP / downsample,
+		downsample,
+	);
+
+	for xi in 0..nframes {
+		let xb_at = |bi: usize, ci: usize| (xi * NBANDS + bi) * nchannels + ci;
+		// frequency masking (low to high): 10 dB/Bark slope
+		for bi in 1..NBANDS {
+			for ci in 0..nchannels {
+				xb[xb_at(bi, ci)] += 0.1 * xb[xb_at(bi - 1, ci)];
+			}
+		}
+		// frequency masking (high to low): 15 dB/Bark slope
+		for bi in (0..NBANDS - 1).rev() {
+			for ci in 0..nchannels {
+				xb[xb_at(bi, ci)] += 0.03 * xb[xb_at(bi + 1, ci)];
+			}
+		}
+		if xi > 0 {
+			// temporal masking: -3 dB/2.5ms slope
+			for bi in 0..NBANDS {
+				for ci in 0..nchannels {
+					xb[xb_at(bi, ci)] += 0.5 * xb[xb_at(bi, ci) - NBANDS * nchannels];
+				}
+			}
+		}
+		// allow some cross-talk
+		if nchannels == 2 {
+			for bi in 0..NBANDS {
+				let l = xb[xb_at(bi, 0)];
+				let r = xb[xb_at(bi, 1)];
+				xb[xb_at(bi, 0)] += 0.01 * r;
+				xb[xb_at(bi, 1)] += 0.01 * l;
+			}
+		}
+		// apply masking
+		for bi in 0..ybands {
+			for xj in BANDS[bi]..BANDS[bi + 1] {
+				for ci in 0..nchannels {
+					let mask = 0.1 * xb[xb_at(bi, ci)];
+					xs[(xi * NFREQS + xj) * nchannels + ci] += mask;
+					ys[(xi * yfreqs + xj) * nchannels + ci] += mask;
+				}
+			}
+		}
+	}
+
+	// average consecutive frames to make the comparison slightly less
+	// sensitive
+	for bi in 0..ybands {
+		for xj in BANDS[bi]..BANDS[bi + 1] {
+			for ci in 0..nchannels {
+				let mut xtmp = xs[xj * nchannels + ci];
+				let mut ytmp = ys[xj * nchannels + ci];
+				for xi in 1..nframes {
+					let xtmp2 = xs[(xi * NFREQS + xj) * nchannels + ci];
+					let ytmp2 = ys[(xi * yfreqs + xj) * nchannels + ci];
+					xs[(xi * NFREQS + xj) * nchannels + ci] += xtmp;
+					ys[(xi * yfreqs + xj) * nchannels + ci] += ytmp;
+					xtmp = xtmp2;
+					ytmp = ytmp2;
+				}
+			}
+		}
+	}
+
+	// at lower sampling rates, ignore the last 300 Hz to allow for different
+	// transition bands; at 12 kHz, the last band already skips 400 Hz
+	let max_compare = match sample_rate {
+		48000 => BANDS[NBANDS],
+		12000 => BANDS[ybands],
+		_ => BANDS[ybands] - 3,
+	};
+	let mut err = 0.0f64;
+	for xi in 0..nframes {
+		let mut ef = 0.0f64;
+		for bi in 0..ybands {
+			let mut eb = 0.0f64;
+			for xj in BANDS[bi]..BANDS[bi + 1].min(max_compare) {
+				for ci in 0..nchannels {
+					let re = ys[(xi * yfreqs + xj) * nchannels + ci]
+						/ xs[(xi * NFREQS + xj) * nchannels + ci];
+					let mut im = (re as f64 - (re as f64).ln() - 1.0) as f32;
+					// less sensitive around the SILK/CELT cross-over to allow
+					// for mode freedom in the filters
+					if (79..=81).contains(&xj) {
+						im *= 0.1;
+					}
+					if xj == 80 {
+						im *= 0.1;
+					}
+					eb += im as f64;
+				}
+			}
+			eb /= ((BANDS[bi + 1] - BANDS[bi]) * nchannels) as f64;
+			ef += eb * eb;
+		}
+		// a fixed normalization accepts slightly lower quality at lower
+		// sampling rates
+		ef /= NBANDS as f64;
+		ef *= ef;
+		err += ef * ef;
+	}
+	let error = (err / nframes as f64).powf(1.0 / 16.0);
+	let quality = 100.0 * (1.0 - 0.5 * (1.0 + error).ln() / 1.13f64.ln());
+	Ok(Comparison { error, quality })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn band_energy(
+	mut out: Option<&mut [f32]>,
+	ps: &mut [f32],
+	bands: &[usize],
+	nbands: usize,
+	input: &[f32],
+	nchannels: usize,
+	nframes: usize,
+	window_sz: usize,
+	step: usize,
+	downsample: usize,
+) {
+	use std::f32::consts::PI;
+	let window: Vec<f32> = (0..window_sz)
+		.map(|xj| 0.5 - 0.5 * ((2.0 * PI / (window_sz - 1) as f32) * xj as f32).cos())
+		.collect();
+	let c: Vec<f32> =
+		(0..window_sz).map(|xj| ((2.0 * PI / window_sz as f32) * xj as f32).cos()).collect();
+	let s: Vec<f32> =
+		(0..window_sz).map(|xj| ((2.0 * PI / window_sz as f32) * xj as f32).sin()).collect();
+	let ps_sz = window_sz / 2;
+	let mut x = vec![0.0f32; nchannels * window_sz];
+
+	for xi in 0..nframes {
+		for ci in 0..nchannels {
+			for xk in 0..window_sz {
+				x[ci * window_sz + xk] = window[xk] * input[(xi * step + xk) * nchannels + ci];
+			}
+		}
+		let mut xj = 0;
+		for bi in 0..nbands {
+			let mut p = [0.0f32; 2];
+			while xj < bands[bi + 1] {
+				for ci in 0..nchannels {
+					let mut re = 0.0f32;
+					let mut im = 0.0f32;
+					let mut ti = 0;
+					for xk in 0..window_sz {
+						re += c[ti] * x[ci * window_sz + xk];
+						im -= s[ti] * x[ci * window_sz + xk];
+						ti += xj;
+						if ti >= window_sz {
+							ti -= window_sz;
+						}
+					}
+					re *= downsample as f32;
+					im *= downsample as f32;
+					let power = re * re + im * im + 100000.0;
+					ps[(xi * ps_sz + xj) * nchannels + ci] = power;
+					p[ci] += power;
+				}
+				xj += 1;
+			}
+			if let Some(ref mut out) = out {
+				let width = (bands[bi + 1] - bands[bi]) as f32;
+				for ci in 0..nchannels {
+					out[(xi * nbands + bi) * nchannels + ci] = p[ci] / width;
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,620 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Inspection of the packets in an Opus stream, in the manner of `opusinfo`.
+//!
+//! Each packet is parsed against the framing rules of RFC 6716 section 3.4,
+//! without decoding it, so streams which libopus would reject can still be
+//! described. Packets of multistream streams are split into their
+//! self-delimited streams as described in RFC 6716 appendix B.
+
+use super::ogg::OggOpusReader;
+use super::opus_demo::DemoBitstreamReader;
+use super::opus_head::OpusHead;
+use super::packet::{Mode, Toc};
+use super::webm::WebmReader;
+use super::{Bandwidth, Channels, FrameSize, Result};
+use std::fmt;
+use std::io::{Cursor, Read};
+
+/// The longest frame allowed, in bytes.
+const MAX_FRAME: usize = 1275;
+/// The longest packet allowed, in samples at 48 kHz.
+const MAX_DURATION: usize = 5760;
+/// The width of each bucket of the packet size histogram, in bytes.
+const SIZE_BUCKET: usize = 32;
+/// The interval over which the bitrate is measured, in samples at 48 kHz.
+const BITRATE_INTERVAL: u64 = 48000;
+
+/// The container an inspected stream was read from.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Format {
+	/// An Ogg Opus file.
+	Ogg,
+	/// A WebM or Matroska file.
+	Webm,
+	/// The `opus_demo` bitstream format.
+	OpusDemo,
+}
+
+/// A violation of the packet framing rules of RFC 6716, whose requirement
+/// numbers are given for each.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub enum Violation {
+	/// [R1] The packet, or one of its streams, is empty.
+	Empty,
+	/// [R2] A frame is longer than 1275 bytes.
+	FrameTooLong,
+	/// [R3], [R6] The payload of a constant bitrate packet cannot be split
+	/// into frames of equal length.
+	UnequalFrames,
+	/// [R4], [R6], [R7] The packet ends within a frame count or length, or
+	/// its frames and padding run past the end of the packet.
+	Truncated,
+	/// [R5] A code 3 packet holds no frames.
+	NoFrames,
+	/// [R5] The packet lasts longer than 120 ms.
+	TooLong,
+}
+
+impl fmt::Display for Violation {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		f.write_str(match *self {
+			Violation::Empty => "empty packet (R1)",
+			Violation::FrameTooLong => "frame longer than 1275 bytes (R2)",
+			Violation::UnequalFrames => "payload not divisible into equal frames (R3, R6)",
+			Violation::Truncated => "frames or padding run past the end (R4, R6, R7)",
+			Violation::NoFrames => "code 3 packet with no frames (R5)",
+			Violation::TooLong => "packet longer than 120 ms (R5)",
+		})
+	}
+}
+
+/// What was found in one packet.
+///
+/// For multistream packets the TOC, frame count and duration describe the
+/// first stream, while the sizes and counts cover every stream.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct PacketInfo {
+	/// The index of the packet in the stream.
+	pub index: u64,
+	/// The start of the packet, in samples at 48 kHz from the start of the
+	/// stream, including any pre-skip.
+	pub timestamp: u64,
+	/// The size of the packet, in bytes.
+	pub size: usize,
+	/// The decoded TOC byte, unless the packet is empty.
+	pub toc: Option<Toc>,
+	/// The number of frames.
+	pub frames: usize,
+	/// The duration of the packet, in samples at 48 kHz.
+	pub duration: usize,
+	/// The bytes of padding, not counting the bytes giving its length.
+	pub padding: usize,
+	/// The number of frames of at most one byte, which the decoder treats
+	/// as discontinuous transmission.
+	pub dtx_frames: usize,
+	/// Whether every frame of the packet is discontinuous transmission.
+	pub dtx: bool,
+	/// Whether the packet was marked as lost by the container.
+	pub lost: bool,
+	/// The first framing rule the packet breaks, if any.
+	pub violation: Option<Violation>,
+}
+
+impl fmt::Display for PacketInfo {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(
+			f,
+			"{:>6} {:>10.3}s {:>5}B",
+			self.index,
+			self.timestamp as f64 / 48000.0,
+			self.size
+		)?;
+		if self.lost {
+			return f.write_str(" lost");
+		}
+		if let Some(toc) = self.toc {
+			write!(
+				f,
+				" {:<6} {:<13} {:>6} {:<6} {}x",
+				toc.mode.as_str(),
+				toc.bandwidth.as_str(),
+				toc.frame_size.as_str(),
+				toc.channels.as_str(),
+				self.frames,
+			)?;
+		}
+		if self.padding > 0 {
+			write!(f, " padding={}", self.padding)?;
+		}
+		if self.dtx {
+			f.write_str(" dtx")?;
+		} else if self.dtx_frames > 0 {
+			write!(f, " dtx={}", self.dtx_frames)?;
+		}
+		if let Some(violation) = self.violation {
+			write!(f, " INVALID: {}", violation)?;
+		}
+		Ok(())
+	}
+}
+
+/// Counts of the packets by each property, holding only values which
+/// occurred, in ascending order.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Histograms {
+	/// Packets by coding mode.
+	pub modes: Vec<(Mode, u64)>,
+	/// Packets by coded bandwidth.
+	pub bandwidths: Vec<(Bandwidth, u64)>,
+	/// Packets by frame duration.
+	pub frame_sizes: Vec<(FrameSize, u64)>,
+	/// Packets by mono or stereo coding.
+	pub channels: Vec<(Channels, u64)>,
+	/// Packets by frame count.
+	pub frame_counts: Vec<(usize, u64)>,
+	/// Packets by size, in buckets of 32 bytes given by their lower bound.
+	pub sizes: Vec<(usize, u64)>,
+}
+
+/// The result of inspecting a stream.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct Report {
+	/// The container the stream was read from.
+	pub format: Format,
+	/// The stream's `OpusHead`, if the container has one.
+	pub head: Option<OpusHead>,
+	/// Every packet, in order.
+	pub packets: Vec<PacketInfo>,
+	/// The number of packets marked as lost.
+	pub lost: u64,
+	/// The total size of the packets, in bytes.
+	pub bytes: u64,
+	/// The total duration of the packets, in samples at 48 kHz.
+	pub duration: u64,
+	/// The number of packets coded during discontinuous transmission,
+	/// having only frames of at most one byte.
+	pub dtx_packets: u64,
+	/// The total padding, in bytes.
+	pub padding: u64,
+	/// Counts of the packets by each property.
+	pub histograms: Histograms,
+	/// The bitrate over each second of the stream, in bits per second.
+	pub bitrate: Vec<f64>,
+	/// The packets breaking the framing rules, by index.
+	pub violations: Vec<(u64, Violation)>,
+}
+
+impl Report {
+	/// Get the average bitrate, in bits per second.
+	pub fn average_bitrate(&self) -> f64 {
+		if self.duration == 0 {
+			0.0
+		} else {
+			self.bytes as f64 * 8.0 * 48000.0 / self.duration as f64
+		}
+	}
+
+	/// Check whether every packet follows the framing rules.
+	pub fn is_valid(&self) -> bool {
+		self.violations.is_empty()
+	}
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(f, "Format: {:?}", self.format)?;
+		if let Some(ref head) = self.head {
+			writeln!(
+				f,
+				"Channels: {}, mapping family {}, {} streams ({} coupled)",
+				head.channels, head.mapping_family, head.streams, head.coupled_streams
+			)?;
+			writeln!(
+				f,
+				"Pre-skip: {}, input sample rate: {} Hz, output gain: {:.2} dB",
+				head.pre_skip,
+				head.input_sample_rate,
+				head.output_gain_db()
+			)?;
+		}
+		writeln!(
+			f,
+			"Packets: {} ({} lost, {} DTX), {} bytes ({} padding), {:.3} s, {:.1} kbit/s",
+			self.packets.len(),
+			self.lost,
+			self.dtx_packets,
+			self.bytes,
+			self.padding,
+			self.duration as f64 / 48000.0,
+			self.average_bitrate() / 1000.0
+		)?;
+
+		let total = self.packets.len() as u64 - self.lost;
+		histogram(f, "Modes", &self.histograms.modes, total, |m| m.to_string())?;
+		histogram(f, "Bandwidths", &self.histograms.bandwidths, total, |b| b.to_string())?;
+		histogram(f, "Frame sizes", &self.histograms.frame_sizes, total, |s| s.to_string())?;
+		histogram(f, "Channels", &self.histograms.channels, total, |c| c.to_string())?;
+		histogram(f, "Frames per packet", &self.histograms.frame_counts, total, |n| n.to_string())?;
+		histogram(f, "Packet sizes", &self.histograms.sizes, total, |&n| {
+			format!("{}-{}", n, n + SIZE_BUCKET - 1)
+		})?;
+
+		if !self.bitrate.is_empty() {
+			writeln!(f, "Bitrate per second (kbit/s):")?;
+			for (second, bitrate) in self.bitrate.iter().enumerate() {
+				writeln!(f, "  {:>6} {:>8.1}", second, bitrate / 1000.0)?;
+			}
+		}
+		if self.violations.is_empty() {
+			writeln!(f, "No RFC 6716 violations")?;
+		} else {
+			writeln!(f, "RFC 6716 violations: {}", self.violations.len())?;
+			for (index, violation) in &self.violations {
+				writeln!(f, "  packet {}: {}", index, violation)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+fn histogram<T>(
+	f: &mut fmt::Formatter,
+	title: &str,
+	bins: &[(T, u64)],
+	total: u64,
+	label: impl Fn(&T) -> String,
+) -> fmt::Result {
+	if bins.is_empty() {
+		return Ok(());
+	}
+	writeln!(f, "{}:", title)?;
+	for (value, count) in bins {
+		let percent = *count as f64 * 100.0 / total.max(1) as f64;
+		writeln!(f, "  {:>13} {:>8} {:>6.1}%", label(value), count, percent)?;
+	}
+	Ok(())
+}
+
+/// Inspects packets one at a time.
+///
+/// `inspect` handles the supported containers; use this directly for
+/// packets from elsewhere, such as RTP.
+#[derive(Debug, Clone)]
+pub struct Inspector {
+	streams: u8,
+	packets: Vec<PacketInfo>,
+	timestamp: u64,
+	/// The duration of the last packet, assumed for lost packets.
+	last_duration: usize,
+}
+
+impl Inspector {
+	/// Create an inspector for packets holding the given number of streams.
+	pub fn new(streams: u8) -> Inspector {
+		Inspector {
+			streams: streams.max(1),
+			packets: Vec::new(),
+			timestamp: 0,
+			last_duration: 960,
+		}
+	}
+
+	/// Inspect the next packet.
+	pub fn add(&mut self, packet: &[u8]) -> &PacketInfo {
+		let mut info = PacketInfo {
+			index: self.packets.len() as u64,
+			timestamp: self.timestamp,
+			size: packet.len(),
+			toc: packet.first().map(|&toc| Toc::from_byte(toc)),
+			frames: 0,
+			duration: 0,
+			padding: 0,
+			dtx_frames: 0,
+			dtx: false,
+			lost: false,
+			violation: None,
+		};
+		let mut rest = packet;
+		let mut frames = 0;
+		for stream in 0..self.streams {
+			let self_delimited = stream + 1 < self.streams;
+			match parse(rest, self_delimited) {
+				Ok(parsed) => {
+					if stream == 0 {
+						info.frames = parsed.frames.len();
+						info.duration = parsed.duration;
+					}
+					frames += parsed.frames.len();
+					info.padding += parsed.padding;
+					info.dtx_frames += parsed.frames.iter().filter(|&&len| len <= 1).count();
+					rest = &rest[parsed.len..];
+				}
+				Err(violation) => {
+					info.violation = Some(violation);
+					break;
+				}
+			}
+		}
+		if info.violation.is_none() {
+			info.dtx = info.dtx_frames == frames;
+			self.last_duration = info.duration;
+		}
+		self.push(info)
+	}
+
+	/// Record a packet marked as lost, assumed to last as long as the one
+	/// before it.
+	pub fn add_lost(&mut self) -> &PacketInfo {
+		let info = PacketInfo {
+			index: self.packets.len() as u64,
+			timestamp: self.timestamp,
+			size: 0,
+			toc: None,
+			frames: 0,
+			duration: self.last_duration,
+			padding: 0,
+			dtx_frames: 0,
+			dtx: false,
+			lost: true,
+			violation: None,
+		};
+		self.push(info)
+	}
+
+	fn push(&mut self, info: PacketInfo) -> &PacketInfo {
+		self.timestamp += info.duration as u64;
+		self.packets.push(info);
+		self.packets.last().unwrap()
+	}
+
+	/// Get the packets inspected so far.
+	pub fn packets(&self) -> &[PacketInfo] {
+		&self.packets
+	}
+
+	/// Summarize the packets into a report.
+	pub fn finish(self, format: Format, head: Option<OpusHead>) -> Report {
+		let mut report = Report {
+			format,
+			head,
+			packets: Vec::new(),
+			lost: 0,
+			bytes: 0,
+			duration: self.timestamp,
+			dtx_packets: 0,
+			padding: 0,
+			histograms: Histograms::default(),
+			bitrate: vec![0.0; self.timestamp.div_ceil(BITRATE_INTERVAL) as usize],
+			violations: Vec::new(),
+		};
+		let histograms = &mut report.histograms;
+		for info in &self.packets {
+			report.bytes += info.size as u64;
+			report.padding += info.padding as u64;
+			if let Some(second) =
+				report.bitrate.get_mut((info.timestamp / BITRATE_INTERVAL) as usize)
+			{
+				*second += info.size as f64 * 8.0;
+			}
+			if info.lost {
+				report.lost += 1;
+				continue;
+			}
+			if let Some(violation) = info.violation {
+				report.violations.push((info.index, violation));
+			}
+			if info.dtx {
+				report.dtx_packets += 1;
+			}
+			if let Some(toc) = info.toc {
+				count(&mut histograms.modes, toc.mode);
+				count(&mut histograms.bandwidths, toc.bandwidth);
+				count(&mut histograms.frame_sizes, toc.frame_size);
+				count(&mut histograms.channels, toc.channels);
+			}
+			count(&mut histograms.frame_counts, info.frames);
+			count(&mut histograms.sizes, info.size / SIZE_BUCKET * SIZE_BUCKET);
+		}
+		histograms.modes.sort_by_key(|&(mode, _)| mode as u8);
+		histograms.bandwidths.sort_by_key(|&(bandwidth, _)| bandwidth as i32);
+		histograms.frame_sizes.sort_by_key(|&(size, _)| size as i32);
+		histograms.channels.sort_by_key(|&(channels, _)| channels as i32);
+		histograms.frame_counts.sort();
+		histograms.sizes.sort();
+
+		// the last interval may be partial
+		for (i, bitrate) in report.bitrate.iter_mut().enumerate() {
+			let samples = (self.timestamp - i as u64 * BITRATE_INTERVAL).min(BITRATE_INTERVAL);
+			*bitrate *= 48000.0 / samples as f64;
+		}
+		report.packets = self.packets;
+		report
+	}
+}
+
+fn count<T: PartialEq>(bins: &mut Vec<(T, u64)>, value: T) {
+	match bins.iter_mut().find(|(bin, _)| *bin == value) {
+		Some((_, count)) => *count += 1,
+		None => bins.push((value, 1)),
+	}
+}
+
+/// Inspect every packet of an Ogg Opus, WebM or `opus_demo` stream, which is
+/// told apart by its first bytes.
+///
+/// Only the first Opus stream of an Ogg or WebM file is inspected. An
+/// `opus_demo` stream is assumed to hold a single Opus stream per packet.
+pub fn inspect<R: Read>(mut reader: R) -> Result<Report> {
+	let mut magic = [0; 4];
+	let mut read = 0;
+	while read < magic.len() {
+		match reader.read(&mut magic[read..])? {
+			0 => break,
+			n => read += n,
+		}
+	}
+	let reader = Cursor::new(magic[..read].to_vec()).chain(reader);
+
+	if &magic[..read] == b"OggS" {
+		let mut ogg = OggOpusReader::new(reader)?;
+		let mut inspector = Inspector::new(ogg.head().streams);
+		while let Some(packet) = ogg.read_packet()? {
+			inspector.add(&packet.data);
+		}
+		Ok(inspector.finish(Format::Ogg, Some(ogg.head().clone())))
+	} else if magic[..read] == [0x1A, 0x45, 0xDF, 0xA3] {
+		let mut webm = WebmReader::new(reader)?;
+		let mut inspector = Inspector::new(webm.head().streams);
+		while let Some(packet) = webm.read_packet()? {
+			inspector.add(&packet.data);
+		}
+		Ok(inspector.finish(Format::Webm, Some(webm.head().clone())))
+	} else {
+		let mut demo = DemoBitstreamReader::new(reader);
+		let mut inspector = Inspector::new(1);
+		while let Some(packet) = demo.read_packet()? {
+			if packet.is_lost() {
+				inspector.add_lost();
+			} else {
+				inspector.add(&packet.data);
+			}
+		}
+		Ok(inspector.finish(Format::OpusDemo, None))
+	}
+}
+
+/// One stream of a packet, split into frames.
+struct Parsed {
+	/// The length of each frame.
+	frames: Vec<usize>,
+	padding: usize,
+	/// The duration in samples at 48 kHz.
+	duration: usize,
+	/// The bytes taken by the stream.
+	len: usize,
+}
+
+/// Split a stream into frames as described in RFC 6716 section 3.2, or its
+/// self-delimiting form from appendix B.
+fn parse(data: &[u8], self_delimited: bool) -> ::std::result::Result<Parsed, Violation> {
+	let toc = match data.first() {
+		Some(&toc) => Toc::from_byte(toc),
+		None => return Err(Violation::Empty),
+	};
+	let mut pos = 1;
+	let mut padding = 0;
+	let frames = match toc.code {
+		0 => {
+			let len = if self_delimited { length(data, &mut pos)? } else { data.len() - pos };
+			vec![len]
+		}
+		1 => {
+			let len = if self_delimited {
+				length(data, &mut pos)?
+			} else if (data.len() - pos) % 2 != 0 {
+				return Err(Violation::UnequalFrames);
+			} else {
+				(data.len() - pos) / 2
+			};
+			vec![len, len]
+		}
+		2 => {
+			let first = length(data, &mut pos)?;
+			let second = if self_delimited {
+				length(data, &mut pos)?
+			} else {
+				(data.len() - pos).checked_sub(first).ok_or(Violation::Truncated)?
+			};
+			vec![first, second]
+		}
+		_ => {
+			let header = *data.get(pos).ok_or(Violation::Truncated)?;
+			pos += 1;
+			let count = (header & 0x3F) as usize;
+			if count == 0 {
+				return Err(Violation::NoFrames);
+			}
+			if count * frame_samples(toc) > MAX_DURATION {
+				return Err(Violation::TooLong);
+			}
+			if header & 0x40 != 0 {
+				loop {
+					let value = *data.get(pos).ok_or(Violation::Truncated)? as usize;
+					pos += 1;
+					if value == 255 {
+						padding += 254;
+					} else {
+						padding += value;
+						break;
+					}
+				}
+			}
+			let mut frames = Vec::with_capacity(count);
+			if header & 0x80 != 0 {
+				// variable bitrate, where the last length is implicit unless
+				// self-delimited
+				let explicit = if self_delimited { count } else { count - 1 };
+				for _ in 0..explicit {
+					frames.push(length(data, &mut pos)?);
+				}
+				if !self_delimited {
+					let used = pos + frames.iter().sum::<usize>() + padding;
+					frames.push(data.len().checked_sub(used).ok_or(Violation::Truncated)?);
+				}
+			} else if self_delimited {
+				let len = length(data, &mut pos)?;
+				frames.resize(count, len);
+			} else {
+				let payload =
+					(data.len() - pos).checked_sub(padding).ok_or(Violation::Truncated)?;
+				if payload % count != 0 {
+					return Err(Violation::UnequalFrames);
+				}
+				frames.resize(count, payload / count);
+			}
+			frames
+		}
+	};
+
+	if frames.iter().any(|&len| len > MAX_FRAME) {
+		return Err(Violation::FrameTooLong);
+	}
+	let len = pos + frames.iter().sum::<usize>() + padding;
+	if len > data.len() {
+		return Err(Violation::Truncated);
+	}
+	Ok(Parsed {
+		duration: frames.len() * frame_samples(toc),
+		frames,
+		padding,
+		len: if self_delimited { len } else { data.len() },
+	})
+}
+
+/// Read a frame length of one or two bytes.
+fn length(data: &[u8], pos: &mut usize) -> ::std::result::Result<usize, Violation> {
+	let first = *data.get(*pos).ok_or(Violation::Truncated)? as usize;
+	if first < 252 {
+		*pos += 1;
+		return Ok(first);
+	}
+	let second = *data.get(*pos + 1).ok_or(Violation::Truncated)? as usize;
+	*pos += 2;
+	Ok(second * 4 + first)
+}
+
+fn frame_samples(toc: Toc) -> usize {
+	toc.frame_size.samples(48000).unwrap_or(0)
+}
@@ -13,11 +13,36 @@
 #![warn(missing_docs)]
 
 extern crate opusic_sys as ffi;
-
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+
+pub mod conformance;
+pub mod inspect;
+pub mod mixer;
+pub mod mp4;
+pub mod ogg;
+pub mod opus_demo;
+pub mod opus_head;
+pub mod pcm;
+pub mod quality;
+pub mod rate;
+pub mod resample;
+pub mod simulate;
+pub mod speaker;
+pub mod stats;
+pub mod verify;
+pub mod webm;
+
+use resample::Resampler;
+use stats::{DecoderStats, EncoderStats};
+use std::borrow::Cow;
 use std::convert::TryFrom;
 use std::ffi::CStr;
 use std::marker::PhantomData;
 use std::os::raw::c_int;
+use std::str::FromStr;
+use std::{fmt, str};
 
 // ============================================================================
 // Constants
@@ -42,7 +67,7 @@ impl Application {
 			ffi::OPUS_APPLICATION_VOIP => Ok(Application::Voip),
 			ffi::OPUS_APPLICATION_AUDIO => Ok(Application::Audio),
 			ffi::OPUS_APPLICATION_RESTRICTED_LOWDELAY => Ok(Application::LowDelay),
-			_ => Err(Error::bad_arg(what)),
+			_ => Err(Error::unexpected(what, raw)),
 		}
 	}
 }
@@ -92,7 +117,7 @@ impl Bandwidth {
 	fn decode(value: i32, what: &'static str) -> Result<Bandwidth> {
 		match Bandwidth::from_int(value) {
 			Some(bandwidth) => Ok(bandwidth),
-			None => Err(Error::bad_arg(what)),
+			None => Err(Error::unexpected(what, value)),
 		}
 	}
 
@@ -194,7 +219,7 @@ impl Signal {
 			ffi::OPUS_AUTO => Ok(Signal::Auto),
 			ffi::OPUS_SIGNAL_VOICE => Ok(Signal::Voice),
 			ffi::OPUS_SIGNAL_MUSIC => Ok(Signal::Music),
-			_ => Err(Error::bad_arg(what)),
+			_ => Err(Error::unexpected(what, raw)),
 		}
 	}
 
@@ -244,13 +269,40 @@ impl FrameSize {
 			ffi::OPUS_FRAMESIZE_80_MS => Ok(FrameSize::Ms80),
 			ffi::OPUS_FRAMESIZE_100_MS => Ok(FrameSize::Ms100),
 			ffi::OPUS_FRAMESIZE_120_MS => Ok(FrameSize::Ms120),
-			_ => Err(Error::bad_arg(what)),
+			_ => Err(Error::unexpected(what, raw)),
 		}
 	}
 
 	fn raw(self) -> i32 {
 		self as i32
 	}
+
+	/// Get the number of samples per channel in a frame of this size at the
+	/// given sample rate.
+	///
+	/// Returns `None` for `FrameSize::Arg`, or if the frame duration is not a
+	/// whole number of samples at that rate.
+	pub fn samples(self, sample_rate: u32) -> Option<usize> {
+		// frame durations in units of 2.5 ms
+		let units = match self {
+			FrameSize::Arg => return None,
+			FrameSize::Ms2_5 => 1,
+			FrameSize::Ms5 => 2,
+			FrameSize::Ms10 => 4,
+			FrameSize::Ms20 => 8,
+			FrameSize::Ms40 => 16,
+			FrameSize::Ms60 => 24,
+			FrameSize::Ms80 => 32,
+			FrameSize::Ms100 => 40,
+			FrameSize::Ms120 => 48,
+		};
+		let total = sample_rate as usize * units;
+		if total % 400 == 0 {
+			Some(total / 400)
+		} else {
+			None
+		}
+	}
 }
 
 /// Get the libopus version string.
@@ -263,6 +315,217 @@ pub fn version() -> &'static str {
 	unsafe { CStr::from_ptr(ffi::opus_get_version_string()) }.to_str().unwrap()
 }
 
+// ============================================================================
+// String Conversions
+
+/// An error parsing one of the setting types from a string.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ParseError {
+	what: &'static str,
+	input: String,
+}
+
+impl ParseError {
+	fn new(what: &'static str, input: &str) -> ParseError {
+		ParseError { what, input: input.to_owned() }
+	}
+}
+
+impl fmt::Display for ParseError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "invalid {}: {:?}", self.what, self.input)
+	}
+}
+
+impl std::error::Error for ParseError {}
+
+/// Implement `Display`, `FromStr`, and optionally serde traits using a fixed
+/// string for each variant. Parsing is case-insensitive and also accepts the
+/// listed aliases.
+macro_rules! string_enum {
+	($t:ident, $what:expr, { $($variant:ident => $name:expr $(, $alias:expr)*;)* }) => {
+		impl $t {
+			/// Get the stable string form used by `Display`, `FromStr`, and serde.
+			pub fn as_str(self) -> &'static str {
+				match self {
+					$($t::$variant => $name,)*
+				}
+			}
+		}
+
+		impl fmt::Display for $t {
+			fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				f.write_str(self.as_str())
+			}
+		}
+
+		impl FromStr for $t {
+			type Err = ParseError;
+
+			fn from_str(s: &str) -> std::result::Result<$t, ParseError> {
+				match &*s.trim().to_ascii_lowercase() {
+					$($name $(| $alias)* => Ok($t::$variant),)*
+					_ => Err(ParseError::new($what, s)),
+				}
+			}
+		}
+
+		#[cfg(feature = "serde")]
+		impl serde::Serialize for $t {
+			fn serialize<S: serde::Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+				serializer.serialize_str(self.as_str())
+			}
+		}
+
+		#[cfg(feature = "serde")]
+		impl<'de> serde::Deserialize<'de> for $t {
+			fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<$t, D::Error> {
+				let s = <std::borrow::Cow<str> as serde::Deserialize>::deserialize(deserializer)?;
+				s.parse().map_err(serde::de::Error::custom)
+			}
+		}
+	};
+}
+
+string_enum!(Application, "application", {
+	Voip => "voip";
+	Audio => "audio";
+	LowDelay => "lowdelay", "low-delay", "low_delay", "restricted-lowdelay";
+});
+
+string_enum!(Channels, "channel count", {
+	Mono => "mono", "1";
+	Stereo => "stereo", "2";
+});
+
+string_enum!(Bandwidth, "bandwidth", {
+	Auto => "auto";
+	Narrowband => "narrowband", "nb";
+	Mediumband => "mediumband", "mb";
+	Wideband => "wideband", "wb";
+	Superwideband => "superwideband", "swb";
+	Fullband => "fullband", "fb";
+});
+
+string_enum!(Signal, "signal", {
+	Auto => "auto";
+	Voice => "voice", "speech";
+	Music => "music";
+});
+
+string_enum!(FrameSize, "frame size", {
+	Arg => "arg";
+	Ms2_5 => "2.5ms", "2.5";
+	Ms5 => "5ms", "5";
+	Ms10 => "10ms", "10";
+	Ms20 => "20ms", "20";
+	Ms40 => "40ms", "40";
+	Ms60 => "60ms", "60";
+	Ms80 => "80ms", "80";
+	Ms100 => "100ms", "100";
+	Ms120 => "120ms", "120";
+});
+
+string_enum!(ErrorCode, "error code", {
+	BadArg => "bad_arg";
+	BufferTooSmall => "buffer_too_small";
+	InternalError => "internal_error";
+	InvalidPacket => "invalid_packet";
+	Unimplemented => "unimplemented";
+	InvalidState => "invalid_state";
+	AllocFail => "alloc_fail";
+	Unknown => "unknown";
+});
+
+string_enum!(Layout, "layout", {
+	Interleaved => "interleaved";
+	Planar => "planar";
+});
+
+/// Bitrates are written as `"auto"`, `"max"`, or a number of bits per
+/// second. Parsing also accepts kilobits per second with a `k` suffix, such
+/// as `"64k"`.
+impl fmt::Display for Bitrate {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match *self {
+			Bitrate::Auto => f.write_str("auto"),
+			Bitrate::Max => f.write_str("max"),
+			Bitrate::Bits(bits) => write!(f, "{}", bits),
+		}
+	}
+}
+
+impl FromStr for Bitrate {
+	type Err = ParseError;
+
+	fn from_str(s: &str) -> std::result::Result<Bitrate, ParseError> {
+		let lower = s.trim().to_ascii_lowercase();
+		match &*lower {
+			"auto" => return Ok(Bitrate::Auto),
+			"max" => return Ok(Bitrate::Max),
+			_ => {}
+		}
+		let (digits, scale) = match lower.strip_suffix('k') {
+			Some(digits) => (digits, 1000),
+			None => (&*lower, 1),
+		};
+		match digits.trim().parse::<i32>().ok().and_then(|n| n.checked_mul(scale)) {
+			Some(bits) => Ok(Bitrate::Bits(bits)),
+			None => Err(ParseError::new("bitrate", s)),
+		}
+	}
+}
+
+#[cfg(feature = "serde")]
+impl serde::Serialize for Bitrate {
+	fn serialize<S: serde::Serializer>(
+		&self,
+		serializer: S,
+	) -> std::result::Result<S::Ok, S::Error> {
+		match *self {
+			Bitrate::Bits(bits) => serializer.serialize_i32(bits),
+			_ => serializer.collect_str(self),
+		}
+	}
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Bitrate {
+	fn deserialize<D: serde::Deserializer<'de>>(
+		deserializer: D,
+	) -> std::result::Result<Bitrate, D::Error> {
+		struct Visitor;
+
+		impl<'de> serde::de::Visitor<'de> for Visitor {
+			type Value = Bitrate;
+
+			fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+				f.write_str("a number of bits per second, \"auto\", or \"max\"")
+			}
+
+			fn visit_i64<E: serde::de::Error>(self, v: i64) -> std::result::Result<Bitrate, E> {
+				match i32::try_from(v) {
+					Ok(bits) => Ok(Bitrate::Bits(bits)),
+					Err(_) => Err(E::custom("bitrate out of range")),
+				}
+			}
+
+			fn visit_u64<E: serde::de::Error>(self, v: u64) -> std::result::Result<Bitrate, E> {
+				match i32::try_from(v) {
+					Ok(bits) => Ok(Bitrate::Bits(bits)),
+					Err(_) => Err(E::custom("bitrate out of range")),
+				}
+			}
+
+			fn visit_str<E: serde::de::Error>(self, v: &str) -> std::result::Result<Bitrate, E> {
+				v.parse().map_err(E::custom)
+			}
+		}
+
+		deserializer.deserialize_any(Visitor)
+	}
+}
+
 macro_rules! ffi {
 	($f:ident $(, $rest:expr)*) => {
 		match unsafe { ffi::$f($($rest),*) } {
@@ -276,66 +539,74 @@ macro_rules! ffi {
 // Generic CTLs
 
 macro_rules! ctl {
-	($f:ident, $this:ident, $ctl:path $(, $rest:expr)*) => {
-		match unsafe { ffi::$f($this.ptr, $ctl $(, $rest)*) } {
-			code if code < 0 => return Err(Error::from_code(
-				concat!(stringify!($f), "(", stringify!($ctl), ")"),
-				code,
-			)),
+	($f:ident, $this:ident, $ctl:ident $(, $rest:expr)*) => {
+		match unsafe { ffi::$f($this.ptr, ffi::$ctl $(, $rest)*) } {
+			code if code < 0 => return Err(
+				Error::from_code(stringify!($f), code).with_context(stringify!($ctl))
+			),
 			_ => (),
 		}
 	}
 }
 
 macro_rules! generic_ctls {
-	($t:ty, $fn:ident) => {
+	// Codecs which resample pass the method resetting the resampler and the
+	// method giving the resampled rate, if any.
+	($t:ty, $fn:ident $(, resampled: $reset:ident, $rate:ident)*) => {
 		/// Generic CTLs. See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__genericctls.html).
 		impl $t {
 			/// Reset the codec state to be equivalent to a freshly initialized state.
 			pub fn reset_state(&mut self) -> Result<()> {
-				ctl!($fn, self, ffi::OPUS_RESET_STATE);
+				ctl!($fn, self, OPUS_RESET_STATE);
+				$(self.$reset();)*
 				Ok(())
 			}
 
 			/// Get the final range of the codec's entropy coder.
 			pub fn get_final_range(&mut self) -> Result<u32> {
 				let mut value: u32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_FINAL_RANGE_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_FINAL_RANGE_REQUEST, &mut value);
 				Ok(value)
 			}
 
 			/// Get the encoder's configured bandpass.
 			pub fn get_bandwidth(&mut self) -> Result<Bandwidth> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_BANDWIDTH_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_BANDWIDTH_REQUEST, &mut value);
 				Bandwidth::decode(value, concat!(stringify!($fn), "(OPUS_GET_BANDWIDTH)"))
 			}
 
 			/// Get the samping rate the encoder was intialized with.
+			///
+			/// For a codec which resamples, this is the rate of the PCM passed
+			/// to or returned from it, not the rate Opus runs at.
 			pub fn get_sample_rate(&mut self) -> Result<u32> {
+				$(if let Some(rate) = self.$rate() {
+					return Ok(rate);
+				})*
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_SAMPLE_RATE_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_SAMPLE_RATE_REQUEST, &mut value);
 				Ok(value as u32)
 			}
 
 			/// If set to true, disables the use of phase inversion for intensity stereo.
 			pub fn set_phase_inversion_disabled(&mut self, disabled: bool) -> Result<()> {
 				let value: i32 = if disabled { 1 } else { 0 };
-				ctl!($fn, self, ffi::OPUS_SET_PHASE_INVERSION_DISABLED_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_PHASE_INVERSION_DISABLED_REQUEST, value);
 				Ok(())
 			}
 
 			/// Get the encoder's configured phase inversion status.
 			pub fn get_phase_inversion_disabled(&mut self) -> Result<bool> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_PHASE_INVERSION_DISABLED_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_PHASE_INVERSION_DISABLED_REQUEST, &mut value);
 				Ok(value != 0)
 			}
 
 			/// Get the DTX state of the encoder.
 			pub fn get_in_dtx(&mut self) -> Result<bool> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_IN_DTX_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_IN_DTX_REQUEST, &mut value);
 				Ok(value != 0)
 			}
 		}
@@ -350,6 +621,8 @@ macro_rules! generic_ctls {
 pub struct Encoder {
 	ptr: *mut ffi::OpusEncoder,
 	channels: Channels,
+	resampler: Option<Resampler>,
+	stats: Option<EncoderStats>,
 }
 
 impl Drop for Encoder {
@@ -373,6 +646,7 @@ unsafe impl Send for Encoder {}
 impl Encoder {
 	/// Create and initialize an encoder.
 	pub fn new(sample_rate: u32, channels: Channels, mode: Application) -> Result<Encoder> {
+		check_sample_rate("Encoder::new", sample_rate)?;
 		let mut error = 0;
 		let ptr = unsafe {
 			ffi::opus_encoder_create(
@@ -385,12 +659,81 @@ impl Encoder {
 		if error != ffi::OPUS_OK || ptr.is_null() {
 			Err(Error::from_code("opus_encoder_create", error))
 		} else {
-			Ok(Encoder { ptr, channels })
+			Ok(Encoder { ptr, channels, resampler: None, stats: None })
+		}
+	}
+
+	/// Create an encoder which accepts input at any sample rate.
+	///
+	/// If Opus does not support `input_rate` natively, input is resampled to
+	/// the nearest higher supported rate before encoding, and the extra delay
+	/// is included in `get_lookahead`. Frames passed to `encode` must then be
+	/// a valid Opus frame duration at both rates, such as 20 ms (882 samples)
+	/// at 44.1 kHz.
+	pub fn with_input_rate(
+		input_rate: u32,
+		channels: Channels,
+		mode: Application,
+	) -> Result<Encoder> {
+		let codec_rate = resample::codec_rate(input_rate);
+		let mut encoder = Encoder::new(codec_rate, channels, mode)?;
+		if codec_rate != input_rate {
+			encoder.resampler = Some(Resampler::new(input_rate, codec_rate, channels as usize)?);
+		}
+		Ok(encoder)
+	}
+
+	fn reset_resampler(&mut self) {
+		if let Some(ref mut resampler) = self.resampler {
+			resampler.reset();
+		}
+	}
+
+	/// Get the sample rate of the input passed to `encode`, if it is
+	/// resampled.
+	fn resampled_input_rate(&self) -> Option<u32> {
+		self.resampler.as_ref().map(|resampler| resampler.input_rate())
+	}
+
+	/// Convert the codec's lookahead to the input rate and add the
+	/// resampler's delay.
+	fn resampled_lookahead(&self, lookahead: i32) -> i32 {
+		match self.resampler {
+			Some(ref resampler) => {
+				let scaled = lookahead as u64 * resampler.input_rate() as u64
+					/ resampler.output_rate() as u64;
+				scaled as i32 + resampler.delay() as i32
+			}
+			None => lookahead,
 		}
 	}
 
+	fn encode_resampled<T: Sample>(&mut self, input: &[T], output: &mut [u8]) -> Result<usize> {
+		let resampled = match self.resampler {
+			Some(ref mut resampler) => resampler.process(input),
+			None => unreachable!(),
+		};
+		let len = ffi!(
+			opus_encode_float,
+			self.ptr,
+			resampled.as_ptr(),
+			len(resampled) / self.channels as c_int,
+			output.as_mut_ptr(),
+			len(output)
+		);
+		let len = len as usize;
+		self.record_stats(&output[..len]);
+		Ok(len)
+	}
+
 	/// Encode an Opus frame.
+	///
+	/// The input length must be a multiple of the channel count.
 	pub fn encode(&mut self, input: &[i16], output: &mut [u8]) -> Result<usize> {
+		check_frame_len("Encoder::encode", input.len(), self.channels as usize)?;
+		if self.resampler.is_some() {
+			return self.encode_resampled(input, output);
+		}
 		let len = ffi!(
 			opus_encode,
 			self.ptr,
@@ -399,11 +742,19 @@ impl Encoder {
 			output.as_mut_ptr(),
 			len(output)
 		);
-		Ok(len as usize)
+		let len = len as usize;
+		self.record_stats(&output[..len]);
+		Ok(len)
 	}
 
 	/// Encode an Opus frame from floating point input.
+	///
+	/// The input length must be a multiple of the channel count.
 	pub fn encode_float(&mut self, input: &[f32], output: &mut [u8]) -> Result<usize> {
+		check_frame_len("Encoder::encode_float", input.len(), self.channels as usize)?;
+		if self.resampler.is_some() {
+			return self.encode_resampled(input, output);
+		}
 		let len = ffi!(
 			opus_encode_float,
 			self.ptr,
@@ -412,7 +763,9 @@ impl Encoder {
 			output.as_mut_ptr(),
 			len(output)
 		);
-		Ok(len as usize)
+		let len = len as usize;
+		self.record_stats(&output[..len]);
+		Ok(len)
 	}
 
 	/// Encode an Opus frame to a new buffer.
@@ -430,63 +783,106 @@ impl Encoder {
 		output.truncate(result);
 		Ok(output)
 	}
+
+	/// Start collecting statistics about each packet produced.
+	///
+	/// Collection makes two extra CTL calls per packet, and a packet is left
+	/// out if either fails; encoding itself never fails because of them. If
+	/// statistics were already enabled, they are left unchanged.
+	pub fn enable_stats(&mut self) {
+		if self.stats.is_none() {
+			self.stats = Some(EncoderStats::default());
+		}
+	}
+
+	/// Stop collecting statistics and discard those collected so far.
+	pub fn disable_stats(&mut self) {
+		self.stats = None;
+	}
+
+	/// Get a snapshot of the statistics, if enabled.
+	pub fn stats(&self) -> Option<EncoderStats> {
+		self.stats
+	}
+
+	/// Clear the statistics collected so far, if enabled.
+	///
+	/// Statistics are not affected by `reset_state`.
+	pub fn reset_stats(&mut self) {
+		if let Some(ref mut stats) = self.stats {
+			*stats = EncoderStats::default();
+		}
+	}
+
+	fn record_stats(&mut self, packet: &[u8]) {
+		// the packet is already encoded, so a failed CTL only skips it
+		if self.stats.is_some() {
+			if let (Ok(in_dtx), Ok(final_range)) = (self.get_in_dtx(), self.get_final_range()) {
+				if let Some(ref mut stats) = self.stats {
+					stats.record(packet, in_dtx, final_range);
+				}
+			}
+		}
+	}
 }
 
 macro_rules! encoder_ctls {
-	($t:ty, $fn:ident) => {
+	// Encoders which resample pass the method adding the resampler's delay to
+	// the codec's lookahead.
+	($t:ty, $fn:ident $(, resampled: $lookahead:ident)*) => {
 		/// Encoder CTLs. See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__encoderctls.html).
 		impl $t {
 			/// Configures the encoder's computational complexity.
 			pub fn set_complexity(&mut self, value: i32) -> Result<()> {
-				ctl!($fn, self, ffi::OPUS_SET_COMPLEXITY_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_COMPLEXITY_REQUEST, value);
 				Ok(())
 			}
 
 			/// Gets the encoder's complexity configuration.
 			pub fn get_complexity(&mut self) -> Result<i32> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_COMPLEXITY_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_COMPLEXITY_REQUEST, &mut value);
 				Ok(value)
 			}
 
 			/// Set the encoder's bitrate.
 			pub fn set_bitrate(&mut self, value: Bitrate) -> Result<()> {
-				ctl!($fn, self, ffi::OPUS_SET_BITRATE_REQUEST, value.raw());
+				ctl!($fn, self, OPUS_SET_BITRATE_REQUEST, value.raw());
 				Ok(())
 			}
 
 			/// Get the encoder's bitrate.
 			pub fn get_bitrate(&mut self) -> Result<Bitrate> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_BITRATE_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_BITRATE_REQUEST, &mut value);
 				Bitrate::from_raw(value)
 			}
 
 			/// Enable or disable variable bitrate.
 			pub fn set_vbr(&mut self, vbr: bool) -> Result<()> {
 				let value: i32 = if vbr { 1 } else { 0 };
-				ctl!($fn, self, ffi::OPUS_SET_VBR_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_VBR_REQUEST, value);
 				Ok(())
 			}
 
 			/// Determine if variable bitrate is enabled.
 			pub fn get_vbr(&mut self) -> Result<bool> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_VBR_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_VBR_REQUEST, &mut value);
 				Ok(value != 0)
 			}
 
 			/// Enable or disable constrained VBR.
 			pub fn set_vbr_constraint(&mut self, vbr: bool) -> Result<()> {
 				let value: i32 = if vbr { 1 } else { 0 };
-				ctl!($fn, self, ffi::OPUS_SET_VBR_CONSTRAINT_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_VBR_CONSTRAINT_REQUEST, value);
 				Ok(())
 			}
 
 			/// Determine if constrained VBR is enabled.
 			pub fn get_vbr_constraint(&mut self) -> Result<bool> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_VBR_CONSTRAINT_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_VBR_CONSTRAINT_REQUEST, &mut value);
 				Ok(value != 0)
 			}
 
@@ -502,116 +898,122 @@ macro_rules! encoder_ctls {
 					Some(Channels::Mono) => 1,
 					Some(Channels::Stereo) => 2,
 				};
-				ctl!($fn, self, ffi::OPUS_SET_FORCE_CHANNELS_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_FORCE_CHANNELS_REQUEST, value);
 				Ok(())
 			}
 
 			/// Gets the encoder's forced channel configuration.
 			pub fn get_force_channels(&mut self) -> Result<Option<Channels>> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_FORCE_CHANNELS_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_FORCE_CHANNELS_REQUEST, &mut value);
 				match value {
 					ffi::OPUS_AUTO => Ok(None),
 					1 => Ok(Some(Channels::Mono)),
 					2 => Ok(Some(Channels::Stereo)),
-					_ => Err(Error::bad_arg(concat!(stringify!($fn), "(OPUS_GET_FORCE_CHANNELS)"))),
+					_ => Err(Error::unexpected(
+						concat!(stringify!($fn), "(OPUS_GET_FORCE_CHANNELS)"),
+						value,
+					)),
 				}
 			}
 
 			/// Configure the maximum bandpass that the encoder will select automatically.
 			pub fn set_max_bandwidth(&mut self, bandwidth: Bandwidth) -> Result<()> {
 				let value: i32 = bandwidth.raw();
-				ctl!($fn, self, ffi::OPUS_SET_MAX_BANDWIDTH_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_MAX_BANDWIDTH_REQUEST, value);
 				Ok(())
 			}
 
 			/// Get the encoder's configured maximum allowed bandpass.
 			pub fn get_max_bandwidth(&mut self) -> Result<Bandwidth> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_MAX_BANDWIDTH_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_MAX_BANDWIDTH_REQUEST, &mut value);
 				Bandwidth::decode(value, concat!(stringify!($fn), "(OPUS_GET_MAX_BANDWIDTH)"))
 			}
 
 			/// Set the encoder's bandpass to a specific value.
 			pub fn set_bandwidth(&mut self, bandwidth: Bandwidth) -> Result<()> {
 				let value: i32 = bandwidth.raw();
-				ctl!($fn, self, ffi::OPUS_SET_BANDWIDTH_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_BANDWIDTH_REQUEST, value);
 				Ok(())
 			}
 
 			/// Configure the type of signal being encoded.
 			pub fn set_signal(&mut self, signal: Signal) -> Result<()> {
 				let value: i32 = signal.raw();
-				ctl!($fn, self, ffi::OPUS_SET_SIGNAL_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_SIGNAL_REQUEST, value);
 				Ok(())
 			}
 
 			/// Gets the encoder's configured signal type.
 			pub fn get_signal(&mut self) -> Result<Signal> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_SIGNAL_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_SIGNAL_REQUEST, &mut value);
 				Signal::from_raw(value, concat!(stringify!($fn), "(OPUS_GET_SIGNAL)"))
 			}
 
 			/// Configure the encoder's intended application.
 			pub fn set_application(&mut self, application: Application) -> Result<()> {
 				let value: i32 = application as i32;
-				ctl!($fn, self, ffi::OPUS_SET_APPLICATION_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_APPLICATION_REQUEST, value);
 				Ok(())
 			}
 
 			/// Get the encoder's configured application.
 			pub fn get_application(&mut self) -> Result<Application> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_APPLICATION_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_APPLICATION_REQUEST, &mut value);
 				Application::from_raw(value, concat!(stringify!($fn), "(OPUS_GET_APPLICATION)"))
 			}
 
 			/// Gets the total samples of delay added by the entire codec.
+			///
+			/// This includes any delay added by resampling the input.
 			pub fn get_lookahead(&mut self) -> Result<i32> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_LOOKAHEAD_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_LOOKAHEAD_REQUEST, &mut value);
+				$(value = self.$lookahead(value);)*
 				Ok(value)
 			}
 
 			/// Configures the encoder's use of inband forward error correction (FEC).
 			pub fn set_inband_fec(&mut self, value: bool) -> Result<()> {
 				let value: i32 = if value { 1 } else { 0 };
-				ctl!($fn, self, ffi::OPUS_SET_INBAND_FEC_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_INBAND_FEC_REQUEST, value);
 				Ok(())
 			}
 
 			/// Gets encoder's configured use of inband forward error correction.
 			pub fn get_inband_fec(&mut self) -> Result<bool> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_INBAND_FEC_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_INBAND_FEC_REQUEST, &mut value);
 				Ok(value != 0)
 			}
 
 			/// Sets the encoder's expected packet loss percentage.
 			pub fn set_packet_loss_perc(&mut self, value: i32) -> Result<()> {
-				ctl!($fn, self, ffi::OPUS_SET_PACKET_LOSS_PERC_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_PACKET_LOSS_PERC_REQUEST, value);
 				Ok(())
 			}
 
 			/// Gets the encoder's expected packet loss percentage.
 			pub fn get_packet_loss_perc(&mut self) -> Result<i32> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_PACKET_LOSS_PERC_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_PACKET_LOSS_PERC_REQUEST, &mut value);
 				Ok(value)
 			}
 
 			/// Configures the encoder's use of discontinuous transmission (DTX).
 			pub fn set_dtx(&mut self, value: bool) -> Result<()> {
 				let value: i32 = if value { 1 } else { 0 };
-				ctl!($fn, self, ffi::OPUS_SET_DTX_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_DTX_REQUEST, value);
 				Ok(())
 			}
 
 			/// Gets encoder's configured use of discontinuous transmission (DTX).
 			pub fn get_dtx(&mut self) -> Result<bool> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_DTX_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_DTX_REQUEST, &mut value);
 				Ok(value != 0)
 			}
 
@@ -619,14 +1021,14 @@ macro_rules! encoder_ctls {
 			///
 			/// Depth should be between 8 and 24 inclusive.
 			pub fn set_lsb_depth(&mut self, depth: i32) -> Result<()> {
-				ctl!($fn, self, ffi::OPUS_SET_LSB_DEPTH_REQUEST, depth);
+				ctl!($fn, self, OPUS_SET_LSB_DEPTH_REQUEST, depth);
 				Ok(())
 			}
 
 			/// Gets the encoder's configured signal depth.
 			pub fn get_lsb_depth(&mut self) -> Result<i32> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_LSB_DEPTH_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_LSB_DEPTH_REQUEST, &mut value);
 				Ok(value)
 			}
 
@@ -635,14 +1037,14 @@ macro_rules! encoder_ctls {
 			/// Do not use this option unless you **really** know what you are doing.
 			pub fn set_expert_frame_duration(&mut self, framesize: FrameSize) -> Result<()> {
 				let value: i32 = framesize.raw();
-				ctl!($fn, self, ffi::OPUS_SET_EXPERT_FRAME_DURATION_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_EXPERT_FRAME_DURATION_REQUEST, value);
 				Ok(())
 			}
 
 			/// Gets the encoder's configured use of variable duration frames.
 			pub fn get_expert_frame_duration(&mut self) -> Result<FrameSize> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_EXPERT_FRAME_DURATION_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_EXPERT_FRAME_DURATION_REQUEST, &mut value);
 				FrameSize::from_raw(
 					value,
 					concat!(stringify!($fn), "(OPUS_GET_EXPERT_FRAME_DURATION)"),
@@ -652,161 +1054,1363 @@ macro_rules! encoder_ctls {
 			/// If set to true, disables almost all use of prediction, making frames almost completely independent.
 			pub fn set_prediction_disabled(&mut self, disabled: bool) -> Result<()> {
 				let value: i32 = if disabled { 1 } else { 0 };
-				ctl!($fn, self, ffi::OPUS_SET_PREDICTION_DISABLED_REQUEST, value);
+				ctl!($fn, self, OPUS_SET_PREDICTION_DISABLED_REQUEST, value);
 				Ok(())
 			}
 
 			/// Gets the encoder's configured prediction status.
 			pub fn get_prediction_disabled(&mut self) -> Result<bool> {
 				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_PREDICTION_DISABLED_REQUEST, &mut value);
+				ctl!($fn, self, OPUS_GET_PREDICTION_DISABLED_REQUEST, &mut value);
 				Ok(value != 0)
 			}
 
 			// TODO(#5): OPUS_SET/GET_DRED_DURATION (since Opus 1.5)
 			// TODO(#5): OPUS_SET_DNN_BLOB (since Opus 1.5)
+
+			/// Apply every setting in an encoder configuration.
+			///
+			/// The configuration is validated first, and settings are applied in
+			/// an order which does not depend on the encoder's previous state.
+			pub fn apply_config(&mut self, config: &EncoderConfig) -> Result<()> {
+				config.validate()?;
+				self.set_application(config.application)?;
+				self.set_signal(config.signal)?;
+				self.set_max_bandwidth(config.max_bandwidth)?;
+				self.set_force_channels(config.force_channels)?;
+				self.set_bitrate(config.bitrate)?;
+				self.set_vbr(config.vbr)?;
+				self.set_vbr_constraint(config.vbr_constraint)?;
+				self.set_complexity(config.complexity)?;
+				self.set_inband_fec(config.inband_fec)?;
+				self.set_packet_loss_perc(config.packet_loss_perc)?;
+				self.set_dtx(config.dtx)?;
+				self.set_lsb_depth(config.lsb_depth)?;
+				self.set_expert_frame_duration(config.frame_duration)?;
+				self.set_prediction_disabled(config.prediction_disabled)?;
+				self.set_phase_inversion_disabled(config.phase_inversion_disabled)?;
+				Ok(())
+			}
 		}
 	};
 }
 
-generic_ctls!(Encoder, opus_encoder_ctl);
-encoder_ctls!(Encoder, opus_encoder_ctl);
+generic_ctls!(Encoder, opus_encoder_ctl, resampled: reset_resampler, resampled_input_rate);
+encoder_ctls!(Encoder, opus_encoder_ctl, resampled: resampled_lookahead);
 
 // ============================================================================
-// Decoder
+// Encoder Configuration
 
-/// An Opus decoder with associated state.
-#[derive(Debug)]
-pub struct Decoder {
-	ptr: *mut ffi::OpusDecoder,
-	channels: Channels,
+/// A complete set of encoder settings, which can be applied in one step.
+///
+/// The default value matches the settings of a freshly created encoder using
+/// `Application::Audio`. With the `serde` feature, missing fields are also
+/// filled in from the default.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(default))]
+pub struct EncoderConfig {
+	/// The intended application.
+	pub application: Application,
+	/// The target bitrate.
+	pub bitrate: Bitrate,
+	/// The computational complexity, from 0 to 10.
+	pub complexity: i32,
+	/// Whether variable bitrate is enabled.
+	pub vbr: bool,
+	/// Whether variable bitrate is constrained.
+	pub vbr_constraint: bool,
+	/// The type of signal being encoded.
+	pub signal: Signal,
+	/// The maximum bandpass the encoder will select automatically.
+	pub max_bandwidth: Bandwidth,
+	/// Forced mono or stereo coding, or `None` to follow the input.
+	pub force_channels: Option<Channels>,
+	/// Whether inband forward error correction is enabled.
+	pub inband_fec: bool,
+	/// The expected packet loss percentage, from 0 to 100.
+	pub packet_loss_perc: i32,
+	/// Whether discontinuous transmission is enabled.
+	pub dtx: bool,
+	/// The depth of the input signal, from 8 to 24.
+	pub lsb_depth: i32,
+	/// The encoder's use of variable duration frames.
+	pub frame_duration: FrameSize,
+	/// Whether inter-frame prediction is disabled.
+	pub prediction_disabled: bool,
+	/// Whether phase inversion for intensity stereo is disabled.
+	pub phase_inversion_disabled: bool,
 }
 
-impl Drop for Decoder {
-	fn drop(&mut self) {
-		unsafe { ffi::opus_decoder_destroy(self.ptr) }
+impl Default for EncoderConfig {
+	fn default() -> EncoderConfig {
+		EncoderConfig {
+			application: Application::Audio,
+			bitrate: Bitrate::Auto,
+			complexity: 9,
+			vbr: true,
+			vbr_constraint: true,
+			signal: Signal::Auto,
+			max_bandwidth: Bandwidth::Fullband,
+			force_channels: None,
+			inband_fec: false,
+			packet_loss_perc: 0,
+			dtx: false,
+			lsb_depth: 24,
+			frame_duration: FrameSize::Arg,
+			prediction_disabled: false,
+			phase_inversion_disabled: false,
+		}
 	}
 }
 
-// See `unsafe impl Send for Encoder`.
-unsafe impl Send for Decoder {}
-
-impl Decoder {
-	/// Create and initialize a decoder.
-	pub fn new(sample_rate: u32, channels: Channels) -> Result<Decoder> {
-		let mut error = 0;
-		let ptr =
-			unsafe { ffi::opus_decoder_create(sample_rate as i32, channels as c_int, &mut error) };
-		if error != ffi::OPUS_OK || ptr.is_null() {
-			Err(Error::from_code("opus_decoder_create", error))
-		} else {
-			Ok(Decoder { ptr, channels })
+impl EncoderConfig {
+	/// Check that every setting is within the range libopus accepts.
+	pub fn validate(&self) -> Result<()> {
+		fn check(ok: bool, field: &'static str) -> Result<()> {
+			if ok {
+				Ok(())
+			} else {
+				Err(Error::bad_arg("EncoderConfig::validate").with_context(field))
+			}
+		}
+		if let Bitrate::Bits(bits) = self.bitrate {
+			check(bits > 0, "bitrate")?;
 		}
+		check((0..=10).contains(&self.complexity), "complexity")?;
+		check(self.max_bandwidth != Bandwidth::Auto, "max_bandwidth")?;
+		check((0..=100).contains(&self.packet_loss_perc), "packet_loss_perc")?;
+		check((8..=24).contains(&self.lsb_depth), "lsb_depth")?;
+		Ok(())
+	}
+}
+
+impl Encoder {
+	/// Create an encoder with every setting taken from a configuration.
+	pub fn from_config(
+		sample_rate: u32,
+		channels: Channels,
+		config: &EncoderConfig,
+	) -> Result<Encoder> {
+		config.validate()?;
+		let mut encoder = Encoder::new(sample_rate, channels, config.application)?;
+		encoder.apply_config(config)?;
+		Ok(encoder)
 	}
 
-	/// Decode an Opus packet.
+	/// Read every setting back from the encoder.
 	///
-	/// To represent packet loss, pass an empty slice `&[]`.
+	/// This is not available for `MSEncoder`, as libopus cannot report the
+	/// maximum bandwidth of a multistream encoder.
 	///
-	/// The return value is the number of samples *per channel* decoded from
-	/// the packet.
-	pub fn decode(&mut self, input: &[u8], output: &mut [i16], fec: bool) -> Result<usize> {
-		let ptr = match input.len() {
-			0 => std::ptr::null(),
-			_ => input.as_ptr(),
-		};
-		let len = ffi!(
-			opus_decode,
-			self.ptr,
-			ptr,
-			len(input),
-			output.as_mut_ptr(),
-			len(output) / self.channels as c_int,
-			fec as c_int
-		);
-		Ok(len as usize)
+	/// libopus reports the effective bitrate rather than `Bitrate::Auto`
+	/// if no explicit bitrate was set.
+	pub fn current_config(&mut self) -> Result<EncoderConfig> {
+		Ok(EncoderConfig {
+			application: self.get_application()?,
+			bitrate: self.get_bitrate()?,
+			complexity: self.get_complexity()?,
+			vbr: self.get_vbr()?,
+			vbr_constraint: self.get_vbr_constraint()?,
+			signal: self.get_signal()?,
+			max_bandwidth: self.get_max_bandwidth()?,
+			force_channels: self.get_force_channels()?,
+			inband_fec: self.get_inband_fec()?,
+			packet_loss_perc: self.get_packet_loss_perc()?,
+			dtx: self.get_dtx()?,
+			lsb_depth: self.get_lsb_depth()?,
+			frame_duration: self.get_expert_frame_duration()?,
+			prediction_disabled: self.get_prediction_disabled()?,
+			phase_inversion_disabled: self.get_phase_inversion_disabled()?,
+		})
 	}
+}
 
-	/// Decode an Opus packet with floating point output.
+// ============================================================================
+// Encoder Presets
+
+/// Ready-made encoder settings for common use cases.
+///
+/// Bitrates follow the ranges recommended by the Opus developers for each
+/// kind of content (see [Opus Recommended Settings](https://wiki.xiph.org/Opus_Recommended_Settings)):
+///
+/// | Content                 | Bitrate        |
+/// |-------------------------|----------------|
+/// | Narrowband speech       | 8-12 kbps      |
+/// | Wideband speech         | 16-20 kbps     |
+/// | Fullband speech         | 28-40 kbps     |
+/// | Fullband mono music     | 48-64 kbps     |
+/// | Fullband stereo music   | 64-128 kbps    |
+///
+/// Opus is generally considered transparent for stereo music at 128 kbps, so
+/// higher rates are only useful for archival or further processing. Speech
+/// presets use constrained VBR, which keeps packet sizes predictable for
+/// networks without giving up the efficiency of VBR entirely.
+///
+/// The presets were measured with `quality::roundtrip` and libopus 1.6.1 on
+/// synthetic audio at 48 kHz: one second of a harmonic chord for the music
+/// presets, and one and a half seconds of a buzzing voice with pauses for the
+/// speech presets. The distance is the `quality::log_spectral_distance` in
+/// dB, so lower is better. The speech presets limit the bandwidth, so they
+/// are compared below 8 kHz, or 4 kHz for narrowband, and their distances
+/// only rank against each other.
+///
+/// | Preset                | Channels | Bitrate    | Distance |
+/// |-----------------------|----------|------------|----------|
+/// | `Voip`                | mono     | 17.5 kbps  | 6.9 dB   |
+/// | `Voip`, narrowband    | mono     | 8.2 kbps   | 7.4 dB   |
+/// | `GameVoice`           | mono     | 18.6 kbps  | 7.3 dB   |
+/// | `Podcast`             | mono     | 45 kbps    | 2.1 dB   |
+/// | `Podcast`             | stereo   | 98 kbps    | 2.3 dB   |
+/// | `Music`               | mono     | 68 kbps    | 1.6 dB   |
+/// | `Music`               | stereo   | 123 kbps   | 2.0 dB   |
+/// | `Archival`            | mono     | 112 kbps   | 1.0 dB   |
+/// | `Archival`            | stereo   | 228 kbps   | 1.2 dB   |
+/// | `LowLatency`          | mono     | 64 kbps    | 3.5 dB   |
+/// | `LowLatency`          | stereo   | 128 kbps   | 3.4 dB   |
+///
+/// The speech presets come in below their target thanks to DTX in the
+/// pauses, while unconstrained VBR spends above the target on the chord's
+/// sustained tones. `LowLatency` pays for its 5 ms frames and CBR with a
+/// larger distance than `Music` at a higher bitrate. Exact figures vary with
+/// the libopus version and build; the crate's tests check that the presets
+/// keep this ranking.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Preset {
+	/// Interactive speech over a network: 24 kbps superwideband, 20 ms
+	/// frames, inband FEC tuned for 10% loss, and DTX.
 	///
-	/// To represent packet loss, pass an empty slice `&[]`.
+	/// With `narrowband_fallback`, the encoder is limited to narrowband at
+	/// 12 kbps, for very constrained links such as cellular fallback or PSTN
+	/// gateways.
+	Voip {
+		/// Limit the encoder to narrowband at 12 kbps.
+		narrowband_fallback: bool,
+	},
+	/// Music streaming: 64 kbps for mono and 96 kbps for stereo, fullband
+	/// with unconstrained VBR and 20 ms frames.
+	Music,
+	/// Spoken word with occasional music: 40 kbps per channel fullband, with
+	/// unconstrained VBR.
+	Podcast,
+	/// In-game voice chat: 24 kbps superwideband with 10 ms frames for lower
+	/// latency, reduced complexity to leave CPU time for the game, inband FEC
+	/// tuned for 5% loss, and DTX.
+	GameVoice,
+	/// Long-term storage of high quality audio: 96 kbps per channel fullband
+	/// at maximum complexity with unconstrained VBR.
+	Archival,
+	/// The lowest latency Opus offers: the restricted low-delay application
+	/// with 5 ms frames and CBR, at 64 kbps per channel.
+	LowLatency,
+}
+
+impl Preset {
+	/// Get the encoder settings for this preset.
 	///
-	/// The return value is the number of samples *per channel* decoded from
-	/// the packet.
-	pub fn decode_float(&mut self, input: &[u8], output: &mut [f32], fec: bool) -> Result<usize> {
-		let ptr = match input.len() {
-			0 => std::ptr::null(),
-			_ => input.as_ptr(),
+	/// Bitrates of presets for music scale with the channel count.
+	pub fn config(self, channels: Channels) -> EncoderConfig {
+		let stereo = channels == Channels::Stereo;
+		let base = EncoderConfig {
+			frame_duration: self.frame_size(),
+			..EncoderConfig::default()
 		};
-		let len = ffi!(
-			opus_decode_float,
-			self.ptr,
-			ptr,
-			len(input),
-			output.as_mut_ptr(),
-			len(output) / self.channels as c_int,
-			fec as c_int
-		);
-		Ok(len as usize)
+		match self {
+			Preset::Voip { narrowband_fallback } => EncoderConfig {
+				application: Application::Voip,
+				bitrate: Bitrate::Bits(if narrowband_fallback { 12000 } else { 24000 }),
+				complexity: 8,
+				signal: Signal::Voice,
+				max_bandwidth: if narrowband_fallback {
+					Bandwidth::Narrowband
+				} else {
+					Bandwidth::Superwideband
+				},
+				inband_fec: true,
+				packet_loss_perc: 10,
+				dtx: true,
+				..base
+			},
+			Preset::Music => EncoderConfig {
+				application: Application::Audio,
+				bitrate: Bitrate::Bits(if stereo { 96000 } else { 64000 }),
+				complexity: 10,
+				vbr_constraint: false,
+				signal: Signal::Music,
+				..base
+			},
+			Preset::Podcast => EncoderConfig {
+				application: Application::Audio,
+				bitrate: Bitrate::Bits(channels as i32 * 40000),
+				complexity: 10,
+				vbr_constraint: false,
+				..base
+			},
+			Preset::GameVoice => EncoderConfig {
+				application: Application::Voip,
+				bitrate: Bitrate::Bits(24000),
+				complexity: 5,
+				signal: Signal::Voice,
+				max_bandwidth: Bandwidth::Superwideband,
+				inband_fec: true,
+				packet_loss_perc: 5,
+				dtx: true,
+				..base
+			},
+			Preset::Archival => EncoderConfig {
+				application: Application::Audio,
+				bitrate: Bitrate::Bits(channels as i32 * 96000),
+				complexity: 10,
+				vbr_constraint: false,
+				..base
+			},
+			Preset::LowLatency => EncoderConfig {
+				application: Application::LowDelay,
+				bitrate: Bitrate::Bits(channels as i32 * 64000),
+				vbr: false,
+				..base
+			},
+		}
 	}
 
-	/// Get the number of samples *per channel* of an Opus packet.
-	pub fn get_nb_samples(&self, packet: &[u8]) -> Result<usize> {
-		let len = ffi!(opus_decoder_get_nb_samples, self.ptr, packet.as_ptr(), packet.len() as i32);
-		Ok(len as usize)
+	/// Get the frame size this preset is designed for.
+	///
+	/// Pass this to `BufferedEncoder::new`, or use it to size the input to
+	/// `encode`.
+	pub fn frame_size(self) -> FrameSize {
+		match self {
+			Preset::GameVoice => FrameSize::Ms10,
+			Preset::LowLatency => FrameSize::Ms5,
+			_ => FrameSize::Ms20,
+		}
 	}
 }
 
-macro_rules! decoder_ctls {
-	($t:ty, $fn:ident) => {
-		/// Decoder CTLs. See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__decoderctls.html).
-		impl $t {
-			/// Configures decoder gain adjustment.
-			///
-			/// Scales the decoded output by a factor specified in Q8 dB units. This has
-			/// a maximum range of -32768 to 32768 inclusive, and returns `BadArg`
-			/// otherwise. The default is zero indicating no adjustment. This setting
-			/// survives decoder reset.
-			///
-			/// `gain = pow(10, x / (20.0 * 256))`
-			pub fn set_gain(&mut self, gain: i32) -> Result<()> {
-				ctl!($fn, self, ffi::OPUS_SET_GAIN_REQUEST, gain);
-				Ok(())
-			}
+impl Encoder {
+	/// Create an encoder using the settings of a preset.
+	pub fn from_preset(sample_rate: u32, channels: Channels, preset: Preset) -> Result<Encoder> {
+		Encoder::from_config(sample_rate, channels, &preset.config(channels))
+	}
+}
 
-			/// Gets the decoder's configured gain adjustment.
-			pub fn get_gain(&mut self) -> Result<i32> {
-				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_GAIN_REQUEST, &mut value);
-				Ok(value)
-			}
+// ============================================================================
+// Buffered Encoding
 
-			/// Gets the duration (in samples) of the last packet successfully decoded
-			/// or concealed.
-			pub fn get_last_packet_duration(&mut self) -> Result<u32> {
-				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_LAST_PACKET_DURATION_REQUEST, &mut value);
-				Ok(value as u32)
-			}
+/// A PCM sample type accepted by the encoder, either `i16` or `f32`.
+pub trait Sample: Copy + Default + sealed::Sample {}
 
-			/// Gets the pitch of the last decoded frame, if available.
-			///
-			/// This can be used for any post-processing algorithm requiring the use of
-			/// pitch, e.g. time stretching/shortening. If the last frame was not
-			/// voiced, or if the pitch was not coded in the frame, then zero is
-			/// returned.
-			pub fn get_pitch(&mut self) -> Result<i32> {
-				let mut value: i32 = 0;
-				ctl!($fn, self, ffi::OPUS_GET_PITCH_REQUEST, &mut value);
-				Ok(value)
-			}
-		}
+impl Sample for i16 {}
+impl Sample for f32 {}
+
+/// An encoder which `BufferedEncoder` can wrap, either `Encoder` or
+/// `MSEncoder`.
+pub trait FrameEncoder: sealed::FrameEncoder {}
+
+impl FrameEncoder for Encoder {}
+impl FrameEncoder for MSEncoder {}
+
+mod sealed {
+	use super::{Decoder, Encoder, FrameSize, MSDecoder, MSEncoder, Result};
+
+	pub trait FrameEncoder {
+		fn channels(&self) -> usize;
+		fn max_packet_size(&self) -> usize;
+		fn sample_rate(&mut self) -> Result<u32>;
+		fn lookahead(&mut self) -> Result<i32>;
+		fn frame_duration(&mut self) -> Result<FrameSize>;
+		fn set_frame_duration(&mut self, frame_size: FrameSize) -> Result<()>;
+		fn encode_frame<T: super::Sample>(&mut self, input: &[T]) -> Result<Vec<u8>>;
+	}
+
+	impl FrameEncoder for Encoder {
+		fn channels(&self) -> usize {
+			self.channels as usize
+		}
+
+		fn max_packet_size(&self) -> usize {
+			super::MAX_PACKET_SIZE
+		}
+
+		fn sample_rate(&mut self) -> Result<u32> {
+			self.get_sample_rate()
+		}
+
+		fn lookahead(&mut self) -> Result<i32> {
+			self.get_lookahead()
+		}
+
+		fn frame_duration(&mut self) -> Result<FrameSize> {
+			self.get_expert_frame_duration()
+		}
+
+		fn set_frame_duration(&mut self, frame_size: FrameSize) -> Result<()> {
+			self.set_expert_frame_duration(frame_size)
+		}
+
+		fn encode_frame<T: super::Sample>(&mut self, input: &[T]) -> Result<Vec<u8>> {
+			T::encode_vec(self, input, super::MAX_PACKET_SIZE)
+		}
+	}
+
+	impl FrameEncoder for MSEncoder {
+		fn channels(&self) -> usize {
+			self.channels as usize
+		}
+
+		// there are at most as many streams as channels
+		fn max_packet_size(&self) -> usize {
+			super::MAX_PACKET_SIZE * self.channels as usize
+		}
+
+		fn sample_rate(&mut self) -> Result<u32> {
+			self.get_sample_rate()
+		}
+
+		fn lookahead(&mut self) -> Result<i32> {
+			self.get_lookahead()
+		}
+
+		fn frame_duration(&mut self) -> Result<FrameSize> {
+			self.get_expert_frame_duration()
+		}
+
+		fn set_frame_duration(&mut self, frame_size: FrameSize) -> Result<()> {
+			self.set_expert_frame_duration(frame_size)
+		}
+
+		fn encode_frame<T: super::Sample>(&mut self, input: &[T]) -> Result<Vec<u8>> {
+			let mut output = vec![0; self.max_packet_size()];
+			let len = T::ms_encode(self, input, &mut output)?;
+			output.truncate(len);
+			Ok(output)
+		}
+	}
+
+	pub trait Sample: Sized {
+		fn to_f32(self) -> f32;
+		fn from_f32(value: f32) -> Self;
+		fn encode(encoder: &mut Encoder, input: &[Self], output: &mut [u8]) -> Result<usize>;
+		fn encode_vec(encoder: &mut Encoder, input: &[Self], max_size: usize) -> Result<Vec<u8>>;
+		fn decode(
+			decoder: &mut Decoder,
+			input: &[u8],
+			output: &mut [Self],
+			fec: bool,
+		) -> Result<usize>;
+		fn ms_encode(encoder: &mut MSEncoder, input: &[Self], output: &mut [u8]) -> Result<usize>;
+		fn ms_decode(
+			decoder: &mut MSDecoder,
+			input: &[u8],
+			output: &mut [Self],
+			fec: bool,
+		) -> Result<usize>;
+	}
+
+	impl Sample for i16 {
+		#[inline]
+		fn to_f32(self) -> f32 {
+			self as f32 / 32768.0
+		}
+
+		#[inline]
+		fn from_f32(value: f32) -> i16 {
+			(value * 32768.0).round().clamp(-32768.0, 32767.0) as i16
+		}
+
+		fn encode(encoder: &mut Encoder, input: &[i16], output: &mut [u8]) -> Result<usize> {
+			encoder.encode(input, output)
+		}
+
+		fn encode_vec(encoder: &mut Encoder, input: &[i16], max_size: usize) -> Result<Vec<u8>> {
+			encoder.encode_vec(input, max_size)
+		}
+
+		fn decode(
+			decoder: &mut Decoder,
+			input: &[u8],
+			output: &mut [i16],
+			fec: bool,
+		) -> Result<usize> {
+			decoder.decode(input, output, fec)
+		}
+
+		fn ms_encode(encoder: &mut MSEncoder, input: &[i16], output: &mut [u8]) -> Result<usize> {
+			encoder.encode(input, output)
+		}
+
+		fn ms_decode(
+			decoder: &mut MSDecoder,
+			input: &[u8],
+			output: &mut [i16],
+			fec: bool,
+		) -> Result<usize> {
+			decoder.decode(input, output, fec)
+		}
+	}
+
+	impl Sample for f32 {
+		#[inline]
+		fn to_f32(self) -> f32 {
+			self
+		}
+
+		#[inline]
+		fn from_f32(value: f32) -> f32 {
+			value
+		}
+
+		fn encode(encoder: &mut Encoder, input: &[f32], output: &mut [u8]) -> Result<usize> {
+			encoder.encode_float(input, output)
+		}
+
+		fn encode_vec(encoder: &mut Encoder, input: &[f32], max_size: usize) -> Result<Vec<u8>> {
+			encoder.encode_vec_float(input, max_size)
+		}
+
+		fn decode(
+			decoder: &mut Decoder,
+			input: &[u8],
+			output: &mut [f32],
+			fec: bool,
+		) -> Result<usize> {
+			decoder.decode_float(input, output, fec)
+		}
+
+		fn ms_encode(encoder: &mut MSEncoder, input: &[f32], output: &mut [u8]) -> Result<usize> {
+			encoder.encode_float(input, output)
+		}
+
+		fn ms_decode(
+			decoder: &mut MSDecoder,
+			input: &[u8],
+			output: &mut [f32],
+			fec: bool,
+		) -> Result<usize> {
+			decoder.decode_float(input, output, fec)
+		}
+	}
+}
+
+/// The recommended maximum size of a single encoded packet.
+const MAX_PACKET_SIZE: usize = 4000;
+
+/// An encoder which accepts PCM input of any length.
+///
+/// Input is buffered internally, and a packet is produced every time a full
+/// frame of the configured size is available. Either an `Encoder` or an
+/// `MSEncoder` may be wrapped.
+#[derive(Debug)]
+pub struct BufferedEncoder<T: Sample, E: FrameEncoder = Encoder> {
+	encoder: E,
+	frame_size: FrameSize,
+	frame_len: usize,
+	buffer: Vec<T>,
+	flushed: bool,
+}
+
+impl<T: Sample, E: FrameEncoder> BufferedEncoder<T, E> {
+	/// Wrap an encoder, producing packets of the given frame size.
+	///
+	/// Returns an `UnsupportedFrameSize` error if the frame size is
+	/// `FrameSize::Arg` or is not a whole number of samples at the encoder's
+	/// sample rate. If the encoder has an explicit frame duration set, it is
+	/// changed to match the frame size.
+	pub fn new(mut encoder: E, frame_size: FrameSize) -> Result<BufferedEncoder<T, E>> {
+		let frame_len = buffered_frame_len("BufferedEncoder::new", &mut encoder, frame_size)?;
+		Ok(BufferedEncoder {
+			encoder,
+			frame_size,
+			frame_len,
+			buffer: Vec::new(),
+			flushed: true,
+		})
+	}
+
+	/// Get the configured frame size.
+	#[inline]
+	pub fn frame_size(&self) -> FrameSize {
+		self.frame_size
+	}
+
+	/// Change the frame size of the packets produced from now on.
+	///
+	/// Buffered input is kept, and is encoded in frames of the new size by
+	/// the next `push` or `finish`. As with `new`, an explicit frame duration
+	/// set on the encoder is changed to match.
+	pub fn set_frame_size(&mut self, frame_size: FrameSize) -> Result<()> {
+		self.frame_len =
+			buffered_frame_len("BufferedEncoder::set_frame_size", &mut self.encoder, frame_size)?;
+		self.frame_size = frame_size;
+		Ok(())
+	}
+
+	/// Get the number of samples *per channel* waiting for a full frame.
+	#[inline]
+	pub fn buffered(&self) -> usize {
+		self.buffer.len() / self.encoder.channels()
+	}
+
+	/// Mutably borrow the underlying encoder, e.g. to change its settings.
+	#[inline]
+	pub fn encoder_mut(&mut self) -> &mut E {
+		&mut self.encoder
+	}
+
+	/// Unwrap the underlying encoder, discarding any buffered input.
+	#[inline]
+	pub fn into_inner(self) -> E {
+		self.encoder
+	}
+
+	/// Add interleaved input of any length, appending the packets for every
+	/// frame which was completed to `packets`.
+	///
+	/// The input length must be a multiple of the channel count. If encoding
+	/// a frame fails, the packets for the frames before it are still
+	/// appended, and the failed frame and everything after it stay buffered.
+	pub fn push(&mut self, input: &[T], packets: &mut Vec<Vec<u8>>) -> Result<()> {
+		check_frame_len("BufferedEncoder::push", input.len(), self.encoder.channels())?;
+		if !input.is_empty() {
+			self.flushed = false;
+		}
+		self.buffer.extend_from_slice(input);
+		self.encode_buffered(packets)
+	}
+
+	/// Pad the buffered input with silence and flush it out of the encoder,
+	/// appending the packets to `packets`.
+	///
+	/// In addition to the partial frame, enough silence is encoded to cover
+	/// the encoder's lookahead, so that the decoder sees all of the input once
+	/// it discards the first `get_lookahead()` samples. The encoder may be
+	/// reused afterwards.
+	pub fn finish(&mut self, packets: &mut Vec<Vec<u8>>) -> Result<()> {
+		if self.flushed {
+			return Ok(());
+		}
+		let channels = self.encoder.channels();
+		let lookahead = self.encoder.lookahead()? as usize * channels;
+		let total = self.buffer.len() + lookahead;
+		let padded = total.div_ceil(self.frame_len) * self.frame_len;
+		self.buffer.resize(padded, T::default());
+		self.encode_buffered(packets)?;
+		self.flushed = true;
+		Ok(())
+	}
+
+	fn encode_buffered(&mut self, packets: &mut Vec<Vec<u8>>) -> Result<()> {
+		let mut offset = 0;
+		let mut result = Ok(());
+		while self.buffer.len() - offset >= self.frame_len {
+			let frame = &self.buffer[offset..offset + self.frame_len];
+			match self.encoder.encode_frame(frame) {
+				Ok(packet) => packets.push(packet),
+				Err(err) => {
+					result = Err(err);
+					break;
+				}
+			}
+			offset += self.frame_len;
+		}
+		self.buffer.drain(..offset);
+		result
+	}
+}
+
+/// Get the interleaved length of a frame, and make sure the encoder will
+/// encode the whole of it.
+fn buffered_frame_len<E: FrameEncoder>(
+	what: &'static str,
+	encoder: &mut E,
+	frame_size: FrameSize,
+) -> Result<usize> {
+	let sample_rate = encoder.sample_rate()?;
+	let samples = match frame_size.samples(sample_rate) {
+		Some(samples) => samples,
+		None => {
+			return Err(Error::new(
+				what,
+				ErrorKind::UnsupportedFrameSize { frame_size, sample_rate },
+			))
+		}
+	};
+	// libopus rejects input shorter than this, and ignores input past it
+	if encoder.frame_duration()? != FrameSize::Arg {
+		encoder.set_frame_duration(frame_size)?;
+	}
+	Ok(samples * encoder.channels())
+}
+
+// ============================================================================
+// Decoder
+
+/// An Opus decoder with associated state.
+#[derive(Debug)]
+pub struct Decoder {
+	ptr: *mut ffi::OpusDecoder,
+	channels: Channels,
+	resampler: Option<Resampler>,
+	decoded: Vec<f32>,
+	pending: Vec<f32>,
+	stats: Option<DecoderStats>,
+}
+
+impl Drop for Decoder {
+	fn drop(&mut self) {
+		unsafe { ffi::opus_decoder_destroy(self.ptr) }
+	}
+}
+
+// See `unsafe impl Send for Encoder`.
+unsafe impl Send for Decoder {}
+
+impl Decoder {
+	/// Create and initialize a decoder.
+	pub fn new(sample_rate: u32, channels: Channels) -> Result<Decoder> {
+		check_sample_rate("Decoder::new", sample_rate)?;
+		let mut error = 0;
+		let ptr =
+			unsafe { ffi::opus_decoder_create(sample_rate as i32, channels as c_int, &mut error) };
+		if error != ffi::OPUS_OK || ptr.is_null() {
+			Err(Error::from_code("opus_decoder_create", error))
+		} else {
+			Ok(Decoder {
+				ptr,
+				channels,
+				resampler: None,
+				decoded: Vec::new(),
+				pending: Vec::new(),
+				stats: None,
+			})
+		}
+	}
+
+	/// Create a decoder which produces output at any sample rate.
+	///
+	/// If Opus does not support `output_rate` natively, packets are decoded at
+	/// the nearest higher supported rate and resampled. The resampler delays
+	/// the output by a further `resampler_delay()` samples.
+	pub fn with_output_rate(output_rate: u32, channels: Channels) -> Result<Decoder> {
+		let codec_rate = resample::codec_rate(output_rate);
+		let mut decoder = Decoder::new(codec_rate, channels)?;
+		if codec_rate != output_rate {
+			decoder.resampler = Some(Resampler::new(codec_rate, output_rate, channels as usize)?);
+		}
+		Ok(decoder)
+	}
+
+	/// Get the delay added by resampling the output, in samples *per channel*
+	/// at the output rate.
+	pub fn resampler_delay(&self) -> usize {
+		match self.resampler {
+			Some(ref resampler) => {
+				resampler.delay() * resampler.output_rate() as usize
+					/ resampler.input_rate() as usize
+			}
+			None => 0,
+		}
+	}
+
+	/// Get the sample rate of the output produced by `decode`, if it is
+	/// resampled.
+	fn resampled_output_rate(&self) -> Option<u32> {
+		self.resampler.as_ref().map(|resampler| resampler.output_rate())
+	}
+
+	fn reset_resampler(&mut self) {
+		if let Some(ref mut resampler) = self.resampler {
+			resampler.reset();
+		}
+		self.pending.clear();
+	}
+
+	fn decode_resampled<T: Sample>(
+		&mut self,
+		input: &[u8],
+		output: &mut [T],
+		fec: bool,
+	) -> Result<usize> {
+		let channels = self.channels as usize;
+		let (codec_rate, output_rate) = match self.resampler {
+			Some(ref resampler) => (resampler.input_rate(), resampler.output_rate()),
+			None => unreachable!(),
+		};
+		let frame_size = if input.is_empty() || fec {
+			// the requested duration is concealed, in multiples of 2.5 ms
+			let unit = codec_rate as usize / 400;
+			let wanted = output.len() / channels * codec_rate as usize / output_rate as usize;
+			std::cmp::max(unit, wanted / unit * unit)
+		} else {
+			// room for the longest possible packet
+			codec_rate as usize * 120 / 1000
+		};
+		self.decoded.resize(frame_size * channels, 0.0);
+
+		let ptr = match input.len() {
+			0 => std::ptr::null(),
+			_ => input.as_ptr(),
+		};
+		let decoded = ffi!(
+			opus_decode_float,
+			self.ptr,
+			ptr,
+			len(input),
+			self.decoded.as_mut_ptr(),
+			check_len(frame_size),
+			fec as c_int
+		) as usize;
+
+		if let Some(ref mut resampler) = self.resampler {
+			self.pending.extend_from_slice(resampler.process(&self.decoded[..decoded * channels]));
+		}
+		let written = std::cmp::min(self.pending.len(), output.len() / channels * channels);
+		for (out, sample) in output.iter_mut().zip(self.pending.drain(..written)) {
+			*out = T::from_f32(sample);
+		}
+		Ok(written / channels)
+	}
+
+	/// Decode an Opus packet.
+	///
+	/// To represent packet loss, pass an empty slice `&[]`.
+	///
+	/// The return value is the number of samples *per channel* decoded from
+	/// the packet. The output length must be a multiple of the channel count.
+	pub fn decode(&mut self, input: &[u8], output: &mut [i16], fec: bool) -> Result<usize> {
+		check_frame_len("Decoder::decode", output.len(), self.channels as usize)?;
+		let result = self.decode_packet(input, output, fec);
+		self.record_stats(input, fec, &result);
+		result
+	}
+
+	fn decode_packet(&mut self, input: &[u8], output: &mut [i16], fec: bool) -> Result<usize> {
+		if self.resampler.is_some() {
+			return self.decode_resampled(input, output, fec);
+		}
+		let ptr = match input.len() {
+			0 => std::ptr::null(),
+			_ => input.as_ptr(),
+		};
+		let len = ffi!(
+			opus_decode,
+			self.ptr,
+			ptr,
+			len(input),
+			output.as_mut_ptr(),
+			len(output) / self.channels as c_int,
+			fec as c_int
+		);
+		Ok(len as usize)
+	}
+
+	/// Decode an Opus packet with floating point output.
+	///
+	/// To represent packet loss, pass an empty slice `&[]`.
+	///
+	/// The return value is the number of samples *per channel* decoded from
+	/// the packet. The output length must be a multiple of the channel count.
+	pub fn decode_float(&mut self, input: &[u8], output: &mut [f32], fec: bool) -> Result<usize> {
+		check_frame_len("Decoder::decode_float", output.len(), self.channels as usize)?;
+		let result = self.decode_packet_float(input, output, fec);
+		self.record_stats(input, fec, &result);
+		result
+	}
+
+	fn decode_packet_float(
+		&mut self,
+		input: &[u8],
+		output: &mut [f32],
+		fec: bool,
+	) -> Result<usize> {
+		if self.resampler.is_some() {
+			return self.decode_resampled(input, output, fec);
+		}
+		let ptr = match input.len() {
+			0 => std::ptr::null(),
+			_ => input.as_ptr(),
+		};
+		let len = ffi!(
+			opus_decode_float,
+			self.ptr,
+			ptr,
+			len(input),
+			output.as_mut_ptr(),
+			len(output) / self.channels as c_int,
+			fec as c_int
+		);
+		Ok(len as usize)
+	}
+
+	/// Start collecting statistics about each packet decoded.
+	///
+	/// If statistics were already enabled, they are left unchanged.
+	pub fn enable_stats(&mut self) {
+		if self.stats.is_none() {
+			self.stats = Some(DecoderStats::default());
+		}
+	}
+
+	/// Stop collecting statistics and discard those collected so far.
+	pub fn disable_stats(&mut self) {
+		self.stats = None;
+	}
+
+	/// Get a snapshot of the statistics, if enabled.
+	pub fn stats(&self) -> Option<DecoderStats> {
+		self.stats
+	}
+
+	/// Clear the statistics collected so far, if enabled.
+	///
+	/// Statistics are not affected by `reset_state`.
+	pub fn reset_stats(&mut self) {
+		if let Some(ref mut stats) = self.stats {
+			*stats = DecoderStats::default();
+		}
+	}
+
+	fn record_stats(&mut self, packet: &[u8], fec: bool, result: &Result<usize>) {
+		if self.stats.is_some() {
+			let rate = self.get_sample_rate().unwrap_or(0);
+			if let Some(ref mut stats) = self.stats {
+				stats.record(packet, fec, result, rate);
+			}
+		}
+	}
+
+	/// Get the number of samples *per channel* of an Opus packet.
+	pub fn get_nb_samples(&self, packet: &[u8]) -> Result<usize> {
+		let len = ffi!(opus_decoder_get_nb_samples, self.ptr, packet.as_ptr(), packet.len() as i32);
+		Ok(match self.resampler {
+			Some(ref resampler) => {
+				len as usize * resampler.output_rate() as usize / resampler.input_rate() as usize
+			}
+			None => len as usize,
+		})
+	}
+}
+
+macro_rules! decoder_ctls {
+	($t:ty, $fn:ident) => {
+		/// Decoder CTLs. See [Opus docs](https://opus-codec.org/docs/opus_api-1.5/group__opus__decoderctls.html).
+		impl $t {
+			/// Configures decoder gain adjustment.
+			///
+			/// Scales the decoded output by a factor specified in Q8 dB units. This has
+			/// a maximum range of -32768 to 32768 inclusive, and returns `BadArg`
+			/// otherwise. The default is zero indicating no adjustment. This setting
+			/// survives decoder reset.
+			///
+			/// `gain = pow(10, x / (20.0 * 256))`
+			pub fn set_gain(&mut self, gain: i32) -> Result<()> {
+				ctl!($fn, self, OPUS_SET_GAIN_REQUEST, gain);
+				Ok(())
+			}
+
+			/// Gets the decoder's configured gain adjustment.
+			pub fn get_gain(&mut self) -> Result<i32> {
+				let mut value: i32 = 0;
+				ctl!($fn, self, OPUS_GET_GAIN_REQUEST, &mut value);
+				Ok(value)
+			}
+
+			/// Gets the duration (in samples) of the last packet successfully decoded
+			/// or concealed.
+			pub fn get_last_packet_duration(&mut self) -> Result<u32> {
+				let mut value: i32 = 0;
+				ctl!($fn, self, OPUS_GET_LAST_PACKET_DURATION_REQUEST, &mut value);
+				Ok(value as u32)
+			}
+
+			/// Gets the pitch of the last decoded frame, if available.
+			///
+			/// This can be used for any post-processing algorithm requiring the use of
+			/// pitch, e.g. time stretching/shortening. If the last frame was not
+			/// voiced, or if the pitch was not coded in the frame, then zero is
+			/// returned.
+			pub fn get_pitch(&mut self) -> Result<i32> {
+				let mut value: i32 = 0;
+				ctl!($fn, self, OPUS_GET_PITCH_REQUEST, &mut value);
+				Ok(value)
+			}
+		}
 	};
 }
 
-generic_ctls!(Decoder, opus_decoder_ctl);
+generic_ctls!(Decoder, opus_decoder_ctl, resampled: reset_resampler, resampled_output_rate);
 decoder_ctls!(Decoder, opus_decoder_ctl);
 
+// ============================================================================
+// Frames
+
+/// The arrangement of channels within a buffer of samples.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Layout {
+	/// Samples from each channel alternate: `L R L R ...`.
+	Interleaved,
+	/// Every sample from the first channel, followed by every sample from the
+	/// next: `L L ... R R ...`.
+	Planar,
+}
+
+/// The frame durations Opus can encode.
+const FRAME_SIZES: [FrameSize; 9] = [
+	FrameSize::Ms2_5,
+	FrameSize::Ms5,
+	FrameSize::Ms10,
+	FrameSize::Ms20,
+	FrameSize::Ms40,
+	FrameSize::Ms60,
+	FrameSize::Ms80,
+	FrameSize::Ms100,
+	FrameSize::Ms120,
+];
+
+fn is_frame_size(samples: usize, sample_rate: u32) -> bool {
+	FRAME_SIZES.iter().any(|size| size.samples(sample_rate) == Some(samples))
+}
+
+fn check_frame_len(what: &'static str, len: usize, channels: usize) -> Result<()> {
+	if channels == 0 {
+		return Err(Error::bad_arg(what).with_context("no channels"));
+	}
+	if len % channels != 0 {
+		return Err(Error::new(what, ErrorKind::UnevenLength { len, channels }));
+	}
+	Ok(())
+}
+
+/// Interleave separate channel buffers into a single buffer.
+///
+/// The number of samples per channel is `output.len() / input.len()`, and
+/// is taken from the start of each channel buffer.
+///
+/// # Panics
+///
+/// Panics if `output` does not hold the same number of samples for every
+/// channel, or if any channel buffer is too short.
+pub fn interleave<T: Copy>(input: &[&[T]], output: &mut [T]) {
+	let channels = input.len();
+	if channels == 0 {
+		assert!(output.is_empty(), "interleave: no channels for output");
+		return;
+	}
+	assert!(output.len() % channels == 0, "interleave: uneven output length");
+	let samples = output.len() / channels;
+	assert!(input.iter().all(|plane| plane.len() >= samples), "interleave: channel too short");
+
+	// Common layouts get loops simple enough for the compiler to vectorize.
+	match channels {
+		1 => output.copy_from_slice(&input[0][..samples]),
+		2 => {
+			for ((out, &left), &right) in output.chunks_exact_mut(2).zip(input[0]).zip(input[1]) {
+				out[0] = left;
+				out[1] = right;
+			}
+		}
+		_ => {
+			for (out, i) in output.chunks_exact_mut(channels).zip(0..samples) {
+				for (sample, plane) in out.iter_mut().zip(input) {
+					*sample = plane[i];
+				}
+			}
+		}
+	}
+}
+
+/// Split an interleaved buffer into separate channel buffers.
+///
+/// The number of samples per channel is `input.len() / output.len()`, and
+/// is written to the start of each channel buffer.
+///
+/// # Panics
+///
+/// Panics if `input` does not hold the same number of samples for every
+/// channel, or if any channel buffer is too short.
+pub fn deinterleave<T: Copy>(input: &[T], output: &mut [&mut [T]]) {
+	let channels = output.len();
+	if channels == 0 {
+		assert!(input.is_empty(), "deinterleave: no channels for input");
+		return;
+	}
+	assert!(input.len() % channels == 0, "deinterleave: uneven input length");
+	let samples = input.len() / channels;
+	assert!(output.iter().all(|plane| plane.len() >= samples), "deinterleave: channel too short");
+
+	match channels {
+		1 => output[0][..samples].copy_from_slice(input),
+		2 => {
+			let (left, right) = output.split_at_mut(1);
+			for ((frame, left), right) in
+				input.chunks_exact(2).zip(left[0].iter_mut()).zip(right[0].iter_mut())
+			{
+				*left = frame[0];
+				*right = frame[1];
+			}
+		}
+		_ => {
+			for (i, frame) in input.chunks_exact(channels).enumerate() {
+				for (plane, &sample) in output.iter_mut().zip(frame) {
+					plane[i] = sample;
+				}
+			}
+		}
+	}
+}
+
+fn check_channels(what: &'static str, expected: usize, actual: usize) -> Result<()> {
+	if expected != actual {
+		return Err(Error::new(what, ErrorKind::ChannelMismatch { expected, actual }));
+	}
+	Ok(())
+}
+
+fn check_planes<T>(what: &'static str, planes: &[&[T]], channels: usize) -> Result<usize> {
+	check_channels(what, channels, planes.len())?;
+	check_plane_lengths(what, planes.iter().map(|plane| plane.len()))
+}
+
+fn check_planes_mut<T>(what: &'static str, planes: &[&mut [T]], channels: usize) -> Result<usize> {
+	check_channels(what, channels, planes.len())?;
+	check_plane_lengths(what, planes.iter().map(|plane| plane.len()))
+}
+
+fn check_plane_lengths<I: Iterator<Item = usize>>(
+	what: &'static str,
+	mut lengths: I,
+) -> Result<usize> {
+	let samples = lengths.next().unwrap_or(0);
+	if lengths.any(|len| len != samples) {
+		return Err(Error::bad_arg(what).with_context("channel lengths differ"));
+	}
+	Ok(samples)
+}
+
+/// A buffer of PCM samples with a known channel count and layout.
+#[derive(Debug, Clone, Copy)]
+pub struct Frame<'a, T: 'a> {
+	data: &'a [T],
+	channels: usize,
+	layout: Layout,
+}
+
+impl<'a, T: Sample> Frame<'a, T> {
+	/// Wrap a buffer of samples.
+	///
+	/// Returns `BadArg` if the buffer does not hold the same number of
+	/// samples for every channel.
+	pub fn new(data: &'a [T], channels: usize, layout: Layout) -> Result<Frame<'a, T>> {
+		check_frame_len("Frame::new", data.len(), channels)?;
+		Ok(Frame { data, channels, layout })
+	}
+
+	/// Wrap a buffer of interleaved samples.
+	pub fn interleaved(data: &'a [T], channels: Channels) -> Result<Frame<'a, T>> {
+		Frame::new(data, channels as usize, Layout::Interleaved)
+	}
+
+	/// Wrap a buffer of planar samples.
+	pub fn planar(data: &'a [T], channels: Channels) -> Result<Frame<'a, T>> {
+		Frame::new(data, channels as usize, Layout::Planar)
+	}
+
+	/// Get the underlying samples.
+	#[inline]
+	pub fn data(&self) -> &'a [T] {
+		self.data
+	}
+
+	/// Get the number of channels.
+	#[inline]
+	pub fn channels(&self) -> usize {
+		self.channels
+	}
+
+	/// Get the arrangement of the channels.
+	#[inline]
+	pub fn layout(&self) -> Layout {
+		self.layout
+	}
+
+	/// Get the number of samples *per channel*.
+	#[inline]
+	pub fn samples(&self) -> usize {
+		self.data.len() / self.channels
+	}
+
+	/// Get the samples in interleaved order, copying them if needed.
+	fn interleaved_data(&self) -> Cow<'a, [T]> {
+		match self.layout {
+			Layout::Interleaved => Cow::Borrowed(self.data),
+			Layout::Planar => {
+				let planes: Vec<&[T]> = self.data.chunks(self.samples()).collect();
+				let mut output = vec![T::default(); self.data.len()];
+				interleave(&planes, &mut output);
+				Cow::Owned(output)
+			}
+		}
+	}
+}
+
+/// A mutable buffer of PCM samples with a known channel count and layout.
+#[derive(Debug)]
+pub struct FrameMut<'a, T: 'a> {
+	data: &'a mut [T],
+	channels: usize,
+	layout: Layout,
+}
+
+impl<'a, T: Sample> FrameMut<'a, T> {
+	/// Wrap a buffer of samples.
+	///
+	/// Returns `BadArg` if the buffer does not hold the same number of
+	/// samples for every channel.
+	pub fn new(data: &'a mut [T], channels: usize, layout: Layout) -> Result<FrameMut<'a, T>> {
+		check_frame_len("FrameMut::new", data.len(), channels)?;
+		Ok(FrameMut { data, channels, layout })
+	}
+
+	/// Wrap a buffer of interleaved samples.
+	pub fn interleaved(data: &'a mut [T], channels: Channels) -> Result<FrameMut<'a, T>> {
+		FrameMut::new(data, channels as usize, Layout::Interleaved)
+	}
+
+	/// Wrap a buffer of planar samples.
+	pub fn planar(data: &'a mut [T], channels: Channels) -> Result<FrameMut<'a, T>> {
+		FrameMut::new(data, channels as usize, Layout::Planar)
+	}
+
+	/// Get the underlying samples.
+	#[inline]
+	pub fn data(&self) -> &[T] {
+		self.data
+	}
+
+	/// Mutably get the underlying samples.
+	#[inline]
+	pub fn data_mut(&mut self) -> &mut [T] {
+		self.data
+	}
+
+	/// Get the number of channels.
+	#[inline]
+	pub fn channels(&self) -> usize {
+		self.channels
+	}
+
+	/// Get the arrangement of the channels.
+	#[inline]
+	pub fn layout(&self) -> Layout {
+		self.layout
+	}
+
+	/// Get the capacity in samples *per channel*.
+	#[inline]
+	pub fn samples(&self) -> usize {
+		self.data.len() / self.channels
+	}
+
+	/// Deinterleave `samples` samples per channel from `input` into the
+	/// frame's planes.
+	fn deinterleave_from(&mut self, input: &[T], samples: usize) {
+		let capacity = self.samples();
+		let mut planes: Vec<&mut [T]> = self.data.chunks_mut(capacity).collect();
+		deinterleave(&input[..samples * self.channels], &mut planes);
+	}
+}
+
+impl Encoder {
+	/// Encode an Opus frame from a typed buffer.
+	///
+	/// Unlike `encode`, this checks that the frame's channel count matches the
+	/// encoder's and that its duration is one Opus can encode, returning
+	/// `BadArg` otherwise. Planar frames are interleaved first.
+	pub fn encode_frame<T: Sample>(
+		&mut self,
+		frame: &Frame<T>,
+		output: &mut [u8],
+	) -> Result<usize> {
+		check_channels("Encoder::encode_frame", self.channels as usize, frame.channels())?;
+		let sample_rate = self.get_sample_rate()?;
+		if !is_frame_size(frame.samples(), sample_rate) {
+			return Err(Error::new(
+				"Encoder::encode_frame",
+				ErrorKind::InvalidFrameSize { samples: frame.samples(), sample_rate },
+			));
+		}
+		T::encode(self, &frame.interleaved_data(), output)
+	}
+
+	/// Encode an Opus frame from one buffer per channel.
+	///
+	/// Every channel buffer must be the same length.
+	pub fn encode_planar<T: Sample>(&mut self, input: &[&[T]], output: &mut [u8]) -> Result<usize> {
+		let samples = check_planes("Encoder::encode_planar", input, self.channels as usize)?;
+		let mut interleaved = vec![T::default(); samples * input.len()];
+		interleave(input, &mut interleaved);
+		T::encode(self, &interleaved, output)
+	}
+}
+
+impl Decoder {
+	/// Decode an Opus packet into a typed buffer.
+	///
+	/// To represent packet loss, pass an empty slice `&[]`.
+	///
+	/// Unlike `decode`, this checks that the frame's channel count matches the
+	/// decoder's, returning `BadArg` otherwise, and that the frame can hold
+	/// the whole packet, returning `BufferTooSmall` otherwise. Planar frames
+	/// are filled from the start of each channel's region.
+	///
+	/// The return value is the number of samples *per channel* decoded from
+	/// the packet.
+	pub fn decode_frame<T: Sample>(
+		&mut self,
+		input: &[u8],
+		frame: &mut FrameMut<T>,
+		fec: bool,
+	) -> Result<usize> {
+		check_channels("Decoder::decode_frame", self.channels as usize, frame.channels())?;
+		if !input.is_empty() {
+			let required = self.get_nb_samples(input)?;
+			if required > frame.samples() {
+				return Err(Error::new(
+					"Decoder::decode_frame",
+					ErrorKind::BufferTooSmall { required, actual: frame.samples() },
+				));
+			}
+		}
+		match frame.layout() {
+			Layout::Interleaved => T::decode(self, input, frame.data_mut(), fec),
+			Layout::Planar => {
+				let mut interleaved = vec![T::default(); frame.data().len()];
+				let samples = T::decode(self, input, &mut interleaved, fec)?;
+				frame.deinterleave_from(&interleaved, samples);
+				Ok(samples)
+			}
+		}
+	}
+
+	/// Decode an Opus packet into one buffer per channel.
+	///
+	/// Every channel buffer must be the same length. To represent packet
+	/// loss, pass an empty slice `&[]`.
+	///
+	/// The return value is the number of samples *per channel* decoded from
+	/// the packet, which are written to the start of each channel buffer.
+	pub fn decode_planar<T: Sample>(
+		&mut self,
+		input: &[u8],
+		output: &mut [&mut [T]],
+		fec: bool,
+	) -> Result<usize> {
+		let capacity = check_planes_mut("Decoder::decode_planar", output, self.channels as usize)?;
+		let mut interleaved = vec![T::default(); capacity * output.len()];
+		let samples = T::decode(self, input, &mut interleaved, fec)?;
+		deinterleave(&interleaved[..samples * output.len()], output);
+		Ok(samples)
+	}
+}
+
 // ============================================================================
 // Packet Analysis
 
@@ -819,7 +2423,7 @@ pub mod packet {
 	/// Get the bandwidth of an Opus packet.
 	pub fn get_bandwidth(packet: &[u8]) -> Result<Bandwidth> {
 		if packet.is_empty() {
-			return Err(Error::bad_arg("opus_packet_get_bandwidth"));
+			return Err(Error::new("opus_packet_get_bandwidth", ErrorKind::EmptyPacket));
 		}
 		let bandwidth = ffi!(opus_packet_get_bandwidth, packet.as_ptr());
 		Bandwidth::decode(bandwidth, "opus_packet_get_bandwidth")
@@ -828,13 +2432,13 @@ pub mod packet {
 	/// Get the number of channels from an Opus packet.
 	pub fn get_nb_channels(packet: &[u8]) -> Result<Channels> {
 		if packet.is_empty() {
-			return Err(Error::bad_arg("opus_packet_get_nb_channels"));
+			return Err(Error::new("opus_packet_get_nb_channels", ErrorKind::EmptyPacket));
 		}
 		let channels = ffi!(opus_packet_get_nb_channels, packet.as_ptr());
 		match channels {
 			1 => Ok(Channels::Mono),
 			2 => Ok(Channels::Stereo),
-			_ => Err(Error::bad_arg("opus_packet_get_nb_channels")),
+			_ => Err(Error::unexpected("opus_packet_get_nb_channels", channels)),
 		}
 	}
 
@@ -854,13 +2458,104 @@ pub mod packet {
 	/// Get the number of samples per frame from an Opus packet.
 	pub fn get_samples_per_frame(packet: &[u8], sample_rate: u32) -> Result<usize> {
 		if packet.is_empty() {
-			return Err(Error::bad_arg("opus_packet_get_samples_per_frame"));
+			return Err(Error::new("opus_packet_get_samples_per_frame", ErrorKind::EmptyPacket));
 		}
 		let samples =
 			ffi!(opus_packet_get_samples_per_frame, packet.as_ptr(), sample_rate as c_int);
 		Ok(samples as usize)
 	}
 
+	/// The coding mode of an Opus packet.
+	#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+	pub enum Mode {
+		/// Linear prediction only, for speech at lower bandwidths.
+		Silk,
+		/// Linear prediction for the low band and MDCT for the high band.
+		Hybrid,
+		/// MDCT only, for music and low delay.
+		Celt,
+	}
+
+	string_enum!(Mode, "mode", {
+		Silk => "silk";
+		Hybrid => "hybrid";
+		Celt => "celt";
+	});
+
+	/// The decoded TOC byte at the start of every Opus packet.
+	#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+	#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+	pub struct Toc {
+		/// The coding mode.
+		pub mode: Mode,
+		/// The coded bandwidth.
+		pub bandwidth: Bandwidth,
+		/// The duration of each frame in the packet.
+		pub frame_size: FrameSize,
+		/// Whether the packet is coded in mono or stereo.
+		pub channels: Channels,
+		/// The frame count code, from 0 to 3, describing how frames are
+		/// packed into the packet.
+		pub code: u8,
+	}
+
+	impl Toc {
+		/// Decode a TOC byte. Every value is valid.
+		pub fn from_byte(toc: u8) -> Toc {
+			use FrameSize::*;
+			let config = toc >> 3;
+			let (mode, bandwidth, frame_size) = match config {
+				0..=11 => {
+					let bandwidth = match config / 4 {
+						0 => Bandwidth::Narrowband,
+						1 => Bandwidth::Mediumband,
+						_ => Bandwidth::Wideband,
+					};
+					(Mode::Silk, bandwidth, [Ms10, Ms20, Ms40, Ms60][config as usize % 4])
+				}
+				12..=15 => {
+					let bandwidth =
+						if config < 14 { Bandwidth::Superwideband } else { Bandwidth::Fullband };
+					(Mode::Hybrid, bandwidth, [Ms10, Ms20][config as usize % 2])
+				}
+				_ => {
+					let bandwidth = match (config - 16) / 4 {
+						0 => Bandwidth::Narrowband,
+						1 => Bandwidth::Wideband,
+						2 => Bandwidth::Superwideband,
+						_ => Bandwidth::Fullband,
+					};
+					(Mode::Celt, bandwidth, [Ms2_5, Ms5, Ms10, Ms20][config as usize % 4])
+				}
+			};
+			Toc {
+				mode,
+				bandwidth,
+				frame_size,
+				channels: if toc & 0x4 != 0 { Channels::Stereo } else { Channels::Mono },
+				code: toc & 0x3,
+			}
+		}
+	}
+
+	/// Decode the TOC byte of an Opus packet.
+	///
+	/// For multistream packets, this describes the first stream.
+	pub fn get_toc(packet: &[u8]) -> Result<Toc> {
+		match packet.first() {
+			Some(&toc) => Ok(Toc::from_byte(toc)),
+			None => Err(Error::new("packet::get_toc", ErrorKind::EmptyPacket)),
+		}
+	}
+
+	/// Check whether a packet was coded during discontinuous transmission.
+	///
+	/// Packets of at most two bytes hold only frames of at most one byte,
+	/// which the decoder treats as silence to be filled with comfort noise.
+	pub fn is_dtx(packet: &[u8]) -> bool {
+		!packet.is_empty() && packet.len() <= 2
+	}
+
 	/// Parse an Opus packet into one or more frames.
 	pub fn parse(packet: &[u8]) -> Result<Packet<'_>> {
 		let mut toc: u8 = 0;
@@ -890,7 +2585,11 @@ pub mod packet {
 	}
 
 	/// A parsed Opus packet, retuned from `parse`.
+	///
+	/// With the `serde` feature, packets can be serialized for inspection, but
+	/// not deserialized, as the frames borrow from the original data.
 	#[derive(Debug)]
+	#[cfg_attr(feature = "serde", derive(Serialize))]
 	pub struct Packet<'a> {
 		/// The TOC byte of the packet.
 		pub toc: u8,
@@ -1096,6 +2795,7 @@ impl<'rp, 'buf> RepacketizerState<'rp, 'buf> {
 pub struct MSEncoder {
 	ptr: *mut ffi::OpusMSEncoder,
 	channels: c_int,
+	stats: Option<EncoderStats>,
 }
 
 impl Drop for MSEncoder {
@@ -1116,6 +2816,7 @@ impl MSEncoder {
 		mapping: &[u8],
 		application: Application,
 	) -> Result<MSEncoder> {
+		check_sample_rate("MSEncoder::new", sample_rate)?;
 		let mut error = 0;
 		let ptr = unsafe {
 			ffi::opus_multistream_encoder_create(
@@ -1131,7 +2832,7 @@ impl MSEncoder {
 		if error != ffi::OPUS_OK || ptr.is_null() {
 			Err(Error::from_code("opus_multistream_encoder_create", error))
 		} else {
-			Ok(MSEncoder { ptr, channels: len(mapping) })
+			Ok(MSEncoder { ptr, channels: len(mapping), stats: None })
 		}
 	}
 
@@ -1139,7 +2840,10 @@ impl MSEncoder {
 	// Doxygen comments.
 
 	/// Encode an Opus frame.
+	///
+	/// The input length must be a multiple of the channel count.
 	pub fn encode(&mut self, input: &[i16], output: &mut [u8]) -> Result<usize> {
+		check_frame_len("MSEncoder::encode", input.len(), self.channels as usize)?;
 		let len = ffi!(
 			opus_multistream_encode,
 			self.ptr,
@@ -1148,11 +2852,16 @@ impl MSEncoder {
 			output.as_mut_ptr(),
 			len(output)
 		);
-		Ok(len as usize)
+		let len = len as usize;
+		self.record_stats(&output[..len]);
+		Ok(len)
 	}
 
 	/// Encode an Opus frame from floating point input.
+	///
+	/// The input length must be a multiple of the channel count.
 	pub fn encode_float(&mut self, input: &[f32], output: &mut [u8]) -> Result<usize> {
+		check_frame_len("MSEncoder::encode_float", input.len(), self.channels as usize)?;
 		let len = ffi!(
 			opus_multistream_encode_float,
 			self.ptr,
@@ -1161,7 +2870,9 @@ impl MSEncoder {
 			output.as_mut_ptr(),
 			len(output)
 		);
-		Ok(len as usize)
+		let len = len as usize;
+		self.record_stats(&output[..len]);
+		Ok(len)
 	}
 
 	/// Encode an Opus frame to a new buffer.
@@ -1179,6 +2890,60 @@ impl MSEncoder {
 		output.truncate(result);
 		Ok(output)
 	}
+
+	/// Start collecting statistics about each packet produced.
+	///
+	/// Collection makes an extra CTL call per packet, and a packet is left out
+	/// if it fails; encoding itself never fails because of it. Multistream
+	/// encoders cannot report DTX state, so `in_dtx` is always false. If
+	/// statistics were already enabled, they are left unchanged.
+	pub fn enable_stats(&mut self) {
+		if self.stats.is_none() {
+			self.stats = Some(EncoderStats::default());
+		}
+	}
+
+	/// Stop collecting statistics and discard those collected so far.
+	pub fn disable_stats(&mut self) {
+		self.stats = None;
+	}
+
+	/// Get a snapshot of the statistics, if enabled.
+	pub fn stats(&self) -> Option<EncoderStats> {
+		self.stats
+	}
+
+	/// Clear the statistics collected so far, if enabled.
+	///
+	/// Statistics are not affected by `reset_state`.
+	pub fn reset_stats(&mut self) {
+		if let Some(ref mut stats) = self.stats {
+			*stats = EncoderStats::default();
+		}
+	}
+
+	fn record_stats(&mut self, packet: &[u8]) {
+		// libopus does not implement OPUS_GET_IN_DTX for multistream encoders
+		if self.stats.is_some() {
+			if let Ok(final_range) = self.get_final_range() {
+				if let Some(ref mut stats) = self.stats {
+					stats.record(packet, false, final_range);
+				}
+			}
+		}
+	}
+}
+
+impl MSEncoder {
+	/// Encode an Opus frame from one buffer per channel.
+	///
+	/// Every channel buffer must be the same length.
+	pub fn encode_planar<T: Sample>(&mut self, input: &[&[T]], output: &mut [u8]) -> Result<usize> {
+		let samples = check_planes("MSEncoder::encode_planar", input, self.channels as usize)?;
+		let mut interleaved = vec![T::default(); samples * input.len()];
+		interleave(input, &mut interleaved);
+		T::ms_encode(self, &interleaved, output)
+	}
 }
 
 generic_ctls!(MSEncoder, opus_multistream_encoder_ctl);
@@ -1191,6 +2956,7 @@ encoder_ctls!(MSEncoder, opus_multistream_encoder_ctl);
 pub struct MSDecoder {
 	ptr: *mut ffi::OpusMSDecoder,
 	channels: c_int,
+	stats: Option<DecoderStats>,
 }
 
 impl Drop for MSDecoder {
@@ -1210,6 +2976,7 @@ impl MSDecoder {
 		coupled_streams: u8,
 		mapping: &[u8],
 	) -> Result<MSDecoder> {
+		check_sample_rate("MSDecoder::new", sample_rate)?;
 		let mut error = 0;
 		let ptr = unsafe {
 			ffi::opus_multistream_decoder_create(
@@ -1224,14 +2991,22 @@ impl MSDecoder {
 		if error != ffi::OPUS_OK || ptr.is_null() {
 			Err(Error::from_code("opus_multistream_decoder_create", error))
 		} else {
-			Ok(MSDecoder { ptr, channels: len(mapping) })
+			Ok(MSDecoder { ptr, channels: len(mapping), stats: None })
 		}
 	}
 
 	/// Decode a multistream Opus packet.
 	///
-	/// To represent packet loss, pass an empty slice `&[]`.
+	/// To represent packet loss, pass an empty slice `&[]`. The output length
+	/// must be a multiple of the channel count.
 	pub fn decode(&mut self, input: &[u8], output: &mut [i16], fec: bool) -> Result<usize> {
+		check_frame_len("MSDecoder::decode", output.len(), self.channels as usize)?;
+		let result = self.decode_packet(input, output, fec);
+		self.record_stats(input, fec, &result);
+		result
+	}
+
+	fn decode_packet(&mut self, input: &[u8], output: &mut [i16], fec: bool) -> Result<usize> {
 		let ptr = match input.len() {
 			0 => std::ptr::null(),
 			_ => input.as_ptr(),
@@ -1249,7 +3024,21 @@ impl MSDecoder {
 	}
 
 	/// Decode a multistream Opus packet with floating point output.
+	///
+	/// The output length must be a multiple of the channel count.
 	pub fn decode_float(&mut self, input: &[u8], output: &mut [f32], fec: bool) -> Result<usize> {
+		check_frame_len("MSDecoder::decode_float", output.len(), self.channels as usize)?;
+		let result = self.decode_packet_float(input, output, fec);
+		self.record_stats(input, fec, &result);
+		result
+	}
+
+	fn decode_packet_float(
+		&mut self,
+		input: &[u8],
+		output: &mut [f32],
+		fec: bool,
+	) -> Result<usize> {
 		let ptr = match input.len() {
 			0 => std::ptr::null(),
 			_ => input.as_ptr(),
@@ -1265,6 +3054,66 @@ impl MSDecoder {
 		);
 		Ok(len as usize)
 	}
+
+	/// Start collecting statistics about each packet decoded.
+	///
+	/// If statistics were already enabled, they are left unchanged.
+	pub fn enable_stats(&mut self) {
+		if self.stats.is_none() {
+			self.stats = Some(DecoderStats::default());
+		}
+	}
+
+	/// Stop collecting statistics and discard those collected so far.
+	pub fn disable_stats(&mut self) {
+		self.stats = None;
+	}
+
+	/// Get a snapshot of the statistics, if enabled.
+	pub fn stats(&self) -> Option<DecoderStats> {
+		self.stats
+	}
+
+	/// Clear the statistics collected so far, if enabled.
+	///
+	/// Statistics are not affected by `reset_state`.
+	pub fn reset_stats(&mut self) {
+		if let Some(ref mut stats) = self.stats {
+			*stats = DecoderStats::default();
+		}
+	}
+
+	fn record_stats(&mut self, packet: &[u8], fec: bool, result: &Result<usize>) {
+		if self.stats.is_some() {
+			let rate = self.get_sample_rate().unwrap_or(0);
+			if let Some(ref mut stats) = self.stats {
+				stats.record(packet, fec, result, rate);
+			}
+		}
+	}
+}
+
+impl MSDecoder {
+	/// Decode a multistream Opus packet into one buffer per channel.
+	///
+	/// Every channel buffer must be the same length. To represent packet
+	/// loss, pass an empty slice `&[]`.
+	///
+	/// The return value is the number of samples *per channel* decoded from
+	/// the packet, which are written to the start of each channel buffer.
+	pub fn decode_planar<T: Sample>(
+		&mut self,
+		input: &[u8],
+		output: &mut [&mut [T]],
+		fec: bool,
+	) -> Result<usize> {
+		let capacity =
+			check_planes_mut("MSDecoder::decode_planar", output, self.channels as usize)?;
+		let mut interleaved = vec![T::default(); capacity * output.len()];
+		let samples = T::ms_decode(self, input, &mut interleaved, fec)?;
+		deinterleave(&interleaved[..samples * output.len()], output);
+		Ok(samples)
+	}
 }
 
 generic_ctls!(MSDecoder, opus_multistream_decoder_ctl);
@@ -1276,53 +3125,238 @@ decoder_ctls!(MSDecoder, opus_multistream_decoder_ctl);
 /// Opus error Result alias.
 pub type Result<T> = std::result::Result<T, Error>;
 
-/// An error generated by the Opus library.
+/// An error generated by the Opus library, or by checks in these bindings
+/// before calling into it.
 #[derive(Debug)]
 pub struct Error {
 	function: &'static str,
-	code: ErrorCode,
+	context: Option<&'static str>,
+	kind: ErrorKind,
+	source: Option<Box<dyn std::error::Error + Send + Sync>>,
+}
+
+/// The specific cause of an `Error`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+	/// libopus returned an error.
+	Opus {
+		/// The error code.
+		code: ErrorCode,
+		/// The raw value returned by libopus.
+		raw: i32,
+	},
+	/// An argument was invalid in a way not covered by another kind.
+	BadArg,
+	/// A buffer had the wrong number of channels for the codec.
+	ChannelMismatch {
+		/// The codec's channel count.
+		expected: usize,
+		/// The buffer's channel count.
+		actual: usize,
+	},
+	/// A buffer's length was not a multiple of its channel count.
+	UnevenLength {
+		/// The length of the buffer, in samples.
+		len: usize,
+		/// The number of channels.
+		channels: usize,
+	},
+	/// A buffer's length was not a frame duration Opus can encode.
+	InvalidFrameSize {
+		/// The number of samples per channel.
+		samples: usize,
+		/// The sample rate of the buffer.
+		sample_rate: u32,
+	},
+	/// A frame size was not a whole number of samples at a sample rate.
+	UnsupportedFrameSize {
+		/// The frame size.
+		frame_size: FrameSize,
+		/// The sample rate.
+		sample_rate: u32,
+	},
+	/// A buffer was too small for the data to be written into it.
+	BufferTooSmall {
+		/// The required number of samples per channel or bytes.
+		required: usize,
+		/// The number of samples per channel or bytes available.
+		actual: usize,
+	},
+	/// A sample rate was not supported by Opus.
+	UnsupportedSampleRate(u32),
+	/// An empty packet was passed to a function which needs its TOC byte.
+	EmptyPacket,
+	/// libopus returned a value these bindings do not recognize.
+	UnexpectedValue(i32),
+	/// An I/O error occurred; see `source()` for details.
+	Io,
+	/// A decoder's final range did not match the encoder's.
+	RangeMismatch {
+		/// The index of the packet, counting from zero.
+		packet: u64,
+		/// The encoder's final range.
+		expected: u32,
+		/// The decoder's final range.
+		actual: u32,
+	},
+	/// Container or header data was malformed or unsupported; see
+	/// `context()` for details.
+	Malformed,
 }
 
 impl Error {
+	fn new(what: &'static str, kind: ErrorKind) -> Error {
+		Error {
+			function: what,
+			context: None,
+			kind,
+			source: None,
+		}
+	}
+
 	fn bad_arg(what: &'static str) -> Error {
-		Error { function: what, code: ErrorCode::BadArg }
+		Error::new(what, ErrorKind::BadArg)
 	}
 
 	fn from_code(what: &'static str, code: c_int) -> Error {
-		Error {
-			function: what,
-			code: ErrorCode::from_int(code),
-		}
+		Error::new(what, ErrorKind::Opus { code: ErrorCode::from_int(code), raw: code })
+	}
+
+	fn unexpected(what: &'static str, value: c_int) -> Error {
+		Error::new(what, ErrorKind::UnexpectedValue(value))
+	}
+
+	fn with_context(mut self, context: &'static str) -> Error {
+		self.context = Some(context);
+		self
 	}
 
-	/// Get the name of the Opus function from which the error originated.
+	/// Get the name of the function from which the error originated.
 	#[inline]
 	pub fn function(&self) -> &'static str {
 		self.function
 	}
 
+	/// Get additional context for the error, such as the name of the CTL
+	/// request which failed.
+	#[inline]
+	pub fn context(&self) -> Option<&'static str> {
+		self.context
+	}
+
+	/// Get the specific cause of the error.
+	#[inline]
+	pub fn kind(&self) -> ErrorKind {
+		self.kind
+	}
+
 	/// Get a textual description of the error provided by Opus.
 	#[inline]
 	pub fn description(&self) -> &'static str {
-		self.code.description()
+		self.code().description()
 	}
 
 	/// Get the Opus error code of the error.
-	#[inline]
+	///
+	/// Errors detected by these bindings are mapped to the code Opus itself
+	/// would have returned, usually `BadArg`.
 	pub fn code(&self) -> ErrorCode {
-		self.code
+		match self.kind {
+			ErrorKind::Opus { code, .. } => code,
+			ErrorKind::BufferTooSmall { .. } => ErrorCode::BufferTooSmall,
+			ErrorKind::UnexpectedValue(_) | ErrorKind::Io => ErrorCode::Unknown,
+			ErrorKind::RangeMismatch { .. } => ErrorCode::InternalError,
+			ErrorKind::Malformed => ErrorCode::InvalidPacket,
+			_ => ErrorCode::BadArg,
+		}
+	}
+
+	/// Get the raw error code returned by libopus, if the error came from
+	/// libopus.
+	pub fn raw_code(&self) -> Option<i32> {
+		match self.kind {
+			ErrorKind::Opus { raw, .. } => Some(raw),
+			_ => None,
+		}
 	}
 }
 
 impl std::fmt::Display for Error {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-		write!(f, "{}: {}", self.function, self.description())
+		f.write_str(self.function)?;
+		if let Some(context) = self.context {
+			write!(f, "({})", context)?;
+		}
+		match self.kind {
+			ErrorKind::Opus { .. } | ErrorKind::BadArg => write!(f, ": {}", self.description()),
+			ErrorKind::ChannelMismatch { expected, actual } => {
+				write!(f, ": expected {} channels, got {}", expected, actual)
+			}
+			ErrorKind::UnevenLength { len, channels } => {
+				write!(f, ": {} samples do not divide evenly into {} channels", len, channels)
+			}
+			ErrorKind::InvalidFrameSize { samples, sample_rate } => write!(
+				f,
+				": {} samples per channel is not a valid frame duration at {} Hz",
+				samples, sample_rate
+			),
+			ErrorKind::UnsupportedFrameSize { frame_size, sample_rate } => {
+				write!(f, ": frame size {:?} is not supported at {} Hz", frame_size, sample_rate)
+			}
+			ErrorKind::BufferTooSmall { required, actual } => {
+				write!(f, ": buffer too small, {} required but {} available", required, actual)
+			}
+			ErrorKind::UnsupportedSampleRate(rate) => {
+				write!(f, ": unsupported sample rate {} Hz", rate)
+			}
+			ErrorKind::EmptyPacket => f.write_str(": empty packet"),
+			ErrorKind::UnexpectedValue(value) => {
+				write!(f, ": unexpected value {} from libopus", value)
+			}
+			ErrorKind::Io => match self.source {
+				Some(ref source) => write!(f, ": {}", source),
+				None => f.write_str(": I/O error"),
+			},
+			ErrorKind::RangeMismatch { packet, expected, actual } => write!(
+				f,
+				": final range mismatch in packet {}, encoder {:#010x} but decoder {:#010x}",
+				packet, expected, actual
+			),
+			ErrorKind::Malformed => f.write_str(": malformed data"),
+		}
 	}
 }
 
 impl std::error::Error for Error {
 	fn description(&self) -> &str {
-		self.code.description()
+		self.code().description()
+	}
+
+	fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+		match self.source {
+			Some(ref source) => Some(&**source),
+			None => None,
+		}
+	}
+}
+
+impl From<std::io::Error> for Error {
+	fn from(err: std::io::Error) -> Error {
+		Error {
+			function: "io",
+			context: None,
+			kind: ErrorKind::Io,
+			source: Some(Box::new(err)),
+		}
+	}
+}
+
+fn check_sample_rate(what: &'static str, sample_rate: u32) -> Result<()> {
+	if resample::SUPPORTED_RATES.contains(&sample_rate) {
+		Ok(())
+	} else {
+		Err(Error::new(what, ErrorKind::UnsupportedSampleRate(sample_rate)))
 	}
 }
 
@@ -0,0 +1,280 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Mixing for multi-party conferences.
+//!
+//! Each participant sends one stream and receives a mix of everyone else,
+//! known as a mix-minus. The mixer decodes each participant's packet for a
+//! frame, sums the sources once, and derives every participant's mix by
+//! subtracting their own contribution, so the cost grows linearly with the
+//! number of participants rather than quadratically.
+
+use super::{
+	packet, Channels, Decoder, Encoder, EncoderConfig, Error, FrameSize, Result, SoftClip,
+	MAX_PACKET_SIZE,
+};
+
+/// Identifies a participant of a `Mixer`.
+///
+/// Identifiers are never reused, even after a participant is removed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, PartialOrd, Ord)]
+pub struct ParticipantId(u64);
+
+/// What a participant sent for the current frame.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Input {
+	/// Nothing was sent, so the participant is left out of the mix.
+	Silent,
+	/// A DTX packet was sent, which is not decoded and is left out of the
+	/// mix.
+	Dtx,
+	/// The packet was lost and was concealed.
+	Lost,
+	/// A packet was decoded.
+	Decoded,
+}
+
+#[derive(Debug)]
+struct Participant {
+	id: ParticipantId,
+	decoder: Decoder,
+	encoder: Encoder,
+	soft_clip: SoftClip,
+	gain: f32,
+	input: Input,
+	/// The decoded audio for the current frame, already scaled by the gain.
+	pcm: Vec<f32>,
+	/// The encoded mix from the last call to `mix`.
+	packet: Vec<u8>,
+}
+
+impl Participant {
+	fn is_mixed(&self) -> bool {
+		match self.input {
+			Input::Lost | Input::Decoded => self.gain != 0.0,
+			Input::Silent | Input::Dtx => false,
+		}
+	}
+}
+
+/// Mixes the streams of a conference's participants.
+///
+/// For each frame, pass every packet received to `push`, call `mix`, and
+/// send each participant the packet from `packet`. Participants who send
+/// nothing for a frame, or send a DTX packet, are skipped without decoding;
+/// pass an empty packet to `push` to conceal a lost packet instead. Each
+/// mix is soft-clipped before encoding, so loud moments with several
+/// speakers distort gently rather than clipping.
+#[derive(Debug)]
+pub struct Mixer {
+	sample_rate: u32,
+	channels: Channels,
+	frame_len: usize,
+	participants: Vec<Participant>,
+	next_id: u64,
+	/// The sum of every mixed source.
+	total: Vec<f32>,
+	scratch: Vec<f32>,
+}
+
+impl Mixer {
+	/// Create a mixer working in frames of the given duration.
+	pub fn new(sample_rate: u32, channels: Channels, frame_size: FrameSize) -> Result<Mixer> {
+		let frame_len = match frame_size.samples(sample_rate) {
+			Some(len) => len,
+			None => return Err(Error::bad_arg("Mixer::new").with_context("frame_size")),
+		};
+		let len = frame_len * channels as usize;
+		Ok(Mixer {
+			sample_rate,
+			channels,
+			frame_len,
+			participants: Vec::new(),
+			next_id: 0,
+			total: vec![0.0; len],
+			scratch: vec![0.0; len],
+		})
+	}
+
+	/// Get the sample rate.
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	/// Get the number of channels of every stream.
+	pub fn channels(&self) -> Channels {
+		self.channels
+	}
+
+	/// Get the number of samples *per channel* in each frame.
+	pub fn frame_len(&self) -> usize {
+		self.frame_len
+	}
+
+	/// Add a participant, whose mix is encoded with the given settings.
+	pub fn add_participant(&mut self, config: &EncoderConfig) -> Result<ParticipantId> {
+		let id = ParticipantId(self.next_id);
+		let participant = Participant {
+			id,
+			decoder: Decoder::new(self.sample_rate, self.channels)?,
+			encoder: Encoder::from_config(self.sample_rate, self.channels, config)?,
+			soft_clip: SoftClip::new(self.channels),
+			gain: 1.0,
+			input: Input::Silent,
+			pcm: vec![0.0; self.total.len()],
+			packet: Vec::new(),
+		};
+		self.next_id += 1;
+		self.participants.push(participant);
+		Ok(id)
+	}
+
+	/// Remove a participant.
+	pub fn remove_participant(&mut self, id: ParticipantId) -> Result<()> {
+		let index = self.index("Mixer::remove_participant", id)?;
+		self.participants.remove(index);
+		Ok(())
+	}
+
+	/// Get the participants, in the order they were added.
+	pub fn participants(&self) -> Vec<ParticipantId> {
+		self.participants.iter().map(|p| p.id).collect()
+	}
+
+	/// Set the linear gain applied to a participant's audio before mixing.
+	pub fn set_gain(&mut self, id: ParticipantId, gain: f32) -> Result<()> {
+		self.get_mut("Mixer::set_gain", id)?.gain = gain;
+		Ok(())
+	}
+
+	/// Get the gain applied to a participant's audio.
+	pub fn gain(&self, id: ParticipantId) -> Result<f32> {
+		Ok(self.get("Mixer::gain", id)?.gain)
+	}
+
+	/// Get a participant's decoder, such as to enable statistics.
+	pub fn decoder(&mut self, id: ParticipantId) -> Result<&mut Decoder> {
+		Ok(&mut self.get_mut("Mixer::decoder", id)?.decoder)
+	}
+
+	/// Get the encoder for a participant's mix, such as to change its
+	/// bitrate.
+	pub fn encoder(&mut self, id: ParticipantId) -> Result<&mut Encoder> {
+		Ok(&mut self.get_mut("Mixer::encoder", id)?.encoder)
+	}
+
+	/// Pass the packet a participant sent for the current frame.
+	///
+	/// An empty packet is concealed as lost, and a DTX packet is not decoded.
+	/// Packets must last exactly one frame. Pushing a second packet for the
+	/// same frame replaces the first.
+	pub fn push(&mut self, id: ParticipantId, packet: &[u8]) -> Result<Input> {
+		let frame_len = self.frame_len;
+		let participant = self.get_mut("Mixer::push", id)?;
+		participant.input = Input::Silent;
+		if packet::is_dtx(packet) {
+			participant.input = Input::Dtx;
+			return Ok(Input::Dtx);
+		}
+		if !packet.is_empty() && participant.decoder.get_nb_samples(packet)? != frame_len {
+			return Err(Error::bad_arg("Mixer::push").with_context("packet duration"));
+		}
+		let input = if packet.is_empty() { Input::Lost } else { Input::Decoded };
+		participant.decoder.decode_float(packet, &mut participant.pcm, false)?;
+		if participant.gain != 1.0 {
+			let gain = participant.gain;
+			participant.pcm.iter_mut().for_each(|sample| *sample *= gain);
+		}
+		participant.input = input;
+		Ok(input)
+	}
+
+	/// Get what a participant sent for the current frame.
+	pub fn input(&self, id: ParticipantId) -> Result<Input> {
+		Ok(self.get("Mixer::input", id)?.input)
+	}
+
+	/// Mix the current frame and encode each participant's mix-minus, then
+	/// start the next frame.
+	///
+	/// Returns the number of sources which were mixed. If encoding fails for
+	/// a participant, their packet is left empty, everyone else's is still
+	/// encoded, and the first error is returned; the next frame starts
+	/// either way.
+	///
+	/// Participants who are not mixed all hear the same total, but it is
+	/// still clipped and encoded for each of them: every receiver's decoder
+	/// follows the state of one encoder, so a shared packet would only suit
+	/// listeners who had heard exactly the same stream from the start.
+	pub fn mix(&mut self) -> Result<usize> {
+		for sample in self.total.iter_mut() {
+			*sample = 0.0;
+		}
+		let mut mixed = 0;
+		for participant in self.participants.iter().filter(|p| p.is_mixed()) {
+			for (total, &sample) in self.total.iter_mut().zip(&participant.pcm) {
+				*total += sample;
+			}
+			mixed += 1;
+		}
+
+		let mut result = Ok(mixed);
+		for participant in &mut self.participants {
+			let mix = &mut self.scratch;
+			if participant.is_mixed() {
+				for ((mix, &total), &own) in mix.iter_mut().zip(&self.total).zip(&participant.pcm) {
+					*mix = total - own;
+				}
+			} else {
+				mix.copy_from_slice(&self.total);
+			}
+			participant.soft_clip.apply(mix);
+			participant.packet.resize(MAX_PACKET_SIZE, 0);
+			match participant.encoder.encode_float(mix, &mut participant.packet) {
+				Ok(len) => participant.packet.truncate(len),
+				Err(err) => {
+					participant.packet.clear();
+					if result.is_ok() {
+						result = Err(err);
+					}
+				}
+			}
+			participant.input = Input::Silent;
+		}
+		result
+	}
+
+	/// Get the sum of every source mixed in the last call to `mix`, before
+	/// soft clipping, such as for recording the conference.
+	pub fn total(&self) -> &[f32] {
+		&self.total
+	}
+
+	/// Get the encoded mix-minus for a participant from the last call to
+	/// `mix`.
+	pub fn packet(&self, id: ParticipantId) -> Result<&[u8]> {
+		Ok(&self.get("Mixer::packet", id)?.packet)
+	}
+
+	fn index(&self, what: &'static str, id: ParticipantId) -> Result<usize> {
+		match self.participants.iter().position(|p| p.id == id) {
+			Some(index) => Ok(index),
+			None => Err(Error::bad_arg(what).with_context("unknown participant")),
+		}
+	}
+
+	fn get(&self, what: &'static str, id: ParticipantId) -> Result<&Participant> {
+		let index = self.index(what, id)?;
+		Ok(&self.participants[index])
+	}
+
+	fn get_mut(&mut self, what: &'static str, id: ParticipantId) -> Result<&mut Participant> {
+		let index = self.index(what, id)?;
+		Ok(&mut self.participants[index])
+	}
+}
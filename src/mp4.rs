@@ -0,0 +1,1013 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Audio-only MP4 files containing a single Opus track.
+//!
+//! This follows the "Encapsulation of Opus in ISO Base Media File Format"
+//! specification. The track uses an `Opus` sample entry holding a `dOps` box,
+//! which carries the same fields as an `OpusHead` in big-endian order. The
+//! pre-skip is given by an edit list which starts the presentation after it,
+//! and also trims padding from the end, and the pre-roll needed after
+//! seeking is given as a `roll` sample group.
+//!
+//! Only progressive files are supported, not fragmented ones.
+
+use super::opus_head::{OpusHead, MAGIC};
+use super::{packet, Error, ErrorKind, MSDecoder, Result, Sample};
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// The media timescale, so that timestamps are in samples at 48 kHz.
+const TIMESCALE: u32 = 48000;
+/// The pre-roll RFC 7845 recommends after seeking, in samples at 48 kHz.
+const PRE_ROLL: u64 = 3840;
+/// The duration after which a new chunk is started, in samples at 48 kHz.
+const CHUNK_SAMPLES: u64 = 48000;
+/// The size of the `mdat` header, which always uses a 64-bit size.
+const MDAT_HEADER: u64 = 16;
+/// The identity transformation matrix for `mvhd` and `tkhd`.
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+/// Writes an audio-only MP4 file containing one Opus track.
+///
+/// Packets are written to the `mdat` box as they arrive, and the `moov` box
+/// describing them is written by `finish`, after which the size of the
+/// `mdat` box is filled in.
+#[derive(Debug)]
+pub struct Mp4Writer<W: Write + Seek> {
+	writer: W,
+	head: OpusHead,
+	/// The absolute position of the `mdat` box.
+	mdat_start: u64,
+	/// The absolute position of the next byte.
+	position: u64,
+	sizes: Vec<u32>,
+	durations: Vec<u32>,
+	/// The absolute offset and sample count of each chunk.
+	chunks: Vec<(u64, u32)>,
+	chunk_samples: u64,
+	samples: u64,
+}
+
+impl<W: Write + Seek> Mp4Writer<W> {
+	/// Start an MP4 file with a track described by the given header.
+	pub fn new(mut writer: W, head: &OpusHead) -> Result<Mp4Writer<W>> {
+		let mut header = Vec::new();
+		boxed(&mut header, b"ftyp", |buf| {
+			buf.extend_from_slice(b"isom");
+			buf.extend_from_slice(&0x200u32.to_be_bytes());
+			for brand in &[b"isom", b"iso2", b"mp41", b"Opus"] {
+				buf.extend_from_slice(*brand);
+			}
+		});
+		let mdat_start = writer.stream_position()? + header.len() as u64;
+		// a 64-bit size, filled in by `finish`
+		header.extend_from_slice(&1u32.to_be_bytes());
+		header.extend_from_slice(b"mdat");
+		header.extend_from_slice(&0u64.to_be_bytes());
+		writer.write_all(&header)?;
+
+		Ok(Mp4Writer {
+			writer,
+			head: head.clone(),
+			mdat_start,
+			position: mdat_start + MDAT_HEADER,
+			sizes: Vec::new(),
+			durations: Vec::new(),
+			chunks: Vec::new(),
+			chunk_samples: 0,
+			samples: 0,
+		})
+	}
+
+	/// Write an encoded packet.
+	///
+	/// The packet's duration is read from its TOC byte.
+	pub fn write_packet(&mut self, packet: &[u8]) -> Result<()> {
+		let duration = packet::get_nb_samples(packet, 48000)? as u64;
+		if packet.len() > u32::MAX as usize {
+			return Err(Error::bad_arg("Mp4Writer::write_packet").with_context("packet too long"));
+		}
+		self.writer.write_all(packet)?;
+
+		if self.chunks.is_empty() || self.chunk_samples >= CHUNK_SAMPLES {
+			self.chunks.push((self.position, 0));
+			self.chunk_samples = 0;
+		}
+		if let Some(chunk) = self.chunks.last_mut() {
+			chunk.1 += 1;
+		}
+		self.chunk_samples += duration;
+		self.position += packet.len() as u64;
+		self.sizes.push(packet.len() as u32);
+		self.durations.push(duration as u32);
+		self.samples += duration;
+		Ok(())
+	}
+
+	/// Get the total duration of the packets written, in samples at 48 kHz,
+	/// including the pre-skip.
+	pub fn samples(&self) -> u64 {
+		self.samples
+	}
+
+	/// Finish the file, keeping all of the decoded audio after the pre-skip.
+	pub fn finish(self) -> Result<W> {
+		let length = self.samples.saturating_sub(self.head.pre_skip as u64);
+		self.finish_with_length(length)
+	}
+
+	/// Finish the file, trimming the decoded audio after the pre-skip to
+	/// `length` samples at 48 kHz.
+	///
+	/// This is normally the length of the encoder's input, so the padding
+	/// added to fill the last frame is discarded on playback.
+	pub fn finish_with_length(mut self, length: u64) -> Result<W> {
+		let pre_skip = self.head.pre_skip as u64;
+		let length = length.min(self.samples.saturating_sub(pre_skip));
+
+		let mut moov = Vec::new();
+		boxed(&mut moov, b"moov", |buf| {
+			full_box(buf, b"mvhd", wide(length), 0, |buf| {
+				times(buf, length);
+				buf.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+				buf.extend_from_slice(&0x0100u16.to_be_bytes());
+				buf.extend_from_slice(&[0; 10]);
+				for value in &MATRIX {
+					buf.extend_from_slice(&value.to_be_bytes());
+				}
+				buf.extend_from_slice(&[0; 24]);
+				// next track ID
+				buf.extend_from_slice(&2u32.to_be_bytes());
+			});
+			boxed(buf, b"trak", |buf| self.write_trak(buf, length));
+		});
+		self.writer.write_all(&moov)?;
+
+		let mdat_size = self.position - self.mdat_start;
+		self.writer.seek(SeekFrom::Start(self.mdat_start + 8))?;
+		self.writer.write_all(&mdat_size.to_be_bytes())?;
+		self.writer.seek(SeekFrom::Start(self.position + moov.len() as u64))?;
+		self.writer.flush()?;
+		Ok(self.writer)
+	}
+
+	fn write_trak(&self, buf: &mut Vec<u8>, length: u64) {
+		let pre_skip = self.head.pre_skip as u64;
+		// enabled and in the movie
+		full_box(buf, b"tkhd", wide(length), 3, |buf| {
+			// the track ID takes the place of the timescale, and a reserved
+			// field comes before the duration
+			if wide(length) == 1 {
+				buf.extend_from_slice(&[0; 16]);
+				buf.extend_from_slice(&1u32.to_be_bytes());
+				buf.extend_from_slice(&[0; 4]);
+				buf.extend_from_slice(&length.to_be_bytes());
+			} else {
+				buf.extend_from_slice(&[0; 8]);
+				buf.extend_from_slice(&1u32.to_be_bytes());
+				buf.extend_from_slice(&[0; 4]);
+				buf.extend_from_slice(&(length as u32).to_be_bytes());
+			}
+			buf.extend_from_slice(&[0; 8]);
+			// layer, then the alternate group shared by audio tracks
+			buf.extend_from_slice(&[0, 0, 0, 1]);
+			buf.extend_from_slice(&0x0100u16.to_be_bytes());
+			buf.extend_from_slice(&[0; 2]);
+			for value in &MATRIX {
+				buf.extend_from_slice(&value.to_be_bytes());
+			}
+			// width and height
+			buf.extend_from_slice(&[0; 8]);
+		});
+		boxed(buf, b"edts", |buf| {
+			let wide = wide(length.max(pre_skip));
+			full_box(buf, b"elst", wide, 0, |buf| {
+				buf.extend_from_slice(&1u32.to_be_bytes());
+				if wide == 1 {
+					buf.extend_from_slice(&length.to_be_bytes());
+					buf.extend_from_slice(&pre_skip.to_be_bytes());
+				} else {
+					buf.extend_from_slice(&(length as u32).to_be_bytes());
+					buf.extend_from_slice(&(pre_skip as u32).to_be_bytes());
+				}
+				// media rate of 1.0
+				buf.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+			});
+		});
+		boxed(buf, b"mdia", |buf| {
+			full_box(buf, b"mdhd", wide(self.samples), 0, |buf| {
+				times(buf, self.samples);
+				// "und"
+				buf.extend_from_slice(&0x55C4u16.to_be_bytes());
+				buf.extend_from_slice(&[0; 2]);
+			});
+			full_box(buf, b"hdlr", 0, 0, |buf| {
+				buf.extend_from_slice(&[0; 4]);
+				buf.extend_from_slice(b"soun");
+				buf.extend_from_slice(&[0; 12]);
+				buf.extend_from_slice(b"SoundHandler\0");
+			});
+			boxed(buf, b"minf", |buf| {
+				full_box(buf, b"smhd", 0, 0, |buf| buf.extend_from_slice(&[0; 4]));
+				boxed(buf, b"dinf", |buf| {
+					full_box(buf, b"dref", 0, 0, |buf| {
+						buf.extend_from_slice(&1u32.to_be_bytes());
+						// the media is in this file
+						full_box(buf, b"url ", 0, 1, |_| {});
+					});
+				});
+				boxed(buf, b"stbl", |buf| self.write_stbl(buf));
+			});
+		});
+	}
+
+	fn write_stbl(&self, buf: &mut Vec<u8>) {
+		full_box(buf, b"stsd", 0, 0, |buf| {
+			buf.extend_from_slice(&1u32.to_be_bytes());
+			boxed(buf, b"Opus", |buf| {
+				buf.extend_from_slice(&[0; 6]);
+				// data reference index
+				buf.extend_from_slice(&1u16.to_be_bytes());
+				buf.extend_from_slice(&[0; 8]);
+				buf.extend_from_slice(&(self.head.channels as u16).to_be_bytes());
+				buf.extend_from_slice(&16u16.to_be_bytes());
+				buf.extend_from_slice(&[0; 4]);
+				buf.extend_from_slice(&(TIMESCALE << 16).to_be_bytes());
+				boxed(buf, b"dOps", |buf| buf.extend_from_slice(&to_dops(&self.head)));
+			});
+		});
+
+		let mut runs: Vec<(u32, u32)> = Vec::new();
+		for &duration in &self.durations {
+			match runs.last_mut() {
+				Some(run) if run.1 == duration => run.0 += 1,
+				_ => runs.push((1, duration)),
+			}
+		}
+		full_box(buf, b"stts", 0, 0, |buf| {
+			buf.extend_from_slice(&(runs.len() as u32).to_be_bytes());
+			for &(count, duration) in &runs {
+				buf.extend_from_slice(&count.to_be_bytes());
+				buf.extend_from_slice(&duration.to_be_bytes());
+			}
+		});
+
+		full_box(buf, b"stsc", 0, 0, |buf| {
+			let mut entries = Vec::new();
+			for (index, &(_, count)) in self.chunks.iter().enumerate() {
+				if entries.last().is_none_or(|&(_, last)| last != count) {
+					entries.push((index as u32 + 1, count));
+				}
+			}
+			buf.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+			for &(first, count) in &entries {
+				buf.extend_from_slice(&first.to_be_bytes());
+				buf.extend_from_slice(&count.to_be_bytes());
+				buf.extend_from_slice(&1u32.to_be_bytes());
+			}
+		});
+
+		full_box(buf, b"stsz", 0, 0, |buf| {
+			buf.extend_from_slice(&0u32.to_be_bytes());
+			buf.extend_from_slice(&(self.sizes.len() as u32).to_be_bytes());
+			for &size in &self.sizes {
+				buf.extend_from_slice(&size.to_be_bytes());
+			}
+		});
+
+		let large = self.chunks.last().is_some_and(|&(offset, _)| offset > u32::MAX as u64);
+		full_box(buf, if large { b"co64" } else { b"stco" }, 0, 0, |buf| {
+			buf.extend_from_slice(&(self.chunks.len() as u32).to_be_bytes());
+			for &(offset, _) in &self.chunks {
+				if large {
+					buf.extend_from_slice(&offset.to_be_bytes());
+				} else {
+					buf.extend_from_slice(&(offset as u32).to_be_bytes());
+				}
+			}
+		});
+
+		// every packet needs the same pre-roll, given in packets
+		if let Some(&shortest) = self.durations.iter().min() {
+			let roll = PRE_ROLL.div_ceil(shortest.max(1) as u64) as i16;
+			full_box(buf, b"sgpd", 1, 0, |buf| {
+				buf.extend_from_slice(b"roll");
+				buf.extend_from_slice(&2u32.to_be_bytes());
+				buf.extend_from_slice(&1u32.to_be_bytes());
+				buf.extend_from_slice(&(-roll).to_be_bytes());
+			});
+			full_box(buf, b"sbgp", 0, 0, |buf| {
+				buf.extend_from_slice(b"roll");
+				buf.extend_from_slice(&1u32.to_be_bytes());
+				buf.extend_from_slice(&(self.sizes.len() as u32).to_be_bytes());
+				buf.extend_from_slice(&1u32.to_be_bytes());
+			});
+		}
+	}
+}
+
+/// A packet read from an MP4 file.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+pub struct Mp4Packet {
+	/// The packet data.
+	pub data: Vec<u8>,
+	/// The packet's timestamp in samples at 48 kHz, including the pre-skip.
+	pub timestamp: u64,
+	/// The packet's duration in samples at 48 kHz.
+	pub duration: u64,
+}
+
+/// A sample from the sample table.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+	offset: u64,
+	size: u32,
+	timestamp: u64,
+	duration: u64,
+}
+
+/// Reads the Opus track of an MP4 file.
+///
+/// The first sound track with an `Opus` sample entry is used. The whole
+/// `moov` box is read up front, wherever it is in the file, so the sample
+/// table gives the position of every packet.
+#[derive(Debug)]
+pub struct Mp4Reader<R: Read + Seek> {
+	reader: R,
+	head: OpusHead,
+	pre_skip: u64,
+	length: u64,
+	roll: u64,
+	entries: Vec<Entry>,
+	next: usize,
+}
+
+impl<R: Read + Seek> Mp4Reader<R> {
+	/// Read the sample table of an MP4 file.
+	pub fn new(mut reader: R) -> Result<Mp4Reader<R>> {
+		let mut moov = None;
+		// the bytes of media data, which limits how many samples there can be
+		let mut media = 0;
+		loop {
+			let start = reader.stream_position()?;
+			let (kind, size, header) = match read_box_header(&mut reader)? {
+				Some(header) => header,
+				None => break,
+			};
+			match &kind {
+				b"moov" if size == u64::MAX => {
+					let mut body = Vec::new();
+					reader.read_to_end(&mut body)?;
+					moov = Some(body);
+					break;
+				}
+				b"moov" => {
+					let mut body = Vec::new();
+					(&mut reader).take(size - header).read_to_end(&mut body)?;
+					if body.len() as u64 != size - header {
+						return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+					}
+					moov = Some(body);
+				}
+				b"moof" => return Err(malformed("fragmented files are not supported")),
+				b"mdat" if size == u64::MAX => {
+					let end = reader.seek(SeekFrom::End(0))?;
+					media += end.saturating_sub(start + header);
+					break;
+				}
+				b"mdat" => {
+					media += size - header;
+					reader.seek(SeekFrom::Start(start + size))?;
+				}
+				_ if size == u64::MAX => break,
+				_ => {
+					reader.seek(SeekFrom::Start(start + size))?;
+				}
+			}
+		}
+		let moov = match moov {
+			Some(moov) => moov,
+			None => return Err(malformed("no movie box")),
+		};
+
+		let mut movie_timescale = TIMESCALE;
+		let mut track = None;
+		for (kind, data) in boxes(&moov)? {
+			match &kind {
+				b"mvhd" => movie_timescale = parse_mvhd(data)?,
+				b"trak" if track.is_none() => track = parse_trak(data, media)?,
+				_ => {}
+			}
+		}
+		let track = match track {
+			Some(track) => track,
+			None => return Err(malformed("no Opus track")),
+		};
+
+		let to_48k = |value: u64, timescale: u32| {
+			let value = value as u128 * 48000 / timescale.max(1) as u128;
+			u64::try_from(value).map_err(|_| malformed("timestamp overflow"))
+		};
+		let total: u64 = track.entries.iter().map(|entry| entry.duration).sum();
+		let total = to_48k(total, track.timescale)?;
+		let (pre_skip, length) = match track.edit {
+			Some((duration, media_time)) => {
+				(to_48k(media_time, track.timescale)?, to_48k(duration, movie_timescale)?)
+			}
+			None => {
+				let pre_skip = track.head.pre_skip as u64;
+				(pre_skip, total.saturating_sub(pre_skip))
+			}
+		};
+		let entries = track
+			.entries
+			.iter()
+			.map(|entry| {
+				Ok(Entry {
+					timestamp: to_48k(entry.timestamp, track.timescale)?,
+					duration: to_48k(entry.duration, track.timescale)?,
+					..*entry
+				})
+			})
+			.collect::<Result<_>>()?;
+		Ok(Mp4Reader {
+			reader,
+			head: track.head,
+			pre_skip,
+			length,
+			roll: track.roll,
+			entries,
+			next: 0,
+		})
+	}
+
+	/// Get the track's identification header, from its `dOps` box.
+	pub fn head(&self) -> &OpusHead {
+		&self.head
+	}
+
+	/// Get the samples at 48 kHz to discard from the start of the decoded
+	/// audio, from the edit list if there is one.
+	pub fn pre_skip(&self) -> u64 {
+		self.pre_skip
+	}
+
+	/// Get the length of the presented audio in samples at 48 kHz, after the
+	/// pre-skip and any trimming at the end.
+	pub fn length(&self) -> u64 {
+		self.length
+	}
+
+	/// Get the number of packets in the track.
+	pub fn packets(&self) -> usize {
+		self.entries.len()
+	}
+
+	/// Get the number of packets which must be decoded before a packet for
+	/// the output to be correct, from the `roll` sample group.
+	pub fn roll_distance(&self) -> u64 {
+		self.roll
+	}
+
+	/// Read the next packet, or `None` at the end of the track.
+	pub fn read_packet(&mut self) -> Result<Option<Mp4Packet>> {
+		let entry = match self.entries.get(self.next) {
+			Some(&entry) => entry,
+			None => return Ok(None),
+		};
+		self.reader.seek(SeekFrom::Start(entry.offset))?;
+		let mut data = vec![0; entry.size as usize];
+		self.reader.read_exact(&mut data)?;
+		self.next += 1;
+		Ok(Some(Mp4Packet {
+			data,
+			timestamp: entry.timestamp,
+			duration: entry.duration,
+		}))
+	}
+
+	/// Seek so that the next packet read is the first which ends after the
+	/// given timestamp, in samples at 48 kHz including the pre-skip.
+	pub fn seek(&mut self, timestamp: u64) {
+		self.next =
+			self.entries.partition_point(|entry| entry.timestamp + entry.duration <= timestamp);
+	}
+
+	/// Unwrap the underlying stream.
+	pub fn into_inner(self) -> R {
+		self.reader
+	}
+}
+
+/// Decodes the Opus track of an MP4 file to PCM.
+///
+/// The pre-skip is discarded from the start of the audio, and the output
+/// ends where the edit list does, so it has exactly the length of the
+/// original input.
+#[derive(Debug)]
+pub struct Mp4Decoder<R: Read + Seek> {
+	reader: Mp4Reader<R>,
+	decoder: MSDecoder,
+	sample_rate: u32,
+	/// Samples left to discard from the start of the next packets.
+	skip: u64,
+	/// Samples left to output before the end of the presentation.
+	remaining: u64,
+	/// After seeking, the timestamp at 48 kHz which output should start at.
+	seek_target: Option<u64>,
+}
+
+impl<R: Read + Seek> Mp4Decoder<R> {
+	/// Create a decoder producing output at the given sample rate.
+	///
+	/// A multistream decoder is always used, built from the track's
+	/// `OpusHead`, so every channel mapping family is supported.
+	pub fn new(reader: Mp4Reader<R>, sample_rate: u32) -> Result<Mp4Decoder<R>> {
+		let decoder = reader.head().decoder(sample_rate)?;
+		let skip = from_48k(reader.pre_skip(), sample_rate);
+		let remaining = from_48k(reader.length(), sample_rate);
+		Ok(Mp4Decoder {
+			reader,
+			decoder,
+			sample_rate,
+			skip,
+			remaining,
+			seek_target: None,
+		})
+	}
+
+	/// Get the number of output channels.
+	pub fn channels(&self) -> usize {
+		self.decoder.channels as usize
+	}
+
+	/// Get the output sample rate.
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	/// Get a reference to the packet reader.
+	pub fn reader(&self) -> &Mp4Reader<R> {
+		&self.reader
+	}
+
+	/// Get a mutable reference to the underlying decoder, such as to enable
+	/// statistics.
+	pub fn decoder(&mut self) -> &mut MSDecoder {
+		&mut self.decoder
+	}
+
+	/// Decode the next packet, appending its interleaved output.
+	///
+	/// Returns the number of samples *per channel* appended, which may be
+	/// zero while the pre-skip is discarded, or `None` at the end of the
+	/// presentation.
+	pub fn decode<T: Sample>(&mut self, output: &mut Vec<T>) -> Result<Option<usize>> {
+		if self.remaining == 0 {
+			return Ok(None);
+		}
+		let packet = match self.reader.read_packet()? {
+			Some(packet) => packet,
+			None => return Ok(None),
+		};
+		if let Some(target) = self.seek_target.take() {
+			self.skip = from_48k(target.saturating_sub(packet.timestamp), self.sample_rate);
+		}
+
+		let channels = self.channels();
+		let start = output.len();
+		// room for 120 ms
+		output.resize(start + self.sample_rate as usize * 120 / 1000 * channels, T::default());
+		let samples =
+			match T::ms_decode(&mut self.decoder, &packet.data, &mut output[start..], false) {
+				Ok(samples) => samples,
+				Err(err) => {
+					output.truncate(start);
+					return Err(err);
+				}
+			};
+		let skip = self.skip.min(samples as u64) as usize;
+		self.skip -= skip as u64;
+		let kept = ((samples - skip) as u64).min(self.remaining) as usize;
+		self.remaining -= kept as u64;
+		output.truncate(start + (skip + kept) * channels);
+		output.drain(start..start + skip * channels);
+		Ok(Some(kept))
+	}
+
+	/// Decode the rest of the file.
+	pub fn decode_all<T: Sample>(&mut self) -> Result<Vec<T>> {
+		let mut output = Vec::new();
+		while self.decode(&mut output)?.is_some() {}
+		Ok(output)
+	}
+
+	/// Seek so that the next output starts at the given sample *per channel*,
+	/// counting from the start of the presented audio.
+	///
+	/// Decoding resumes far enough before the target for the decoder to
+	/// converge, as given by the `roll` sample group or 80 ms, whichever is
+	/// longer.
+	pub fn seek(&mut self, sample: u64) -> Result<()> {
+		let presented = sample.min(from_48k(self.reader.length(), self.sample_rate));
+		let target = (presented as u128 * 48000 / self.sample_rate as u128) as u64;
+		let target = target.saturating_add(self.reader.pre_skip());
+		self.reader.seek(target.saturating_sub(PRE_ROLL));
+		let roll = self.reader.roll_distance() as usize;
+		let by_roll =
+			self.reader.entries.partition_point(|entry| entry.timestamp + entry.duration <= target);
+		self.reader.next = self.reader.next.min(by_roll.saturating_sub(roll));
+		self.decoder.reset_state()?;
+		self.seek_target = Some(target);
+		self.remaining = from_48k(self.reader.length(), self.sample_rate) - presented;
+		Ok(())
+	}
+
+	/// Unwrap the packet reader.
+	pub fn into_reader(self) -> Mp4Reader<R> {
+		self.reader
+	}
+}
+
+/// The parts of a track needed to read it.
+struct Track {
+	head: OpusHead,
+	timescale: u32,
+	/// The segment duration in the movie timescale and the media time of
+	/// the first edit.
+	edit: Option<(u64, u64)>,
+	roll: u64,
+	entries: Vec<Entry>,
+}
+
+fn parse_mvhd(data: &[u8]) -> Result<u32> {
+	let (version, body) = full_box_body(data)?;
+	let at = if version == 1 { 16 } else { 8 };
+	read_u32(body, at)
+}
+
+/// Parse a track, returning it if it is an Opus sound track.
+///
+/// Every sample is at least one byte, so there can be no more than `media`
+/// of them.
+fn parse_trak(trak: &[u8], media: u64) -> Result<Option<Track>> {
+	let mut edit = None;
+	let mut mdia = None;
+	for (kind, data) in boxes(trak)? {
+		match &kind {
+			b"edts" => {
+				for (kind, data) in boxes(data)? {
+					if &kind == b"elst" {
+						edit = parse_elst(data)?;
+					}
+				}
+			}
+			b"mdia" => mdia = Some(data),
+			_ => {}
+		}
+	}
+	let mdia = match mdia {
+		Some(mdia) => mdia,
+		None => return Ok(None),
+	};
+
+	let mut timescale = None;
+	let mut sound = false;
+	let mut stbl = None;
+	for (kind, data) in boxes(mdia)? {
+		match &kind {
+			b"mdhd" => {
+				let (version, body) = full_box_body(data)?;
+				timescale = Some(read_u32(body, if version == 1 { 16 } else { 8 })?);
+			}
+			b"hdlr" => {
+				let (_, body) = full_box_body(data)?;
+				sound = body.get(4..8) == Some(b"soun");
+			}
+			b"minf" => {
+				for (kind, data) in boxes(data)? {
+					if &kind == b"stbl" {
+						stbl = Some(data);
+					}
+				}
+			}
+			_ => {}
+		}
+	}
+	let (timescale, stbl) = match (timescale, stbl) {
+		(Some(timescale), Some(stbl)) if sound && timescale > 0 => (timescale, stbl),
+		_ => return Ok(None),
+	};
+
+	let mut head = None;
+	let mut duration_runs = Vec::new();
+	let mut chunk_runs = Vec::new();
+	let mut sizes = Vec::new();
+	let mut offsets = Vec::new();
+	let mut roll = None;
+	for (kind, data) in boxes(stbl)? {
+		let (version, body) = full_box_body(data)?;
+		match &kind {
+			b"stsd" => {
+				// the entries follow the count
+				for (kind, entry) in boxes(body.get(4..).unwrap_or(&[]))? {
+					if &kind == b"Opus" && head.is_none() {
+						// the audio sample entry fields come before the boxes
+						for (kind, data) in boxes(entry.get(28..).unwrap_or(&[]))? {
+							if &kind == b"dOps" {
+								head = Some(from_dops(data)?);
+							}
+						}
+					}
+				}
+			}
+			b"stts" => {
+				for entry in table(body, 8)? {
+					duration_runs.push((read_u32(entry, 0)?, read_u32(entry, 4)? as u64));
+				}
+			}
+			b"stsc" => {
+				for entry in table(body, 12)? {
+					chunk_runs.push((read_u32(entry, 0)?, read_u32(entry, 4)?));
+				}
+			}
+			b"stsz" => {
+				let fixed = read_u32(body, 0)?;
+				let count = read_u32(body, 4)?;
+				if fixed != 0 {
+					if count as u64 * fixed as u64 > media {
+						return Err(malformed("samples exceed the media data"));
+					}
+					sizes = vec![fixed; count as usize];
+				} else {
+					for entry in table(&body[4..], 4)? {
+						sizes.push(read_u32(entry, 0)?);
+					}
+				}
+			}
+			b"stco" => {
+				for entry in table(body, 4)? {
+					offsets.push(read_u32(entry, 0)? as u64);
+				}
+			}
+			b"co64" => {
+				for entry in table(body, 8)? {
+					offsets.push(read_u64(entry, 0)?);
+				}
+			}
+			b"sgpd" if body.get(..4) == Some(b"roll") => {
+				// version 1 gives the length of each entry
+				let at = if version == 1 { 12 } else { 8 };
+				let distance = match body.get(at..at + 2) {
+					Some(bytes) => i16::from_be_bytes([bytes[0], bytes[1]]),
+					None => return Err(malformed("truncated box")),
+				};
+				roll = Some(distance.unsigned_abs() as u64);
+			}
+			_ => {}
+		}
+	}
+	let head = match head {
+		Some(head) => head,
+		None => return Ok(None),
+	};
+	if sizes.len() as u64 > media {
+		return Err(malformed("samples exceed the media data"));
+	}
+	let samples: u64 = duration_runs.iter().map(|&(count, _)| count as u64).sum();
+	if samples != sizes.len() as u64 {
+		return Err(malformed("sample tables differ in length"));
+	}
+	let mut durations = duration_runs
+		.iter()
+		.flat_map(|&(count, duration)| std::iter::repeat_n(duration, count as usize));
+
+	// expand the chunk runs into a position for every sample
+	let mut entries = Vec::with_capacity(sizes.len());
+	let mut timestamp = 0;
+	let mut sample = 0;
+	for (chunk, &offset) in offsets.iter().enumerate() {
+		let chunk = chunk as u32 + 1;
+		let per_chunk = match chunk_runs.iter().rev().find(|&&(first, _)| first <= chunk) {
+			Some(&(_, count)) => count as usize,
+			None => return Err(malformed("invalid sample-to-chunk table")),
+		};
+		let mut offset = offset;
+		for _ in 0..per_chunk {
+			if sample == sizes.len() {
+				break;
+			}
+			let duration = durations.next().expect("the sample tables are the same length");
+			entries.push(Entry {
+				offset,
+				size: sizes[sample],
+				timestamp,
+				duration,
+			});
+			offset = offset
+				.checked_add(sizes[sample] as u64)
+				.ok_or_else(|| malformed("sample offset overflow"))?;
+			timestamp =
+				timestamp.checked_add(duration).ok_or_else(|| malformed("timestamp overflow"))?;
+			sample += 1;
+		}
+	}
+	if sample != sizes.len() {
+		return Err(malformed("samples missing from chunks"));
+	}
+
+	let roll = roll.unwrap_or_else(|| {
+		let shortest = entries.iter().map(|entry| entry.duration).min().unwrap_or(1).max(1);
+		(PRE_ROLL * timescale as u64 / 48000).div_ceil(shortest)
+	});
+	Ok(Some(Track { head, timescale, edit, roll, entries }))
+}
+
+/// Parse the first edit which is not an empty edit.
+fn parse_elst(data: &[u8]) -> Result<Option<(u64, u64)>> {
+	let (version, body) = full_box_body(data)?;
+	let size = if version == 1 { 20 } else { 12 };
+	for entry in table(body, size)? {
+		let (duration, media_time) = if version == 1 {
+			(read_u64(entry, 0)?, read_u64(entry, 8)? as i64)
+		} else {
+			(read_u32(entry, 0)? as u64, read_u32(entry, 4)? as i32 as i64)
+		};
+		if media_time >= 0 {
+			return Ok(Some((duration, media_time as u64)));
+		}
+	}
+	Ok(None)
+}
+
+/// Convert an `OpusHead` to the body of a `dOps` box.
+fn to_dops(head: &OpusHead) -> Vec<u8> {
+	let mut data = vec![0, head.channels];
+	data.extend_from_slice(&head.pre_skip.to_be_bytes());
+	data.extend_from_slice(&head.input_sample_rate.to_be_bytes());
+	data.extend_from_slice(&head.output_gain.to_be_bytes());
+	data.push(head.mapping_family);
+	if head.mapping_family != 0 {
+		data.push(head.streams);
+		data.push(head.coupled_streams);
+		data.extend_from_slice(&head.mapping);
+	}
+	data
+}
+
+/// Convert the body of a `dOps` box to an `OpusHead`.
+fn from_dops(data: &[u8]) -> Result<OpusHead> {
+	if data.len() < 11 || data[0] != 0 {
+		return Err(malformed("unsupported dOps box"));
+	}
+	// rearrange into the little-endian layout and parse that
+	let mut head = MAGIC.to_vec();
+	head.push(1);
+	head.push(data[1]);
+	head.extend_from_slice(&[data[3], data[2]]);
+	head.extend_from_slice(&[data[7], data[6], data[5], data[4]]);
+	head.extend_from_slice(&[data[9], data[8]]);
+	head.extend_from_slice(&data[10..]);
+	OpusHead::parse(&head)
+}
+
+fn from_48k(samples: u64, sample_rate: u32) -> u64 {
+	(samples as u128 * sample_rate as u128 / 48000) as u64
+}
+
+// ============================================================================
+// Box Reading and Writing
+
+fn malformed(context: &'static str) -> Error {
+	Error::new("Mp4Reader", ErrorKind::Malformed).with_context(context)
+}
+
+/// Read a box's type, total size and header size, or `None` at the end of
+/// the stream. A box extending to the end of the file has size `u64::MAX`.
+fn read_box_header<R: Read>(reader: &mut R) -> Result<Option<([u8; 4], u64, u64)>> {
+	let mut header = [0; 8];
+	let mut read = 0;
+	while read < header.len() {
+		match reader.read(&mut header[read..]) {
+			Ok(0) if read == 0 => return Ok(None),
+			Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+			Ok(n) => read += n,
+			Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+			Err(err) => return Err(err.into()),
+		}
+	}
+	let kind = [header[4], header[5], header[6], header[7]];
+	match u32::from_be_bytes([header[0], header[1], header[2], header[3]]) {
+		0 => Ok(Some((kind, u64::MAX, 8))),
+		1 => {
+			let mut large = [0; 8];
+			reader.read_exact(&mut large)?;
+			let size = u64::from_be_bytes(large);
+			if size < 16 {
+				return Err(malformed("invalid box size"));
+			}
+			Ok(Some((kind, size, 16)))
+		}
+		size if size < 8 => Err(malformed("invalid box size")),
+		size => Ok(Some((kind, size as u64, 8))),
+	}
+}
+
+/// Split the body of a container box into its children.
+fn boxes(mut data: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+	let mut children = Vec::new();
+	while !data.is_empty() {
+		let (kind, size, header) = match read_box_header(&mut data)? {
+			Some(header) => header,
+			None => break,
+		};
+		let len = if size == u64::MAX { data.len() as u64 } else { size - header };
+		if len > data.len() as u64 {
+			return Err(malformed("box overruns its parent"));
+		}
+		let (body, rest) = data.split_at(len as usize);
+		children.push((kind, body));
+		data = rest;
+	}
+	Ok(children)
+}
+
+/// Split a full box into its version and the rest of its body.
+fn full_box_body(data: &[u8]) -> Result<(u8, &[u8])> {
+	if data.len() < 4 {
+		return Err(malformed("truncated box"));
+	}
+	Ok((data[0], &data[4..]))
+}
+
+/// Split a table with a 32-bit entry count into its entries.
+fn table(data: &[u8], entry_size: usize) -> Result<std::slice::Chunks<'_, u8>> {
+	let count = read_u32(data, 0)? as usize;
+	let entries = &data[4..];
+	if entries.len() / entry_size < count {
+		return Err(malformed("truncated table"));
+	}
+	Ok(entries[..count * entry_size].chunks(entry_size))
+}
+
+fn read_u32(data: &[u8], at: usize) -> Result<u32> {
+	match data.get(at..at + 4) {
+		Some(bytes) => Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])),
+		None => Err(malformed("truncated box")),
+	}
+}
+
+fn read_u64(data: &[u8], at: usize) -> Result<u64> {
+	Ok((read_u32(data, at)? as u64) << 32 | read_u32(data, at + 4)? as u64)
+}
+
+/// Whether a duration needs version 1 of a box, with 64-bit times.
+fn wide(duration: u64) -> u8 {
+	if duration > u32::MAX as u64 {
+		1
+	} else {
+		0
+	}
+}
+
+/// Write creation and modification times of zero, the timescale and a
+/// duration, in the layout of the given box version.
+fn times(buf: &mut Vec<u8>, duration: u64) {
+	if wide(duration) == 1 {
+		buf.extend_from_slice(&[0; 16]);
+		buf.extend_from_slice(&TIMESCALE.to_be_bytes());
+		buf.extend_from_slice(&duration.to_be_bytes());
+	} else {
+		buf.extend_from_slice(&[0; 8]);
+		buf.extend_from_slice(&TIMESCALE.to_be_bytes());
+		buf.extend_from_slice(&(duration as u32).to_be_bytes());
+	}
+}
+
+fn boxed<F: FnOnce(&mut Vec<u8>)>(buf: &mut Vec<u8>, kind: &[u8; 4], f: F) {
+	let start = buf.len();
+	buf.extend_from_slice(&[0; 4]);
+	buf.extend_from_slice(kind);
+	f(buf);
+	let size = (buf.len() - start) as u32;
+	buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn full_box<F: FnOnce(&mut Vec<u8>)>(
+	buf: &mut Vec<u8>,
+	kind: &[u8; 4],
+	version: u8,
+	flags: u32,
+	f: F,
+) {
+	boxed(buf, kind, |buf| {
+		buf.extend_from_slice(&(u32::from(version) << 24 | flags).to_be_bytes());
+		f(buf);
+	});
+}
@@ -0,0 +1,529 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Ogg Opus files, as defined by RFC 7845.
+//!
+//! An Ogg Opus stream starts with a page holding the `OpusHead`, followed by
+//! the `OpusTags` comment header, and then the audio packets. The granule
+//! position of each page counts the samples at 48 kHz decoded by the end of
+//! the last packet completed on it, including the pre-skip, and the final
+//! page's granule position trims any padding from the end.
+
+use super::opus_head::OpusHead;
+use super::{packet, Error, ErrorKind, Result};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+
+/// The magic signature at the start of an `OpusTags` header.
+pub const TAGS_MAGIC: &[u8; 8] = b"OpusTags";
+
+/// The data size after which a page is sent, rather than adding to it.
+const PAGE_DATA: usize = 4096;
+/// The duration after which a page is sent, in samples at 48 kHz.
+const PAGE_SAMPLES: u64 = 48000;
+
+/// The header type flag for a page continuing a packet from the last page.
+const CONTINUED: u8 = 1;
+/// The header type flag for the first page of a stream.
+const BEGINNING: u8 = 2;
+/// The header type flag for the last page of a stream.
+const END: u8 = 4;
+
+/// An `OpusTags` comment header.
+///
+/// Comments are `KEY=value` pairs in the Vorbis comment format. Keys are
+/// case-insensitive and may repeat.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct OpusTags {
+	/// The name of the encoder which produced the stream.
+	pub vendor: String,
+	/// The comments, as key and value pairs.
+	pub comments: Vec<(String, String)>,
+}
+
+impl Default for OpusTags {
+	fn default() -> OpusTags {
+		OpusTags::new()
+	}
+}
+
+impl OpusTags {
+	/// Create an empty comment header naming the linked libopus as the
+	/// vendor.
+	pub fn new() -> OpusTags {
+		OpusTags {
+			vendor: super::version().to_owned(),
+			comments: Vec::new(),
+		}
+	}
+
+	/// Add a comment.
+	pub fn add(&mut self, key: &str, value: &str) {
+		self.comments.push((key.to_owned(), value.to_owned()));
+	}
+
+	/// Get the value of the first comment with the given key.
+	pub fn get(&self, key: &str) -> Option<&str> {
+		self.comments.iter().find(|(k, _)| k.eq_ignore_ascii_case(key)).map(|(_, v)| v.as_str())
+	}
+
+	/// Parse a comment header.
+	///
+	/// Comments without an `=` are kept with an empty value, and any data
+	/// after the comments is ignored.
+	pub fn parse(data: &[u8]) -> Result<OpusTags> {
+		if data.len() < 16 || &data[..8] != TAGS_MAGIC {
+			return Err(tags_malformed("not an OpusTags header"));
+		}
+		let mut pos = 8;
+		let vendor = read_string(data, &mut pos)?;
+		let count = read_u32(data, &mut pos)?;
+		let mut comments = Vec::new();
+		for _ in 0..count {
+			let comment = read_string(data, &mut pos)?;
+			match comment.find('=') {
+				Some(at) => comments.push((comment[..at].to_owned(), comment[at + 1..].to_owned())),
+				None => comments.push((comment, String::new())),
+			}
+		}
+		Ok(OpusTags { vendor, comments })
+	}
+
+	/// Serialize the comment header.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut data = TAGS_MAGIC.to_vec();
+		data.extend_from_slice(&(self.vendor.len() as u32).to_le_bytes());
+		data.extend_from_slice(self.vendor.as_bytes());
+		data.extend_from_slice(&(self.comments.len() as u32).to_le_bytes());
+		for (key, value) in &self.comments {
+			data.extend_from_slice(&((key.len() + 1 + value.len()) as u32).to_le_bytes());
+			data.extend_from_slice(key.as_bytes());
+			data.push(b'=');
+			data.extend_from_slice(value.as_bytes());
+		}
+		data
+	}
+}
+
+fn read_u32(data: &[u8], pos: &mut usize) -> Result<u32> {
+	match data.get(*pos..*pos + 4) {
+		Some(bytes) => {
+			*pos += 4;
+			Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+		}
+		None => Err(tags_malformed("truncated OpusTags header")),
+	}
+}
+
+fn read_string(data: &[u8], pos: &mut usize) -> Result<String> {
+	let len = read_u32(data, pos)? as usize;
+	match data.get(*pos..).and_then(|rest| rest.get(..len)) {
+		Some(bytes) => {
+			*pos += len;
+			Ok(String::from_utf8_lossy(bytes).into_owned())
+		}
+		None => Err(tags_malformed("truncated OpusTags header")),
+	}
+}
+
+fn tags_malformed(context: &'static str) -> Error {
+	Error::new("OpusTags::parse", ErrorKind::Malformed).with_context(context)
+}
+
+/// Writes an Ogg Opus stream.
+///
+/// Packets are gathered into pages of about 4 KB or one second, whichever
+/// comes first. The page being filled is only written once the next packet
+/// arrives or the stream is finished, so that the final page can be marked
+/// as the end of the stream with a granule position which trims the padding.
+#[derive(Debug)]
+pub struct OggOpusWriter<W: Write> {
+	writer: W,
+	serial: u32,
+	sequence: u32,
+	pre_skip: u64,
+	/// The lacing values and data of the page being filled.
+	segments: Vec<u8>,
+	data: Vec<u8>,
+	/// Whether the page being filled starts with the rest of a packet.
+	continued: bool,
+	/// Whether the page being filled has a packet ending on it.
+	completed: bool,
+	page_samples: u64,
+	samples: u64,
+}
+
+impl<W: Write> OggOpusWriter<W> {
+	/// Start a stream with the given headers and serial number, writing the
+	/// header pages.
+	pub fn new(
+		writer: W,
+		head: &OpusHead,
+		tags: &OpusTags,
+		serial: u32,
+	) -> Result<OggOpusWriter<W>> {
+		let mut ogg = OggOpusWriter {
+			writer,
+			serial,
+			sequence: 0,
+			pre_skip: head.pre_skip as u64,
+			segments: Vec::new(),
+			data: Vec::new(),
+			continued: false,
+			completed: false,
+			page_samples: 0,
+			samples: 0,
+		};
+		// each header is alone on its pages
+		ogg.add(&head.to_bytes())?;
+		ogg.flush(BEGINNING, 0)?;
+		ogg.add(&tags.to_bytes())?;
+		ogg.flush(0, 0)?;
+		Ok(ogg)
+	}
+
+	/// Write an encoded packet.
+	///
+	/// The packet's duration is read from its TOC byte.
+	pub fn write_packet(&mut self, packet: &[u8]) -> Result<()> {
+		let duration = packet::get_nb_samples(packet, 48000)? as u64;
+		let segments = packet.len() / 255 + 1;
+		if self.completed
+			&& (self.data.len() + packet.len() > PAGE_DATA
+				|| self.segments.len() + segments > 255
+				|| self.page_samples >= PAGE_SAMPLES)
+		{
+			let granule = self.samples;
+			self.flush(0, granule)?;
+		}
+		self.add(packet)?;
+		self.samples += duration;
+		self.page_samples += duration;
+		Ok(())
+	}
+
+	/// Get the total duration of the packets written, in samples at 48 kHz,
+	/// including the pre-skip.
+	pub fn samples(&self) -> u64 {
+		self.samples
+	}
+
+	/// Finish the stream, keeping all of the decoded audio after the
+	/// pre-skip.
+	pub fn finish(self) -> Result<W> {
+		let length = self.samples.saturating_sub(self.pre_skip);
+		self.finish_with_length(length)
+	}
+
+	/// Finish the stream, trimming the decoded audio after the pre-skip to
+	/// `length` samples at 48 kHz.
+	pub fn finish_with_length(mut self, length: u64) -> Result<W> {
+		let granule = (self.pre_skip + length).min(self.samples);
+		self.flush(END, granule)?;
+		self.writer.flush()?;
+		Ok(self.writer)
+	}
+
+	/// Add a packet to the page being filled, writing out full pages.
+	fn add(&mut self, packet: &[u8]) -> Result<()> {
+		let mut rest = packet;
+		loop {
+			if self.segments.len() == 255 {
+				// the packet continues on the next page
+				let granule = if self.completed { self.samples } else { u64::MAX };
+				self.flush(0, granule)?;
+				self.continued = true;
+			}
+			let len = rest.len().min(255);
+			self.segments.push(len as u8);
+			self.data.extend_from_slice(&rest[..len]);
+			rest = &rest[len..];
+			if len < 255 {
+				self.completed = true;
+				return Ok(());
+			}
+		}
+	}
+
+	/// Write the page being filled.
+	fn flush(&mut self, flags: u8, granule: u64) -> Result<()> {
+		let flags = flags | if self.continued { CONTINUED } else { 0 };
+		let page = page(flags, granule, self.serial, self.sequence, &self.segments, &self.data);
+		self.writer.write_all(&page)?;
+		self.sequence += 1;
+		self.segments.clear();
+		self.data.clear();
+		self.continued = false;
+		self.completed = false;
+		self.page_samples = 0;
+		Ok(())
+	}
+}
+
+/// A packet read from an Ogg Opus stream.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+pub struct OggPacket {
+	/// The packet data.
+	pub data: Vec<u8>,
+	/// The granule position of the page, if this is the last packet
+	/// completed on it.
+	pub granule_position: Option<u64>,
+	/// Whether this is the last packet of the stream.
+	pub end_of_stream: bool,
+}
+
+/// Reads an Ogg Opus stream.
+///
+/// The first logical stream starting with an `OpusHead` is used, and pages
+/// of any other streams are skipped. Reading stops at the end of that
+/// stream, so later links of a chained file are not returned. Page
+/// checksums are verified, and a packet whose pages are missing is dropped
+/// rather than returned incomplete.
+#[derive(Debug)]
+pub struct OggOpusReader<R: Read> {
+	reader: R,
+	serial: u32,
+	head: OpusHead,
+	tags: OpusTags,
+	/// Complete packets which have not yet been returned.
+	queue: VecDeque<OggPacket>,
+	/// The start of a packet continuing on the next page.
+	partial: Vec<u8>,
+	/// The sequence number expected for the next page.
+	sequence: u32,
+	ended: bool,
+}
+
+impl<R: Read> OggOpusReader<R> {
+	/// Read the `OpusHead` and `OpusTags` headers of a stream.
+	pub fn new(mut reader: R) -> Result<OggOpusReader<R>> {
+		// find the beginning of an Opus stream
+		let (serial, page) = loop {
+			let page = match read_page(&mut reader)? {
+				Some(page) => page,
+				None => return Err(malformed("no Opus stream found")),
+			};
+			if page.flags & BEGINNING != 0 && page.data.starts_with(b"OpusHead") {
+				break (page.serial, page);
+			}
+		};
+		// the OpusHead is alone on the first page
+		if page.segments.last().is_none_or(|&s| s == 255) || page.flags & CONTINUED != 0 {
+			return Err(malformed("OpusHead not alone on its page"));
+		}
+		let head = OpusHead::parse(&page.data)?;
+		let mut ogg = OggOpusReader {
+			reader,
+			serial,
+			head,
+			tags: OpusTags::new(),
+			queue: VecDeque::new(),
+			partial: Vec::new(),
+			sequence: page.sequence.wrapping_add(1),
+			ended: page.flags & END != 0,
+		};
+		let tags = ogg.next_packet()?.ok_or_else(|| malformed("missing OpusTags header"))?;
+		ogg.tags = OpusTags::parse(&tags.data)?;
+		Ok(ogg)
+	}
+
+	/// Get the stream's `OpusHead`.
+	pub fn head(&self) -> &OpusHead {
+		&self.head
+	}
+
+	/// Get the stream's `OpusTags`.
+	pub fn tags(&self) -> &OpusTags {
+		&self.tags
+	}
+
+	/// Get the serial number of the stream.
+	pub fn serial(&self) -> u32 {
+		self.serial
+	}
+
+	/// Read the next audio packet, or `None` at the end of the stream.
+	pub fn read_packet(&mut self) -> Result<Option<OggPacket>> {
+		self.next_packet()
+	}
+
+	/// Unwrap the underlying stream.
+	pub fn into_inner(self) -> R {
+		self.reader
+	}
+
+	fn next_packet(&mut self) -> Result<Option<OggPacket>> {
+		loop {
+			if let Some(packet) = self.queue.pop_front() {
+				return Ok(Some(packet));
+			}
+			if self.ended {
+				return Ok(None);
+			}
+			match read_page(&mut self.reader)? {
+				Some(page) => {
+					if page.serial == self.serial {
+						self.add_page(page);
+					}
+				}
+				// a stream cut off without an end page still ends here
+				None => self.ended = true,
+			}
+		}
+	}
+
+	/// Split a page of this stream into packets.
+	fn add_page(&mut self, page: Page) {
+		// a gap in the sequence loses any packet spanning it
+		let mut skip = page.sequence != self.sequence;
+		self.sequence = page.sequence.wrapping_add(1);
+		if page.flags & CONTINUED == 0 {
+			skip = false;
+			self.partial.clear();
+		} else if skip || self.partial.is_empty() {
+			// the start of this packet is missing
+			skip = true;
+			self.partial.clear();
+		}
+
+		let mut pos = 0;
+		let first = self.queue.len();
+		for &segment in &page.segments {
+			let len = segment as usize;
+			if !skip {
+				self.partial.extend_from_slice(&page.data[pos..pos + len]);
+			}
+			pos += len;
+			if segment < 255 {
+				if !skip {
+					self.queue.push_back(OggPacket {
+						data: std::mem::take(&mut self.partial),
+						granule_position: None,
+						end_of_stream: false,
+					});
+				}
+				skip = false;
+			}
+		}
+		if self.queue.len() > first {
+			let last = self.queue.back_mut().unwrap();
+			if page.granule != u64::MAX {
+				last.granule_position = Some(page.granule);
+			}
+			last.end_of_stream = page.flags & END != 0;
+		}
+		if page.flags & END != 0 {
+			self.ended = true;
+		}
+	}
+}
+
+/// A page read from an Ogg stream.
+#[derive(Debug)]
+struct Page {
+	flags: u8,
+	granule: u64,
+	serial: u32,
+	sequence: u32,
+	segments: Vec<u8>,
+	data: Vec<u8>,
+}
+
+/// Read the next page, or `None` at the end of the stream, checking its
+/// checksum.
+fn read_page<R: Read>(reader: &mut R) -> Result<Option<Page>> {
+	let mut header = [0; 27];
+	// the stream may only end between pages
+	let mut read = 0;
+	while read < header.len() {
+		match reader.read(&mut header[read..]) {
+			Ok(0) if read == 0 => return Ok(None),
+			Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+			Ok(n) => read += n,
+			Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+			Err(err) => return Err(err.into()),
+		}
+	}
+	if &header[..4] != b"OggS" || header[4] != 0 {
+		return Err(malformed("invalid page header"));
+	}
+	let mut segments = vec![0; header[26] as usize];
+	reader.read_exact(&mut segments)?;
+	let mut data = vec![0; segments.iter().map(|&s| s as usize).sum()];
+	reader.read_exact(&mut data)?;
+
+	let stored = u32::from_le_bytes([header[22], header[23], header[24], header[25]]);
+	header[22..26].copy_from_slice(&[0; 4]);
+	let crc = [&header[..], &segments, &data].iter().fold(0, |crc, part| update_crc(crc, part));
+	if crc != stored {
+		return Err(malformed("page checksum mismatch"));
+	}
+
+	let mut granule = [0; 8];
+	granule.copy_from_slice(&header[6..14]);
+	Ok(Some(Page {
+		flags: header[5],
+		granule: u64::from_le_bytes(granule),
+		serial: u32::from_le_bytes([header[14], header[15], header[16], header[17]]),
+		sequence: u32::from_le_bytes([header[18], header[19], header[20], header[21]]),
+		segments,
+		data,
+	}))
+}
+
+fn malformed(context: &'static str) -> Error {
+	Error::new("OggOpusReader", ErrorKind::Malformed).with_context(context)
+}
+
+/// Build an Ogg page, including its checksum.
+fn page(
+	flags: u8,
+	granule: u64,
+	serial: u32,
+	sequence: u32,
+	segments: &[u8],
+	data: &[u8],
+) -> Vec<u8> {
+	let mut page = Vec::with_capacity(27 + segments.len() + data.len());
+	page.extend_from_slice(b"OggS");
+	page.push(0);
+	page.push(flags);
+	page.extend_from_slice(&granule.to_le_bytes());
+	page.extend_from_slice(&serial.to_le_bytes());
+	page.extend_from_slice(&sequence.to_le_bytes());
+	page.extend_from_slice(&[0; 4]);
+	page.push(segments.len() as u8);
+	page.extend_from_slice(segments);
+	page.extend_from_slice(data);
+	let crc = update_crc(0, &page);
+	page[22..26].copy_from_slice(&crc.to_le_bytes());
+	page
+}
+
+/// The CRC-32 used by Ogg, with polynomial 0x04C11DB7 and no reflection.
+fn update_crc(crc: u32, data: &[u8]) -> u32 {
+	data.iter().fold(crc, |crc, &b| crc << 8 ^ CRC_TABLE[((crc >> 24) as u8 ^ b) as usize])
+}
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+	let mut table = [0; 256];
+	let mut i = 0;
+	while i < 256 {
+		let mut value = (i as u32) << 24;
+		let mut bit = 0;
+		while bit < 8 {
+			value = if value & 0x8000_0000 != 0 { value << 1 ^ 0x04C1_1DB7 } else { value << 1 };
+			bit += 1;
+		}
+		table[i] = value;
+		i += 1;
+	}
+	table
+}
@@ -0,0 +1,165 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The framed bitstream format used by libopus's `opus_demo`.
+//!
+//! Each packet is stored as its length and the encoder's final range, both
+//! 32-bit big-endian, followed by the packet data. A length of zero marks a
+//! lost packet. The official test vectors also use this format.
+
+use super::{Error, Result};
+use std::io::{self, Read, Write};
+
+/// A packet read from an `opus_demo` bitstream.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+pub struct DemoPacket {
+	/// The packet data, empty if the packet was lost.
+	pub data: Vec<u8>,
+	/// The encoder's final range after coding the packet.
+	pub final_range: u32,
+}
+
+impl DemoPacket {
+	/// Check whether the packet is marked as lost.
+	pub fn is_lost(&self) -> bool {
+		self.data.is_empty()
+	}
+}
+
+/// Writes packets in the `opus_demo` bitstream format.
+#[derive(Debug)]
+pub struct DemoBitstreamWriter<W: Write> {
+	writer: W,
+	packets: u64,
+}
+
+impl<W: Write> DemoBitstreamWriter<W> {
+	/// Create a writer which writes to the given stream.
+	pub fn new(writer: W) -> DemoBitstreamWriter<W> {
+		DemoBitstreamWriter { writer, packets: 0 }
+	}
+
+	/// Write a packet along with the encoder's final range for it.
+	pub fn write_packet(&mut self, packet: &[u8], final_range: u32) -> Result<()> {
+		if packet.len() > i32::MAX as usize {
+			return Err(
+				Error::bad_arg("DemoBitstreamWriter::write_packet").with_context("packet too long")
+			);
+		}
+		self.writer.write_all(&(packet.len() as u32).to_be_bytes())?;
+		self.writer.write_all(&final_range.to_be_bytes())?;
+		self.writer.write_all(packet)?;
+		self.packets += 1;
+		Ok(())
+	}
+
+	/// Write a marker for a lost packet.
+	pub fn write_lost(&mut self) -> Result<()> {
+		self.write_packet(&[], 0)
+	}
+
+	/// Get the number of packets written, including lost packets.
+	pub fn packets(&self) -> u64 {
+		self.packets
+	}
+
+	/// Flush the underlying stream.
+	pub fn flush(&mut self) -> Result<()> {
+		self.writer.flush()?;
+		Ok(())
+	}
+
+	/// Get a reference to the underlying stream.
+	pub fn get_ref(&self) -> &W {
+		&self.writer
+	}
+
+	/// Unwrap the underlying stream.
+	pub fn into_inner(self) -> W {
+		self.writer
+	}
+}
+
+/// Reads packets in the `opus_demo` bitstream format.
+///
+/// Reading also works as an iterator over `Result<DemoPacket>`.
+#[derive(Debug)]
+pub struct DemoBitstreamReader<R: Read> {
+	reader: R,
+	packets: u64,
+}
+
+impl<R: Read> DemoBitstreamReader<R> {
+	/// Create a reader which reads from the given stream.
+	pub fn new(reader: R) -> DemoBitstreamReader<R> {
+		DemoBitstreamReader { reader, packets: 0 }
+	}
+
+	/// Read the next packet, or `None` at the end of the stream.
+	pub fn read_packet(&mut self) -> Result<Option<DemoPacket>> {
+		let mut data = Vec::new();
+		Ok(self.read_packet_into(&mut data)?.map(|final_range| DemoPacket { data, final_range }))
+	}
+
+	/// Read the next packet into a reusable buffer, returning its final
+	/// range, or `None` at the end of the stream.
+	pub fn read_packet_into(&mut self, data: &mut Vec<u8>) -> Result<Option<u32>> {
+		let mut header = [0; 8];
+		// the stream may only end between packets
+		let mut read = 0;
+		while read < header.len() {
+			match self.reader.read(&mut header[read..]) {
+				Ok(0) if read == 0 => return Ok(None),
+				Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+				Ok(n) => read += n,
+				Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+				Err(err) => return Err(err.into()),
+			}
+		}
+		let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+		let final_range = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+		if len > i32::MAX as u32 {
+			return Err(Error::bad_arg("DemoBitstreamReader::read_packet")
+				.with_context("invalid payload length"));
+		}
+		data.clear();
+		(&mut self.reader).take(len as u64).read_to_end(data)?;
+		if data.len() != len as usize {
+			return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+		}
+		self.packets += 1;
+		Ok(Some(final_range))
+	}
+
+	/// Get the number of packets read, including lost packets.
+	pub fn packets(&self) -> u64 {
+		self.packets
+	}
+
+	/// Get a reference to the underlying stream.
+	pub fn get_ref(&self) -> &R {
+		&self.reader
+	}
+
+	/// Unwrap the underlying stream.
+	pub fn into_inner(self) -> R {
+		self.reader
+	}
+}
+
+impl<R: Read> Iterator for DemoBitstreamReader<R> {
+	type Item = Result<DemoPacket>;
+
+	fn next(&mut self) -> Option<Result<DemoPacket>> {
+		match self.read_packet() {
+			Ok(Some(packet)) => Some(Ok(packet)),
+			Ok(None) => None,
+			Err(err) => Some(Err(err)),
+		}
+	}
+}
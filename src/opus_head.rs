@@ -0,0 +1,257 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! The `OpusHead` identification header.
+//!
+//! Defined for Ogg in RFC 7845, the same structure is stored as the codec
+//! private data in WebM and, with a different byte order, in the `dOps` box
+//! of MP4. It gives the channel count and mapping needed to build a decoder,
+//! the pre-skip to discard from the start of the decoded audio, and an output
+//! gain.
+
+use super::{Channels, Encoder, Error, ErrorKind, MSDecoder, MSEncoder, Result};
+
+/// The magic signature at the start of an `OpusHead`.
+pub const MAGIC: &[u8; 8] = b"OpusHead";
+
+/// An `OpusHead` identification header.
+///
+/// The mapping is always filled in, including the implicit mapping of family
+/// 0, so `decoder` works for every family.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct OpusHead {
+	/// The number of output channels.
+	pub channels: u8,
+	/// The number of samples at 48 kHz to discard from the start of the
+	/// decoded audio.
+	pub pre_skip: u16,
+	/// The sample rate of the original input, for information only.
+	pub input_sample_rate: u32,
+	/// The gain to apply to the decoded audio, in dB in Q7.8 format.
+	pub output_gain: i16,
+	/// The channel mapping family: 0 for mono or stereo, 1 for Vorbis
+	/// channel order, and 255 for an undefined order.
+	pub mapping_family: u8,
+	/// The number of Opus streams in each packet.
+	pub streams: u8,
+	/// The number of streams which are coupled stereo pairs.
+	pub coupled_streams: u8,
+	/// For each output channel, the index of the decoded channel it is taken
+	/// from, or 255 for silence.
+	pub mapping: Vec<u8>,
+}
+
+impl OpusHead {
+	/// Create a header for a mono or stereo stream with mapping family 0.
+	pub fn new(channels: Channels, pre_skip: u16, input_sample_rate: u32) -> OpusHead {
+		let count = channels as u8;
+		OpusHead {
+			channels: count,
+			pre_skip,
+			input_sample_rate,
+			output_gain: 0,
+			mapping_family: 0,
+			streams: 1,
+			coupled_streams: count - 1,
+			mapping: (0..count).collect(),
+		}
+	}
+
+	/// Create a header for a multistream stream with an explicit mapping.
+	///
+	/// The channel count is the length of `mapping`.
+	pub fn multistream(
+		mapping_family: u8,
+		streams: u8,
+		coupled_streams: u8,
+		mapping: &[u8],
+		pre_skip: u16,
+		input_sample_rate: u32,
+	) -> Result<OpusHead> {
+		let head = OpusHead {
+			channels: mapping.len().min(255) as u8,
+			pre_skip,
+			input_sample_rate,
+			output_gain: 0,
+			mapping_family,
+			streams,
+			coupled_streams,
+			mapping: mapping.to_vec(),
+		};
+		match head.check() {
+			Ok(()) if mapping.len() <= 255 => Ok(head),
+			Ok(()) => {
+				Err(Error::bad_arg("OpusHead::multistream").with_context("too many channels"))
+			}
+			Err(context) => Err(Error::bad_arg("OpusHead::multistream").with_context(context)),
+		}
+	}
+
+	/// Create a header describing an encoder's output, with its lookahead as
+	/// the pre-skip.
+	pub fn for_encoder(encoder: &mut Encoder) -> Result<OpusHead> {
+		let input_rate = encoder.get_sample_rate()?;
+		let pre_skip = to_48k(encoder.get_lookahead()?, input_rate);
+		Ok(OpusHead::new(encoder.channels, pre_skip, input_rate))
+	}
+
+	/// Create a header describing a multistream encoder's output, with its
+	/// lookahead as the pre-skip.
+	///
+	/// The stream counts and mapping must be those the encoder was created
+	/// with.
+	pub fn for_ms_encoder(
+		encoder: &mut MSEncoder,
+		mapping_family: u8,
+		streams: u8,
+		coupled_streams: u8,
+		mapping: &[u8],
+	) -> Result<OpusHead> {
+		let input_rate = encoder.get_sample_rate()?;
+		let pre_skip = to_48k(encoder.get_lookahead()?, input_rate);
+		if mapping.len() != encoder.channels as usize {
+			return Err(Error::new(
+				"OpusHead::for_ms_encoder",
+				ErrorKind::ChannelMismatch {
+					expected: encoder.channels as usize,
+					actual: mapping.len(),
+				},
+			));
+		}
+		OpusHead::multistream(
+			mapping_family,
+			streams,
+			coupled_streams,
+			mapping,
+			pre_skip,
+			input_rate,
+		)
+	}
+
+	/// Parse a header in the little-endian layout used by Ogg and WebM.
+	///
+	/// Any trailing data after the mapping table is ignored, as RFC 7845
+	/// requires.
+	pub fn parse(data: &[u8]) -> Result<OpusHead> {
+		let malformed = |context| {
+			Err(Error::new("OpusHead::parse", ErrorKind::Malformed).with_context(context))
+		};
+		if data.len() < 19 || &data[..8] != MAGIC {
+			return malformed("not an OpusHead");
+		}
+		// only the major version, in the upper four bits, is incompatible
+		if data[8] >> 4 != 0 {
+			return malformed("unsupported version");
+		}
+		let mut head = OpusHead {
+			channels: data[9],
+			pre_skip: u16::from_le_bytes([data[10], data[11]]),
+			input_sample_rate: u32::from_le_bytes([data[12], data[13], data[14], data[15]]),
+			output_gain: i16::from_le_bytes([data[16], data[17]]),
+			mapping_family: data[18],
+			streams: 1,
+			coupled_streams: 0,
+			mapping: Vec::new(),
+		};
+		if head.mapping_family == 0 {
+			head.coupled_streams = head.channels.saturating_sub(1);
+			head.mapping = (0..head.channels).collect();
+		} else {
+			let end = 21 + head.channels as usize;
+			if data.len() < end {
+				return malformed("truncated mapping table");
+			}
+			head.streams = data[19];
+			head.coupled_streams = data[20];
+			head.mapping = data[21..end].to_vec();
+		}
+		match head.check() {
+			Ok(()) => Ok(head),
+			Err(context) => malformed(context),
+		}
+	}
+
+	/// Serialize the header in the little-endian layout used by Ogg and WebM.
+	pub fn to_bytes(&self) -> Vec<u8> {
+		let mut data = Vec::with_capacity(21 + self.mapping.len());
+		data.extend_from_slice(MAGIC);
+		data.push(1);
+		data.push(self.channels);
+		data.extend_from_slice(&self.pre_skip.to_le_bytes());
+		data.extend_from_slice(&self.input_sample_rate.to_le_bytes());
+		data.extend_from_slice(&self.output_gain.to_le_bytes());
+		data.push(self.mapping_family);
+		if self.mapping_family != 0 {
+			data.push(self.streams);
+			data.push(self.coupled_streams);
+			data.extend_from_slice(&self.mapping);
+		}
+		data
+	}
+
+	/// Create a decoder for the stream at the given sample rate, with the
+	/// output gain applied.
+	///
+	/// A multistream decoder is used for every mapping family, so that all
+	/// streams can be handled the same way.
+	pub fn decoder(&self, sample_rate: u32) -> Result<MSDecoder> {
+		let mut decoder =
+			MSDecoder::new(sample_rate, self.streams, self.coupled_streams, &self.mapping)?;
+		if self.output_gain != 0 {
+			decoder.set_gain(self.output_gain as i32)?;
+		}
+		Ok(decoder)
+	}
+
+	/// Get the output gain in dB.
+	pub fn output_gain_db(&self) -> f64 {
+		self.output_gain as f64 / 256.0
+	}
+
+	/// Get the output gain as a linear factor to multiply samples by.
+	pub fn output_gain_factor(&self) -> f32 {
+		10f64.powf(self.output_gain_db() / 20.0) as f32
+	}
+
+	fn check(&self) -> std::result::Result<(), &'static str> {
+		if self.channels == 0 {
+			return Err("no channels");
+		}
+		if self.mapping_family == 0 {
+			if self.channels > 2 {
+				return Err("mapping family 0 is limited to two channels");
+			}
+			if self.streams != 1
+				|| self.coupled_streams != self.channels - 1
+				|| self.mapping.iter().enumerate().any(|(i, &c)| c as usize != i)
+			{
+				return Err("mapping family 0 requires the default mapping");
+			}
+		}
+		if self.streams == 0 || self.coupled_streams > self.streams {
+			return Err("invalid stream counts");
+		}
+		let decoded = self.streams as usize + self.coupled_streams as usize;
+		if decoded > 255 {
+			return Err("too many streams");
+		}
+		if self.mapping.len() != self.channels as usize {
+			return Err("mapping length differs from channel count");
+		}
+		if self.mapping.iter().any(|&c| c != 255 && c as usize >= decoded) {
+			return Err("mapping refers to a missing channel");
+		}
+		Ok(())
+	}
+}
+
+fn to_48k(samples: i32, sample_rate: u32) -> u16 {
+	let scaled = samples.max(0) as u64 * 48000 / sample_rate as u64;
+	scaled.min(u16::MAX as u64) as u16
+}
@@ -0,0 +1,942 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Reading and writing uncompressed PCM audio files.
+//!
+//! `PcmReader` reads WAV files, including `WAVE_FORMAT_EXTENSIBLE` headers
+//! with channel masks and RF64 files over 4 GB, and Core Audio Format (CAF)
+//! files with linear PCM data. Samples in any supported format are converted
+//! to `i16` or `f32` as they are read.
+//!
+//! `StreamLayout` chooses the multistream configuration for a file's channels,
+//! reordering the channels given by a WAV channel mask into the Vorbis order
+//! used by channel mapping family 1, and `WavWriter` writes decoded audio.
+
+use super::opus_head::OpusHead;
+use super::{Application, Error, ErrorKind, FrameSize, MSEncoder, Result, Sample};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+/// The format tag for integer PCM.
+const FORMAT_PCM: u16 = 1;
+/// The format tag for IEEE floating-point PCM.
+const FORMAT_FLOAT: u16 = 3;
+/// The format tag for a header with a channel mask and a subformat.
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+/// The end of the subformat GUID following its format tag.
+const SUBFORMAT_GUID: [u8; 14] =
+	[0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71];
+/// The size of the `ds64` chunk written for RF64 files.
+const DS64_SIZE: u32 = 28;
+/// The CAF channel layout tag for a layout given by a channel bitmap.
+const CAF_USE_BITMAP: u32 = 1 << 16;
+/// The CAF channel layout tags for mono and stereo.
+const CAF_MONO: u32 = 100 << 16 | 1;
+const CAF_STEREO: u32 = 101 << 16 | 2;
+
+// WAV channel mask bits. CAF channel bitmaps use the same bits.
+const FRONT_LEFT: u32 = 0x1;
+const FRONT_RIGHT: u32 = 0x2;
+const FRONT_CENTER: u32 = 0x4;
+const LFE: u32 = 0x8;
+const BACK_LEFT: u32 = 0x10;
+const BACK_RIGHT: u32 = 0x20;
+const BACK_CENTER: u32 = 0x100;
+const SIDE_LEFT: u32 = 0x200;
+const SIDE_RIGHT: u32 = 0x400;
+
+/// The container a `PcmReader` found.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Container {
+	/// A RIFF WAVE file.
+	Wav,
+	/// An RF64 WAVE file, with 64-bit sizes.
+	Rf64,
+	/// A Core Audio Format file.
+	Caf,
+	/// Headerless samples in a format given by the caller.
+	Raw,
+}
+
+/// The encoding of the samples in a PCM file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum SampleFormat {
+	/// Unsigned 8-bit integers.
+	U8,
+	/// Signed 16-bit integers.
+	I16,
+	/// Signed 24-bit integers.
+	I24,
+	/// Signed 32-bit integers.
+	I32,
+	/// 32-bit floats.
+	F32,
+	/// 64-bit floats.
+	F64,
+}
+
+impl SampleFormat {
+	/// Get the number of bytes each sample takes.
+	pub fn bytes(self) -> usize {
+		match self {
+			SampleFormat::U8 => 1,
+			SampleFormat::I16 => 2,
+			SampleFormat::I24 => 3,
+			SampleFormat::I32 | SampleFormat::F32 => 4,
+			SampleFormat::F64 => 8,
+		}
+	}
+
+	/// Check whether the samples are floating-point.
+	pub fn is_float(self) -> bool {
+		matches!(self, SampleFormat::F32 | SampleFormat::F64)
+	}
+
+	fn from_bytes(bytes: usize, float: bool) -> Option<SampleFormat> {
+		match (bytes, float) {
+			(1, false) => Some(SampleFormat::U8),
+			(2, false) => Some(SampleFormat::I16),
+			(3, false) => Some(SampleFormat::I24),
+			(4, false) => Some(SampleFormat::I32),
+			(4, true) => Some(SampleFormat::F32),
+			(8, true) => Some(SampleFormat::F64),
+			_ => None,
+		}
+	}
+
+	/// Convert one sample, which must be `self.bytes()` long.
+	fn read(self, data: &[u8], big_endian: bool) -> f32 {
+		let mut bytes = [0; 8];
+		let len = data.len();
+		if big_endian {
+			bytes[..len].copy_from_slice(data);
+		} else {
+			for (i, &b) in data.iter().enumerate() {
+				bytes[len - 1 - i] = b;
+			}
+		}
+		// the sample is now big-endian at the start of `bytes`
+		match self {
+			SampleFormat::U8 => (bytes[0] as f32 - 128.0) / 128.0,
+			SampleFormat::I16 => i16::from_be_bytes([bytes[0], bytes[1]]) as f32 / 32768.0,
+			SampleFormat::I24 => {
+				(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], 0]) >> 8) as f32 / 8_388_608.0
+			}
+			SampleFormat::I32 => {
+				i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as f32
+					/ 2_147_483_648.0
+			}
+			SampleFormat::F32 => f32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+			SampleFormat::F64 => f64::from_be_bytes(bytes) as f32,
+		}
+	}
+
+	/// Append one sample in little-endian order.
+	fn write(self, value: f32, output: &mut Vec<u8>) {
+		let int = |scale: f64, min: f64, max: f64| (value as f64 * scale).round().clamp(min, max);
+		match self {
+			SampleFormat::U8 => output.push((int(128.0, -128.0, 127.0) + 128.0) as u8),
+			SampleFormat::I16 => {
+				output.extend_from_slice(&(int(32768.0, -32768.0, 32767.0) as i16).to_le_bytes())
+			}
+			SampleFormat::I24 => {
+				let value = int(8_388_608.0, -8_388_608.0, 8_388_607.0) as i32;
+				output.extend_from_slice(&value.to_le_bytes()[..3]);
+			}
+			SampleFormat::I32 => {
+				let value = int(2_147_483_648.0, -2_147_483_648.0, 2_147_483_647.0) as i32;
+				output.extend_from_slice(&value.to_le_bytes());
+			}
+			SampleFormat::F32 => output.extend_from_slice(&value.to_le_bytes()),
+			SampleFormat::F64 => output.extend_from_slice(&(value as f64).to_le_bytes()),
+		}
+	}
+}
+
+/// The format of the audio in a PCM file.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct PcmFormat {
+	/// The number of interleaved channels.
+	pub channels: u16,
+	/// The sample rate in Hz.
+	pub sample_rate: u32,
+	/// The encoding of each sample.
+	pub sample_format: SampleFormat,
+	/// The speaker positions of the channels, as a WAV channel mask, if the
+	/// file gives them.
+	pub channel_mask: Option<u32>,
+}
+
+impl PcmFormat {
+	/// Get the number of bytes each frame of samples for all channels takes.
+	pub fn frame_bytes(&self) -> usize {
+		self.channels as usize * self.sample_format.bytes()
+	}
+}
+
+/// Reads a WAV, RF64 or CAF file containing PCM audio.
+///
+/// The headers are read up to the start of the audio data, so the stream
+/// need not be seekable.
+#[derive(Debug)]
+pub struct PcmReader<R: Read> {
+	reader: R,
+	container: Container,
+	format: PcmFormat,
+	big_endian: bool,
+	/// The bytes left in the data chunk, or `None` if it runs to the end of
+	/// the stream.
+	remaining: Option<u64>,
+	/// For each output channel, the file channel it is read from.
+	order: Option<Vec<usize>>,
+	buffer: Vec<u8>,
+}
+
+impl<R: Read> PcmReader<R> {
+	/// Read the headers of a WAV, RF64 or CAF file.
+	pub fn new(mut reader: R) -> Result<PcmReader<R>> {
+		let mut magic = [0; 4];
+		reader.read_exact(&mut magic)?;
+		match &magic {
+			b"RIFF" | b"RF64" | b"BW64" => read_wav(reader, &magic != b"RIFF"),
+			b"caff" => read_caf(reader),
+			_ => Err(malformed("not a WAV or CAF file")),
+		}
+	}
+
+	/// Read headerless samples in the given format until the end of the
+	/// stream.
+	pub fn raw(reader: R, format: &PcmFormat, big_endian: bool) -> Result<PcmReader<R>> {
+		if format.channels == 0 {
+			return Err(Error::bad_arg("PcmReader::raw").with_context("no channels"));
+		}
+		Ok(PcmReader {
+			reader,
+			container: Container::Raw,
+			format: *format,
+			big_endian,
+			remaining: None,
+			order: None,
+			buffer: Vec::new(),
+		})
+	}
+
+	/// Get the container the file uses.
+	pub fn container(&self) -> Container {
+		self.container
+	}
+
+	/// Get the format of the audio.
+	pub fn format(&self) -> &PcmFormat {
+		&self.format
+	}
+
+	/// Get the number of frames left to read, if the header gives the length
+	/// of the audio data.
+	pub fn frames(&self) -> Option<u64> {
+		self.remaining.map(|bytes| bytes / self.format.frame_bytes() as u64)
+	}
+
+	/// Reorder the channels as they are read to suit a multistream layout.
+	pub fn reorder(&mut self, layout: &StreamLayout) -> Result<()> {
+		if layout.order.len() != self.format.channels as usize {
+			return Err(Error::new(
+				"PcmReader::reorder",
+				ErrorKind::ChannelMismatch {
+					expected: self.format.channels as usize,
+					actual: layout.order.len(),
+				},
+			));
+		}
+		self.order = Some(layout.order.clone());
+		Ok(())
+	}
+
+	/// Read interleaved samples, converting them to the output type.
+	///
+	/// Returns the number of samples *per channel* read, which is only less
+	/// than fits in `output` at the end of the audio.
+	pub fn read<T: Sample>(&mut self, output: &mut [T]) -> Result<usize> {
+		let channels = self.format.channels as usize;
+		let frame_bytes = self.format.frame_bytes();
+		let mut want = (output.len() / channels * frame_bytes) as u64;
+		if let Some(remaining) = self.remaining {
+			want = want.min(remaining);
+		}
+		self.buffer.resize(want as usize, 0);
+		let mut read = 0;
+		while read < self.buffer.len() {
+			match self.reader.read(&mut self.buffer[read..]) {
+				Ok(0) => break,
+				Ok(n) => read += n,
+				Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+				Err(err) => return Err(err.into()),
+			}
+		}
+		// a partial frame at the end of a truncated file is dropped
+		let frames = read / frame_bytes;
+		self.remaining = match self.remaining {
+			Some(_) if read < self.buffer.len() => Some(0),
+			Some(remaining) => Some(remaining - read as u64),
+			None => None,
+		};
+
+		let bytes = self.format.sample_format.bytes();
+		for (frame, data) in
+			output.chunks_mut(channels).zip(self.buffer.chunks(frame_bytes)).take(frames)
+		{
+			for (i, sample) in frame.iter_mut().enumerate() {
+				let source = match self.order {
+					Some(ref order) => order[i],
+					None => i,
+				};
+				let data = &data[source * bytes..(source + 1) * bytes];
+				*sample = T::from_f32(self.format.sample_format.read(data, self.big_endian));
+			}
+		}
+		Ok(frames)
+	}
+
+	/// Read the rest of the audio.
+	pub fn read_all<T: Sample>(&mut self) -> Result<Vec<T>> {
+		let channels = self.format.channels as usize;
+		let mut output = Vec::new();
+		let mut buffer = vec![T::from_f32(0.0); 4096 * channels];
+		loop {
+			let frames = self.read(&mut buffer)?;
+			output.extend_from_slice(&buffer[..frames * channels]);
+			if frames < 4096 {
+				return Ok(output);
+			}
+		}
+	}
+
+	/// Read one frame of audio and encode it.
+	///
+	/// The last frame is padded with silence, and `None` is returned once the
+	/// audio is used up. The encoder's channels and sample rate must match
+	/// the file's.
+	pub fn encode_packet<T: Sample>(
+		&mut self,
+		encoder: &mut MSEncoder,
+		frame_size: FrameSize,
+	) -> Result<Option<Vec<u8>>> {
+		let channels = self.format.channels as usize;
+		if encoder.channels as usize != channels {
+			return Err(Error::new(
+				"PcmReader::encode_packet",
+				ErrorKind::ChannelMismatch {
+					expected: channels,
+					actual: encoder.channels as usize,
+				},
+			));
+		}
+		let sample_rate = encoder.get_sample_rate()?;
+		if sample_rate != self.format.sample_rate {
+			return Err(
+				Error::bad_arg("PcmReader::encode_packet").with_context("sample rates differ")
+			);
+		}
+		let frame_len = match frame_size.samples(sample_rate) {
+			Some(samples) => samples,
+			None => {
+				return Err(Error::new(
+					"PcmReader::encode_packet",
+					ErrorKind::UnsupportedFrameSize { frame_size, sample_rate },
+				))
+			}
+		};
+
+		let mut input = vec![T::from_f32(0.0); frame_len * channels];
+		if self.read(&mut input)? == 0 {
+			return Ok(None);
+		}
+		let mut output = vec![0; super::MAX_PACKET_SIZE];
+		let len = T::ms_encode(encoder, &input, &mut output)?;
+		output.truncate(len);
+		Ok(Some(output))
+	}
+
+	/// Unwrap the underlying stream, positioned within the audio data.
+	pub fn into_inner(self) -> R {
+		self.reader
+	}
+}
+
+/// How a file's channels are coded as Opus streams.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct StreamLayout {
+	/// The channel mapping family: 0 for mono or stereo, 1 for the surround
+	/// layouts with a Vorbis channel order, or 255 for anything else.
+	pub mapping_family: u8,
+	/// The number of Opus streams.
+	pub streams: u8,
+	/// The number of streams which are coupled stereo pairs.
+	pub coupled_streams: u8,
+	/// The mapping from output channels to decoded channels.
+	pub mapping: Vec<u8>,
+	/// For each channel in the encoder's order, the file channel it is taken
+	/// from.
+	pub order: Vec<usize>,
+}
+
+impl StreamLayout {
+	/// Choose the layout for a file's channels.
+	///
+	/// Three to eight channels use family 1 if their channel mask, or the
+	/// default mask for their count, gives the speakers of a Vorbis layout.
+	/// Other layouts use family 255, with every channel coded on its own.
+	pub fn for_format(format: &PcmFormat) -> Result<StreamLayout> {
+		let channels = format.channels as usize;
+		let family = if channels <= 2 {
+			0
+		} else if channels <= 8 && vorbis_order(channels, mask(format)).is_some() {
+			1
+		} else {
+			255
+		};
+		StreamLayout::with_family(format, family)
+	}
+
+	/// Choose the layout for a file's channels using the given mapping
+	/// family.
+	///
+	/// Family 0 is limited to mono and stereo, and family 1 to the Vorbis
+	/// layouts of one to eight channels. Family 255 codes every channel on
+	/// its own, in the file's order.
+	pub fn with_family(format: &PcmFormat, mapping_family: u8) -> Result<StreamLayout> {
+		let channels = format.channels as usize;
+		let unsupported =
+			|context| Err(Error::bad_arg("StreamLayout::with_family").with_context(context));
+		if channels == 0 || channels > 255 {
+			return unsupported("unsupported channel count");
+		}
+		let identity = (0..channels).collect();
+		match mapping_family {
+			0 if channels <= 2 => Ok(StreamLayout {
+				mapping_family: 0,
+				streams: 1,
+				coupled_streams: channels as u8 - 1,
+				mapping: (0..channels as u8).collect(),
+				order: identity,
+			}),
+			1 if channels <= 8 => {
+				let order = if channels <= 2 {
+					Some(identity)
+				} else {
+					vorbis_order(channels, mask(format))
+				};
+				let order = match order {
+					Some(order) => order,
+					None => return unsupported("channel mask does not fit a Vorbis layout"),
+				};
+				let (streams, coupled_streams, mapping) = VORBIS_MAPPINGS[channels - 1];
+				Ok(StreamLayout {
+					mapping_family: 1,
+					streams,
+					coupled_streams,
+					mapping: mapping.to_vec(),
+					order,
+				})
+			}
+			255 => Ok(StreamLayout {
+				mapping_family: 255,
+				streams: channels as u8,
+				coupled_streams: 0,
+				mapping: (0..channels as u8).collect(),
+				order: identity,
+			}),
+			0 | 1 => unsupported("too many channels for the mapping family"),
+			_ => unsupported("unsupported mapping family"),
+		}
+	}
+
+	/// Create a multistream encoder for this layout.
+	pub fn encoder(&self, sample_rate: u32, application: Application) -> Result<MSEncoder> {
+		MSEncoder::new(sample_rate, self.streams, self.coupled_streams, &self.mapping, application)
+	}
+
+	/// Create the `OpusHead` describing an encoder made by `encoder`.
+	pub fn head(&self, encoder: &mut MSEncoder) -> Result<OpusHead> {
+		OpusHead::for_ms_encoder(
+			encoder,
+			self.mapping_family,
+			self.streams,
+			self.coupled_streams,
+			&self.mapping,
+		)
+	}
+}
+
+/// The stream counts and mappings of channel mapping family 1, from RFC 7845.
+const VORBIS_MAPPINGS: [(u8, u8, &[u8]); 8] = [
+	(1, 0, &[0]),
+	(1, 1, &[0, 1]),
+	(2, 1, &[0, 2, 1]),
+	(2, 2, &[0, 1, 2, 3]),
+	(3, 2, &[0, 4, 1, 2, 3]),
+	(4, 2, &[0, 4, 1, 2, 3, 5]),
+	(4, 3, &[0, 4, 1, 2, 3, 5, 6]),
+	(5, 3, &[0, 6, 1, 2, 3, 4, 5, 7]),
+];
+
+/// Get a format's channel mask, or the default for its channel count.
+fn mask(format: &PcmFormat) -> u32 {
+	format.channel_mask.unwrap_or_else(|| default_mask(format.channels as usize))
+}
+
+/// The channel mask WAV assumes for files which don't give one.
+fn default_mask(channels: usize) -> u32 {
+	match channels {
+		1 => FRONT_CENTER,
+		2 => FRONT_LEFT | FRONT_RIGHT,
+		3 => FRONT_LEFT | FRONT_RIGHT | FRONT_CENTER,
+		4 => FRONT_LEFT | FRONT_RIGHT | BACK_LEFT | BACK_RIGHT,
+		5 => FRONT_LEFT | FRONT_RIGHT | FRONT_CENTER | BACK_LEFT | BACK_RIGHT,
+		6 => FRONT_LEFT | FRONT_RIGHT | FRONT_CENTER | LFE | BACK_LEFT | BACK_RIGHT,
+		7 => FRONT_LEFT | FRONT_RIGHT | FRONT_CENTER | LFE | BACK_CENTER | SIDE_LEFT | SIDE_RIGHT,
+		8 => {
+			FRONT_LEFT
+				| FRONT_RIGHT
+				| FRONT_CENTER
+				| LFE | BACK_LEFT
+				| BACK_RIGHT | SIDE_LEFT
+				| SIDE_RIGHT
+		}
+		_ => 0,
+	}
+}
+
+/// Find the file channel for each position of the Vorbis layout with this
+/// many channels, or `None` if the mask doesn't match it.
+fn vorbis_order(channels: usize, mask: u32) -> Option<Vec<usize>> {
+	// each position lists the speakers which may fill it, in preference order
+	const REAR_LEFT: &[u32] = &[BACK_LEFT, SIDE_LEFT];
+	const REAR_RIGHT: &[u32] = &[BACK_RIGHT, SIDE_RIGHT];
+	let positions: &[&[u32]] = match channels {
+		3 => &[&[FRONT_LEFT], &[FRONT_CENTER], &[FRONT_RIGHT]],
+		4 => &[&[FRONT_LEFT], &[FRONT_RIGHT], REAR_LEFT, REAR_RIGHT],
+		5 => &[&[FRONT_LEFT], &[FRONT_CENTER], &[FRONT_RIGHT], REAR_LEFT, REAR_RIGHT],
+		6 => &[&[FRONT_LEFT], &[FRONT_CENTER], &[FRONT_RIGHT], REAR_LEFT, REAR_RIGHT, &[LFE]],
+		7 => &[
+			&[FRONT_LEFT],
+			&[FRONT_CENTER],
+			&[FRONT_RIGHT],
+			&[SIDE_LEFT],
+			&[SIDE_RIGHT],
+			&[BACK_CENTER],
+			&[LFE],
+		],
+		8 => &[
+			&[FRONT_LEFT],
+			&[FRONT_CENTER],
+			&[FRONT_RIGHT],
+			&[SIDE_LEFT],
+			&[SIDE_RIGHT],
+			&[BACK_LEFT],
+			&[BACK_RIGHT],
+			&[LFE],
+		],
+		_ => return None,
+	};
+	if mask.count_ones() as usize != channels {
+		return None;
+	}
+	let mut used = 0;
+	let mut order = Vec::with_capacity(channels);
+	for speakers in positions {
+		let bit = *speakers.iter().find(|&&bit| mask & bit != 0 && used & bit == 0)?;
+		used |= bit;
+		// file channels are in increasing order of their mask bits
+		order.push((mask & (bit - 1)).count_ones() as usize);
+	}
+	Some(order)
+}
+
+/// Writes a WAV file.
+///
+/// The header is written with placeholder sizes which `finish` fills in. If
+/// the audio grows past 4 GB the file becomes an RF64 file, using space
+/// reserved in the header for its 64-bit sizes. Outputs which can't seek,
+/// such as pipes, are written with `streaming` instead.
+#[derive(Debug)]
+pub struct WavWriter<W: Write> {
+	writer: W,
+	format: PcmFormat,
+	/// The absolute position of the start of the file.
+	start: u64,
+	/// The offset of the data chunk's size from the start of the file.
+	data_size_at: u64,
+	data_len: u64,
+	buffer: Vec<u8>,
+}
+
+impl<W: Write> WavWriter<W> {
+	/// Start a WAV file on an output which can't seek, such as a pipe.
+	///
+	/// The sizes in the header are left at their maximum, which readers take
+	/// to mean that the data runs to the end of the stream. Finish the file
+	/// with `finish_streaming`.
+	pub fn streaming(mut writer: W, format: &PcmFormat) -> Result<WavWriter<W>> {
+		let (mut header, data_size_at) = wav_header("WavWriter::streaming", format)?;
+		let at = data_size_at as usize;
+		header[4..8].copy_from_slice(&u32::MAX.to_le_bytes());
+		header[at..at + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+		writer.write_all(&header)?;
+		Ok(WavWriter {
+			writer,
+			format: *format,
+			start: 0,
+			data_size_at,
+			data_len: 0,
+			buffer: Vec::new(),
+		})
+	}
+
+	/// Get the format being written.
+	pub fn format(&self) -> &PcmFormat {
+		&self.format
+	}
+
+	/// Write interleaved samples, converting them to the file's format.
+	pub fn write<T: Sample>(&mut self, samples: &[T]) -> Result<()> {
+		super::check_frame_len("WavWriter::write", samples.len(), self.format.channels as usize)?;
+		self.buffer.clear();
+		for &sample in samples {
+			self.format.sample_format.write(sample.to_f32(), &mut self.buffer);
+		}
+		self.writer.write_all(&self.buffer)?;
+		self.data_len += self.buffer.len() as u64;
+		Ok(())
+	}
+
+	/// Finish a file started with `streaming`.
+	///
+	/// The data is not padded to an even length, as a reader would take the
+	/// padding for audio.
+	pub fn finish_streaming(mut self) -> Result<W> {
+		self.writer.flush()?;
+		Ok(self.writer)
+	}
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+	/// Start a WAV file with the given format.
+	///
+	/// An extensible header is written when there are more than two
+	/// channels, more than 16 bits per sample or a channel mask.
+	pub fn new(mut writer: W, format: &PcmFormat) -> Result<WavWriter<W>> {
+		let start = writer.stream_position()?;
+		let (header, data_size_at) = wav_header("WavWriter::new", format)?;
+		writer.write_all(&header)?;
+
+		Ok(WavWriter {
+			writer,
+			format: *format,
+			start,
+			data_size_at,
+			data_len: 0,
+			buffer: Vec::new(),
+		})
+	}
+
+	/// Finish the file, filling in the sizes in its header.
+	pub fn finish(mut self) -> Result<W> {
+		if self.data_len % 2 != 0 {
+			self.writer.write_all(&[0])?;
+		}
+		let end = self.writer.stream_position()?;
+		let riff_len = end - self.start - 8;
+
+		if riff_len <= u32::MAX as u64 && self.data_len < u32::MAX as u64 {
+			self.writer.seek(SeekFrom::Start(self.start + 4))?;
+			self.writer.write_all(&(riff_len as u32).to_le_bytes())?;
+			self.writer.seek(SeekFrom::Start(self.start + self.data_size_at))?;
+			self.writer.write_all(&(self.data_len as u32).to_le_bytes())?;
+		} else {
+			let frames = self.data_len / self.format.frame_bytes() as u64;
+			let mut ds64 = b"ds64".to_vec();
+			ds64.extend_from_slice(&DS64_SIZE.to_le_bytes());
+			ds64.extend_from_slice(&riff_len.to_le_bytes());
+			ds64.extend_from_slice(&self.data_len.to_le_bytes());
+			ds64.extend_from_slice(&frames.to_le_bytes());
+			ds64.extend_from_slice(&0u32.to_le_bytes());
+			self.writer.seek(SeekFrom::Start(self.start))?;
+			self.writer.write_all(b"RF64")?;
+			self.writer.write_all(&u32::MAX.to_le_bytes())?;
+			self.writer.seek(SeekFrom::Start(self.start + 12))?;
+			self.writer.write_all(&ds64)?;
+			self.writer.seek(SeekFrom::Start(self.start + self.data_size_at))?;
+			self.writer.write_all(&u32::MAX.to_le_bytes())?;
+		}
+		self.writer.seek(SeekFrom::Start(end))?;
+		self.writer.flush()?;
+		Ok(self.writer)
+	}
+}
+
+/// Build a WAV header with placeholder sizes, returning it with the offset of
+/// the data chunk's size.
+fn wav_header(what: &'static str, format: &PcmFormat) -> Result<(Vec<u8>, u64)> {
+	if format.channels == 0 {
+		return Err(Error::bad_arg(what).with_context("no channels"));
+	}
+	let bytes = format.sample_format.bytes();
+	let tag = if format.sample_format.is_float() { FORMAT_FLOAT } else { FORMAT_PCM };
+	let extensible = format.channels > 2 || bytes > 2 || format.channel_mask.is_some();
+
+	let mut fmt = Vec::new();
+	fmt.extend_from_slice(&if extensible { FORMAT_EXTENSIBLE } else { tag }.to_le_bytes());
+	fmt.extend_from_slice(&format.channels.to_le_bytes());
+	fmt.extend_from_slice(&format.sample_rate.to_le_bytes());
+	fmt.extend_from_slice(&(format.sample_rate * format.frame_bytes() as u32).to_le_bytes());
+	fmt.extend_from_slice(&(format.frame_bytes() as u16).to_le_bytes());
+	fmt.extend_from_slice(&(bytes as u16 * 8).to_le_bytes());
+	if extensible {
+		let mask = format.channel_mask.unwrap_or_else(|| default_mask(format.channels as usize));
+		fmt.extend_from_slice(&22u16.to_le_bytes());
+		fmt.extend_from_slice(&(bytes as u16 * 8).to_le_bytes());
+		fmt.extend_from_slice(&mask.to_le_bytes());
+		fmt.extend_from_slice(&tag.to_le_bytes());
+		fmt.extend_from_slice(&SUBFORMAT_GUID);
+	} else if tag == FORMAT_FLOAT {
+		fmt.extend_from_slice(&0u16.to_le_bytes());
+	}
+
+	let mut header = Vec::new();
+	header.extend_from_slice(b"RIFF");
+	header.extend_from_slice(&0u32.to_le_bytes());
+	header.extend_from_slice(b"WAVE");
+	// room for a ds64 chunk, should the file need one
+	header.extend_from_slice(b"JUNK");
+	header.extend_from_slice(&DS64_SIZE.to_le_bytes());
+	header.extend_from_slice(&[0; DS64_SIZE as usize]);
+	header.extend_from_slice(b"fmt ");
+	header.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+	header.extend_from_slice(&fmt);
+	header.extend_from_slice(b"data");
+	let data_size_at = header.len() as u64;
+	header.extend_from_slice(&0u32.to_le_bytes());
+	Ok((header, data_size_at))
+}
+
+fn read_wav<R: Read>(mut reader: R, rf64: bool) -> Result<PcmReader<R>> {
+	let mut header = [0; 8];
+	reader.read_exact(&mut header)?;
+	if &header[4..] != b"WAVE" {
+		return Err(malformed("not a WAV file"));
+	}
+
+	let mut format = None;
+	let mut data_size64 = None;
+	loop {
+		reader.read_exact(&mut header)?;
+		let size = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+		// chunks are padded to an even length
+		let padding = (size & 1) as u64;
+		match &header[..4] {
+			b"fmt " => {
+				format = Some(parse_fmt(&read_chunk(&mut reader, size as u64)?)?);
+				skip(&mut reader, padding)?;
+			}
+			b"ds64" if rf64 => {
+				let ds64 = read_chunk(&mut reader, size as u64)?;
+				skip(&mut reader, padding)?;
+				if ds64.len() < 16 {
+					return Err(malformed("truncated ds64 chunk"));
+				}
+				let mut bytes = [0; 8];
+				bytes.copy_from_slice(&ds64[8..16]);
+				data_size64 = Some(u64::from_le_bytes(bytes));
+			}
+			b"data" => {
+				let format = match format {
+					Some(format) => format,
+					None => return Err(malformed("data chunk before fmt chunk")),
+				};
+				let remaining = match size {
+					u32::MAX if rf64 => match data_size64 {
+						Some(size) => Some(size),
+						None => return Err(malformed("missing ds64 chunk")),
+					},
+					// written by a streaming encoder which never filled it in
+					0 | u32::MAX => None,
+					size => Some(size as u64),
+				};
+				return Ok(PcmReader {
+					reader,
+					container: if rf64 { Container::Rf64 } else { Container::Wav },
+					format,
+					big_endian: false,
+					remaining,
+					order: None,
+					buffer: Vec::new(),
+				});
+			}
+			_ => skip(&mut reader, size as u64 + padding)?,
+		}
+	}
+}
+
+fn parse_fmt(fmt: &[u8]) -> Result<PcmFormat> {
+	if fmt.len() < 16 {
+		return Err(malformed("truncated fmt chunk"));
+	}
+	let u16_at = |at: usize| u16::from_le_bytes([fmt[at], fmt[at + 1]]);
+	let mut tag = u16_at(0);
+	let channels = u16_at(2);
+	let sample_rate = u32::from_le_bytes([fmt[4], fmt[5], fmt[6], fmt[7]]);
+	let block_align = u16_at(12) as usize;
+	let mut channel_mask = None;
+	if tag == FORMAT_EXTENSIBLE {
+		if fmt.len() < 40 {
+			return Err(malformed("truncated fmt chunk"));
+		}
+		let mask = u32::from_le_bytes([fmt[20], fmt[21], fmt[22], fmt[23]]);
+		if mask != 0 {
+			channel_mask = Some(mask);
+		}
+		tag = u16_at(24);
+	}
+	if channels == 0 || sample_rate == 0 || block_align % channels as usize != 0 {
+		return Err(malformed("invalid fmt chunk"));
+	}
+	// the container size is used, so 20-bit samples in 24 bits are read too
+	let bytes = block_align / channels as usize;
+	let sample_format = match tag {
+		FORMAT_PCM => SampleFormat::from_bytes(bytes, false),
+		FORMAT_FLOAT => SampleFormat::from_bytes(bytes, true),
+		_ => None,
+	};
+	match sample_format {
+		Some(sample_format) => Ok(PcmFormat {
+			channels,
+			sample_rate,
+			sample_format,
+			channel_mask,
+		}),
+		None => Err(malformed("unsupported sample format")),
+	}
+}
+
+fn read_caf<R: Read>(mut reader: R) -> Result<PcmReader<R>> {
+	let mut header = [0; 12];
+	reader.read_exact(&mut header[..4])?;
+	if header[..2] != [0, 1] {
+		return Err(malformed("unsupported CAF version"));
+	}
+
+	let mut description = None;
+	let mut channel_mask = None;
+	loop {
+		reader.read_exact(&mut header)?;
+		let mut size = [0; 8];
+		size.copy_from_slice(&header[4..]);
+		let size = i64::from_be_bytes(size);
+		match &header[..4] {
+			b"desc" => description = Some(read_chunk(&mut reader, size.max(0) as u64)?),
+			b"chan" => {
+				let chan = read_chunk(&mut reader, size.max(0) as u64)?;
+				if chan.len() < 8 {
+					return Err(malformed("truncated chan chunk"));
+				}
+				let tag = u32::from_be_bytes([chan[0], chan[1], chan[2], chan[3]]);
+				channel_mask = match tag {
+					CAF_USE_BITMAP => {
+						Some(u32::from_be_bytes([chan[4], chan[5], chan[6], chan[7]]))
+					}
+					CAF_MONO => Some(FRONT_CENTER),
+					CAF_STEREO => Some(FRONT_LEFT | FRONT_RIGHT),
+					_ => None,
+				};
+			}
+			b"data" => {
+				let description = match description {
+					Some(description) => description,
+					None => return Err(malformed("data chunk before desc chunk")),
+				};
+				let (mut format, big_endian) = parse_desc(&description)?;
+				format.channel_mask = channel_mask;
+				// the data starts with an edit count
+				skip(&mut reader, 4)?;
+				let remaining = if size < 0 { None } else { Some((size as u64).saturating_sub(4)) };
+				return Ok(PcmReader {
+					reader,
+					container: Container::Caf,
+					format,
+					big_endian,
+					remaining,
+					order: None,
+					buffer: Vec::new(),
+				});
+			}
+			_ if size < 0 => return Err(malformed("invalid chunk size")),
+			_ => skip(&mut reader, size as u64)?,
+		}
+	}
+}
+
+fn parse_desc(desc: &[u8]) -> Result<(PcmFormat, bool)> {
+	if desc.len() < 32 {
+		return Err(malformed("truncated desc chunk"));
+	}
+	let u32_at =
+		|at: usize| u32::from_be_bytes([desc[at], desc[at + 1], desc[at + 2], desc[at + 3]]);
+	let mut rate = [0; 8];
+	rate.copy_from_slice(&desc[..8]);
+	let sample_rate = f64::from_be_bytes(rate);
+	if &desc[8..12] != b"lpcm" {
+		return Err(malformed("unsupported CAF format"));
+	}
+	let flags = u32_at(12);
+	let bytes_per_packet = u32_at(16) as usize;
+	let frames_per_packet = u32_at(20);
+	let channels = u32_at(24);
+	if channels == 0
+		|| channels > u16::MAX as u32
+		|| frames_per_packet != 1
+		|| sample_rate < 1.0
+		|| sample_rate > u32::MAX as f64
+		|| bytes_per_packet % channels as usize != 0
+	{
+		return Err(malformed("invalid desc chunk"));
+	}
+	let sample_format =
+		match SampleFormat::from_bytes(bytes_per_packet / channels as usize, flags & 1 != 0) {
+			Some(sample_format) => sample_format,
+			None => return Err(malformed("unsupported sample format")),
+		};
+	let format = PcmFormat {
+		channels: channels as u16,
+		sample_rate: sample_rate.round() as u32,
+		sample_format,
+		channel_mask: None,
+	};
+	Ok((format, flags & 2 == 0))
+}
+
+fn read_chunk<R: Read>(reader: &mut R, size: u64) -> Result<Vec<u8>> {
+	// header chunks are small, so anything large is corrupt
+	if size > 1 << 20 {
+		return Err(malformed("header chunk too large"));
+	}
+	let mut data = vec![0; size as usize];
+	reader.read_exact(&mut data)?;
+	Ok(data)
+}
+
+fn skip<R: Read>(reader: &mut R, len: u64) -> Result<()> {
+	let skipped = io::copy(&mut reader.take(len), &mut io::sink())?;
+	if skipped < len {
+		return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+	}
+	Ok(())
+}
+
+fn malformed(context: &'static str) -> Error {
+	Error::new("PcmReader", ErrorKind::Malformed).with_context(context)
+}
@@ -0,0 +1,487 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Offline objective quality measurement.
+//!
+//! `roundtrip` encodes and decodes audio, removing the codec's delay so the
+//! output lines up with the input, and `score` compares the result against
+//! the original. A `Sweep` repeats this across encoder settings to produce a
+//! table of scores.
+//!
+//! These metrics are rough guides for comparing settings against each other.
+//! They are not substitutes for listening tests or for standardized models
+//! such as PESQ or POLQA.
+
+use super::conformance::{self, RATES};
+use super::resample::Resampler;
+use super::{
+	Application, Bitrate, Channels, Decoder, Encoder, Error, ErrorKind, FrameSize, Result, Sample,
+	MAX_PACKET_SIZE,
+};
+use std::f64::consts::PI;
+use std::fmt;
+
+/// The highest segmental SNR counted for one segment, in dB.
+const SEGMENT_MAX: f64 = 35.0;
+
+/// The lowest segmental SNR counted for one segment, in dB.
+const SEGMENT_MIN: f64 = -10.0;
+
+/// Segments whose mean square falls below this, about -80 dBFS, are treated
+/// as silence and skipped.
+const SILENCE: f64 = 1e-8;
+
+/// Added to spectral powers to keep logarithms finite.
+const POWER_FLOOR: f64 = 1e-10;
+
+/// Objective quality scores for decoded audio against the original.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Scores {
+	/// The signal-to-noise ratio over the whole signal, in dB.
+	pub snr: f64,
+	/// The mean signal-to-noise ratio of 20 ms segments, in dB.
+	pub segmental_snr: f64,
+	/// The mean log-spectral distance, in dB. Lower is better.
+	pub log_spectral_distance: f64,
+	/// A simplified perceptual score, the quality percentage from
+	/// `conformance::compare`. Higher is better, and negative values are
+	/// audibly degraded.
+	pub perceptual: f64,
+}
+
+/// Compute every score for decoded audio against the original.
+///
+/// Both buffers are interleaved with the given channel count, at the same
+/// sample rate, and must already be aligned, such as by `roundtrip`.
+pub fn score(
+	reference: &[f32],
+	degraded: &[f32],
+	channels: usize,
+	sample_rate: u32,
+) -> Result<Scores> {
+	check_pair("quality::score", reference, degraded, channels)?;
+	Ok(Scores {
+		snr: snr(reference, degraded),
+		segmental_snr: segmental_snr(reference, degraded, channels, sample_rate)?,
+		log_spectral_distance: log_spectral_distance(reference, degraded, channels, sample_rate)?,
+		perceptual: perceptual(reference, degraded, channels, sample_rate)?,
+	})
+}
+
+/// Compute the signal-to-noise ratio in dB, treating the difference between
+/// the buffers as noise.
+///
+/// Identical buffers give infinity.
+pub fn snr(reference: &[f32], degraded: &[f32]) -> f64 {
+	let (signal, noise) = energies(reference, degraded);
+	10.0 * (signal / noise).log10()
+}
+
+/// Compute the mean signal-to-noise ratio of 20 ms segments in dB.
+///
+/// Each segment is clamped to between -10 and 35 dB, so that a few very good
+/// or very bad segments don't dominate, and silent segments are skipped.
+/// Returns NaN if every segment is silent.
+pub fn segmental_snr(
+	reference: &[f32],
+	degraded: &[f32],
+	channels: usize,
+	sample_rate: u32,
+) -> Result<f64> {
+	check_pair("quality::segmental_snr", reference, degraded, channels)?;
+	let segment = (sample_rate as usize / 50).max(1) * channels;
+	let mut total = 0.0;
+	let mut count = 0;
+	for (reference, degraded) in reference.chunks(segment).zip(degraded.chunks(segment)) {
+		let (signal, noise) = energies(reference, degraded);
+		if signal / (reference.len() as f64) < SILENCE {
+			continue;
+		}
+		let snr = 10.0 * (signal / noise).log10();
+		total += snr.clamp(SEGMENT_MIN, SEGMENT_MAX);
+		count += 1;
+	}
+	Ok(total / count as f64)
+}
+
+/// Compute the mean log-spectral distance in dB.
+///
+/// Each channel is split into Hann-windowed frames of about 20 ms, and the
+/// root-mean-square difference between the log power spectra is averaged
+/// over the frames which are not silent in the reference. Returns NaN if
+/// every frame is silent.
+pub fn log_spectral_distance(
+	reference: &[f32],
+	degraded: &[f32],
+	channels: usize,
+	sample_rate: u32,
+) -> Result<f64> {
+	check_pair("quality::log_spectral_distance", reference, degraded, channels)?;
+	let size = (sample_rate as usize / 50).next_power_of_two();
+	let hop = size / 2;
+	let window: Vec<f64> =
+		(0..size).map(|i| 0.5 - 0.5 * (2.0 * PI * i as f64 / size as f64).cos()).collect();
+	let frames = reference.len() / channels;
+
+	let mut x = Spectrum::new(size);
+	let mut y = Spectrum::new(size);
+	let mut total = 0.0;
+	let mut count = 0;
+	for channel in 0..channels {
+		let mut start = 0;
+		while start + size <= frames {
+			let at = |i: usize| (start + i) * channels + channel;
+			let energy: f64 = (0..size).map(|i| (reference[at(i)] as f64).powi(2)).sum();
+			if energy / (size as f64) >= SILENCE {
+				x.compute((0..size).map(|i| reference[at(i)] as f64 * window[i]));
+				y.compute((0..size).map(|i| degraded[at(i)] as f64 * window[i]));
+				let bins = size / 2 + 1;
+				let mut sum = 0.0;
+				for bin in 0..bins {
+					let diff = 10.0
+						* ((x.power(bin) + POWER_FLOOR) / (y.power(bin) + POWER_FLOOR)).log10();
+					sum += diff * diff;
+				}
+				total += (sum / bins as f64).sqrt();
+				count += 1;
+			}
+			start += hop;
+		}
+	}
+	Ok(total / count as f64)
+}
+
+/// Compute a simplified perceptual score, the quality percentage from
+/// `conformance::compare`.
+///
+/// The reference is resampled to 48 kHz as `compare` requires, and the
+/// decoded audio is also resampled if `compare` does not support its rate
+/// directly. Only mono and stereo are supported, and at least 10 ms of audio
+/// is required.
+pub fn perceptual(
+	reference: &[f32],
+	degraded: &[f32],
+	channels: usize,
+	sample_rate: u32,
+) -> Result<f64> {
+	check_pair("quality::perceptual", reference, degraded, channels)?;
+	if channels > 2 {
+		return Err(Error::bad_arg("quality::perceptual")
+			.with_context("only mono and stereo are supported"));
+	}
+	let x = to_48k(reference, channels, sample_rate)?;
+	let comparison = if RATES.contains(&sample_rate) {
+		let y: Vec<f32> = degraded.iter().map(|&s| s * 32768.0).collect();
+		conformance::compare_f32(&x, &y, channels, sample_rate)?
+	} else {
+		let y = to_48k(degraded, channels, sample_rate)?;
+		conformance::compare_f32(&x, &y, channels, 48000)?
+	};
+	Ok(comparison.quality)
+}
+
+/// The output of `roundtrip`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Roundtrip {
+	/// The decoded audio, interleaved and aligned with the input, in the
+	/// range used by `decode_float`.
+	pub output: Vec<f32>,
+	/// The number of packets encoded.
+	pub packets: usize,
+	/// The total size of the packets, in bytes.
+	pub bytes: usize,
+	/// The bitrate actually produced, in bits per second.
+	pub bitrate: f64,
+}
+
+/// Encode interleaved audio, decode it again, and align the output with the
+/// input.
+///
+/// The input is padded with silence to flush out the codec's delay, as given
+/// by `get_lookahead` and `resampler_delay`, which is then trimmed from the
+/// start of the output. The output has the same length as the input. The
+/// encoder and decoder must have the same channel count, and the decoder's
+/// output rate must match the encoder's input rate.
+pub fn roundtrip<T: Sample>(
+	encoder: &mut Encoder,
+	decoder: &mut Decoder,
+	input: &[T],
+	frame_size: FrameSize,
+) -> Result<Roundtrip> {
+	let channels = encoder.channels as usize;
+	if decoder.channels != encoder.channels {
+		return Err(Error::bad_arg("quality::roundtrip").with_context("channel counts differ"));
+	}
+	let sample_rate = encoder.get_sample_rate()?;
+	if decoder.get_sample_rate()? != sample_rate {
+		return Err(Error::bad_arg("quality::roundtrip").with_context("sample rates differ"));
+	}
+	let frame_len = match frame_size.samples(sample_rate) {
+		Some(samples) => samples,
+		None => {
+			return Err(Error::new(
+				"quality::roundtrip",
+				ErrorKind::UnsupportedFrameSize { frame_size, sample_rate },
+			))
+		}
+	};
+	super::check_frame_len("quality::roundtrip", input.len(), channels)?;
+
+	let len = input.len() / channels;
+	let delay = encoder.get_lookahead()? as usize + decoder.resampler_delay();
+	let packets = (len + delay).div_ceil(frame_len);
+	let mut padded: Vec<f32> = input.iter().map(|&s| s.to_f32()).collect();
+	padded.resize(packets * frame_len * channels, 0.0);
+
+	let mut decoded = Vec::with_capacity(padded.len());
+	// room for 120 ms at the highest rate
+	let mut buffer = vec![0.0f32; 5760 * channels];
+	let mut bytes = 0;
+	for frame in padded.chunks(frame_len * channels) {
+		let packet = encoder.encode_vec_float(frame, MAX_PACKET_SIZE)?;
+		bytes += packet.len();
+		let samples = decoder.decode_float(&packet, &mut buffer, false)?;
+		decoded.extend_from_slice(&buffer[..samples * channels]);
+	}
+	// the resampler may hold back a few samples; treat them as silence
+	decoded.resize((delay + len) * channels, 0.0);
+
+	let duration = (packets * frame_len) as f64 / sample_rate as f64;
+	Ok(Roundtrip {
+		output: decoded.split_off(delay * channels),
+		packets,
+		bytes,
+		bitrate: bytes as f64 * 8.0 / duration,
+	})
+}
+
+/// Encoder settings to measure quality across.
+///
+/// Every combination of bitrate, complexity and frame size is measured.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sweep {
+	/// The application to create encoders with.
+	pub application: Application,
+	/// The bitrates to measure.
+	pub bitrates: Vec<Bitrate>,
+	/// The complexities to measure, from 0 to 10.
+	pub complexities: Vec<i32>,
+	/// The frame sizes to measure.
+	pub frame_sizes: Vec<FrameSize>,
+}
+
+impl Default for Sweep {
+	fn default() -> Sweep {
+		Sweep {
+			application: Application::Audio,
+			bitrates: vec![
+				Bitrate::Bits(16000),
+				Bitrate::Bits(32000),
+				Bitrate::Bits(64000),
+				Bitrate::Bits(128000),
+			],
+			complexities: vec![0, 5, 10],
+			frame_sizes: vec![FrameSize::Ms10, FrameSize::Ms20, FrameSize::Ms60],
+		}
+	}
+}
+
+impl Sweep {
+	/// Measure the quality of interleaved audio at every combination of
+	/// settings.
+	///
+	/// Fresh encoders and decoders are used for each combination, resampling
+	/// if Opus does not support `sample_rate` natively.
+	pub fn run<T: Sample>(
+		&self,
+		input: &[T],
+		sample_rate: u32,
+		channels: Channels,
+	) -> Result<Report> {
+		let reference: Vec<f32> = input.iter().map(|&s| s.to_f32()).collect();
+		let mut rows = Vec::new();
+		for &bitrate in &self.bitrates {
+			for &complexity in &self.complexities {
+				for &frame_size in &self.frame_sizes {
+					let mut encoder =
+						Encoder::with_input_rate(sample_rate, channels, self.application)?;
+					encoder.set_bitrate(bitrate)?;
+					encoder.set_complexity(complexity)?;
+					let mut decoder = Decoder::with_output_rate(sample_rate, channels)?;
+					let result = roundtrip(&mut encoder, &mut decoder, input, frame_size)?;
+					let scores = score(&reference, &result.output, channels as usize, sample_rate)?;
+					rows.push(Row {
+						bitrate,
+						complexity,
+						frame_size,
+						actual_bitrate: result.bitrate,
+						scores,
+					});
+				}
+			}
+		}
+		Ok(Report { rows })
+	}
+}
+
+/// The quality measured with one combination of settings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Row {
+	/// The requested bitrate.
+	pub bitrate: Bitrate,
+	/// The encoder complexity.
+	pub complexity: i32,
+	/// The frame size.
+	pub frame_size: FrameSize,
+	/// The bitrate actually produced, in bits per second.
+	pub actual_bitrate: f64,
+	/// The quality scores.
+	pub scores: Scores,
+}
+
+/// The results of a `Sweep`.
+///
+/// Displaying a report formats it as a plain-text table.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Report {
+	/// One row for each combination of settings, in the order measured.
+	pub rows: Vec<Row>,
+}
+
+impl Report {
+	/// Get the row with the highest perceptual score, if any.
+	pub fn best(&self) -> Option<&Row> {
+		self.rows.iter().max_by(|a, b| a.scores.perceptual.total_cmp(&b.scores.perceptual))
+	}
+}
+
+impl fmt::Display for Report {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		writeln!(
+			f,
+			"{:>8} {:>10} {:>6} {:>10} {:>8} {:>8} {:>8} {:>10}",
+			"bitrate", "complexity", "frame", "actual", "snr", "segsnr", "lsd", "perceptual"
+		)?;
+		for row in &self.rows {
+			writeln!(
+				f,
+				"{:>8} {:>10} {:>6} {:>10.0} {:>8.2} {:>8.2} {:>8.2} {:>10.2}",
+				row.bitrate.to_string(),
+				row.complexity,
+				row.frame_size.to_string(),
+				row.actual_bitrate,
+				row.scores.snr,
+				row.scores.segmental_snr,
+				row.scores.log_spectral_distance,
+				row.scores.perceptual,
+			)?;
+		}
+		Ok(())
+	}
+}
+
+fn check_pair(
+	what: &'static str,
+	reference: &[f32],
+	degraded: &[f32],
+	channels: usize,
+) -> Result<()> {
+	super::check_frame_len(what, reference.len(), channels)?;
+	if reference.len() != degraded.len() {
+		return Err(Error::bad_arg(what).with_context("lengths differ"));
+	}
+	Ok(())
+}
+
+/// The energy of the reference and of the difference between the buffers.
+fn energies(reference: &[f32], degraded: &[f32]) -> (f64, f64) {
+	let mut signal = 0.0;
+	let mut noise = 0.0;
+	for (&x, &y) in reference.iter().zip(degraded) {
+		signal += (x as f64).powi(2);
+		noise += (x as f64 - y as f64).powi(2);
+	}
+	(signal, noise)
+}
+
+/// Resample to 48 kHz, compensating for the resampler's delay, and scale to
+/// the 16-bit range `compare` expects.
+fn to_48k(input: &[f32], channels: usize, sample_rate: u32) -> Result<Vec<f32>> {
+	if sample_rate == 48000 {
+		return Ok(input.iter().map(|&s| s * 32768.0).collect());
+	}
+	let mut resampler = Resampler::new(sample_rate, 48000, channels)?;
+	let len = input.len() / channels;
+	let wanted = len * 48000 / sample_rate as usize;
+	let delay = resampler.delay() * 48000 / sample_rate as usize;
+
+	let flush = vec![0.0f32; 2 * (resampler.delay() + 1) * channels];
+	let mut output = resampler.process(input).to_vec();
+	output.extend_from_slice(resampler.process(&flush));
+	output.resize((delay + wanted) * channels, 0.0);
+	Ok(output.split_off(delay * channels).into_iter().map(|s| s * 32768.0).collect())
+}
+
+/// A radix-2 FFT of real input, with reusable buffers.
+struct Spectrum {
+	re: Vec<f64>,
+	im: Vec<f64>,
+}
+
+impl Spectrum {
+	fn new(size: usize) -> Spectrum {
+		Spectrum { re: vec![0.0; size], im: vec![0.0; size] }
+	}
+
+	fn compute<I: Iterator<Item = f64>>(&mut self, input: I) {
+		let n = self.re.len();
+		for (re, value) in self.re.iter_mut().zip(input) {
+			*re = value;
+		}
+		for im in &mut self.im {
+			*im = 0.0;
+		}
+
+		// bit-reversal permutation
+		let mut j = 0;
+		for i in 1..n {
+			let mut bit = n >> 1;
+			while j & bit != 0 {
+				j ^= bit;
+				bit >>= 1;
+			}
+			j |= bit;
+			if i < j {
+				self.re.swap(i, j);
+			}
+		}
+
+		let mut len = 2;
+		while len <= n {
+			let angle = -2.0 * PI / len as f64;
+			for start in (0..n).step_by(len) {
+				for k in 0..len / 2 {
+					let (sin, cos) = (angle * k as f64).sin_cos();
+					let a = start + k;
+					let b = a + len / 2;
+					let re = self.re[b] * cos - self.im[b] * sin;
+					let im = self.re[b] * sin + self.im[b] * cos;
+					self.re[b] = self.re[a] - re;
+					self.im[b] = self.im[a] - im;
+					self.re[a] += re;
+					self.im[a] += im;
+				}
+			}
+			len <<= 1;
+		}
+	}
+
+	fn power(&self, bin: usize) -> f64 {
+		self.re[bin] * self.re[bin] + self.im[bin] * self.im[bin]
+	}
+}
@@ -0,0 +1,371 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Adaptive bitrate control driven by network feedback.
+//!
+//! A `RateController` is fed periodic `Feedback` reports, such as those
+//! derived from RTCP receiver reports or a transport-wide congestion control
+//! estimate, and decides on the bitrate, loss resilience, bandwidth and frame
+//! duration an encoder should use.
+
+use super::{Bandwidth, Bitrate, BufferedEncoder, Encoder, Error, FrameSize, Result, Sample};
+
+/// Loss above which the bitrate is reduced.
+const LOSS_BACKOFF: f32 = 0.1;
+
+/// Loss below which the bitrate may increase.
+const LOSS_INCREASE: f32 = 0.02;
+
+/// Queuing delay, in milliseconds above the lowest round-trip time seen,
+/// which is treated as congestion.
+const DELAY_BACKOFF_MS: u32 = 100;
+
+/// Fraction of the available bitrate which is used, leaving room for
+/// packet overhead and other streams.
+const AVAILABLE_HEADROOM: f64 = 0.9;
+
+/// Bitrates at which each bandwidth in `BANDWIDTHS` becomes worthwhile.
+const BANDWIDTH_THRESHOLDS: [i32; 3] = [12000, 20000, 28000];
+const BANDWIDTHS: [Bandwidth; 4] =
+	[Bandwidth::Narrowband, Bandwidth::Wideband, Bandwidth::Superwideband, Bandwidth::Fullband];
+
+/// Bitrates at which each frame size in `FRAME_SIZES` becomes worthwhile.
+/// Longer frames spend less on packet overhead at low bitrates.
+const FRAME_SIZE_THRESHOLDS: [i32; 2] = [10000, 16000];
+const FRAME_SIZES: [FrameSize; 3] = [FrameSize::Ms60, FrameSize::Ms40, FrameSize::Ms20];
+
+/// A network feedback report.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Feedback {
+	/// The fraction of packets lost since the last report, from 0 to 1.
+	pub loss: f32,
+	/// The round-trip time in milliseconds, if known.
+	pub rtt_ms: Option<u32>,
+	/// The estimated available send bitrate in bits per second, if known.
+	pub available_bitrate: Option<i32>,
+}
+
+/// Limits and tuning for a `RateController`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateControllerConfig {
+	/// The lowest bitrate which will be chosen.
+	pub min_bitrate: i32,
+	/// The highest bitrate which will be chosen.
+	pub max_bitrate: i32,
+	/// The bitrate used before any feedback arrives.
+	pub start_bitrate: i32,
+	/// The number of consecutive low-loss reports required before the
+	/// bitrate starts increasing.
+	pub increase_holdoff: u32,
+	/// The smoothed loss at which inband FEC is enabled.
+	pub fec_enable_loss: f32,
+	/// The smoothed loss below which inband FEC is disabled again.
+	pub fec_disable_loss: f32,
+	/// The shortest frame duration which will be chosen.
+	pub min_frame_size: FrameSize,
+	/// The longest frame duration which will be chosen.
+	pub max_frame_size: FrameSize,
+}
+
+impl Default for RateControllerConfig {
+	fn default() -> RateControllerConfig {
+		RateControllerConfig {
+			min_bitrate: 6000,
+			max_bitrate: 128000,
+			start_bitrate: 32000,
+			increase_holdoff: 3,
+			fec_enable_loss: 0.02,
+			fec_disable_loss: 0.01,
+			min_frame_size: FrameSize::Ms20,
+			max_frame_size: FrameSize::Ms60,
+		}
+	}
+}
+
+impl RateControllerConfig {
+	/// Check that the limits are consistent, without creating a controller.
+	pub fn validate(&self) -> Result<()> {
+		let err = |field| Err(Error::bad_arg("RateControllerConfig::validate").with_context(field));
+		if self.min_bitrate <= 0 || self.min_bitrate > self.max_bitrate {
+			return err("min_bitrate");
+		}
+		if self.start_bitrate < self.min_bitrate || self.start_bitrate > self.max_bitrate {
+			return err("start_bitrate");
+		}
+		if !(0.0..=1.0).contains(&self.fec_enable_loss) {
+			return err("fec_enable_loss");
+		}
+		if !(0.0..=self.fec_enable_loss).contains(&self.fec_disable_loss) {
+			return err("fec_disable_loss");
+		}
+		let (min, max) = match (duration(self.min_frame_size), duration(self.max_frame_size)) {
+			(Some(min), Some(max)) => (min, max),
+			(None, _) => return err("min_frame_size"),
+			(_, None) => return err("max_frame_size"),
+		};
+		if min > max {
+			return err("min_frame_size");
+		}
+		Ok(())
+	}
+}
+
+/// Why a `RateController` chose its most recent bitrate.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum RateReason {
+	/// No feedback has been received yet.
+	Initial,
+	/// Conditions did not justify a change.
+	Hold,
+	/// Loss has been low for long enough to probe upward.
+	Increase,
+	/// Loss was high enough to back off.
+	LossBackoff,
+	/// Round-trip time rose enough to indicate queuing.
+	DelayBackoff,
+	/// The bitrate was capped by the available bitrate estimate.
+	BandwidthLimit,
+}
+
+/// The encoder settings chosen by a `RateController`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateDecision {
+	/// The target bitrate in bits per second.
+	pub bitrate: i32,
+	/// The expected packet loss percentage, from 0 to 100.
+	pub packet_loss_perc: i32,
+	/// Whether inband forward error correction should be enabled.
+	pub inband_fec: bool,
+	/// The maximum bandpass the encoder should select.
+	pub max_bandwidth: Bandwidth,
+	/// The frame duration the encoder should use.
+	///
+	/// `apply` leaves the frame duration alone, since it is decided by the
+	/// length of the input passed to `encode`; callers feeding fixed-size
+	/// frames can ignore it. `apply_buffered` passes it on to a
+	/// `BufferedEncoder`, which reframes its input to match.
+	pub frame_size: FrameSize,
+	/// Why the bitrate was chosen.
+	pub reason: RateReason,
+	/// Whether any setting differs from the previous decision.
+	pub changed: bool,
+}
+
+impl RateDecision {
+	/// Apply these settings, except the frame size, to an encoder.
+	pub fn apply(&self, encoder: &mut Encoder) -> Result<()> {
+		encoder.set_bitrate(Bitrate::Bits(self.bitrate))?;
+		encoder.set_packet_loss_perc(self.packet_loss_perc)?;
+		encoder.set_inband_fec(self.inband_fec)?;
+		encoder.set_max_bandwidth(self.max_bandwidth)?;
+		Ok(())
+	}
+
+	/// Apply these settings, including the frame size, to a buffered
+	/// encoder.
+	pub fn apply_buffered<T: Sample>(&self, encoder: &mut BufferedEncoder<T>) -> Result<()> {
+		self.apply(encoder.encoder_mut())?;
+		encoder.set_frame_size(self.frame_size)
+	}
+}
+
+/// An adaptive bitrate controller.
+///
+/// Decreases take effect as soon as a report shows loss or queuing, while
+/// increases wait for several good reports in a row. FEC, bandwidth and
+/// frame duration only switch once their thresholds are clearly crossed, so
+/// that feedback hovering near a threshold does not cause flapping.
+#[derive(Debug, Clone)]
+pub struct RateController {
+	config: RateControllerConfig,
+	smoothed_loss: f32,
+	min_rtt: Option<u32>,
+	good_reports: u32,
+	bandwidth_step: usize,
+	frame_step: usize,
+	decision: RateDecision,
+}
+
+impl RateController {
+	/// Create a controller with the given limits.
+	pub fn new(config: RateControllerConfig) -> Result<RateController> {
+		config.validate()?;
+		let bandwidth_step = threshold_index(&BANDWIDTH_THRESHOLDS, config.start_bitrate);
+		let frame_step = threshold_index(&FRAME_SIZE_THRESHOLDS, config.start_bitrate);
+		Ok(RateController {
+			config,
+			smoothed_loss: 0.0,
+			min_rtt: None,
+			good_reports: 0,
+			bandwidth_step,
+			frame_step,
+			decision: RateDecision {
+				bitrate: config.start_bitrate,
+				packet_loss_perc: 0,
+				inband_fec: false,
+				max_bandwidth: BANDWIDTHS[bandwidth_step],
+				frame_size: clamp_frame_size(&config, FRAME_SIZES[frame_step]),
+				reason: RateReason::Initial,
+				changed: false,
+			},
+		})
+	}
+
+	/// Get the controller's limits.
+	pub fn config(&self) -> &RateControllerConfig {
+		&self.config
+	}
+
+	/// Get the most recent decision.
+	pub fn decision(&self) -> &RateDecision {
+		&self.decision
+	}
+
+	/// Get the smoothed loss fraction which drives FEC and the loss
+	/// percentage.
+	pub fn smoothed_loss(&self) -> f32 {
+		self.smoothed_loss
+	}
+
+	/// Forget all feedback, returning to the starting decision.
+	pub fn reset(&mut self) {
+		*self = RateController::new(self.config).expect("config was already validated");
+	}
+
+	/// Process a feedback report and decide on new settings.
+	pub fn update(&mut self, feedback: &Feedback) -> RateDecision {
+		let previous = self.decision;
+		let loss = if feedback.loss.is_nan() { 0.0 } else { feedback.loss.clamp(0.0, 1.0) };
+		// react quickly to rising loss, but recover slowly
+		let alpha = if loss > self.smoothed_loss { 0.5 } else { 0.1 };
+		self.smoothed_loss += alpha * (loss - self.smoothed_loss);
+
+		let mut queuing = false;
+		if let Some(rtt) = feedback.rtt_ms {
+			let min_rtt = self.min_rtt.map_or(rtt, |min| min.min(rtt));
+			self.min_rtt = Some(min_rtt);
+			queuing = rtt - min_rtt > DELAY_BACKOFF_MS;
+		}
+
+		let mut bitrate = previous.bitrate as f64;
+		let mut reason;
+		if loss > LOSS_BACKOFF {
+			bitrate *= 1.0 - 0.5 * loss as f64;
+			reason = RateReason::LossBackoff;
+			self.good_reports = 0;
+		} else if queuing {
+			bitrate *= 0.85;
+			reason = RateReason::DelayBackoff;
+			self.good_reports = 0;
+		} else if loss < LOSS_INCREASE {
+			self.good_reports += 1;
+			if self.good_reports >= self.config.increase_holdoff {
+				bitrate = f64::max(bitrate * 1.08, bitrate + 1000.0);
+				reason = RateReason::Increase;
+			} else {
+				reason = RateReason::Hold;
+			}
+		} else {
+			reason = RateReason::Hold;
+			self.good_reports = 0;
+		}
+		if let Some(available) = feedback.available_bitrate {
+			let limit = available as f64 * AVAILABLE_HEADROOM;
+			if bitrate > limit {
+				bitrate = limit;
+				reason = RateReason::BandwidthLimit;
+			}
+		}
+		let bitrate = bitrate
+			.clamp(self.config.min_bitrate as f64, self.config.max_bitrate as f64)
+			.round() as i32;
+
+		let mut packet_loss_perc = (self.smoothed_loss * 100.0).round() as i32;
+		if packet_loss_perc != 0 && (packet_loss_perc - previous.packet_loss_perc).abs() < 2 {
+			packet_loss_perc = previous.packet_loss_perc;
+		}
+
+		let inband_fec = if previous.inband_fec {
+			self.smoothed_loss >= self.config.fec_disable_loss
+		} else {
+			self.smoothed_loss >= self.config.fec_enable_loss
+		};
+
+		self.bandwidth_step = hysteresis(&BANDWIDTH_THRESHOLDS, self.bandwidth_step, bitrate);
+		self.frame_step = hysteresis(&FRAME_SIZE_THRESHOLDS, self.frame_step, bitrate);
+
+		let mut decision = RateDecision {
+			bitrate,
+			packet_loss_perc,
+			inband_fec,
+			max_bandwidth: BANDWIDTHS[self.bandwidth_step],
+			frame_size: clamp_frame_size(&self.config, FRAME_SIZES[self.frame_step]),
+			reason,
+			changed: false,
+		};
+		decision.changed = decision.bitrate != previous.bitrate
+			|| decision.packet_loss_perc != previous.packet_loss_perc
+			|| decision.inband_fec != previous.inband_fec
+			|| decision.max_bandwidth != previous.max_bandwidth
+			|| decision.frame_size != previous.frame_size;
+		self.decision = decision;
+		decision
+	}
+
+	/// Process a feedback report and apply the resulting settings, except
+	/// the frame size, to an encoder.
+	pub fn apply(&mut self, encoder: &mut Encoder, feedback: &Feedback) -> Result<RateDecision> {
+		let decision = self.update(feedback);
+		decision.apply(encoder)?;
+		Ok(decision)
+	}
+
+	/// Process a feedback report and apply the resulting settings, including
+	/// the frame size, to a buffered encoder.
+	pub fn apply_buffered<T: Sample>(
+		&mut self,
+		encoder: &mut BufferedEncoder<T>,
+		feedback: &Feedback,
+	) -> Result<RateDecision> {
+		let decision = self.update(feedback);
+		decision.apply_buffered(encoder)?;
+		Ok(decision)
+	}
+}
+
+/// The frame duration in samples at 48 kHz, for comparisons.
+fn duration(frame_size: FrameSize) -> Option<usize> {
+	frame_size.samples(48000)
+}
+
+fn clamp_frame_size(config: &RateControllerConfig, frame_size: FrameSize) -> FrameSize {
+	if duration(frame_size) < duration(config.min_frame_size) {
+		config.min_frame_size
+	} else if duration(frame_size) > duration(config.max_frame_size) {
+		config.max_frame_size
+	} else {
+		frame_size
+	}
+}
+
+fn threshold_index(thresholds: &[i32], bitrate: i32) -> usize {
+	thresholds.iter().filter(|&&threshold| bitrate >= threshold).count()
+}
+
+/// Move between steps only once the bitrate is 10% past a threshold.
+fn hysteresis(thresholds: &[i32], current: usize, bitrate: i32) -> usize {
+	let up = threshold_index(thresholds, bitrate - bitrate / 11);
+	let down = threshold_index(thresholds, bitrate + bitrate / 10);
+	if up > current {
+		up
+	} else if down < current {
+		down
+	} else {
+		current
+	}
+}
@@ -0,0 +1,230 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Sample rate conversion for rates which Opus does not support natively.
+
+use super::{Error, ErrorKind, Result, Sample};
+use std::f64::consts::PI;
+
+/// The sample rates supported natively by Opus.
+pub const SUPPORTED_RATES: [u32; 5] = [8000, 12000, 16000, 24000, 48000];
+
+/// Zero crossings on each side of the filter kernel at full bandwidth.
+const ZERO_CROSSINGS: f64 = 16.0;
+
+/// Fraction of the Nyquist frequency which is passed through.
+const PASSBAND: f64 = 0.95;
+
+/// Most filter phases to tabulate. Ratios with more phases than this, such
+/// as 44101 to 48000, interpolate between adjacent phases.
+const MAX_PHASES: usize = 1024;
+
+/// Longest filter kernel, which limits downsampling to a factor of 64.
+const MAX_TAPS: usize = 2048;
+
+/// Pick the rate Opus should run at for audio at the given sample rate.
+///
+/// This is the lowest natively supported rate which is at least as high as
+/// the given rate, so no bandwidth is lost.
+pub fn codec_rate(sample_rate: u32) -> u32 {
+	for &rate in SUPPORTED_RATES.iter() {
+		if rate >= sample_rate {
+			return rate;
+		}
+	}
+	48000
+}
+
+/// A polyphase windowed-sinc resampler for interleaved audio.
+///
+/// The conversion ratio is exact, so feeding `n` input samples always
+/// produces `n * output_rate / input_rate` output samples over time, with a
+/// constant delay given by `delay`. The filter table is bounded in size
+/// whatever the rates; only downsampling by more than a factor of 64 is
+/// rejected.
+#[derive(Debug, Clone)]
+pub struct Resampler {
+	input_rate: u32,
+	output_rate: u32,
+	channels: usize,
+	up: usize,
+	down: usize,
+	taps: usize,
+	phases: usize,
+	filter: Vec<f32>,
+	coeffs: Vec<f32>,
+	phase: usize,
+	history: Vec<f32>,
+	output: Vec<f32>,
+}
+
+impl Resampler {
+	/// Create a resampler between two rates for the given channel count.
+	pub fn new(input_rate: u32, output_rate: u32, channels: usize) -> Result<Resampler> {
+		for &rate in &[input_rate, output_rate] {
+			if rate == 0 {
+				return Err(Error::new("Resampler::new", ErrorKind::UnsupportedSampleRate(rate)));
+			}
+		}
+		if channels == 0 {
+			return Err(Error::bad_arg("Resampler::new").with_context("no channels"));
+		}
+		let gcd = gcd(input_rate, output_rate);
+		let up = (output_rate / gcd) as usize;
+		let down = (input_rate / gcd) as usize;
+
+		// When downsampling, the cutoff drops below the input's Nyquist
+		// frequency and the kernel widens to match.
+		let ratio = f64::min(1.0, up as f64 / down as f64);
+		let cutoff = PASSBAND * ratio;
+		let half = (ZERO_CROSSINGS / ratio).ceil() as usize;
+		let taps = 2 * half;
+		if taps > MAX_TAPS {
+			return Err(
+				Error::bad_arg("Resampler::new").with_context("downsampling ratio too high")
+			);
+		}
+
+		// The extra row is phase zero advanced by one sample, so that the
+		// last phase has a neighbour to interpolate towards.
+		let phases = up.min(MAX_PHASES);
+		let mut filter = Vec::with_capacity((phases + 1) * taps);
+		for phase in 0..phases + 1 {
+			let start = filter.len();
+			for j in 0..taps {
+				let t = j as f64 - (half as f64 - 1.0) - phase as f64 / phases as f64;
+				filter.push((cutoff * sinc(cutoff * t) * blackman(t / half as f64)) as f32);
+			}
+			// normalize each phase to unity gain at DC
+			let sum: f32 = filter[start..].iter().sum();
+			for coeff in &mut filter[start..] {
+				*coeff /= sum;
+			}
+		}
+
+		let mut resampler = Resampler {
+			input_rate,
+			output_rate,
+			channels,
+			up,
+			down,
+			taps,
+			phases,
+			filter,
+			coeffs: vec![0.0; taps],
+			phase: 0,
+			history: Vec::new(),
+			output: Vec::new(),
+		};
+		resampler.reset();
+		Ok(resampler)
+	}
+
+	/// Get the input sample rate.
+	#[inline]
+	pub fn input_rate(&self) -> u32 {
+		self.input_rate
+	}
+
+	/// Get the output sample rate.
+	#[inline]
+	pub fn output_rate(&self) -> u32 {
+		self.output_rate
+	}
+
+	/// Get the number of interleaved channels.
+	#[inline]
+	pub fn channels(&self) -> usize {
+		self.channels
+	}
+
+	/// Get the delay introduced by the filter, in samples *per channel* at
+	/// the input rate.
+	#[inline]
+	pub fn delay(&self) -> usize {
+		self.taps / 2
+	}
+
+	/// Clear the filter history, as if no input had been processed.
+	pub fn reset(&mut self) {
+		self.phase = 0;
+		self.history.clear();
+		self.history.resize((self.taps - 1) * self.channels, 0.0);
+	}
+
+	/// Resample interleaved input, returning all of the output which is now
+	/// available.
+	///
+	/// The input length should be a multiple of the channel count. Output is
+	/// floating point in the range used by `encode_float`, and is valid until
+	/// the next call.
+	pub fn process<T: Sample>(&mut self, input: &[T]) -> &[f32] {
+		let channels = self.channels;
+		let taps = self.taps;
+		self.history.extend(input.iter().map(|&s| s.to_f32()));
+		self.output.clear();
+
+		let frames = self.history.len() / channels;
+		let mut index = 0;
+		while index + taps <= frames {
+			let position = self.phase * self.phases;
+			let row = position / self.up;
+			let coeffs = &self.filter[row * taps..(row + 1) * taps];
+			let coeffs = if position % self.up == 0 {
+				coeffs
+			} else {
+				let next = &self.filter[(row + 1) * taps..(row + 2) * taps];
+				let frac = (position % self.up) as f32 / self.up as f32;
+				for ((out, &a), &b) in self.coeffs.iter_mut().zip(coeffs).zip(next) {
+					*out = a + frac * (b - a);
+				}
+				&self.coeffs[..]
+			};
+			let window = &self.history[index * channels..(index + taps) * channels];
+			for channel in 0..channels {
+				let mut acc = 0.0;
+				for (&coeff, &sample) in
+					coeffs.iter().zip(window[channel..].iter().step_by(channels))
+				{
+					acc += coeff * sample;
+				}
+				self.output.push(acc);
+			}
+			self.phase += self.down;
+			index += self.phase / self.up;
+			self.phase %= self.up;
+		}
+		self.history.drain(..index * channels);
+		&self.output
+	}
+}
+
+fn gcd(mut a: u32, mut b: u32) -> u32 {
+	while b != 0 {
+		let t = a % b;
+		a = b;
+		b = t;
+	}
+	a
+}
+
+fn sinc(x: f64) -> f64 {
+	if x == 0.0 {
+		1.0
+	} else {
+		(PI * x).sin() / (PI * x)
+	}
+}
+
+fn blackman(x: f64) -> f64 {
+	if x.abs() > 1.0 {
+		0.0
+	} else {
+		0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+	}
+}
@@ -0,0 +1,373 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Offline simulation of network impairments for decoder testing.
+//!
+//! `transmit` passes a sequence of encoded packets through a simulated
+//! network which may lose, reorder, duplicate or corrupt them, or `Network`
+//! does the same one packet at a time, and
+//! `simulate` then plays the result out through a `Decoder` as a receiver
+//! with a small jitter buffer would, using inband FEC or concealment for
+//! packets which do not arrive in time.
+//!
+//! All randomness comes from a generator seeded by `Impairments::seed`, so
+//! runs are reproducible.
+
+use super::{Decoder, ErrorCode, Result, Sample};
+
+/// How packets are chosen to be lost.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LossModel {
+	/// No packets are lost.
+	None,
+	/// Each packet is lost independently with the given probability.
+	Bernoulli {
+		/// The probability of losing each packet, from 0 to 1.
+		loss: f64,
+	},
+	/// A two-state Markov model producing bursts of loss.
+	///
+	/// The network alternates between a good and a bad state, each with its
+	/// own loss probability, and may change state after each packet.
+	GilbertElliott {
+		/// The probability of moving from the good to the bad state.
+		good_to_bad: f64,
+		/// The probability of moving from the bad to the good state.
+		bad_to_good: f64,
+		/// The probability of losing a packet in the good state.
+		loss_good: f64,
+		/// The probability of losing a packet in the bad state.
+		loss_bad: f64,
+	},
+}
+
+/// The impairments applied by the simulated network.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Impairments {
+	/// How packets are lost.
+	pub loss: LossModel,
+	/// The probability that a packet is delayed behind later packets.
+	pub reorder: f64,
+	/// The most packet intervals by which a reordered packet is delayed.
+	pub max_delay: usize,
+	/// The probability that a packet is delivered twice.
+	pub duplicate: f64,
+	/// The probability that each bit of a delivered packet is flipped.
+	pub bit_error_rate: f64,
+	/// The seed for the random number generator.
+	pub seed: u64,
+}
+
+impl Default for Impairments {
+	fn default() -> Impairments {
+		Impairments {
+			loss: LossModel::None,
+			reorder: 0.0,
+			max_delay: 3,
+			duplicate: 0.0,
+			bit_error_rate: 0.0,
+			seed: 1,
+		}
+	}
+}
+
+/// A packet delivered by the simulated network.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+pub struct Arrival {
+	/// The index of the packet in the original sequence.
+	pub seq: usize,
+	/// The time the packet arrived, in packet intervals.
+	pub time: usize,
+	/// The packet data, possibly corrupted.
+	pub data: Vec<u8>,
+	/// Whether any bits of the packet were flipped.
+	pub corrupted: bool,
+	/// Whether this is a second copy of the packet.
+	pub duplicate: bool,
+}
+
+/// A simulated network which packets are sent through one at a time, such
+/// as while streaming a file.
+#[derive(Debug)]
+pub struct Network {
+	impairments: Impairments,
+	rng: Rng,
+	bad_state: bool,
+	seq: usize,
+}
+
+impl Network {
+	/// Create a network with the given impairments.
+	pub fn new(impairments: &Impairments) -> Network {
+		Network {
+			impairments: *impairments,
+			rng: Rng::new(impairments.seed),
+			bad_state: false,
+			seq: 0,
+		}
+	}
+
+	/// Send the next packet, returning the copies of it which are delivered.
+	///
+	/// A lost packet has none, and a duplicated packet has two. Copies which
+	/// are reordered arrive later than the time the packet was sent.
+	pub fn send(&mut self, packet: &[u8]) -> Vec<Arrival> {
+		let impairments = &self.impairments;
+		let rng = &mut self.rng;
+		let seq = self.seq;
+		self.seq += 1;
+		let lost = match impairments.loss {
+			LossModel::None => false,
+			LossModel::Bernoulli { loss } => rng.chance(loss),
+			LossModel::GilbertElliott { good_to_bad, bad_to_good, loss_good, loss_bad } => {
+				let lost = rng.chance(if self.bad_state { loss_bad } else { loss_good });
+				self.bad_state =
+					if self.bad_state { !rng.chance(bad_to_good) } else { rng.chance(good_to_bad) };
+				lost
+			}
+		};
+		if lost {
+			return Vec::new();
+		}
+
+		let copies = if rng.chance(impairments.duplicate) { 2 } else { 1 };
+		(0..copies)
+			.map(|copy| {
+				let mut delay = 0;
+				if impairments.max_delay > 0 && rng.chance(impairments.reorder) {
+					delay = 1 + rng.below(impairments.max_delay);
+				}
+				let mut data = packet.to_vec();
+				let mut corrupted = false;
+				if impairments.bit_error_rate > 0.0 {
+					for byte in &mut data {
+						for bit in 0..8 {
+							if rng.chance(impairments.bit_error_rate) {
+								*byte ^= 1 << bit;
+								corrupted = true;
+							}
+						}
+					}
+				}
+				Arrival {
+					seq,
+					time: seq + delay,
+					data,
+					corrupted,
+					duplicate: copy > 0,
+				}
+			})
+			.collect()
+	}
+}
+
+/// Pass packets through the simulated network, returning those delivered in
+/// order of arrival.
+///
+/// Packet `n` is sent at time `n`, and arrives at the same time unless it is
+/// reordered.
+pub fn transmit(packets: &[Vec<u8>], impairments: &Impairments) -> Vec<Arrival> {
+	let mut network = Network::new(impairments);
+	let mut arrivals: Vec<Arrival> =
+		packets.iter().flat_map(|packet| network.send(packet)).collect();
+	// stable, so packets arriving at the same time keep their sending order
+	arrivals.sort_by_key(|arrival| arrival.time);
+	arrivals
+}
+
+/// How the simulated receiver plays out packets.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Receiver {
+	/// How many packet intervals the receiver waits before playing out each
+	/// packet. Packets arriving later than this are discarded as late.
+	pub jitter_buffer: usize,
+	/// Whether to recover missing packets from the inband FEC data of the
+	/// following packet, when it has arrived in time.
+	pub fec: bool,
+}
+
+impl Default for Receiver {
+	fn default() -> Receiver {
+		Receiver { jitter_buffer: 2, fec: true }
+	}
+}
+
+/// What the receiver did to produce audio for a packet.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub enum Action {
+	/// The packet was decoded normally.
+	Decoded,
+	/// The packet was recovered from the next packet's FEC data.
+	Fec,
+	/// The packet was concealed.
+	Plc,
+}
+
+/// What happened to one packet of the original sequence.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct TraceEntry {
+	/// The index of the packet in the original sequence.
+	pub seq: usize,
+	/// Whether the network dropped the packet.
+	pub lost: bool,
+	/// Whether the packet arrived, but after it was needed.
+	pub late: bool,
+	/// Whether the packet arrived behind later packets.
+	pub reordered: bool,
+	/// Whether the packet was delivered more than once.
+	pub duplicated: bool,
+	/// Whether the copy used was corrupted.
+	pub corrupted: bool,
+	/// How the receiver produced audio for the packet.
+	pub action: Action,
+	/// The error from decoding the packet, if it failed and was concealed
+	/// instead.
+	pub error: Option<ErrorCode>,
+	/// The number of samples *per channel* produced.
+	pub samples: usize,
+}
+
+/// The output of a simulation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Simulation<T> {
+	/// The decoded audio, interleaved.
+	pub pcm: Vec<T>,
+	/// What happened to each packet, in sequence order.
+	pub trace: Vec<TraceEntry>,
+}
+
+impl<T> Simulation<T> {
+	/// Count the packets produced by the given action.
+	pub fn count(&self, action: Action) -> usize {
+		self.trace.iter().filter(|entry| entry.action == action).count()
+	}
+
+	/// Get the fraction of packets which were not decoded normally.
+	pub fn effective_loss(&self) -> f64 {
+		if self.trace.is_empty() {
+			0.0
+		} else {
+			1.0 - self.count(Action::Decoded) as f64 / self.trace.len() as f64
+		}
+	}
+}
+
+/// Transmit packets through the simulated network and decode what arrives.
+///
+/// Each missing packet is concealed, or recovered with FEC if enabled, for
+/// the same duration as the original packet, so the output has the length of
+/// a loss-free decode. Packets which fail to decode, such as due to
+/// corruption, are concealed. Corruption which changes a packet's TOC byte
+/// may still change its duration.
+pub fn simulate<T: Sample>(
+	decoder: &mut Decoder,
+	packets: &[Vec<u8>],
+	impairments: &Impairments,
+	receiver: &Receiver,
+) -> Result<Simulation<T>> {
+	let channels = decoder.channels as usize;
+	let arrivals = transmit(packets, impairments);
+
+	// the first copy of each packet to arrive in time, and whether any copy
+	// arrived at all
+	let mut usable: Vec<Option<&Arrival>> = vec![None; packets.len()];
+	let mut arrived = vec![0usize; packets.len()];
+	let mut reordered = vec![false; packets.len()];
+	for arrival in &arrivals {
+		arrived[arrival.seq] += 1;
+		reordered[arrival.seq] |= arrival.time > arrival.seq;
+		if arrival.time <= arrival.seq + receiver.jitter_buffer && usable[arrival.seq].is_none() {
+			usable[arrival.seq] = Some(arrival);
+		}
+	}
+
+	let mut pcm = Vec::new();
+	let mut trace = Vec::with_capacity(packets.len());
+	let mut buffer = Vec::new();
+	for seq in 0..packets.len() {
+		let samples = decoder.get_nb_samples(&packets[seq])?;
+		buffer.clear();
+		buffer.resize(samples * channels, T::default());
+
+		let mut entry = TraceEntry {
+			seq,
+			lost: arrived[seq] == 0,
+			late: arrived[seq] > 0 && usable[seq].is_none(),
+			reordered: reordered[seq],
+			duplicated: arrived[seq] > 1,
+			corrupted: usable[seq].is_some_and(|arrival| arrival.corrupted),
+			action: Action::Plc,
+			error: None,
+			samples: 0,
+		};
+
+		// the next packet must have arrived by the time this one is played
+		let next = usable
+			.get(seq + 1)
+			.and_then(|next| *next)
+			.filter(|next| next.time <= seq + receiver.jitter_buffer);
+		let mut decoded = None;
+		if let Some(arrival) = usable[seq] {
+			match T::decode(decoder, &arrival.data, &mut buffer, false) {
+				Ok(n) => {
+					entry.action = Action::Decoded;
+					decoded = Some(n);
+				}
+				Err(err) => entry.error = Some(err.code()),
+			}
+		}
+		if decoded.is_none() && receiver.fec {
+			if let Some(next) = next {
+				if let Ok(n) = T::decode(decoder, &next.data, &mut buffer, true) {
+					entry.action = Action::Fec;
+					decoded = Some(n);
+				}
+			}
+		}
+		let n = match decoded {
+			Some(n) => n,
+			None => T::decode(decoder, &[], &mut buffer, false)?,
+		};
+		entry.samples = n;
+		pcm.extend_from_slice(&buffer[..n * channels]);
+		trace.push(entry);
+	}
+	Ok(Simulation { pcm, trace })
+}
+
+/// A small xorshift generator, so that simulations are reproducible without
+/// depending on an external crate.
+#[derive(Debug)]
+struct Rng(u64);
+
+impl Rng {
+	fn new(seed: u64) -> Rng {
+		// the state must never be zero
+		Rng(seed ^ 0x9E37_79B9_7F4A_7C15 | 1)
+	}
+
+	fn next(&mut self) -> u64 {
+		self.0 ^= self.0 << 13;
+		self.0 ^= self.0 >> 7;
+		self.0 ^= self.0 << 17;
+		self.0
+	}
+
+	/// A uniform value in `[0, 1)`.
+	fn uniform(&mut self) -> f64 {
+		(self.next() >> 11) as f64 / (1u64 << 53) as f64
+	}
+
+	fn chance(&mut self, probability: f64) -> bool {
+		probability > 0.0 && self.uniform() < probability
+	}
+
+	fn below(&mut self, n: usize) -> usize {
+		(self.uniform() * n as f64) as usize
+	}
+}
@@ -0,0 +1,254 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Active speaker detection from packets alone, without decoding.
+//!
+//! A variable bitrate encoder spends more bits on active speech than on
+//! background noise, and codes silence as DTX packets or frames of one or
+//! two bytes. The packet size relative to what is typical for the packet's
+//! mode and bandwidth therefore gives a rough level, which is enough to rank
+//! the speakers in a conference for selective forwarding. Where the sender
+//! provides the RFC 6464 audio level header extension, its level is used
+//! instead. Constant bitrate streams give every packet the same size, so
+//! only their silence can be detected without the header.
+
+use super::packet::{self, Mode};
+use super::{Bandwidth, Result};
+
+/// The interval at which libopus sends a packet during DTX, in samples at
+/// 48 kHz.
+const DTX_INTERVAL: usize = 19200;
+
+/// The audio level at or below which a packet counts as silent, in -dBov.
+const LEVEL_FLOOR: f32 = 80.0;
+/// The audio level at or above which a packet counts as full level, in
+/// -dBov.
+const LEVEL_CEILING: f32 = 20.0;
+
+/// The level at or above which a packet is taken to hold speech.
+pub const VOICE_LEVEL: f32 = 0.1;
+
+/// The RFC 6464 audio level sent in an RTP header extension.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct AudioLevel {
+	/// The level of the audio in the packet, from 0 for 0 dBov down to 127
+	/// for -127 dBov or quieter.
+	pub level: u8,
+	/// Whether the sender's voice activity detection found speech, if it
+	/// was negotiated with the `vad=on` attribute.
+	pub voice: Option<bool>,
+}
+
+impl AudioLevel {
+	/// Decode the one-byte extension element, whose top bit is the voice
+	/// activity flag when `vad` was negotiated.
+	pub fn from_byte(byte: u8, vad: bool) -> AudioLevel {
+		AudioLevel {
+			level: byte & 0x7F,
+			voice: if vad { Some(byte & 0x80 != 0) } else { None },
+		}
+	}
+
+	/// Encode the one-byte extension element.
+	pub fn to_byte(self) -> u8 {
+		let voice = if self.voice == Some(true) { 0x80 } else { 0 };
+		voice | self.level.min(127)
+	}
+}
+
+/// The activity estimated for one packet.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Activity {
+	/// Whether the packet is coded silence, holding only frames of at most
+	/// two bytes.
+	pub silent: bool,
+	/// Whether the packet is likely to hold speech.
+	pub voice: bool,
+	/// The estimated level, from 0 for silence to 1 for loud speech.
+	pub level: f32,
+	/// The duration of the packet, in samples at 48 kHz.
+	pub duration: usize,
+}
+
+/// Estimate the activity of a packet from its size and TOC byte, or from
+/// the RFC 6464 audio level where available.
+///
+/// A packet is taken to hold speech when it is not silent, its level is at
+/// least `VOICE_LEVEL`, and any voice activity flag sent with it is set.
+pub fn estimate(packet: &[u8], audio_level: Option<AudioLevel>) -> Result<Activity> {
+	let parsed = packet::parse(packet)?;
+	let duration = packet::get_nb_samples(packet, 48000)?;
+	let silent = parsed.frames.iter().all(|frame| frame.len() <= 2);
+	let toc = packet::get_toc(packet)?;
+
+	let level = match audio_level {
+		Some(audio) => scale(LEVEL_FLOOR - audio.level as f32, LEVEL_FLOOR - LEVEL_CEILING),
+		None if silent || duration == 0 => 0.0,
+		None => {
+			let (floor, ceiling) = bitrate_range(toc.mode, toc.bandwidth);
+			let bitrate = packet.len() as f32 * 8.0 * 48000.0 / duration as f32;
+			scale(bitrate - floor, ceiling - floor)
+		}
+	};
+	let flagged = audio_level.and_then(|audio| audio.voice).unwrap_or(true);
+	Ok(Activity {
+		silent,
+		voice: !silent && flagged && level >= VOICE_LEVEL,
+		level: if silent { 0.0 } else { level },
+		duration,
+	})
+}
+
+fn scale(value: f32, range: f32) -> f32 {
+	(value / range).clamp(0.0, 1.0)
+}
+
+/// The bitrates, in bits per second, which a VBR encoder typically uses for
+/// background noise and for loud speech in each mode and bandwidth.
+fn bitrate_range(mode: Mode, bandwidth: Bandwidth) -> (f32, f32) {
+	match (mode, bandwidth) {
+		(Mode::Silk, Bandwidth::Narrowband) => (5000.0, 14000.0),
+		(Mode::Silk, Bandwidth::Mediumband) => (7000.0, 18000.0),
+		(Mode::Silk, _) => (8000.0, 24000.0),
+		(Mode::Hybrid, Bandwidth::Superwideband) => (14000.0, 32000.0),
+		(Mode::Hybrid, _) => (16000.0, 40000.0),
+		(Mode::Celt, Bandwidth::Narrowband) => (8000.0, 24000.0),
+		(Mode::Celt, Bandwidth::Wideband) => (12000.0, 40000.0),
+		(Mode::Celt, Bandwidth::Superwideband) => (16000.0, 56000.0),
+		(Mode::Celt, _) => (20000.0, 64000.0),
+	}
+}
+
+/// Settings for a `SpeakerDetector`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DetectorConfig {
+	/// The smoothed level above which a source counts as speaking.
+	pub threshold: f32,
+	/// How quickly the smoothed level rises, as the time constant in
+	/// milliseconds.
+	pub attack_ms: u32,
+	/// How quickly the smoothed level falls, as the time constant in
+	/// milliseconds.
+	pub release_ms: u32,
+}
+
+impl Default for DetectorConfig {
+	fn default() -> DetectorConfig {
+		DetectorConfig {
+			threshold: 0.15,
+			attack_ms: 100,
+			release_ms: 800,
+		}
+	}
+}
+
+#[derive(Debug, Clone)]
+struct Source {
+	id: u32,
+	score: f32,
+	last: Activity,
+}
+
+/// Tracks the activity of many sources to pick the active speakers.
+///
+/// Sources are identified by a number such as their RTP SSRC, and are added
+/// when their first packet arrives. Each source's packet levels are smoothed
+/// over time, rising quickly at the start of speech and falling slowly in
+/// pauses, so the ranking does not flap between words. While silent,
+/// libopus sends a DTX packet only every 400 ms, so each DTX packet counts
+/// as that much silence.
+#[derive(Debug, Clone)]
+pub struct SpeakerDetector {
+	config: DetectorConfig,
+	sources: Vec<Source>,
+}
+
+impl Default for SpeakerDetector {
+	fn default() -> SpeakerDetector {
+		SpeakerDetector::new(DetectorConfig::default())
+	}
+}
+
+impl SpeakerDetector {
+	/// Create a detector with no sources.
+	pub fn new(config: DetectorConfig) -> SpeakerDetector {
+		SpeakerDetector { config, sources: Vec::new() }
+	}
+
+	/// Get the settings.
+	pub fn config(&self) -> &DetectorConfig {
+		&self.config
+	}
+
+	/// Update a source with its next packet, returning the packet's
+	/// estimated activity.
+	pub fn update(
+		&mut self,
+		source: u32,
+		packet: &[u8],
+		audio_level: Option<AudioLevel>,
+	) -> Result<Activity> {
+		let activity = estimate(packet, audio_level)?;
+		let duration = if packet::is_dtx(packet) {
+			DTX_INTERVAL.max(activity.duration)
+		} else {
+			activity.duration
+		};
+		let target = if activity.voice { activity.level } else { 0.0 };
+
+		let index = match self.sources.iter().position(|s| s.id == source) {
+			Some(index) => index,
+			None => {
+				self.sources.push(Source { id: source, score: 0.0, last: activity });
+				self.sources.len() - 1
+			}
+		};
+		let state = &mut self.sources[index];
+		let time_ms =
+			if target > state.score { self.config.attack_ms } else { self.config.release_ms };
+		let weight = 1.0 - (-(duration as f32 / 48.0) / time_ms.max(1) as f32).exp();
+		state.score += weight * (target - state.score);
+		state.last = activity;
+		Ok(activity)
+	}
+
+	/// Forget a source, returning whether it was known.
+	pub fn remove(&mut self, source: u32) -> bool {
+		let len = self.sources.len();
+		self.sources.retain(|s| s.id != source);
+		self.sources.len() != len
+	}
+
+	/// Get the sources seen so far, in the order they first sent a packet.
+	pub fn sources(&self) -> Vec<u32> {
+		self.sources.iter().map(|s| s.id).collect()
+	}
+
+	/// Get a source's smoothed level, from 0 to 1.
+	pub fn score(&self, source: u32) -> Option<f32> {
+		self.sources.iter().find(|s| s.id == source).map(|s| s.score)
+	}
+
+	/// Get the activity of a source's most recent packet.
+	pub fn last_activity(&self, source: u32) -> Option<Activity> {
+		self.sources.iter().find(|s| s.id == source).map(|s| s.last)
+	}
+
+	/// Check whether a source's smoothed level is above the threshold.
+	pub fn is_speaking(&self, source: u32) -> bool {
+		self.score(source).is_some_and(|score| score > self.config.threshold)
+	}
+
+	/// Get up to `n` speaking sources, loudest first.
+	pub fn top(&self, n: usize) -> Vec<u32> {
+		let mut speaking: Vec<&Source> =
+			self.sources.iter().filter(|s| s.score > self.config.threshold).collect();
+		speaking.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.id.cmp(&b.id)));
+		speaking.into_iter().take(n).map(|s| s.id).collect()
+	}
+}
@@ -0,0 +1,277 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Opt-in statistics about the packets passing through a codec.
+//!
+//! Collection is disabled by default, and enabled per codec state with
+//! `enable_stats`. Snapshots are plain values which can be copied out and
+//! exported to a monitoring system.
+
+use super::packet::{self, Mode};
+use super::{Bandwidth, Channels, ErrorCode, Result};
+use std::time::Duration;
+
+/// Weight of each new packet in the recent bitrate average, which then
+/// covers roughly the last 20 packets.
+const RECENT_WEIGHT: f64 = 0.05;
+
+/// Statistics about the packets produced by an encoder.
+///
+/// Modes and bandwidths are those actually chosen by the encoder, read from
+/// each packet's TOC byte. For multistream encoders they describe the first
+/// stream.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct EncoderStats {
+	/// The number of packets produced.
+	pub packets: u64,
+	/// The total size of all packets, in bytes.
+	pub bytes: u64,
+	/// The total duration of all packets, in samples at 48 kHz.
+	pub samples: u64,
+	/// The size of the most recent packet, in bytes.
+	pub last_packet_size: usize,
+	/// The size of the smallest packet, in bytes.
+	pub min_packet_size: usize,
+	/// The size of the largest packet, in bytes.
+	pub max_packet_size: usize,
+	/// The number of packets coded with SILK only.
+	pub silk_packets: u64,
+	/// The number of packets coded in hybrid mode.
+	pub hybrid_packets: u64,
+	/// The number of packets coded with CELT only.
+	pub celt_packets: u64,
+	/// The mode of the most recent packet.
+	pub mode: Option<Mode>,
+	/// The bandwidth of the most recent packet.
+	pub bandwidth: Option<Bandwidth>,
+	/// The number of times the bandwidth changed between packets.
+	pub bandwidth_changes: u64,
+	/// The number of packets produced while in discontinuous transmission.
+	pub dtx_packets: u64,
+	/// Whether the encoder was in discontinuous transmission for the most
+	/// recent packet. Always false for multistream encoders.
+	pub in_dtx: bool,
+	/// The final range of the most recent packet.
+	pub final_range: u32,
+	/// The average bitrate over all packets, in bits per second.
+	pub average_bitrate: f64,
+	/// An exponentially weighted average of the bitrate of recent packets,
+	/// in bits per second.
+	pub recent_bitrate: f64,
+	/// The average packet duration, in milliseconds.
+	pub average_frame_duration_ms: f64,
+}
+
+impl EncoderStats {
+	pub(crate) fn record(&mut self, data: &[u8], in_dtx: bool, final_range: u32) {
+		let size = data.len();
+		let samples = packet::get_nb_samples(data, 48000).unwrap_or(0) as u64;
+
+		self.packets += 1;
+		self.bytes += size as u64;
+		self.samples += samples;
+		self.last_packet_size = size;
+		self.min_packet_size =
+			if self.packets == 1 { size } else { self.min_packet_size.min(size) };
+		self.max_packet_size = self.max_packet_size.max(size);
+
+		if let Ok(toc) = packet::get_toc(data) {
+			match toc.mode {
+				Mode::Silk => self.silk_packets += 1,
+				Mode::Hybrid => self.hybrid_packets += 1,
+				Mode::Celt => self.celt_packets += 1,
+			}
+			if self.bandwidth.is_some() && self.bandwidth != Some(toc.bandwidth) {
+				self.bandwidth_changes += 1;
+			}
+			self.mode = Some(toc.mode);
+			self.bandwidth = Some(toc.bandwidth);
+		}
+
+		if in_dtx {
+			self.dtx_packets += 1;
+		}
+		self.in_dtx = in_dtx;
+		self.final_range = final_range;
+
+		if self.samples > 0 {
+			self.average_bitrate = bitrate(self.bytes, self.samples);
+			self.average_frame_duration_ms = self.samples as f64 / 48.0 / self.packets as f64;
+		}
+		if samples > 0 {
+			let current = bitrate(size as u64, samples);
+			self.recent_bitrate = if self.packets == 1 {
+				current
+			} else {
+				self.recent_bitrate + RECENT_WEIGHT * (current - self.recent_bitrate)
+			};
+		}
+	}
+}
+
+fn bitrate(bytes: u64, samples: u64) -> f64 {
+	bytes as f64 * 8.0 * 48000.0 / samples as f64
+}
+
+/// Statistics about the packets passed to a decoder.
+///
+/// Packets are counted as decoded normally unless empty, in which case packet
+/// loss concealment is invoked, or passed with `fec` set, in which case the
+/// frame before them is recovered from their inband FEC data. libopus
+/// silently falls back to concealment when a packet has no FEC data, so
+/// `fec_frames` counts attempts rather than successes.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct DecoderStats {
+	/// The number of packets decoded normally.
+	pub packets: u64,
+	/// The total size of packets decoded normally, in bytes.
+	pub bytes: u64,
+	/// The total duration of all output, in samples at 48 kHz.
+	pub samples: u64,
+	/// The number of times packet loss concealment was invoked.
+	pub plc_frames: u64,
+	/// The number of frames recovered using inband FEC data.
+	pub fec_frames: u64,
+	/// The total duration of output produced by concealment or FEC, in
+	/// samples at 48 kHz.
+	pub concealed_samples: u64,
+	/// The number of calls which failed, by error code.
+	pub errors: ErrorCounts,
+	/// The mode of the most recent packet.
+	pub mode: Option<Mode>,
+	/// The bandwidth of the most recent packet.
+	pub bandwidth: Option<Bandwidth>,
+	/// The channel count of the most recent packet.
+	pub channels: Option<Channels>,
+	/// The number of times the mode changed between packets.
+	pub mode_changes: u64,
+	/// The number of times the bandwidth changed between packets.
+	pub bandwidth_changes: u64,
+	/// The number of times the coded channel count changed between packets.
+	pub channel_changes: u64,
+}
+
+impl DecoderStats {
+	/// Get the total duration of output produced by concealment or FEC.
+	pub fn concealed_duration(&self) -> Duration {
+		Duration::from_micros(self.concealed_samples * 1000 / 48)
+	}
+
+	/// Get the fraction of output produced by concealment or FEC.
+	pub fn concealed_fraction(&self) -> f64 {
+		if self.samples == 0 {
+			0.0
+		} else {
+			self.concealed_samples as f64 / self.samples as f64
+		}
+	}
+
+	pub(crate) fn record(&mut self, data: &[u8], fec: bool, result: &Result<usize>, rate: u32) {
+		let samples = match *result {
+			Ok(samples) if rate > 0 => samples as u64 * 48000 / rate as u64,
+			Ok(_) => 0,
+			Err(ref err) => {
+				self.errors.record(err.code());
+				return;
+			}
+		};
+		self.samples += samples;
+		if data.is_empty() {
+			self.plc_frames += 1;
+			self.concealed_samples += samples;
+			return;
+		}
+		if fec {
+			self.fec_frames += 1;
+			self.concealed_samples += samples;
+			return;
+		}
+
+		self.packets += 1;
+		self.bytes += data.len() as u64;
+		if let Ok(toc) = packet::get_toc(data) {
+			if self.mode.is_some() && self.mode != Some(toc.mode) {
+				self.mode_changes += 1;
+			}
+			if self.bandwidth.is_some() && self.bandwidth != Some(toc.bandwidth) {
+				self.bandwidth_changes += 1;
+			}
+			if self.channels.is_some() && self.channels != Some(toc.channels) {
+				self.channel_changes += 1;
+			}
+			self.mode = Some(toc.mode);
+			self.bandwidth = Some(toc.bandwidth);
+			self.channels = Some(toc.channels);
+		}
+	}
+}
+
+/// Counts of failed calls for each error code.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ErrorCounts {
+	/// Calls which failed with `ErrorCode::BadArg`.
+	pub bad_arg: u64,
+	/// Calls which failed with `ErrorCode::BufferTooSmall`.
+	pub buffer_too_small: u64,
+	/// Calls which failed with `ErrorCode::InternalError`.
+	pub internal_error: u64,
+	/// Calls which failed with `ErrorCode::InvalidPacket`.
+	pub invalid_packet: u64,
+	/// Calls which failed with `ErrorCode::Unimplemented`.
+	pub unimplemented: u64,
+	/// Calls which failed with `ErrorCode::InvalidState`.
+	pub invalid_state: u64,
+	/// Calls which failed with `ErrorCode::AllocFail`.
+	pub alloc_fail: u64,
+	/// Calls which failed with `ErrorCode::Unknown`.
+	pub unknown: u64,
+}
+
+impl ErrorCounts {
+	/// Get the count for a particular error code.
+	pub fn get(&self, code: ErrorCode) -> u64 {
+		*match code {
+			ErrorCode::BadArg => &self.bad_arg,
+			ErrorCode::BufferTooSmall => &self.buffer_too_small,
+			ErrorCode::InternalError => &self.internal_error,
+			ErrorCode::InvalidPacket => &self.invalid_packet,
+			ErrorCode::Unimplemented => &self.unimplemented,
+			ErrorCode::InvalidState => &self.invalid_state,
+			ErrorCode::AllocFail => &self.alloc_fail,
+			ErrorCode::Unknown => &self.unknown,
+		}
+	}
+
+	/// Get the total number of failed calls.
+	pub fn total(&self) -> u64 {
+		self.bad_arg
+			+ self.buffer_too_small
+			+ self.internal_error
+			+ self.invalid_packet
+			+ self.unimplemented
+			+ self.invalid_state
+			+ self.alloc_fail
+			+ self.unknown
+	}
+
+	fn record(&mut self, code: ErrorCode) {
+		*match code {
+			ErrorCode::BadArg => &mut self.bad_arg,
+			ErrorCode::BufferTooSmall => &mut self.buffer_too_small,
+			ErrorCode::InternalError => &mut self.internal_error,
+			ErrorCode::InvalidPacket => &mut self.invalid_packet,
+			ErrorCode::Unimplemented => &mut self.unimplemented,
+			ErrorCode::InvalidState => &mut self.invalid_state,
+			ErrorCode::AllocFail => &mut self.alloc_fail,
+			ErrorCode::Unknown => &mut self.unknown,
+		} += 1;
+	}
+}
@@ -0,0 +1,176 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Bitstream conformance checks using the final range of the entropy coder.
+//!
+//! After coding each packet, the encoder and decoder both report the final
+//! state of their range coder through `get_final_range`. The values match
+//! whenever the decoder has interpreted the packet exactly as the encoder
+//! wrote it, so a mismatch points to memory corruption or mismatched library
+//! builds. This is the same check `opus_demo` performs.
+
+use super::{Decoder, Encoder, Error, ErrorKind, FrameSize, Result, Sample, MAX_PACKET_SIZE};
+use std::collections::VecDeque;
+
+/// Matches encoder final ranges against decoder final ranges, packet by
+/// packet.
+///
+/// Call `record` with the encoder's final range after encoding each packet,
+/// and `verify` with the decoder's final range after decoding it. Packets
+/// which are lost or concealed should be passed to `skip` instead, as the
+/// decoder's range is meaningless for them.
+#[derive(Debug, Clone, Default)]
+pub struct RangeVerifier {
+	pending: VecDeque<u32>,
+	next_packet: u64,
+	verified: u64,
+	skipped: u64,
+}
+
+impl RangeVerifier {
+	/// Create a verifier with no packets recorded.
+	pub fn new() -> RangeVerifier {
+		RangeVerifier::default()
+	}
+
+	/// Record the encoder's final range for the next packet.
+	pub fn record(&mut self, range: u32) {
+		self.pending.push_back(range);
+	}
+
+	/// Check the decoder's final range for the oldest unverified packet.
+	pub fn verify(&mut self, range: u32) -> Result<()> {
+		let expected = self.take("RangeVerifier::verify")?;
+		let packet = self.next_packet - 1;
+		if expected != range {
+			return Err(Error::new(
+				"RangeVerifier::verify",
+				ErrorKind::RangeMismatch { packet, expected, actual: range },
+			));
+		}
+		self.verified += 1;
+		Ok(())
+	}
+
+	/// Discard the oldest unverified packet, such as when it was lost.
+	pub fn skip(&mut self) -> Result<()> {
+		self.take("RangeVerifier::skip")?;
+		self.skipped += 1;
+		Ok(())
+	}
+
+	/// Get the number of packets recorded but not yet verified or skipped.
+	pub fn pending(&self) -> usize {
+		self.pending.len()
+	}
+
+	/// Get the number of packets whose ranges matched.
+	pub fn verified(&self) -> u64 {
+		self.verified
+	}
+
+	/// Get the number of packets skipped.
+	pub fn skipped(&self) -> u64 {
+		self.skipped
+	}
+
+	fn take(&mut self, what: &'static str) -> Result<u32> {
+		match self.pending.pop_front() {
+			Some(range) => {
+				self.next_packet += 1;
+				Ok(range)
+			}
+			None => Err(Error::bad_arg(what).with_context("no packets recorded")),
+		}
+	}
+}
+
+/// The result of `verify_roundtrip`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct RoundtripReport {
+	/// The number of packets encoded and decoded, including any divergent
+	/// packet.
+	pub packets: u64,
+	/// The first packet whose final ranges differed, if any. Coding stops at
+	/// the first divergence.
+	pub divergence: Option<Divergence>,
+}
+
+impl RoundtripReport {
+	/// Check whether every packet's final range matched.
+	pub fn is_conformant(&self) -> bool {
+		self.divergence.is_none()
+	}
+}
+
+/// A packet whose encoder and decoder final ranges differed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+pub struct Divergence {
+	/// The index of the packet, counting from zero.
+	pub packet: u64,
+	/// The position in the input at which the packet starts, in samples
+	/// *per channel*.
+	pub offset: usize,
+	/// The encoder's final range.
+	pub expected: u32,
+	/// The decoder's final range.
+	pub actual: u32,
+}
+
+/// Encode interleaved audio and decode each packet immediately, comparing
+/// final ranges, and report the first divergence.
+///
+/// The input is split into frames of `frame_size`, with the last frame padded
+/// with silence. Errors from the codecs themselves are returned as errors
+/// rather than divergences.
+pub fn verify_roundtrip<T: Sample>(
+	encoder: &mut Encoder,
+	decoder: &mut Decoder,
+	input: &[T],
+	frame_size: FrameSize,
+) -> Result<RoundtripReport> {
+	let channels = encoder.channels as usize;
+	let sample_rate = encoder.get_sample_rate()?;
+	let frame_len = match frame_size.samples(sample_rate) {
+		Some(samples) => samples,
+		None => {
+			return Err(Error::new(
+				"verify_roundtrip",
+				ErrorKind::UnsupportedFrameSize { frame_size, sample_rate },
+			))
+		}
+	};
+	super::check_frame_len("verify_roundtrip", input.len(), channels)?;
+
+	// room for 120 ms at the highest rate
+	let mut output = vec![0.0f32; 5760 * decoder.channels as usize];
+	let mut frame = vec![T::default(); frame_len * channels];
+	let mut report = RoundtripReport { packets: 0, divergence: None };
+	for (index, chunk) in input.chunks(frame_len * channels).enumerate() {
+		frame[..chunk.len()].copy_from_slice(chunk);
+		for sample in &mut frame[chunk.len()..] {
+			*sample = T::default();
+		}
+
+		let packet = T::encode_vec(encoder, &frame, MAX_PACKET_SIZE)?;
+		let expected = encoder.get_final_range()?;
+		decoder.decode_float(&packet, &mut output, false)?;
+		let actual = decoder.get_final_range()?;
+		report.packets += 1;
+		if expected != actual {
+			report.divergence = Some(Divergence {
+				packet: index as u64,
+				offset: index * frame_len,
+				expected,
+				actual,
+			});
+			break;
+		}
+	}
+	Ok(report)
+}
@@ -0,0 +1,1074 @@
+// Copyright 2016 Tad Hardesty
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified, or distributed
+// except according to those terms.
+
+//! Audio-only WebM files containing a single Opus track.
+//!
+//! WebM is a subset of Matroska, which stores elements in EBML, a binary
+//! format of nested length-prefixed elements. The Opus track stores an
+//! `OpusHead` as its codec private data, and gives the pre-skip as
+//! `CodecDelay` in nanoseconds. Samples to trim from the end of the stream
+//! are given as `DiscardPadding` on the final block.
+//!
+//! `WebmWriter` writes such files, `WebmReader` reads their packets back, and
+//! `WebmDecoder` decodes them to PCM with the delay and padding removed.
+
+use super::opus_head::OpusHead;
+use super::{packet, Error, ErrorKind, MSDecoder, Result, Sample};
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+// EBML header
+const EBML: u32 = 0x1A45_DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+
+// segment
+const SEGMENT: u32 = 0x1853_8067;
+const SEEK_HEAD: u32 = 0x114D_9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const INFO: u32 = 0x1549_A966;
+const TIMESTAMP_SCALE: u32 = 0x2A_D7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+
+// tracks
+const TRACKS: u32 = 0x1654_AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const CODEC_PRIVATE: u32 = 0x63A2;
+const CODEC_DELAY: u32 = 0x56AA;
+const SEEK_PRE_ROLL: u32 = 0x56BB;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+
+// clusters
+const CLUSTER: u32 = 0x1F43_B675;
+const TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const BLOCK_GROUP: u32 = 0xA0;
+const BLOCK: u32 = 0xA1;
+const DISCARD_PADDING: u32 = 0x75A2;
+
+// cues
+const CUES: u32 = 0x1C53_BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+/// The codec ID of Opus tracks.
+const CODEC_OPUS: &str = "A_OPUS";
+/// The track type of audio tracks.
+const TRACK_AUDIO: u64 = 2;
+/// The track number of the Opus track.
+const TRACK: u64 = 1;
+/// Timestamps are in milliseconds.
+const SCALE: u64 = 1_000_000;
+/// The pre-roll RFC 7845 recommends after seeking, in nanoseconds.
+const PRE_ROLL: u64 = 80_000_000;
+/// The duration after which a new cluster is started, in milliseconds.
+const CLUSTER_MS: u64 = 5000;
+/// The size which marks an element of unknown size.
+const UNKNOWN_SIZE: u64 = (1 << 56) - 1;
+
+/// Writes an audio-only WebM file containing one Opus track.
+///
+/// The output is written sequentially and needs no seeking, so it may be
+/// streamed. Each cluster is buffered until it is complete, and cue points
+/// for every cluster are written by `finish`. Because the segment is written
+/// with an unknown size, no duration is recorded; players compute it from
+/// the cues or the blocks themselves.
+#[derive(Debug)]
+pub struct WebmWriter<W: Write> {
+	writer: W,
+	/// The position of the next byte relative to the start of the segment
+	/// data.
+	position: u64,
+	/// The timestamp of the current cluster, in milliseconds.
+	cluster_time: u64,
+	/// The blocks of the current cluster.
+	cluster: Vec<u8>,
+	cues: Vec<(u64, u64)>,
+	/// The packet waiting to be written, held back in case it is the last.
+	pending: Option<Vec<u8>>,
+	/// The samples at 48 kHz written so far, including the pending packet.
+	samples: u64,
+	pre_skip: u64,
+}
+
+impl<W: Write> WebmWriter<W> {
+	/// Start a WebM file with a track described by the given header.
+	pub fn new(mut writer: W, head: &OpusHead) -> Result<WebmWriter<W>> {
+		let mut header = Vec::new();
+		master(&mut header, EBML, |buf| {
+			uint(buf, EBML_VERSION, 1);
+			uint(buf, EBML_READ_VERSION, 1);
+			uint(buf, EBML_MAX_ID_LENGTH, 4);
+			uint(buf, EBML_MAX_SIZE_LENGTH, 8);
+			string(buf, DOC_TYPE, "webm");
+			uint(buf, DOC_TYPE_VERSION, 4);
+			uint(buf, DOC_TYPE_READ_VERSION, 2);
+		});
+		id(&mut header, SEGMENT);
+		size(&mut header, UNKNOWN_SIZE);
+		writer.write_all(&header)?;
+
+		let mut segment = Vec::new();
+		master(&mut segment, INFO, |buf| {
+			uint(buf, TIMESTAMP_SCALE, SCALE);
+			string(buf, MUXING_APP, concat!("opus-rs ", env!("CARGO_PKG_VERSION")));
+			string(buf, WRITING_APP, concat!("opus-rs ", env!("CARGO_PKG_VERSION")));
+		});
+		master(&mut segment, TRACKS, |buf| {
+			master(buf, TRACK_ENTRY, |buf| {
+				uint(buf, TRACK_NUMBER, TRACK);
+				uint(buf, TRACK_UID, TRACK);
+				uint(buf, TRACK_TYPE, TRACK_AUDIO);
+				uint(buf, FLAG_LACING, 0);
+				string(buf, CODEC_ID, CODEC_OPUS);
+				binary(buf, CODEC_PRIVATE, &head.to_bytes());
+				uint(buf, CODEC_DELAY, samples_to_ns(head.pre_skip as u64));
+				uint(buf, SEEK_PRE_ROLL, PRE_ROLL);
+				master(buf, AUDIO, |buf| {
+					float(buf, SAMPLING_FREQUENCY, 48000.0);
+					uint(buf, CHANNELS, head.channels as u64);
+				});
+			});
+		});
+		writer.write_all(&segment)?;
+
+		Ok(WebmWriter {
+			writer,
+			position: segment.len() as u64,
+			cluster_time: 0,
+			cluster: Vec::new(),
+			cues: Vec::new(),
+			pending: None,
+			samples: 0,
+			pre_skip: head.pre_skip as u64,
+		})
+	}
+
+	/// Write an encoded packet.
+	///
+	/// The packet's duration is read from its TOC byte.
+	pub fn write_packet(&mut self, packet: &[u8]) -> Result<()> {
+		let duration = packet::get_nb_samples(packet, 48000)? as u64;
+		if let Some(previous) = self.pending.take() {
+			self.write_block(&previous, 0)?;
+		}
+		self.pending = Some(packet.to_vec());
+		self.samples += duration;
+		Ok(())
+	}
+
+	/// Get the total duration of the packets written, in samples at 48 kHz,
+	/// including the pre-skip.
+	pub fn samples(&self) -> u64 {
+		self.samples
+	}
+
+	/// Finish the file, keeping all of the decoded audio after the pre-skip.
+	pub fn finish(self) -> Result<W> {
+		self.finish_with_padding(0)
+	}
+
+	/// Finish the file, trimming the decoded audio after the pre-skip to
+	/// `length` samples at 48 kHz.
+	///
+	/// This is normally the length of the encoder's input, so the padding
+	/// added to fill the last frame is discarded on playback. At most the
+	/// duration of the final packet can be trimmed.
+	pub fn finish_with_length(self, length: u64) -> Result<W> {
+		let padding = self.samples.saturating_sub(self.pre_skip + length);
+		self.finish_with_padding(padding)
+	}
+
+	fn finish_with_padding(mut self, padding: u64) -> Result<W> {
+		if let Some(last) = self.pending.take() {
+			let duration = packet::get_nb_samples(&last, 48000)? as u64;
+			self.write_block(&last, padding.min(duration))?;
+		}
+		self.flush_cluster()?;
+
+		if !self.cues.is_empty() {
+			let mut cues = Vec::new();
+			master(&mut cues, CUES, |buf| {
+				for &(time, position) in &self.cues {
+					master(buf, CUE_POINT, |buf| {
+						uint(buf, CUE_TIME, time);
+						master(buf, CUE_TRACK_POSITIONS, |buf| {
+							uint(buf, CUE_TRACK, TRACK);
+							uint(buf, CUE_CLUSTER_POSITION, position);
+						});
+					});
+				}
+			});
+			self.writer.write_all(&cues)?;
+		}
+		self.writer.flush()?;
+		Ok(self.writer)
+	}
+
+	fn write_block(&mut self, packet: &[u8], padding: u64) -> Result<()> {
+		let duration = packet::get_nb_samples(packet, 48000)? as u64;
+		let time = samples_to_ns(self.samples - duration) / SCALE;
+		if self.cluster.is_empty() || time >= self.cluster_time + CLUSTER_MS {
+			self.flush_cluster()?;
+			self.cluster_time = time;
+			uint(&mut self.cluster, TIMESTAMP, time);
+		}
+
+		let mut block = Vec::with_capacity(packet.len() + 4);
+		size(&mut block, TRACK);
+		block.extend_from_slice(&((time - self.cluster_time) as i16).to_be_bytes());
+		if padding == 0 {
+			// keyframe
+			block.push(0x80);
+			block.extend_from_slice(packet);
+			binary(&mut self.cluster, SIMPLE_BLOCK, &block);
+		} else {
+			block.push(0);
+			block.extend_from_slice(packet);
+			master(&mut self.cluster, BLOCK_GROUP, |buf| {
+				binary(buf, BLOCK, &block);
+				int(buf, DISCARD_PADDING, samples_to_ns(padding) as i64);
+			});
+		}
+		Ok(())
+	}
+
+	fn flush_cluster(&mut self) -> Result<()> {
+		if self.cluster.is_empty() {
+			return Ok(());
+		}
+		let mut header = Vec::new();
+		id(&mut header, CLUSTER);
+		size(&mut header, self.cluster.len() as u64);
+		self.writer.write_all(&header)?;
+		self.writer.write_all(&self.cluster)?;
+		self.cues.push((self.cluster_time, self.position));
+		self.position += (header.len() + self.cluster.len()) as u64;
+		self.cluster.clear();
+		Ok(())
+	}
+}
+
+fn samples_to_ns(samples: u64) -> u64 {
+	samples * 1_000_000_000 / 48000
+}
+
+/// A packet read from a WebM file.
+#[derive(Debug, Clone, Eq, PartialEq, Hash, Default)]
+pub struct WebmPacket {
+	/// The packet data.
+	pub data: Vec<u8>,
+	/// The packet's timestamp in nanoseconds, as stored in the file.
+	///
+	/// This includes the codec delay, so the first packet is normally at
+	/// zero even though its audio is not presented.
+	pub timestamp: u64,
+	/// The nanoseconds of decoded audio to discard from the end of the
+	/// packet. Negative values in the file are treated as zero.
+	pub discard_padding: u64,
+}
+
+/// Reads the Opus track of a WebM or Matroska file.
+///
+/// The first audio track with the `A_OPUS` codec is used, and blocks from
+/// any other tracks are skipped. Packets are read sequentially, so any
+/// `Read` works; `seek` additionally requires `Seek`, and uses the cue
+/// points to find the right cluster when the file has them. The stream must
+/// start at the beginning of the file.
+#[derive(Debug)]
+pub struct WebmReader<R: Read> {
+	reader: Counter<R>,
+	head: OpusHead,
+	track: u64,
+	codec_delay: u64,
+	seek_pre_roll: u64,
+	/// Nanoseconds per timestamp unit.
+	scale: u64,
+	segment_start: u64,
+	segment_end: Option<u64>,
+	first_cluster: u64,
+	/// The position of the cues given by the seek head, if any.
+	cues_position: Option<u64>,
+	/// Cue times in nanoseconds and absolute cluster positions, once loaded.
+	cues: Option<Vec<(u64, u64)>>,
+	/// The timestamp of the current cluster, in timestamp units.
+	cluster_time: u64,
+	/// Packets from the current block which have not yet been returned.
+	queue: VecDeque<WebmPacket>,
+	/// After seeking, packets which end at or before this time are skipped.
+	skip_until: Option<u64>,
+}
+
+impl<R: Read> WebmReader<R> {
+	/// Read the headers of a WebM file, up to its first cluster.
+	pub fn new(reader: R) -> Result<WebmReader<R>> {
+		let mut reader = Counter { inner: reader, position: 0 };
+		match read_header(&mut reader)? {
+			Some((EBML, size)) => {
+				let body = read_body(&mut reader, size)?;
+				let doc_type = children(&body)?
+					.into_iter()
+					.find(|&(id, _)| id == DOC_TYPE)
+					.map(|(_, data)| string_value(data))
+					.unwrap_or_default();
+				if doc_type != "webm" && doc_type != "matroska" {
+					return Err(malformed("unsupported document type"));
+				}
+			}
+			_ => return Err(malformed("not an EBML file")),
+		}
+		let segment_size = match read_header(&mut reader)? {
+			Some((SEGMENT, size)) => size,
+			_ => return Err(malformed("no segment")),
+		};
+		let segment_start = reader.position;
+		let segment_end = match segment_size {
+			UNKNOWN_SIZE => None,
+			size => Some(segment_start + size),
+		};
+
+		let mut scale = SCALE;
+		let mut track = None;
+		let mut cues_position = None;
+		let mut cues = None;
+		let first_cluster;
+		loop {
+			let start = reader.position;
+			if segment_end.is_some_and(|end| start >= end) {
+				first_cluster = start;
+				break;
+			}
+			let (id, size) = match read_header(&mut reader)? {
+				Some(header) => header,
+				None => {
+					first_cluster = start;
+					break;
+				}
+			};
+			match id {
+				CLUSTER => {
+					// the cluster's contents are read by `read_packet`
+					first_cluster = start;
+					break;
+				}
+				SEEK_HEAD => {
+					for (id, seek) in children(&read_body(&mut reader, size)?)? {
+						if id != SEEK {
+							continue;
+						}
+						let mut target = None;
+						let mut position = None;
+						for (id, data) in children(seek)? {
+							match id {
+								SEEK_ID => target = Some(data.to_vec()),
+								SEEK_POSITION => position = Some(uint_value(data)?),
+								_ => {}
+							}
+						}
+						if let (Some(target), Some(position)) = (target, position) {
+							if target == CUES.to_be_bytes() {
+								cues_position = Some(segment_start + position);
+							}
+						}
+					}
+				}
+				INFO => {
+					for (id, data) in children(&read_body(&mut reader, size)?)? {
+						if id == TIMESTAMP_SCALE {
+							scale = uint_value(data)?;
+						}
+					}
+				}
+				TRACKS => {
+					for (id, entry) in children(&read_body(&mut reader, size)?)? {
+						if id == TRACK_ENTRY && track.is_none() {
+							track = parse_track(entry)?;
+						}
+					}
+				}
+				CUES => cues = Some(read_body(&mut reader, size)?),
+				_ => skip(&mut reader, size)?,
+			}
+		}
+
+		let (number, head, codec_delay, seek_pre_roll) = match track {
+			Some(track) => track,
+			None => return Err(malformed("no Opus track")),
+		};
+		let cues = match cues {
+			Some(body) => Some(parse_cues(&body, number, scale, segment_start)?),
+			None => None,
+		};
+		Ok(WebmReader {
+			reader,
+			head,
+			track: number,
+			codec_delay,
+			seek_pre_roll,
+			scale,
+			segment_start,
+			segment_end,
+			first_cluster,
+			cues_position,
+			cues,
+			cluster_time: 0,
+			queue: VecDeque::new(),
+			skip_until: None,
+		})
+	}
+
+	/// Get the Opus track's identification header, from its codec private
+	/// data.
+	pub fn head(&self) -> &OpusHead {
+		&self.head
+	}
+
+	/// Get the codec delay in nanoseconds, which is the pre-skip.
+	pub fn codec_delay(&self) -> u64 {
+		self.codec_delay
+	}
+
+	/// Get the pre-roll in nanoseconds needed to decode correctly after
+	/// seeking.
+	pub fn seek_pre_roll(&self) -> u64 {
+		self.seek_pre_roll
+	}
+
+	/// Read the next packet of the Opus track, or `None` at the end of the
+	/// file.
+	pub fn read_packet(&mut self) -> Result<Option<WebmPacket>> {
+		loop {
+			if let Some(packet) = self.queue.pop_front() {
+				if let Some(target) = self.skip_until {
+					let duration = packet::get_nb_samples(&packet.data, 48000).unwrap_or(0);
+					if packet.timestamp + samples_to_ns(duration as u64) <= target {
+						continue;
+					}
+					self.skip_until = None;
+				}
+				return Ok(Some(packet));
+			}
+
+			if self.segment_end.is_some_and(|end| self.reader.position >= end) {
+				return Ok(None);
+			}
+			let (id, size) = match read_header(&mut self.reader)? {
+				Some(header) => header,
+				None => return Ok(None),
+			};
+			// clusters may have an unknown size, so their children are read
+			// at the same level as the clusters themselves
+			match id {
+				CLUSTER => {}
+				TIMESTAMP => self.cluster_time = uint_value(&read_body(&mut self.reader, size)?)?,
+				SIMPLE_BLOCK => {
+					let block = read_body(&mut self.reader, size)?;
+					self.queue_block(&block, 0)?;
+				}
+				BLOCK_GROUP => {
+					let group = read_body(&mut self.reader, size)?;
+					let mut block = None;
+					let mut padding = 0;
+					for (id, data) in children(&group)? {
+						match id {
+							BLOCK => block = Some(data),
+							DISCARD_PADDING => padding = int_value(data)?.max(0) as u64,
+							_ => {}
+						}
+					}
+					if let Some(block) = block {
+						self.queue_block(block, padding)?;
+					}
+				}
+				_ => skip(&mut self.reader, size)?,
+			}
+		}
+	}
+
+	/// Unwrap the underlying stream.
+	pub fn into_inner(self) -> R {
+		self.reader.inner
+	}
+
+	fn queue_block(&mut self, block: &[u8], padding: u64) -> Result<()> {
+		let mut data = block;
+		let track = read_vint(&mut data, false)?;
+		if track != self.track {
+			return Ok(());
+		}
+		if data.len() < 3 {
+			return Err(malformed("truncated block"));
+		}
+		let relative = i16::from_be_bytes([data[0], data[1]]) as i64;
+		let flags = data[2];
+		data = &data[3..];
+
+		// blocks before the start of the stream are clamped to zero
+		let time = match self.cluster_time.checked_add_signed(relative) {
+			Some(time) => time,
+			None if relative < 0 => 0,
+			None => return Err(malformed("timestamp overflow")),
+		};
+		let mut timestamp = scale_timestamp(time, self.scale)?;
+		let frames = read_lacing(&mut data, flags)?;
+		let count = frames.len();
+		for (i, frame) in frames.into_iter().enumerate() {
+			let duration = packet::get_nb_samples(frame, 48000).unwrap_or(0);
+			self.queue.push_back(WebmPacket {
+				data: frame.to_vec(),
+				timestamp,
+				discard_padding: if i + 1 == count { padding } else { 0 },
+			});
+			timestamp = timestamp
+				.checked_add(samples_to_ns(duration as u64))
+				.ok_or_else(|| malformed("timestamp overflow"))?;
+		}
+		Ok(())
+	}
+}
+
+impl<R: Read + Seek> WebmReader<R> {
+	/// Seek so that the next packet read is the first which ends after the
+	/// given timestamp, in nanoseconds as stored in the file.
+	///
+	/// The cue points are used to find the cluster to start reading from.
+	/// Without them, reading starts again from the first cluster.
+	pub fn seek(&mut self, timestamp: u64) -> Result<()> {
+		if self.cues.is_none() {
+			self.cues = Some(self.load_cues()?);
+		}
+		let position = self
+			.cues
+			.iter()
+			.flatten()
+			.rev()
+			.find(|&&(time, _)| time <= timestamp)
+			.map_or(self.first_cluster, |&(_, position)| position);
+		self.reader.seek_to(position)?;
+		self.cluster_time = 0;
+		self.queue.clear();
+		self.skip_until = Some(timestamp);
+		Ok(())
+	}
+
+	/// Find the cues, either through the seek head or by skipping over the
+	/// clusters.
+	fn load_cues(&mut self) -> Result<Vec<(u64, u64)>> {
+		let start = self.cues_position.unwrap_or(self.first_cluster);
+		self.reader.seek_to(start)?;
+		loop {
+			if self.segment_end.is_some_and(|end| self.reader.position >= end) {
+				break;
+			}
+			match read_header(&mut self.reader)? {
+				Some((CUES, size)) => {
+					let body = read_body(&mut self.reader, size)?;
+					return parse_cues(&body, self.track, self.scale, self.segment_start);
+				}
+				Some((_, UNKNOWN_SIZE)) | None => break,
+				Some((_, size)) => {
+					let next = self.reader.position + size;
+					self.reader.seek_to(next)?;
+				}
+			}
+		}
+		Ok(Vec::new())
+	}
+}
+
+/// Decodes the Opus track of a WebM file to PCM.
+///
+/// The codec delay is discarded from the start of the audio, and the
+/// discard padding from the end of each packet, so the output has exactly
+/// the length of the original input.
+#[derive(Debug)]
+pub struct WebmDecoder<R: Read> {
+	reader: WebmReader<R>,
+	decoder: MSDecoder,
+	sample_rate: u32,
+	/// Samples left to discard from the start of the next packets.
+	skip: u64,
+	/// After seeking, the timestamp which output should start at.
+	seek_target: Option<u64>,
+}
+
+impl<R: Read> WebmDecoder<R> {
+	/// Create a decoder producing output at the given sample rate.
+	///
+	/// A multistream decoder is always used, built from the track's
+	/// `OpusHead`, so every channel mapping family is supported.
+	pub fn new(reader: WebmReader<R>, sample_rate: u32) -> Result<WebmDecoder<R>> {
+		let decoder = reader.head().decoder(sample_rate)?;
+		let skip = ns_to_samples(reader.codec_delay(), sample_rate)?;
+		Ok(WebmDecoder {
+			reader,
+			decoder,
+			sample_rate,
+			skip,
+			seek_target: None,
+		})
+	}
+
+	/// Get the number of output channels.
+	pub fn channels(&self) -> usize {
+		self.decoder.channels as usize
+	}
+
+	/// Get the output sample rate.
+	pub fn sample_rate(&self) -> u32 {
+		self.sample_rate
+	}
+
+	/// Get a reference to the packet reader.
+	pub fn reader(&self) -> &WebmReader<R> {
+		&self.reader
+	}
+
+	/// Get a mutable reference to the underlying decoder, such as to enable
+	/// statistics.
+	pub fn decoder(&mut self) -> &mut MSDecoder {
+		&mut self.decoder
+	}
+
+	/// Decode the next packet, appending its interleaved output.
+	///
+	/// Returns the number of samples *per channel* appended, which may be
+	/// zero while the codec delay is discarded, or `None` at the end of the
+	/// file.
+	pub fn decode<T: Sample>(&mut self, output: &mut Vec<T>) -> Result<Option<usize>> {
+		let packet = match self.reader.read_packet()? {
+			Some(packet) => packet,
+			None => return Ok(None),
+		};
+		if let Some(target) = self.seek_target.take() {
+			self.skip = ns_to_samples(target.saturating_sub(packet.timestamp), self.sample_rate)?;
+		}
+		let padding = ns_to_samples(packet.discard_padding, self.sample_rate)?;
+
+		let channels = self.channels();
+		let start = output.len();
+		// room for 120 ms
+		output.resize(start + self.sample_rate as usize * 120 / 1000 * channels, T::default());
+		let samples =
+			match T::ms_decode(&mut self.decoder, &packet.data, &mut output[start..], false) {
+				Ok(samples) => samples,
+				Err(err) => {
+					output.truncate(start);
+					return Err(err);
+				}
+			};
+		let end = samples - padding.min(samples as u64) as usize;
+		let skip = (self.skip.min(end as u64)) as usize;
+		self.skip -= skip as u64;
+		output.truncate(start + end * channels);
+		output.drain(start..start + skip * channels);
+		Ok(Some(end - skip))
+	}
+
+	/// Decode the rest of the file.
+	pub fn decode_all<T: Sample>(&mut self) -> Result<Vec<T>> {
+		let mut output = Vec::new();
+		while self.decode(&mut output)?.is_some() {}
+		Ok(output)
+	}
+
+	/// Unwrap the packet reader.
+	pub fn into_reader(self) -> WebmReader<R> {
+		self.reader
+	}
+}
+
+impl<R: Read + Seek> WebmDecoder<R> {
+	/// Seek so that the next output starts at the given sample *per channel*,
+	/// counting from the start of the presented audio.
+	///
+	/// Decoding resumes the seek pre-roll before the target so the decoder
+	/// can converge, and the output is accurate to the precision of the
+	/// file's timestamps.
+	pub fn seek(&mut self, sample: u64) -> Result<()> {
+		let target = u64::try_from(sample as u128 * 1_000_000_000 / self.sample_rate as u128)
+			.ok()
+			.and_then(|ns| ns.checked_add(self.reader.codec_delay()))
+			.ok_or_else(|| {
+				Error::bad_arg("WebmDecoder::seek").with_context("sample out of range")
+			})?;
+		self.reader.seek(target.saturating_sub(self.reader.seek_pre_roll()))?;
+		self.decoder.reset_state()?;
+		self.seek_target = Some(target);
+		Ok(())
+	}
+}
+
+/// Parse a track entry, returning its number, header, codec delay and seek
+/// pre-roll if it is an Opus track.
+fn parse_track(entry: &[u8]) -> Result<Option<(u64, OpusHead, u64, u64)>> {
+	let mut number = None;
+	let mut codec = String::new();
+	let mut private = None;
+	let mut codec_delay = None;
+	let mut seek_pre_roll = PRE_ROLL;
+	for (id, data) in children(entry)? {
+		match id {
+			TRACK_NUMBER => number = Some(uint_value(data)?),
+			CODEC_ID => codec = string_value(data),
+			CODEC_PRIVATE => private = Some(data),
+			CODEC_DELAY => codec_delay = Some(uint_value(data)?),
+			SEEK_PRE_ROLL => seek_pre_roll = uint_value(data)?,
+			_ => {}
+		}
+	}
+	let (number, private) = match (number, private) {
+		(Some(number), Some(private)) if codec == CODEC_OPUS => (number, private),
+		_ => return Ok(None),
+	};
+	let head = OpusHead::parse(private)?;
+	// the delay can't be more than the longest pre-skip OpusHead can hold
+	let codec_delay = codec_delay.unwrap_or_else(|| samples_to_ns(head.pre_skip as u64));
+	if codec_delay > samples_to_ns(u16::MAX as u64) {
+		return Err(malformed("implausible codec delay"));
+	}
+	Ok(Some((number, head, codec_delay, seek_pre_roll)))
+}
+
+/// Parse the cue points for a track into times in nanoseconds and absolute
+/// cluster positions.
+fn parse_cues(cues: &[u8], track: u64, scale: u64, segment_start: u64) -> Result<Vec<(u64, u64)>> {
+	let mut points = Vec::new();
+	for (id, point) in children(cues)? {
+		if id != CUE_POINT {
+			continue;
+		}
+		let mut time = None;
+		let mut position = None;
+		for (id, data) in children(point)? {
+			match id {
+				CUE_TIME => time = Some(uint_value(data)?),
+				CUE_TRACK_POSITIONS => {
+					let mut cue_track = track;
+					let mut cluster = None;
+					for (id, data) in children(data)? {
+						match id {
+							CUE_TRACK => cue_track = uint_value(data)?,
+							CUE_CLUSTER_POSITION => cluster = Some(uint_value(data)?),
+							_ => {}
+						}
+					}
+					if cue_track == track && position.is_none() {
+						position = cluster;
+					}
+				}
+				_ => {}
+			}
+		}
+		if let (Some(time), Some(position)) = (time, position) {
+			let position =
+				segment_start.checked_add(position).ok_or_else(|| malformed("cue overflow"))?;
+			points.push((scale_timestamp(time, scale)?, position));
+		}
+	}
+	points.sort_unstable();
+	Ok(points)
+}
+
+/// Split a block's data into frames according to its lacing.
+fn read_lacing<'a>(data: &mut &'a [u8], flags: u8) -> Result<Vec<&'a [u8]>> {
+	let lacing = (flags >> 1) & 3;
+	if lacing == 0 {
+		return Ok(vec![*data]);
+	}
+	let count = match data.first() {
+		Some(&count) => count as usize + 1,
+		None => return Err(malformed("truncated block")),
+	};
+	*data = &data[1..];
+
+	let mut sizes = Vec::with_capacity(count);
+	match lacing {
+		// Xiph lacing
+		1 => {
+			for _ in 1..count {
+				let mut size = 0;
+				loop {
+					let byte = match data.first() {
+						Some(&byte) => byte,
+						None => return Err(malformed("truncated lacing")),
+					};
+					*data = &data[1..];
+					size += byte as usize;
+					if byte != 255 {
+						break;
+					}
+				}
+				sizes.push(size);
+			}
+		}
+		// fixed-size lacing
+		2 => {
+			if data.len() % count != 0 {
+				return Err(malformed("uneven fixed-size lacing"));
+			}
+			sizes.resize(count - 1, data.len() / count);
+		}
+		// EBML lacing
+		_ => {
+			if count > 1 {
+				let mut size = read_vint(data, false)? as i64;
+				sizes.push(size as usize);
+				for _ in 2..count {
+					let first = data.first().copied().unwrap_or(0);
+					let len = first.leading_zeros() as i64 + 1;
+					// signed values are offset by half the range
+					size += read_vint(data, false)? as i64 - ((1 << (7 * len - 1)) - 1);
+					if size < 0 {
+						return Err(malformed("invalid lacing"));
+					}
+					sizes.push(size as usize);
+				}
+			}
+		}
+	}
+
+	let mut frames = Vec::with_capacity(count);
+	for size in sizes {
+		if size > data.len() {
+			return Err(malformed("truncated lacing"));
+		}
+		let (frame, rest) = data.split_at(size);
+		frames.push(frame);
+		*data = rest;
+	}
+	frames.push(*data);
+	Ok(frames)
+}
+
+fn ns_to_samples(ns: u64, sample_rate: u32) -> Result<u64> {
+	let samples = (ns as u128 * sample_rate as u128 + 500_000_000) / 1_000_000_000;
+	u64::try_from(samples).map_err(|_| malformed("timestamp overflow"))
+}
+
+// ============================================================================
+// EBML Reading
+
+/// Tracks the position within the file.
+#[derive(Debug)]
+struct Counter<R> {
+	inner: R,
+	position: u64,
+}
+
+impl<R: Read> Read for Counter<R> {
+	fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+		let n = self.inner.read(buf)?;
+		self.position += n as u64;
+		Ok(n)
+	}
+}
+
+impl<R: Seek> Counter<R> {
+	fn seek_to(&mut self, position: u64) -> io::Result<()> {
+		self.inner.seek(SeekFrom::Start(position))?;
+		self.position = position;
+		Ok(())
+	}
+}
+
+fn malformed(context: &'static str) -> Error {
+	Error::new("WebmReader", ErrorKind::Malformed).with_context(context)
+}
+
+/// Convert a timestamp in units of the timestamp scale to nanoseconds.
+fn scale_timestamp(time: u64, scale: u64) -> Result<u64> {
+	time.checked_mul(scale).ok_or_else(|| malformed("timestamp overflow"))
+}
+
+/// Read a variable-length integer, with or without its length marker.
+///
+/// Sizes with every value bit set are returned as `UNKNOWN_SIZE`.
+fn read_vint<R: Read>(reader: &mut R, keep_marker: bool) -> Result<u64> {
+	let mut first = [0];
+	reader.read_exact(&mut first)?;
+	read_vint_from(reader, first[0], keep_marker)
+}
+
+fn read_vint_from<R: Read>(reader: &mut R, first: u8, keep_marker: bool) -> Result<u64> {
+	let len = first.leading_zeros() as usize + 1;
+	if len > 8 {
+		return Err(malformed("invalid variable-length integer"));
+	}
+	let mut bytes = [0; 8];
+	reader.read_exact(&mut bytes[..len - 1])?;
+	let mut value = first as u64;
+	if !keep_marker {
+		value &= 0xFF >> len;
+	}
+	for &byte in &bytes[..len - 1] {
+		value = value << 8 | byte as u64;
+	}
+	if !keep_marker && value == (1 << (7 * len)) - 1 {
+		return Ok(UNKNOWN_SIZE);
+	}
+	Ok(value)
+}
+
+/// Read an element's ID and size, or `None` at the end of the stream.
+fn read_header<R: Read>(reader: &mut R) -> Result<Option<(u32, u64)>> {
+	let mut first = [0];
+	loop {
+		match reader.read(&mut first) {
+			Ok(0) => return Ok(None),
+			Ok(_) => break,
+			Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+			Err(err) => return Err(err.into()),
+		}
+	}
+	if first[0].leading_zeros() >= 4 {
+		return Err(malformed("invalid element ID"));
+	}
+	let id = read_vint_from(reader, first[0], true)? as u32;
+	let size = read_vint(reader, false)?;
+	Ok(Some((id, size)))
+}
+
+fn read_body<R: Read>(reader: &mut R, size: u64) -> Result<Vec<u8>> {
+	if size == UNKNOWN_SIZE {
+		return Err(malformed("unexpected element of unknown size"));
+	}
+	let mut body = Vec::new();
+	reader.take(size).read_to_end(&mut body)?;
+	if body.len() as u64 != size {
+		return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+	}
+	Ok(body)
+}
+
+fn skip<R: Read>(reader: &mut R, size: u64) -> Result<()> {
+	if size == UNKNOWN_SIZE {
+		return Err(malformed("unexpected element of unknown size"));
+	}
+	if io::copy(&mut reader.take(size), &mut io::sink())? != size {
+		return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+	}
+	Ok(())
+}
+
+/// Split the body of a master element into its children.
+fn children(mut data: &[u8]) -> Result<Vec<(u32, &[u8])>> {
+	let mut children = Vec::new();
+	while let Some((id, size)) = read_header(&mut data)? {
+		if size > data.len() as u64 {
+			return Err(malformed("element overruns its parent"));
+		}
+		let (body, rest) = data.split_at(size as usize);
+		children.push((id, body));
+		data = rest;
+	}
+	Ok(children)
+}
+
+fn uint_value(data: &[u8]) -> Result<u64> {
+	if data.len() > 8 {
+		return Err(malformed("integer too long"));
+	}
+	Ok(data.iter().fold(0, |acc, &b| acc << 8 | b as u64))
+}
+
+fn int_value(data: &[u8]) -> Result<i64> {
+	let value = uint_value(data)?;
+	if data.is_empty() {
+		return Ok(0);
+	}
+	// sign-extend from the element's length
+	let shift = 64 - 8 * data.len() as u32;
+	Ok(((value << shift) as i64) >> shift)
+}
+
+fn string_value(data: &[u8]) -> String {
+	let end = data.iter().position(|&b| b == 0).unwrap_or(data.len());
+	String::from_utf8_lossy(&data[..end]).into_owned()
+}
+
+// ============================================================================
+// EBML Writing
+
+fn id(buf: &mut Vec<u8>, id: u32) {
+	let bytes = id.to_be_bytes();
+	let skip = bytes.iter().take_while(|&&b| b == 0).count();
+	buf.extend_from_slice(&bytes[skip..]);
+}
+
+fn size(buf: &mut Vec<u8>, size: u64) {
+	let mut len = 1;
+	while len < 8 && size >= (1 << (7 * len)) - 1 {
+		len += 1;
+	}
+	let marked = size | (1 << (7 * len));
+	buf.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+fn binary(buf: &mut Vec<u8>, element: u32, data: &[u8]) {
+	id(buf, element);
+	size(buf, data.len() as u64);
+	buf.extend_from_slice(data);
+}
+
+fn uint(buf: &mut Vec<u8>, element: u32, value: u64) {
+	let bytes = value.to_be_bytes();
+	let skip = bytes.iter().take_while(|&&b| b == 0).count().min(7);
+	binary(buf, element, &bytes[skip..]);
+}
+
+fn int(buf: &mut Vec<u8>, element: u32, value: i64) {
+	let bytes = value.to_be_bytes();
+	// drop leading bytes which only repeat the sign bit
+	let mut skip = 0;
+	while skip < 7 {
+		let redundant = match bytes[skip] {
+			0 => bytes[skip + 1] & 0x80 == 0,
+			0xFF => bytes[skip + 1] & 0x80 != 0,
+			_ => false,
+		};
+		if !redundant {
+			break;
+		}
+		skip += 1;
+	}
+	binary(buf, element, &bytes[skip..]);
+}
+
+fn float(buf: &mut Vec<u8>, element: u32, value: f64) {
+	binary(buf, element, &value.to_be_bytes());
+}
+
+fn string(buf: &mut Vec<u8>, element: u32, value: &str) {
+	binary(buf, element, value.as_bytes());
+}
+
+fn master<F: FnOnce(&mut Vec<u8>)>(buf: &mut Vec<u8>, element: u32, f: F) {
+	let mut body = Vec::new();
+	f(&mut body);
+	binary(buf, element, &body);
+}
@@ -0,0 +1,92 @@
+//! Test that the buffered encoder accepts input of arbitrary length.
+
+extern crate opus;
+use opus::*;
+
+#[test]
+fn frame_size_samples() {
+	assert_eq!(FrameSize::Ms20.samples(48000), Some(960));
+	assert_eq!(FrameSize::Ms2_5.samples(8000), Some(20));
+	assert_eq!(FrameSize::Ms20.samples(44100), Some(882));
+	assert_eq!(FrameSize::Ms2_5.samples(44100), None);
+	assert_eq!(FrameSize::Arg.samples(48000), None);
+}
+
+#[test]
+fn odd_chunks() {
+	let encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let mut buffered = BufferedEncoder::<i16>::new(encoder, FrameSize::Ms20).unwrap();
+
+	// 441-sample callbacks from a capture device
+	let chunk = [0i16; 441 * 2];
+	let mut packets = Vec::new();
+	for _ in 0..10 {
+		buffered.push(&chunk, &mut packets).unwrap();
+	}
+	// 4410 samples make four full 960-sample frames
+	assert_eq!(packets.len(), 4);
+	assert_eq!(buffered.buffered(), 4410 - 4 * 960);
+
+	buffered.finish(&mut packets).unwrap();
+	assert!(packets.len() > 4);
+	assert_eq!(buffered.buffered(), 0);
+	for packet in &packets {
+		assert_eq!(packet::get_nb_samples(packet, 48000).unwrap(), 960);
+	}
+
+	// nothing left to flush
+	let count = packets.len();
+	buffered.finish(&mut packets).unwrap();
+	assert_eq!(packets.len(), count);
+}
+
+#[test]
+fn float_input() {
+	let encoder = Encoder::new(16000, Channels::Mono, Application::Voip).unwrap();
+	let mut buffered = BufferedEncoder::<f32>::new(encoder, FrameSize::Ms10).unwrap();
+	let mut packets = Vec::new();
+	buffered.push(&[0.25; 480], &mut packets).unwrap();
+	assert_eq!(packets.len(), 3);
+	assert_eq!(buffered.buffered(), 0);
+}
+
+#[test]
+fn bad_input() {
+	let encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let err = BufferedEncoder::<i16>::new(encoder, FrameSize::Arg).unwrap_err();
+	let kind = ErrorKind::UnsupportedFrameSize {
+		frame_size: FrameSize::Arg,
+		sample_rate: 48000,
+	};
+	assert_eq!(err.kind(), kind);
+
+	let encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let mut buffered = BufferedEncoder::<i16>::new(encoder, FrameSize::Ms20).unwrap();
+	let mut packets = Vec::new();
+	let err = buffered.push(&[0; 3], &mut packets).unwrap_err();
+	assert_eq!(err.code(), ErrorCode::BadArg);
+	assert!(packets.is_empty());
+
+	// settings are changed through the wrapped encoder
+	buffered.encoder_mut().set_bitrate(Bitrate::Bits(64000)).unwrap();
+	assert_eq!(buffered.encoder_mut().get_bitrate().unwrap(), Bitrate::Bits(64000));
+}
+
+#[test]
+fn multistream() {
+	// 5.1 surround as four streams, two of them coupled
+	let encoder = MSEncoder::new(48000, 4, 2, &[0, 4, 1, 2, 3, 5], Application::Audio).unwrap();
+	let mut buffered = BufferedEncoder::<f32, MSEncoder>::new(encoder, FrameSize::Ms10).unwrap();
+	let mut packets = Vec::new();
+	buffered.push(&[0.0; 700 * 6], &mut packets).unwrap();
+	assert_eq!(packets.len(), 1);
+	assert_eq!(buffered.buffered(), 700 - 480);
+
+	buffered.finish(&mut packets).unwrap();
+	assert_eq!(buffered.buffered(), 0);
+	let mut decoder = MSDecoder::new(48000, 4, 2, &[0, 4, 1, 2, 3, 5]).unwrap();
+	let mut output = [0f32; 480 * 6];
+	for packet in &packets {
+		assert_eq!(decoder.decode_float(packet, &mut output, false).unwrap(), 480);
+	}
+}
@@ -0,0 +1,355 @@
+//! Test the command-line tools.
+#![cfg(feature = "cli")]
+
+extern crate opus;
+extern crate serde_json;
+use opus::ogg::OpusTags;
+use opus::opus_head::OpusHead;
+use opus::pcm::*;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::process::Command;
+
+fn temp(name: &str) -> PathBuf {
+	let dir = std::env::temp_dir().join(format!("opus-rs-cli-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	dir.join(name)
+}
+
+fn wav(path: &PathBuf, channels: u16, sample_rate: u32, frames: usize) {
+	let format = PcmFormat {
+		channels,
+		sample_rate,
+		sample_format: SampleFormat::I16,
+		channel_mask: None,
+	};
+	let mut writer = WavWriter::new(Cursor::new(Vec::new()), &format).unwrap();
+	let samples: Vec<f32> = (0..frames * channels as usize)
+		.map(|i| ((i / channels as usize) as f32 * 0.03).sin() * 0.3)
+		.collect();
+	writer.write(&samples).unwrap();
+	std::fs::write(path, writer.finish().unwrap().into_inner()).unwrap();
+}
+
+/// Split an Ogg stream into its packets and the last granule position.
+fn ogg_packets(mut data: &[u8]) -> (Vec<Vec<u8>>, u64) {
+	let mut packets = Vec::new();
+	let mut packet = Vec::new();
+	let mut granule = 0;
+	while !data.is_empty() {
+		assert_eq!(&data[..4], b"OggS");
+		let mut bytes = [0; 8];
+		bytes.copy_from_slice(&data[6..14]);
+		granule = u64::from_le_bytes(bytes);
+		let count = data[26] as usize;
+		let mut pos = 27 + count;
+		for &segment in &data[27..27 + count] {
+			packet.extend_from_slice(&data[pos..pos + segment as usize]);
+			pos += segment as usize;
+			if segment < 255 {
+				packets.push(std::mem::take(&mut packet));
+			}
+		}
+		data = &data[pos..];
+	}
+	(packets, granule)
+}
+
+fn enc(args: &[&str]) -> std::process::Output {
+	Command::new(env!("CARGO_BIN_EXE_opus-rs-enc")).args(args).output().unwrap()
+}
+
+fn dec(args: &[&str]) -> std::process::Output {
+	Command::new(env!("CARGO_BIN_EXE_opus-rs-dec")).args(args).output().unwrap()
+}
+
+fn info(args: &[&str]) -> std::process::Output {
+	Command::new(env!("CARGO_BIN_EXE_opus-rs-info")).args(args).output().unwrap()
+}
+
+fn read_wav(path: &PathBuf) -> (PcmFormat, Vec<f32>) {
+	let mut reader = PcmReader::new(Cursor::new(std::fs::read(path).unwrap())).unwrap();
+	(*reader.format(), reader.read_all().unwrap())
+}
+
+#[test]
+fn encode_wav() {
+	let input = temp("stereo.wav");
+	let output = temp("stereo.opus");
+	wav(&input, 2, 44100, 44100);
+	let result = enc(&[
+		"--bitrate",
+		"96",
+		"--framesize",
+		"10",
+		"--title",
+		"Tone",
+		"--quiet",
+		input.to_str().unwrap(),
+		output.to_str().unwrap(),
+	]);
+	assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+	assert!(result.stderr.is_empty());
+
+	let (packets, granule) = ogg_packets(&std::fs::read(&output).unwrap());
+	let head = OpusHead::parse(&packets[0]).unwrap();
+	assert_eq!((head.channels, head.mapping_family, head.input_sample_rate), (2, 0, 44100));
+	let tags = OpusTags::parse(&packets[1]).unwrap();
+	assert_eq!(tags.get("TITLE"), Some("Tone"));
+	assert!(tags.get("ENCODER").unwrap().starts_with("opus-rs-enc"));
+	// one second of audio, after the pre-skip which includes the resampler
+	assert_eq!(granule, head.pre_skip as u64 + 48000);
+	for packet in &packets[2..] {
+		assert_eq!(opus::packet::get_nb_samples(packet, 48000).unwrap(), 480);
+	}
+	assert!((packets.len() - 2) * 480 >= granule as usize);
+}
+
+#[test]
+fn encode_surround_raw() {
+	let input = temp("surround.raw");
+	let output = temp("surround.opus");
+	let samples: Vec<u8> =
+		(0..6 * 4800).flat_map(|i: i32| ((i % 300) as i16 * 20).to_be_bytes()).collect();
+	std::fs::write(&input, &samples).unwrap();
+	let result = enc(&[
+		"--raw",
+		"--raw-chan",
+		"6",
+		"--raw-rate",
+		"16000",
+		"--raw-endianness",
+		"1",
+		"--hard-cbr",
+		"--dtx",
+		input.to_str().unwrap(),
+		output.to_str().unwrap(),
+	]);
+	assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+	assert!(String::from_utf8_lossy(&result.stderr).contains("6-channel"));
+
+	let (packets, granule) = ogg_packets(&std::fs::read(&output).unwrap());
+	let head = OpusHead::parse(&packets[0]).unwrap();
+	assert_eq!((head.mapping_family, head.streams, head.coupled_streams), (1, 4, 2));
+	assert_eq!(head.input_sample_rate, 16000);
+	assert_eq!(granule, head.pre_skip as u64 + 14400);
+
+	// family 255 keeps every channel separate
+	let result = enc(&[
+		"--raw",
+		"--raw-chan",
+		"6",
+		"--mapping-family",
+		"255",
+		input.to_str().unwrap(),
+		output.to_str().unwrap(),
+	]);
+	assert!(result.status.success());
+	let (packets, _) = ogg_packets(&std::fs::read(&output).unwrap());
+	let head = OpusHead::parse(&packets[0]).unwrap();
+	assert_eq!((head.mapping_family, head.streams, head.coupled_streams), (255, 6, 0));
+}
+
+#[test]
+fn bad_arguments() {
+	let input = temp("bad.wav");
+	wav(&input, 1, 48000, 100);
+	let input = input.to_str().unwrap();
+	let output = temp("bad.opus");
+	let output = output.to_str().unwrap();
+	for args in &[
+		&["--framesize", "7", input, output][..],
+		&["--bogus", input, output],
+		&[input],
+		&["--bitrate"],
+	] {
+		assert_eq!(enc(args).status.code(), Some(2), "{:?}", args);
+	}
+	let result = enc(&["--mapping-family", "0", "--raw", "--raw-chan", "3", input, output]);
+	assert_eq!(result.status.code(), Some(1));
+	let result = enc(&["missing.wav", output]);
+	assert_eq!(result.status.code(), Some(1));
+	assert!(String::from_utf8_lossy(&result.stderr).contains("missing.wav"));
+	assert!(enc(&["--help"]).status.success());
+}
+
+#[test]
+fn decode_roundtrip() {
+	let input = temp("roundtrip.wav");
+	let encoded = temp("roundtrip.opus");
+	let output = temp("roundtrip-out.wav");
+	wav(&input, 2, 44100, 22050);
+	assert!(enc(&["--quiet", input.to_str().unwrap(), encoded.to_str().unwrap()]).status.success());
+
+	// the pre-skip and padding are trimmed, so the length is kept
+	let result = dec(&["--rate", "44100", encoded.to_str().unwrap(), output.to_str().unwrap()]);
+	assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+	assert!(String::from_utf8_lossy(&result.stderr).contains("0 lost"));
+	let (format, samples) = read_wav(&output);
+	assert_eq!((format.channels, format.sample_rate), (2, 44100));
+	assert_eq!(format.sample_format, SampleFormat::I16);
+	assert_eq!(samples.len(), 22050 * 2);
+	// the tone comes back in time with the input
+	let (_, original) = read_wav(&input);
+	let error: f32 = original.iter().zip(&samples).map(|(a, b)| (a - b) * (a - b)).sum();
+	let energy: f32 = original.iter().map(|a| a * a).sum();
+	assert!(error < energy * 0.1, "{} vs {}", error, energy);
+
+	// float output at a native rate, with gain and soft clipping
+	let result = dec(&[
+		"--float",
+		"--gain",
+		"12",
+		"--soft-clip",
+		"--quiet",
+		encoded.to_str().unwrap(),
+		output.to_str().unwrap(),
+	]);
+	assert!(result.status.success());
+	assert!(result.stderr.is_empty());
+	let (format, loud) = read_wav(&output);
+	assert_eq!((format.sample_rate, format.sample_format), (48000, SampleFormat::F32));
+	assert_eq!(loud.len(), 24000 * 2);
+	let peak = loud.iter().fold(0.0f32, |peak, s| peak.max(s.abs()));
+	assert!(peak > 0.6 && peak <= 1.0, "{}", peak);
+
+	// raw output is the same audio without the header
+	let raw = temp("roundtrip.raw");
+	assert!(dec(&["--raw", "--quiet", encoded.to_str().unwrap(), raw.to_str().unwrap()])
+		.status
+		.success());
+	assert_eq!(std::fs::read(&raw).unwrap().len(), 24000 * 2 * 2);
+
+	// a WAV file streamed to standard output holds the same audio
+	let result = dec(&["--rate", "44100", "--quiet", encoded.to_str().unwrap(), "-"]);
+	assert!(result.status.success());
+	let mut reader = PcmReader::new(Cursor::new(result.stdout)).unwrap();
+	assert_eq!(reader.format().sample_rate, 44100);
+	let streamed: Vec<f32> = reader.read_all().unwrap();
+	assert_eq!(streamed, samples);
+}
+
+#[test]
+fn decode_loss() {
+	let input = temp("loss.wav");
+	let encoded = temp("loss.opus");
+	let output = temp("loss-out.wav");
+	wav(&input, 1, 16000, 32000);
+	let result = enc(&[
+		"--fec",
+		"--expect-loss",
+		"20",
+		"--bitrate",
+		"32",
+		"--quiet",
+		input.to_str().unwrap(),
+		encoded.to_str().unwrap(),
+	]);
+	assert!(result.status.success());
+
+	let decode = |extra: &[&str]| {
+		let mut args = vec!["--packet-loss", "20", "--rate", "16000"];
+		args.extend_from_slice(extra);
+		args.push(encoded.to_str().unwrap());
+		args.push(output.to_str().unwrap());
+		let result = dec(&args);
+		assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+		(String::from_utf8_lossy(&result.stderr).into_owned(), read_wav(&output).1)
+	};
+	// concealment keeps the length
+	let (summary, plc) = decode(&[]);
+	assert!(summary.contains("0 recovered with FEC"), "{}", summary);
+	assert!(!summary.contains(" 0 lost"), "{}", summary);
+	assert_eq!(plc.len(), 32000);
+	// the same seed drops the same packets, which FEC recovers
+	let (summary, fec) = decode(&["--fec"]);
+	assert!(!summary.contains("0 recovered with FEC"), "{}", summary);
+	assert_eq!(fec.len(), 32000);
+	assert_ne!(plc, fec);
+	assert_eq!(decode(&["--fec"]).1, fec);
+	assert_ne!(decode(&["--fec", "--seed", "2"]).1, fec);
+}
+
+#[test]
+fn decode_opus_demo() {
+	let input = temp("demo.bit");
+	let output = temp("demo.wav");
+	let mut encoder =
+		opus::Encoder::new(48000, opus::Channels::Mono, opus::Application::Audio).unwrap();
+	let frame: Vec<f32> = (0..960).map(|i| (i as f32 * 0.05).sin() * 0.3).collect();
+	let mut writer = opus::opus_demo::DemoBitstreamWriter::new(Vec::new());
+	for i in 0..10 {
+		let packet = encoder.encode_vec_float(&frame, 4000).unwrap();
+		// a lost packet is concealed for the last packet's duration
+		if i == 5 {
+			writer.write_lost().unwrap();
+		} else {
+			writer.write_packet(&packet, encoder.get_final_range().unwrap()).unwrap();
+		}
+	}
+	std::fs::write(&input, writer.into_inner()).unwrap();
+
+	let result = dec(&["--channels", "1", input.to_str().unwrap(), output.to_str().unwrap()]);
+	assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+	assert!(String::from_utf8_lossy(&result.stderr).contains("1 concealed"));
+	let (format, samples) = read_wav(&output);
+	assert_eq!(format.channels, 1);
+	// nothing is trimmed without a pre-skip
+	assert_eq!(samples.len(), 9600);
+
+	// a corrupt packet after the lost one is concealed, even when it is used
+	// for FEC
+	let mut writer = opus::opus_demo::DemoBitstreamWriter::new(Vec::new());
+	let packet = encoder.encode_vec_float(&frame, 4000).unwrap();
+	writer.write_packet(&packet, encoder.get_final_range().unwrap()).unwrap();
+	writer.write_lost().unwrap();
+	// two CBR frames can't share three bytes
+	writer.write_packet(&[0xfb, 0x02, 0, 0, 0], 0).unwrap();
+	std::fs::write(&input, writer.into_inner()).unwrap();
+	let result =
+		dec(&["--fec", "--channels", "1", input.to_str().unwrap(), output.to_str().unwrap()]);
+	assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+	assert!(String::from_utf8_lossy(&result.stderr).contains("2 failed to decode"));
+	assert_eq!(read_wav(&output).1.len(), 960 * 4);
+
+	assert_eq!(dec(&["--channels", "3", "a", "b"]).status.code(), Some(2));
+	assert_eq!(dec(&["--rate"]).status.code(), Some(2));
+	assert_eq!(dec(&["missing.opus", "out.wav"]).status.code(), Some(1));
+}
+
+#[test]
+fn inspect() {
+	let input = temp("info.wav");
+	let encoded = temp("info.opus");
+	wav(&input, 2, 48000, 48000);
+	assert!(enc(&["--quiet", input.to_str().unwrap(), encoded.to_str().unwrap()]).status.success());
+	let encoded = encoded.to_str().unwrap();
+
+	let result = info(&["--packets", encoded]);
+	assert!(result.status.success(), "{}", String::from_utf8_lossy(&result.stderr));
+	let text = String::from_utf8_lossy(&result.stdout);
+	assert!(text.contains("Format: Ogg"), "{}", text);
+	assert!(text.contains("Channels: 2, mapping family 0"), "{}", text);
+	assert!(text.contains("Bitrate per second"), "{}", text);
+	assert!(text.contains("No RFC 6716 violations"), "{}", text);
+	assert!(text.lines().any(|line| line.trim_start().starts_with("0 ")), "{}", text);
+
+	let result = info(&["--json", encoded]);
+	assert!(result.status.success());
+	let json: serde_json::Value = serde_json::from_slice(&result.stdout).unwrap();
+	assert_eq!(json["format"], "Ogg");
+	assert_eq!(json["head"]["channels"], 2);
+	let packets = json["packets"].as_array().unwrap();
+	assert!(packets.len() >= 50);
+	assert_eq!(packets[0]["toc"]["frame_size"], "20ms");
+
+	// an invalid packet sets the exit status
+	let demo = temp("info.bit");
+	std::fs::write(&demo, [0, 0, 0, 2, 0, 0, 0, 0, 0xF9, 1]).unwrap();
+	let result = info(&[demo.to_str().unwrap()]);
+	assert_eq!(result.status.code(), Some(3));
+	assert!(String::from_utf8_lossy(&result.stdout).contains("packet 0: payload not divisible"));
+
+	assert_eq!(info(&[]).status.code(), Some(2));
+	assert_eq!(info(&["missing.opus"]).status.code(), Some(1));
+}
@@ -0,0 +1,225 @@
+//! Test applying and reading back complete encoder configurations.
+
+extern crate opus;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+use opus::resample::Resampler;
+use opus::*;
+
+#[test]
+fn default_matches_new() {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let current = encoder.current_config().unwrap();
+	// the effective bitrate is reported rather than Auto
+	assert_eq!(
+		EncoderConfig {
+			bitrate: current.bitrate,
+			..EncoderConfig::default()
+		},
+		current
+	);
+}
+
+#[test]
+fn roundtrip() {
+	let config = EncoderConfig {
+		application: Application::Voip,
+		bitrate: Bitrate::Bits(24000),
+		complexity: 5,
+		vbr: true,
+		vbr_constraint: false,
+		signal: Signal::Voice,
+		max_bandwidth: Bandwidth::Wideband,
+		force_channels: Some(Channels::Mono),
+		inband_fec: true,
+		packet_loss_perc: 10,
+		dtx: true,
+		lsb_depth: 16,
+		frame_duration: FrameSize::Ms20,
+		prediction_disabled: false,
+		phase_inversion_disabled: true,
+	};
+	let mut encoder = Encoder::from_config(48000, Channels::Stereo, &config).unwrap();
+	assert_eq!(encoder.current_config().unwrap(), config);
+
+	let mut other = Encoder::new(16000, Channels::Stereo, Application::Audio).unwrap();
+	other.apply_config(&config).unwrap();
+	assert_eq!(other.current_config().unwrap(), config);
+
+	let mapping = [0, 1];
+	let mut ms = MSEncoder::new(48000, 1, 1, &mapping, Application::Audio).unwrap();
+	ms.apply_config(&config).unwrap();
+	assert_eq!(ms.get_complexity().unwrap(), config.complexity);
+	assert_eq!(ms.get_expert_frame_duration().unwrap(), config.frame_duration);
+}
+
+#[test]
+fn validation() {
+	let bad = [
+		EncoderConfig { complexity: 11, ..Default::default() },
+		EncoderConfig { packet_loss_perc: -1, ..Default::default() },
+		EncoderConfig { lsb_depth: 4, ..Default::default() },
+		EncoderConfig {
+			bitrate: Bitrate::Bits(0),
+			..Default::default()
+		},
+		EncoderConfig {
+			max_bandwidth: Bandwidth::Auto,
+			..Default::default()
+		},
+	];
+	for config in bad.iter() {
+		let err = config.validate().unwrap_err();
+		assert_eq!(err.code(), ErrorCode::BadArg);
+		assert!(err.context().is_some());
+		assert!(Encoder::from_config(48000, Channels::Mono, config).is_err());
+	}
+	assert!(EncoderConfig::default().validate().is_ok());
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_partial() {
+	let config: EncoderConfig =
+		serde_json::from_str(r#"{ "complexity": 3, "dtx": true }"#).unwrap();
+	assert_eq!(
+		config,
+		EncoderConfig {
+			complexity: 3,
+			dtx: true,
+			..Default::default()
+		}
+	);
+
+	let json = serde_json::to_string(&config).unwrap();
+	assert_eq!(serde_json::from_str::<EncoderConfig>(&json).unwrap(), config);
+}
+
+#[test]
+fn presets() {
+	let presets = [
+		Preset::Voip { narrowband_fallback: false },
+		Preset::Voip { narrowband_fallback: true },
+		Preset::Music,
+		Preset::Podcast,
+		Preset::GameVoice,
+		Preset::Archival,
+		Preset::LowLatency,
+	];
+	for &preset in presets.iter() {
+		for &channels in [Channels::Mono, Channels::Stereo].iter() {
+			let config = preset.config(channels);
+			let mut encoder = Encoder::from_preset(48000, channels, preset).unwrap();
+			assert_eq!(encoder.current_config().unwrap(), config);
+
+			let samples = preset.frame_size().samples(48000).unwrap() * channels as usize;
+			let packet = encoder.encode_vec(&vec![0; samples], 4000).unwrap();
+			assert_eq!(
+				packet::get_nb_samples(&packet, 48000).unwrap(),
+				preset.frame_size().samples(48000).unwrap()
+			);
+		}
+	}
+
+	let voip = Preset::Voip { narrowband_fallback: false }.config(Channels::Mono);
+	assert!(voip.inband_fec && voip.dtx && voip.vbr);
+	assert_eq!(
+		Preset::Voip { narrowband_fallback: true }.config(Channels::Mono).max_bandwidth,
+		Bandwidth::Narrowband
+	);
+	assert_eq!(Preset::Music.config(Channels::Stereo).bitrate, Bitrate::Bits(96000));
+	assert_eq!(Preset::Podcast.config(Channels::Stereo).bitrate, Bitrate::Bits(80000));
+}
+
+/// A harmonic chord with a slow tremolo, detuned slightly between channels.
+fn chord(channels: usize, seconds: f64) -> Vec<f32> {
+	let len = (48000.0 * seconds) as usize;
+	let mut output = Vec::with_capacity(len * channels);
+	for i in 0..len {
+		let t = i as f64 / 48000.0;
+		let envelope = 0.6 + 0.4 * (2.0 * std::f64::consts::PI * 3.0 * t).sin();
+		for channel in 0..channels {
+			let mut value = 0.0;
+			for harmonic in 1..6 {
+				let freq = 220.0 * harmonic as f64 * (1.0 + 0.01 * channel as f64);
+				value += (2.0 * std::f64::consts::PI * freq * t).sin() / harmonic as f64;
+			}
+			output.push((0.2 * envelope * value) as f32);
+		}
+	}
+	output
+}
+
+/// A buzzing voice with noise, pausing for a third of every 750 ms.
+fn voice(seconds: f64) -> Vec<f32> {
+	let mut seed = 1u32;
+	(0..(48000.0 * seconds) as usize)
+		.map(|i| {
+			let t = i as f32 / 48000.0;
+			seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+			let noise = (seed >> 8) as f32 / (1 << 24) as f32 - 0.5;
+			let syllable = if (t * 4.0) as usize % 3 == 2 { 0.0 } else { 1.0 };
+			let buzz = (t * (140.0 + 30.0 * (t * 3.0).sin()) * std::f32::consts::TAU).sin();
+			0.3 * syllable * (0.7 * buzz + 0.3 * noise)
+		})
+		.collect()
+}
+
+/// Resample to a lower rate, to compare only the band a preset keeps.
+fn band_limit(input: &[f32], channels: usize, rate: u32) -> Vec<f32> {
+	if rate == 48000 {
+		return input.to_vec();
+	}
+	let mut resampler = Resampler::new(48000, rate, channels).unwrap();
+	let mut output = resampler.process(input).to_vec();
+	output.extend_from_slice(resampler.process(&vec![0.0f32; 4800 * channels]));
+	output.truncate(input.len() * rate as usize / 48000);
+	output
+}
+
+/// Check that the presets rank as the `Preset` docs describe.
+///
+/// The exact figures depend on the libopus version, so only the ordering and
+/// a loose quality floor are checked.
+#[test]
+fn preset_ranking() {
+	use opus::quality::{log_spectral_distance, roundtrip};
+
+	// the bitrate produced and the log-spectral distance below `rate` / 2
+	let measure = |preset: Preset, channels: Channels, input: &[f32], rate: u32| {
+		let mut encoder = Encoder::from_preset(48000, channels, preset).unwrap();
+		let mut decoder = Decoder::new(48000, channels).unwrap();
+		let result = roundtrip(&mut encoder, &mut decoder, input, preset.frame_size()).unwrap();
+		let reference = band_limit(input, channels as usize, rate);
+		let output = band_limit(&result.output, channels as usize, rate);
+		let distance = log_spectral_distance(&reference, &output, channels as usize, rate).unwrap();
+		(result.bitrate, distance)
+	};
+
+	let speech = voice(1.5);
+	let voip = measure(Preset::Voip { narrowband_fallback: false }, Channels::Mono, &speech, 16000);
+	let narrow = measure(Preset::Voip { narrowband_fallback: true }, Channels::Mono, &speech, 8000);
+	let game = measure(Preset::GameVoice, Channels::Mono, &speech, 16000);
+	assert!(narrow.0 < voip.0, "{:?} {:?}", narrow, voip);
+	for &(bitrate, distance) in [voip, narrow, game].iter() {
+		// DTX keeps speech below the 24 kbps target
+		assert!(bitrate < 24000.0, "{}", bitrate);
+		assert!(distance < 10.0, "{}", distance);
+	}
+
+	for &channels in [Channels::Mono, Channels::Stereo].iter() {
+		let input = chord(channels as usize, 1.0);
+		let ranked = [Preset::Podcast, Preset::Music, Preset::Archival];
+		let results: Vec<_> =
+			ranked.iter().map(|&preset| measure(preset, channels, &input, 48000)).collect();
+		for pair in results.windows(2) {
+			assert!(pair[0].0 < pair[1].0, "{:?}: {:?}", channels, results);
+			assert!(pair[0].1 > pair[1].1, "{:?}: {:?}", channels, results);
+		}
+		assert!(results.iter().all(|&(_, distance)| distance < 3.0), "{:?}", results);
+
+		let (bitrate, distance) = measure(Preset::LowLatency, channels, &input, 48000);
+		assert!((bitrate / (channels as i32 * 64000) as f64 - 1.0).abs() < 0.02, "{}", bitrate);
+		assert!(distance < 5.0, "{}", distance);
+	}
+}
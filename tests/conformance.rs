@@ -0,0 +1,129 @@
+//! Test the conformance runner on generated vectors, and on the official
+//! test vectors in the directory named by `OPUS_TEST_VECTORS` when ignored
+//! tests are run.
+
+extern crate opus;
+use opus::conformance::*;
+use opus::*;
+use std::path::Path;
+
+fn sweep(len: usize) -> Vec<i16> {
+	(0..len)
+		.map(|i| {
+			let t = (i / 2) as f64 / 48000.0;
+			let s = (2.0 * std::f64::consts::PI * (200.0 + 2000.0 * t) * t).sin();
+			(s * if i % 2 == 0 { 12000.0 } else { 6000.0 }) as i16
+		})
+		.collect()
+}
+
+/// Encode audio into the test vector bitstream format.
+fn bitstream(input: &[i16]) -> Vec<u8> {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let mut bits = Vec::new();
+	for frame in input.chunks(2 * 960) {
+		let packet = encoder.encode_vec(frame, 4000).unwrap();
+		bits.extend_from_slice(&(packet.len() as u32).to_be_bytes());
+		bits.extend_from_slice(&encoder.get_final_range().unwrap().to_be_bytes());
+		bits.extend_from_slice(&packet);
+	}
+	bits
+}
+
+#[test]
+fn generated_vector() {
+	let bits = bitstream(&sweep(2 * 960 * 25));
+	let reference = decode(&bits[..], 48000, Channels::Stereo).unwrap();
+	assert_eq!(reference.len(), 2 * 960 * 25);
+
+	let same = compare(&reference, &reference, Channels::Stereo, 48000).unwrap();
+	assert!(same.passes());
+	assert!(same.quality > 99.9, "{:?}", same);
+
+	let mono = decode(&bits[..], 16000, Channels::Mono).unwrap();
+	let comparison = compare(&reference, &mono, Channels::Mono, 16000).unwrap();
+	assert!(comparison.passes(), "{:?}", comparison);
+
+	// heavily distorted output fails
+	let distorted: Vec<i16> = reference
+		.iter()
+		.enumerate()
+		.map(|(i, &s)| s / 8 + (i as i16).wrapping_mul(7919) / 4)
+		.collect();
+	assert!(!compare(&reference, &distorted, Channels::Stereo, 48000).unwrap().passes());
+
+	assert!(compare(&reference, &mono, Channels::Mono, 48000).is_err());
+	assert!(compare(&reference, &mono, Channels::Mono, 44100).is_err());
+}
+
+#[test]
+fn range_mismatch() {
+	let mut bits = bitstream(&sweep(2 * 960 * 3));
+	// corrupt the first packet's final range
+	bits[7] ^= 1;
+	let err = decode(&bits[..], 48000, Channels::Stereo).unwrap_err();
+	match err.kind() {
+		ErrorKind::RangeMismatch { packet, .. } => assert_eq!(packet, 0),
+		kind => panic!("unexpected error {:?}", kind),
+	}
+
+	// truncated streams are I/O errors
+	let bits = bitstream(&sweep(2 * 960 * 3));
+	let err = decode(&bits[..bits.len() - 1], 48000, Channels::Stereo).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::Io);
+}
+
+#[test]
+fn run_files() {
+	let dir = std::env::temp_dir().join(format!("opus-conformance-{}", std::process::id()));
+	std::fs::create_dir_all(&dir).unwrap();
+	let bits = bitstream(&sweep(2 * 960 * 10));
+	let reference = decode(&bits[..], 48000, Channels::Stereo).unwrap();
+	let mut dec = Vec::new();
+	for sample in &reference {
+		dec.extend_from_slice(&sample.to_le_bytes());
+	}
+	// the first reference is wrong, but every configuration matches the
+	// alternate
+	let wrong: Vec<u8> = dec.iter().map(|&byte| byte.wrapping_mul(31)).collect();
+	std::fs::write(dir.join("vector.bit"), &bits).unwrap();
+	std::fs::write(dir.join("vector.dec"), &wrong).unwrap();
+	std::fs::write(dir.join("vectorm.dec"), &dec).unwrap();
+
+	let alternate = dir.join("vectorm.dec");
+	let results =
+		run_vector(&dir.join("vector.bit"), &dir.join("vector.dec"), Some(&alternate)).unwrap();
+	std::fs::remove_dir_all(&dir).unwrap();
+	assert_eq!(results.len(), 15);
+	assert_eq!(results.iter().filter(|result| result.multistream).count(), 5);
+	for result in &results {
+		assert!(result.comparison.passes(), "{:?}", result);
+	}
+}
+
+#[test]
+fn multistream_matches_decoder() {
+	let bits = bitstream(&sweep(2 * 960 * 10));
+	for &channels in [Channels::Stereo, Channels::Mono].iter() {
+		assert_eq!(
+			decode_multistream(&bits[..], 24000, channels).unwrap(),
+			decode(&bits[..], 24000, channels).unwrap()
+		);
+	}
+}
+
+#[test]
+#[ignore = "needs the official test vectors; see scripts/fetch-test-vectors.sh"]
+fn official_vectors() {
+	let dir = std::env::var_os("OPUS_TEST_VECTORS").expect("OPUS_TEST_VECTORS is not set");
+	let dir = Path::new(&dir);
+	for n in 1..=12 {
+		let bit = dir.join(format!("testvector{:02}.bit", n));
+		let dec = dir.join(format!("testvector{:02}.dec", n));
+		let alternate = dir.join(format!("testvector{:02}m.dec", n));
+		let alternate = if alternate.exists() { Some(alternate.as_path()) } else { None };
+		for result in run_vector(&bit, &dec, alternate).unwrap() {
+			assert!(result.comparison.passes(), "testvector{:02}: {:?}", n, result);
+		}
+	}
+}
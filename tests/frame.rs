@@ -0,0 +1,162 @@
+//! Test that typed frames are checked against the codec's configuration.
+
+extern crate opus;
+use opus::*;
+
+#[test]
+fn frame_lengths() {
+	assert!(Frame::new(&[0i16; 5], 2, Layout::Interleaved).is_err());
+	assert!(Frame::new(&[0i16; 5], 0, Layout::Interleaved).is_err());
+	assert!(FrameMut::new(&mut [0.0f32; 3], 2, Layout::Planar).is_err());
+
+	let frame = Frame::interleaved(&[0i16; 1920], Channels::Stereo).unwrap();
+	assert_eq!(frame.samples(), 960);
+	assert_eq!(frame.channels(), 2);
+	assert_eq!(frame.layout(), Layout::Interleaved);
+}
+
+#[test]
+fn encode_checks() {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let mut output = [0; 256];
+
+	// wrong channel count
+	let mono = [0i16; 960];
+	let frame = Frame::interleaved(&mono, Channels::Mono).unwrap();
+	assert_eq!(encoder.encode_frame(&frame, &mut output).unwrap_err().code(), ErrorCode::BadArg);
+
+	// not a valid Opus frame duration
+	let short = [0i16; 2 * 900];
+	let frame = Frame::interleaved(&short, Channels::Stereo).unwrap();
+	assert_eq!(encoder.encode_frame(&frame, &mut output).unwrap_err().code(), ErrorCode::BadArg);
+
+	let stereo = [0i16; 2 * 960];
+	let frame = Frame::interleaved(&stereo, Channels::Stereo).unwrap();
+	let len = encoder.encode_frame(&frame, &mut output).unwrap();
+	assert_eq!(&output[..len], &[252, 255, 254]);
+}
+
+#[test]
+fn slice_lengths() {
+	// odd-length stereo buffers are rejected rather than truncated
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let mut output = [0; 256];
+	let err = encoder.encode(&[0; 2 * 960 + 1], &mut output).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::UnevenLength { len: 1921, channels: 2 });
+	assert!(encoder.encode_float(&[0.0; 2 * 960 - 1], &mut output).is_err());
+	let packet = encoder.encode_vec(&[0; 2 * 960], 256).unwrap();
+
+	let mut decoder = Decoder::new(48000, Channels::Stereo).unwrap();
+	let err = decoder.decode(&packet, &mut [0; 2 * 960 + 1], false).unwrap_err();
+	assert_eq!(err.code(), ErrorCode::BadArg);
+	assert!(decoder.decode_float(&packet, &mut [0.0; 2 * 960 + 1], false).is_err());
+	assert_eq!(decoder.decode(&packet, &mut [0; 2 * 960], false).unwrap(), 960);
+
+	let mut encoder = MSEncoder::new(48000, 1, 1, &[0, 1], Application::Audio).unwrap();
+	assert!(encoder.encode(&[0; 2 * 960 + 1], &mut output).is_err());
+	assert!(encoder.encode_float(&[0.0; 2 * 960 + 1], &mut output).is_err());
+	let mut decoder = MSDecoder::new(48000, 1, 1, &[0, 1]).unwrap();
+	assert!(decoder.decode(&packet, &mut [0; 2 * 960 + 1], false).is_err());
+	assert!(decoder.decode_float(&packet, &mut [0.0; 2 * 960 + 1], false).is_err());
+}
+
+#[test]
+fn planar_roundtrip() {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let mut decoder = Decoder::new(48000, Channels::Stereo).unwrap();
+
+	// left channel silent, right channel a loud square wave
+	let mut input = vec![0.0f32; 2 * 960];
+	for (i, sample) in input[960..].iter_mut().enumerate() {
+		*sample = if i / 24 % 2 == 0 { 0.5 } else { -0.5 };
+	}
+
+	let mut output = vec![0.0f32; 2 * 960];
+	for _ in 0..5 {
+		let frame = Frame::planar(&input, Channels::Stereo).unwrap();
+		let mut packet = [0; 4000];
+		let len = encoder.encode_frame(&frame, &mut packet).unwrap();
+		let packet = &packet[..len];
+		let mut frame = FrameMut::planar(&mut output, Channels::Stereo).unwrap();
+		assert_eq!(decoder.decode_frame(packet, &mut frame, false).unwrap(), 960);
+	}
+
+	let energy = |samples: &[f32]| samples.iter().map(|s| s * s).sum::<f32>();
+	assert!(energy(&output[960..]) > 100.0 * energy(&output[..960]));
+}
+
+#[test]
+fn decode_checks() {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let packet = encoder.encode_vec(&[0i16; 960], 4000).unwrap();
+
+	let mut stereo = [0i16; 2 * 960];
+	let mut frame = FrameMut::interleaved(&mut stereo, Channels::Stereo).unwrap();
+	let err = decoder.decode_frame(&packet, &mut frame, false).unwrap_err();
+	assert_eq!(err.code(), ErrorCode::BadArg);
+
+	let mut small = [0i16; 480];
+	let mut frame = FrameMut::interleaved(&mut small, Channels::Mono).unwrap();
+	let err = decoder.decode_frame(&packet, &mut frame, false).unwrap_err();
+	assert_eq!(err.code(), ErrorCode::BufferTooSmall);
+}
+
+#[test]
+fn interleave_helpers() {
+	for channels in 1..5 {
+		let planes: Vec<Vec<i16>> =
+			(0..channels).map(|c| (0..7).map(|i| (c * 100 + i) as i16).collect()).collect();
+		let refs: Vec<&[i16]> = planes.iter().map(|p| &p[..]).collect();
+		let mut interleaved = vec![0i16; 7 * channels];
+		interleave(&refs, &mut interleaved);
+		for (i, frame) in interleaved.chunks(channels).enumerate() {
+			for (c, &sample) in frame.iter().enumerate() {
+				assert_eq!(sample, (c * 100 + i) as i16);
+			}
+		}
+
+		let mut output = vec![vec![0i16; 9]; channels];
+		{
+			let mut refs: Vec<&mut [i16]> = output.iter_mut().map(|p| &mut p[..]).collect();
+			deinterleave(&interleaved, &mut refs);
+		}
+		for (plane, original) in output.iter().zip(planes.iter()) {
+			assert_eq!(&plane[..7], &original[..]);
+			assert_eq!(&plane[7..], &[0, 0]);
+		}
+	}
+}
+
+#[test]
+fn planar_multistream() {
+	let mapping = [0, 1, 2];
+	let mut encoder = MSEncoder::new(48000, 2, 1, &mapping, Application::Audio).unwrap();
+	let mut decoder = MSDecoder::new(48000, 2, 1, &mapping).unwrap();
+
+	let silent = [0.0f32; 960];
+	let loud: Vec<f32> = (0..960).map(|i| if i / 24 % 2 == 0 { 0.5 } else { -0.5 }).collect();
+	let input: [&[f32]; 3] = [&silent, &silent, &loud];
+
+	let mut output = vec![vec![0.0f32; 960]; 3];
+	for _ in 0..5 {
+		let mut packet = [0; 4000];
+		let len = encoder.encode_planar(&input, &mut packet).unwrap();
+		let mut planes: Vec<&mut [f32]> = output.iter_mut().map(|p| &mut p[..]).collect();
+		assert_eq!(decoder.decode_planar(&packet[..len], &mut planes, false).unwrap(), 960);
+	}
+	let energy = |samples: &[f32]| samples.iter().map(|s| s * s).sum::<f32>();
+	assert!(energy(&output[2]) > 100.0 * (energy(&output[0]) + energy(&output[1])));
+
+	// wrong number of channels, or channels of different lengths
+	let mut packet = [0; 4000];
+	assert!(encoder.encode_planar(&input[..2], &mut packet).is_err());
+	let uneven: [&[f32]; 3] = [&silent, &silent, &loud[..480]];
+	assert!(encoder.encode_planar(&uneven, &mut packet).is_err());
+
+	let len = encoder.encode_planar(&input, &mut packet).unwrap();
+	let mut buffers = [vec![0.0f32; 960], vec![0.0; 960], vec![0.0; 480]];
+	let mut uneven: Vec<&mut [f32]> = buffers.iter_mut().map(|p| &mut p[..]).collect();
+	let err = decoder.decode_planar(&packet[..len], &mut uneven, false).unwrap_err();
+	assert_eq!(err.code(), ErrorCode::BadArg);
+}
@@ -0,0 +1,193 @@
+//! Test stream inspection.
+
+extern crate opus;
+use opus::inspect::*;
+use opus::ogg::{OggOpusWriter, OpusTags};
+use opus::opus_demo::DemoBitstreamWriter;
+use opus::opus_head::OpusHead;
+use opus::packet::Mode;
+use opus::webm::WebmWriter;
+use opus::*;
+
+/// A CELT fullband 20 ms TOC byte with the given frame count code.
+fn toc(code: u8) -> u8 {
+	31 << 3 | code
+}
+
+fn check(packet: &[u8]) -> PacketInfo {
+	Inspector::new(1).add(packet).clone()
+}
+
+#[test]
+fn framing() {
+	let info = check(&[toc(0), 1, 2, 3]);
+	assert_eq!((info.frames, info.duration, info.padding, info.violation), (1, 960, 0, None));
+	let toc_info = info.toc.unwrap();
+	assert_eq!((toc_info.mode, toc_info.bandwidth), (Mode::Celt, Bandwidth::Fullband));
+	assert_eq!(check(&[toc(1), 1, 2]).frames, 2);
+	assert_eq!(check(&[toc(2), 1, 9, 8, 7]).frames, 2);
+
+	// two-byte frame lengths
+	let mut packet = vec![toc(2), 252, 12];
+	packet.extend_from_slice(&[0; 310]);
+	let info = check(&packet);
+	assert_eq!((info.frames, info.violation), (2, None));
+
+	// code 3 with constant and variable frame lengths, and padding
+	let info = check(&[toc(3), 0x42, 2, 1, 2, 3, 4, 0, 0]);
+	assert_eq!((info.frames, info.duration, info.padding), (2, 1920, 2));
+	let info = check(&[toc(3), 0x83, 1, 2, 9, 8, 8, 7, 7, 7]);
+	assert_eq!((info.frames, info.violation), (3, None));
+	let mut packet = vec![toc(3), 0x41, 255, 1, 5];
+	packet.extend_from_slice(&[0; 255]);
+	let info = check(&packet);
+	assert_eq!((info.frames, info.padding, info.violation), (1, 255, None));
+
+	// frames of at most one byte are DTX
+	let info = check(&[toc(0)]);
+	assert!(info.dtx);
+	let info = check(&[toc(3), 0x83, 0, 1, 5, 6, 7]);
+	assert_eq!((info.dtx_frames, info.dtx), (2, false));
+}
+
+#[test]
+fn violations() {
+	let cases: &[(&[u8], Violation)] = &[
+		(&[], Violation::Empty),
+		(&[toc(1), 1, 2, 3], Violation::UnequalFrames),
+		(&[toc(2), 5, 1, 2], Violation::Truncated),
+		(&[toc(2), 253], Violation::Truncated),
+		(&[toc(3)], Violation::Truncated),
+		(&[toc(3), 0x00], Violation::NoFrames),
+		(&[toc(3), 0x07], Violation::TooLong),
+		(&[toc(3), 0x02, 1, 2, 3], Violation::UnequalFrames),
+		(&[toc(3), 0xC2, 10, 1, 5], Violation::Truncated),
+		(&[toc(3), 0x42, 255], Violation::Truncated),
+	];
+	for &(packet, violation) in cases {
+		assert_eq!(check(packet).violation, Some(violation), "{:?}", packet);
+	}
+	let mut long = vec![toc(0)];
+	long.extend_from_slice(&[0; 1276]);
+	assert_eq!(check(&long).violation, Some(Violation::FrameTooLong));
+	// 120 ms is the limit
+	assert_eq!(check(&[toc(3), 0x06]).violation, None);
+}
+
+#[test]
+fn multistream() {
+	let mut inspector = Inspector::new(2);
+	// a self-delimited stream followed by a normal one
+	let info = inspector.add(&[toc(0), 3, 1, 2, 3, toc(1), 4, 5]).clone();
+	assert_eq!((info.frames, info.duration, info.violation), (1, 960, None));
+	let info = inspector.add(&[toc(3), 0xC1, 1, 0, 1, 2, toc(0)]).clone();
+	assert_eq!((info.padding, info.dtx_frames, info.dtx), (1, 1, false));
+	let info = inspector.add(&[toc(0), 0, toc(0)]).clone();
+	assert!(info.dtx);
+	assert_eq!(inspector.add(&[toc(0), 3, 1, 2, 3]).violation, Some(Violation::Empty));
+	assert_eq!(inspector.add(&[toc(0), 9, 1]).violation, Some(Violation::Truncated));
+	assert_eq!(inspector.packets().len(), 5);
+	assert_eq!(inspector.packets()[1].timestamp, 960);
+}
+
+#[test]
+fn report() {
+	let mut inspector = Inspector::new(1);
+	let mut packet = vec![toc(0)];
+	packet.extend_from_slice(&[7; 99]);
+	for _ in 0..60 {
+		inspector.add(&packet);
+	}
+	inspector.add_lost();
+	inspector.add(&[toc(1), 1]);
+	let report = inspector.finish(Format::OpusDemo, None);
+	assert_eq!((report.packets.len(), report.lost, report.bytes), (62, 1, 6002));
+	// the invalid packet has no duration
+	assert_eq!(report.duration, 61 * 960);
+	assert_eq!(report.packets[61].timestamp, 61 * 960);
+	assert_eq!(report.violations, [(61, Violation::UnequalFrames)]);
+	assert!(!report.is_valid());
+	assert_eq!(report.histograms.modes, [(Mode::Celt, 61)]);
+	assert_eq!(report.histograms.frame_counts, [(0, 1), (1, 60)]);
+	assert_eq!(report.histograms.sizes, [(0, 1), (96, 60)]);
+	// the last second is partial
+	assert_eq!(report.bitrate.len(), 2);
+	assert_eq!(report.bitrate[0], 40000.0);
+	assert!((report.bitrate[1] - (10.0 * 800.0 + 16.0) / 0.22).abs() < 1e-6);
+
+	let text = report.to_string();
+	assert!(text.contains("Packets: 62 (1 lost, 0 DTX)"), "{}", text);
+	assert!(text.contains("packet 61: payload not divisible"), "{}", text);
+	assert!(report.packets[0].to_string().contains("celt"));
+	assert!(report.packets[60].to_string().ends_with("lost"));
+}
+
+fn encode(frames: usize, dtx: bool) -> (OpusHead, Vec<Vec<u8>>) {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Voip).unwrap();
+	encoder.set_dtx(dtx).unwrap();
+	let head = OpusHead::for_encoder(&mut encoder).unwrap();
+	let tone: Vec<f32> = (0..1920).map(|i| ((i / 2) as f32 * 0.05).sin() * 0.3).collect();
+	let silence = [0.0; 1920];
+	let packets = (0..frames)
+		.map(|i| {
+			let input = if i < frames / 2 { &tone[..] } else { &silence[..] };
+			encoder.encode_vec_float(input, 4000).unwrap()
+		})
+		.collect();
+	(head, packets)
+}
+
+#[test]
+fn containers() {
+	let (head, packets) = encode(100, true);
+
+	let mut ogg = OggOpusWriter::new(Vec::new(), &head, &OpusTags::new(), 1).unwrap();
+	for packet in &packets {
+		ogg.write_packet(packet).unwrap();
+	}
+	let report = inspect(&ogg.finish().unwrap()[..]).unwrap();
+	assert_eq!(report.format, Format::Ogg);
+	assert_eq!(report.head.as_ref(), Some(&head));
+	assert_eq!(report.packets.len(), 100);
+	assert_eq!(report.duration, 96000);
+	assert!(report.is_valid());
+	assert!(report.dtx_packets > 0);
+	assert_eq!(report.bitrate.len(), 2);
+	let stereo = report.histograms.channels.iter().map(|&(_, count)| count).sum::<u64>();
+	assert_eq!(stereo, 100);
+
+	let mut webm = WebmWriter::new(Vec::new(), &head).unwrap();
+	for packet in &packets {
+		webm.write_packet(packet).unwrap();
+	}
+	let webm_report = inspect(&webm.finish().unwrap()[..]).unwrap();
+	assert_eq!(webm_report.format, Format::Webm);
+	assert_eq!(webm_report.packets, report.packets);
+
+	let mut demo = DemoBitstreamWriter::new(Vec::new());
+	for packet in &packets[..10] {
+		demo.write_packet(packet, 0).unwrap();
+	}
+	demo.write_lost().unwrap();
+	let report = inspect(&demo.into_inner()[..]).unwrap();
+	assert_eq!((report.format, report.head, report.lost), (Format::OpusDemo, None, 1));
+	assert_eq!(report.packets.len(), 11);
+
+	assert!(inspect(&b"OggS"[..]).is_err());
+	assert_eq!(inspect(&b""[..]).unwrap().packets.len(), 0);
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn json() {
+	extern crate serde_json;
+	let mut inspector = Inspector::new(1);
+	inspector.add(&[toc(0), 1, 2]);
+	inspector.add(&[]);
+	let report = inspector.finish(Format::OpusDemo, None);
+	let json = serde_json::to_value(&report).unwrap();
+	assert_eq!(json["format"], "OpusDemo");
+	assert_eq!(json["packets"][0]["toc"]["mode"], "celt");
+	assert_eq!(json["histograms"]["bandwidths"][0][0], "fullband");
+	assert_eq!(json["violations"][0][1], "Empty");
+}
@@ -0,0 +1,174 @@
+//! Test conference mixing.
+
+extern crate opus;
+use opus::mixer::*;
+use opus::*;
+
+/// Encode `frames` frames of a tone at the given frequency.
+fn speak(freq: f32, frames: usize) -> Vec<Vec<u8>> {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	(0..frames)
+		.map(|frame| {
+			let pcm: Vec<f32> = (0..960)
+				.map(|i| {
+					((frame * 960 + i) as f32 * freq * std::f32::consts::TAU / 48000.0).sin() * 0.3
+				})
+				.collect();
+			encoder.encode_vec_float(&pcm, 4000).unwrap()
+		})
+		.collect()
+}
+
+/// Decode each participant's packets and measure the energy of the second
+/// half, once the codecs have settled.
+fn energy(packets: &[Vec<u8>]) -> f32 {
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let mut pcm = vec![0.0; 960];
+	let mut energy = 0.0;
+	for (i, packet) in packets.iter().enumerate() {
+		decoder.decode_float(packet, &mut pcm, false).unwrap();
+		if i >= packets.len() / 2 {
+			energy += pcm.iter().map(|s| s * s).sum::<f32>();
+		}
+	}
+	energy
+}
+
+fn mixer() -> (Mixer, Vec<ParticipantId>) {
+	let mut mixer = Mixer::new(48000, Channels::Mono, FrameSize::Ms20).unwrap();
+	let config = EncoderConfig {
+		application: Application::Voip,
+		..EncoderConfig::default()
+	};
+	let ids = (0..3).map(|_| mixer.add_participant(&config).unwrap()).collect();
+	(mixer, ids)
+}
+
+#[test]
+fn mix_minus() {
+	let (mut mixer, ids) = mixer();
+	assert_eq!((mixer.frame_len(), mixer.participants()), (960, ids.clone()));
+	let alice = speak(440.0, 40);
+	let mut outputs = vec![Vec::new(); 3];
+	for packet in &alice {
+		// only the first participant speaks
+		assert_eq!(mixer.push(ids[0], packet).unwrap(), Input::Decoded);
+		assert_eq!(mixer.input(ids[1]).unwrap(), Input::Silent);
+		assert_eq!(mixer.mix().unwrap(), 1);
+		assert_eq!(mixer.input(ids[0]).unwrap(), Input::Silent);
+		for (output, &id) in outputs.iter_mut().zip(&ids) {
+			output.push(mixer.packet(id).unwrap().to_vec());
+		}
+	}
+	// the speaker doesn't hear themselves, while everyone else does
+	let heard: Vec<f32> = outputs.iter().map(|packets| energy(packets)).collect();
+	assert!(heard[1] > 100.0, "{:?}", heard);
+	assert!((heard[1] - heard[2]).abs() < heard[1] * 0.1, "{:?}", heard);
+	assert!(heard[0] < heard[1] * 0.001, "{:?}", heard);
+	assert!(mixer.total().iter().any(|&s| s != 0.0));
+}
+
+#[test]
+fn gain_and_dtx() {
+	let (mut mixer, ids) = mixer();
+	let alice = speak(440.0, 20);
+	let bob = speak(700.0, 20);
+	mixer.set_gain(ids[1], 0.0).unwrap();
+	assert_eq!(mixer.gain(ids[1]).unwrap(), 0.0);
+	let mut outputs = vec![Vec::new(); 3];
+	for (a, b) in alice.iter().zip(&bob) {
+		mixer.push(ids[0], a).unwrap();
+		mixer.push(ids[1], b).unwrap();
+		// a muted source isn't mixed
+		assert_eq!(mixer.mix().unwrap(), 1);
+		for (output, &id) in outputs.iter_mut().zip(&ids) {
+			output.push(mixer.packet(id).unwrap().to_vec());
+		}
+	}
+	let heard: Vec<f32> = outputs.iter().map(|packets| energy(packets)).collect();
+	assert!(heard[0] < heard[2] * 0.001, "{:?}", heard);
+	assert!(heard[1] > 100.0, "{:?}", heard);
+
+	// DTX packets are skipped without decoding, and lost ones concealed
+	assert!(packet::is_dtx(&[alice[0][0]]) && packet::is_dtx(&alice[0][..2]));
+	assert!(!packet::is_dtx(&[]) && !packet::is_dtx(&alice[0]));
+	mixer.decoder(ids[2]).unwrap().enable_stats();
+	assert_eq!(mixer.push(ids[2], &[alice[0][0]]).unwrap(), Input::Dtx);
+	assert_eq!(mixer.push(ids[2], &[]).unwrap(), Input::Lost);
+	assert_eq!(mixer.mix().unwrap(), 1);
+	let stats = mixer.decoder(ids[2]).unwrap().stats().unwrap();
+	assert_eq!((stats.packets, stats.plc_frames), (0, 1));
+}
+
+#[test]
+fn soft_clip() {
+	let (mut mixer, ids) = mixer();
+	mixer.set_gain(ids[0], 8.0).unwrap();
+	mixer.set_gain(ids[1], 8.0).unwrap();
+	let alice = speak(440.0, 10);
+	let bob = speak(440.0, 10);
+	let mut peak = 0.0f32;
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let mut pcm = vec![0.0; 960];
+	for (a, b) in alice.iter().zip(&bob) {
+		mixer.push(ids[0], a).unwrap();
+		mixer.push(ids[1], b).unwrap();
+		mixer.mix().unwrap();
+		decoder.decode_float(mixer.packet(ids[2]).unwrap(), &mut pcm, false).unwrap();
+		peak = peak.max(pcm.iter().fold(0.0f32, |peak, s| peak.max(s.abs())));
+	}
+	// the sum reaches about 4.8, but the mix stays near full scale
+	assert!(mixer.total().iter().any(|s| s.abs() > 2.0));
+	assert!(peak > 0.5 && peak < 1.2, "{}", peak);
+}
+
+#[test]
+fn participants() {
+	let (mut mixer, ids) = mixer();
+	mixer.remove_participant(ids[1]).unwrap();
+	assert_eq!(mixer.participants(), [ids[0], ids[2]]);
+	for err in &[
+		mixer.push(ids[1], &[0; 10]).unwrap_err(),
+		mixer.packet(ids[1]).unwrap_err(),
+		mixer.remove_participant(ids[1]).unwrap_err(),
+	] {
+		assert_eq!(err.kind(), ErrorKind::BadArg);
+	}
+	// identifiers are not reused
+	let id = mixer.add_participant(&EncoderConfig::default()).unwrap();
+	assert!(!ids.contains(&id));
+	mixer.encoder(id).unwrap().set_bitrate(Bitrate::Bits(24000)).unwrap();
+
+	// packets must last one frame
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	let long = encoder.encode_vec_float(&[0.1; 1920], 4000).unwrap();
+	assert_eq!(mixer.push(id, &long).unwrap_err().kind(), ErrorKind::BadArg);
+	assert!(Mixer::new(8000, Channels::Mono, FrameSize::Arg).is_err());
+}
+
+#[test]
+fn encode_failure() {
+	let (mut mixer, ids) = mixer();
+	let alice = speak(440.0, 2);
+	let bob = speak(660.0, 2);
+	mixer.push(ids[0], &alice[0]).unwrap();
+	mixer.push(ids[2], &bob[0]).unwrap();
+	mixer.mix().unwrap();
+	let first = mixer.packet(ids[2]).unwrap().to_vec();
+
+	// a frame duration longer than the mixer's makes one encode fail
+	mixer.encoder(ids[1]).unwrap().set_expert_frame_duration(FrameSize::Ms40).unwrap();
+	mixer.push(ids[0], &alice[1]).unwrap();
+	mixer.push(ids[2], &bob[1]).unwrap();
+	assert_eq!(mixer.mix().unwrap_err().code(), ErrorCode::BadArg);
+	assert!(mixer.packet(ids[1]).unwrap().is_empty());
+	// the participants after it still get this frame's mix, and nobody's
+	// audio is carried into the next frame
+	assert!(!mixer.packet(ids[2]).unwrap().is_empty());
+	assert_ne!(mixer.packet(ids[2]).unwrap(), &first[..]);
+	for &id in &ids {
+		assert_eq!(mixer.input(id).unwrap(), Input::Silent);
+	}
+	mixer.encoder(ids[1]).unwrap().set_expert_frame_duration(FrameSize::Arg).unwrap();
+	assert_eq!(mixer.mix().unwrap(), 0);
+}
@@ -0,0 +1,277 @@
+//! Test MP4 muxing and demuxing.
+
+extern crate opus;
+use opus::mp4::*;
+use opus::opus_head::OpusHead;
+use opus::*;
+use std::io::Cursor;
+
+/// A parsed box, enough to check the structure of the output.
+#[derive(Debug)]
+struct Mp4Box {
+	kind: [u8; 4],
+	offset: usize,
+	data: Vec<u8>,
+	children: Vec<Mp4Box>,
+}
+
+impl Mp4Box {
+	fn find(&self, path: &[&[u8; 4]]) -> &Mp4Box {
+		match path.split_first() {
+			None => self,
+			Some((kind, rest)) => {
+				self.children.iter().find(|child| &child.kind == *kind).unwrap().find(rest)
+			}
+		}
+	}
+
+	fn u32_at(&self, at: usize) -> u32 {
+		u32::from_be_bytes([self.data[at], self.data[at + 1], self.data[at + 2], self.data[at + 3]])
+	}
+}
+
+const CONTAINERS: &[&[u8; 4]] = &[b"moov", b"trak", b"edts", b"mdia", b"minf", b"stbl"];
+
+fn parse(data: &[u8], base: usize) -> Vec<Mp4Box> {
+	let mut boxes = Vec::new();
+	let mut pos = 0;
+	while pos < data.len() {
+		let mut size =
+			u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+		let mut kind = [0; 4];
+		kind.copy_from_slice(&data[pos + 4..pos + 8]);
+		let mut header = 8;
+		if size == 1 {
+			let mut large = [0; 8];
+			large.copy_from_slice(&data[pos + 8..pos + 16]);
+			size = u64::from_be_bytes(large) as usize;
+			header = 16;
+		}
+		let body = &data[pos + header..pos + size];
+		let children =
+			if CONTAINERS.contains(&&kind) { parse(body, base + pos + header) } else { Vec::new() };
+		boxes.push(Mp4Box {
+			kind,
+			offset: base + pos,
+			data: body.to_vec(),
+			children,
+		});
+		pos += size;
+	}
+	boxes
+}
+
+fn encode(seconds: usize) -> (OpusHead, Vec<Vec<u8>>) {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let head = OpusHead::for_encoder(&mut encoder).unwrap();
+	let frame: Vec<f32> = (0..1920).map(|i| ((i / 2) as f32 * 0.05).sin() * 0.3).collect();
+	let packets =
+		(0..seconds * 50).map(|_| encoder.encode_vec_float(&frame, 4000).unwrap()).collect();
+	(head, packets)
+}
+
+fn mux(head: &OpusHead, packets: &[Vec<u8>], length: u64) -> Vec<u8> {
+	let mut writer = Mp4Writer::new(Cursor::new(Vec::new()), head).unwrap();
+	for packet in packets {
+		writer.write_packet(packet).unwrap();
+	}
+	writer.finish_with_length(length).unwrap().into_inner()
+}
+
+#[test]
+fn structure() {
+	let (head, packets) = encode(3);
+	let length = 960 * 150 - head.pre_skip as u64 - 480;
+	let data = mux(&head, &packets, length);
+
+	let top = parse(&data, 0);
+	let kinds: Vec<&[u8; 4]> = top.iter().map(|b| &b.kind).collect();
+	assert_eq!(kinds, [b"ftyp", b"mdat", b"moov"]);
+	assert_eq!(&top[0].data[..4], b"isom");
+	assert!(top[0].data.windows(4).any(|w| w == b"Opus"));
+
+	let moov = &top[2];
+	assert_eq!(moov.find(&[b"mvhd"]).u32_at(12), 48000);
+	assert_eq!(moov.find(&[b"mvhd"]).u32_at(16) as u64, length);
+	let trak = moov.find(&[b"trak"]);
+	assert_eq!(trak.find(&[b"tkhd"]).u32_at(12), 1);
+	assert_eq!(trak.find(&[b"tkhd"]).u32_at(20) as u64, length);
+
+	// the edit list skips the pre-skip and the padding at the end
+	let elst = trak.find(&[b"edts", b"elst"]);
+	assert_eq!(elst.u32_at(4), 1);
+	assert_eq!(elst.u32_at(8) as u64, length);
+	assert_eq!(elst.u32_at(12), head.pre_skip as u32);
+
+	let mdia = trak.find(&[b"mdia"]);
+	assert_eq!(mdia.find(&[b"mdhd"]).u32_at(12), 48000);
+	assert_eq!(mdia.find(&[b"mdhd"]).u32_at(16), 960 * 150);
+	assert_eq!(&mdia.find(&[b"hdlr"]).data[8..12], b"soun");
+
+	let stbl = mdia.find(&[b"minf", b"stbl"]);
+	let stsd = &stbl.find(&[b"stsd"]).data;
+	assert_eq!(&stsd[12..16], b"Opus");
+	// channel count and sample rate of the audio sample entry
+	assert_eq!(&stsd[32..34], &[0, 2]);
+	assert_eq!(&stsd[40..44], &[0xBB, 0x80, 0, 0]);
+	assert_eq!(&stsd[48..52], b"dOps");
+	let pre_skip = head.pre_skip.to_be_bytes();
+	assert_eq!(&stsd[52..58], &[0, 2, pre_skip[0], pre_skip[1], 0, 0]);
+
+	let stts = stbl.find(&[b"stts"]);
+	assert_eq!((stts.u32_at(4), stts.u32_at(8), stts.u32_at(12)), (1, 150, 960));
+	let stsz = stbl.find(&[b"stsz"]);
+	assert_eq!(stsz.u32_at(8), 150);
+	assert_eq!(stsz.u32_at(12) as usize, packets[0].len());
+
+	// chunks of one second, all in the media data
+	let stco = stbl.find(&[b"stco"]);
+	assert_eq!(stco.u32_at(4), 3);
+	assert_eq!(stco.u32_at(8) as usize, top[1].offset + 16);
+	let second = stco.u32_at(12) as usize;
+	assert_eq!(&data[second..second + packets[50].len()], &packets[50][..]);
+	let stsc = stbl.find(&[b"stsc"]);
+	assert_eq!((stsc.u32_at(4), stsc.u32_at(8), stsc.u32_at(12)), (1, 1, 50));
+
+	// four 20 ms packets cover the 80 ms pre-roll
+	let sgpd = stbl.find(&[b"sgpd"]);
+	assert_eq!(&sgpd.data[4..8], b"roll");
+	assert_eq!(&sgpd.data[16..18], &(-4i16).to_be_bytes());
+	let sbgp = stbl.find(&[b"sbgp"]);
+	assert_eq!((sbgp.u32_at(8), sbgp.u32_at(12), sbgp.u32_at(16)), (1, 150, 1));
+}
+
+#[test]
+fn demux() {
+	let (head, packets) = encode(3);
+	let length = 960 * 150 - head.pre_skip as u64 - 100;
+	let data = mux(&head, &packets, length);
+	let mut reader = Mp4Reader::new(Cursor::new(&data[..])).unwrap();
+	assert_eq!(reader.head(), &head);
+	assert_eq!(reader.pre_skip(), head.pre_skip as u64);
+	assert_eq!(reader.length(), length);
+	assert_eq!(reader.packets(), 150);
+	assert_eq!(reader.roll_distance(), 4);
+	for (i, packet) in packets.iter().enumerate() {
+		let read = reader.read_packet().unwrap().unwrap();
+		assert_eq!(&read.data, packet);
+		assert_eq!((read.timestamp, read.duration), (i as u64 * 960, 960));
+	}
+	assert!(reader.read_packet().unwrap().is_none());
+
+	reader.seek(960 * 70 + 5);
+	assert_eq!(reader.read_packet().unwrap().unwrap().timestamp, 960 * 70);
+
+	// a movie box of size 0 extends to the end of the file
+	let mut open_ended = data.clone();
+	let moov = parse(&data, 0).into_iter().find(|b| &b.kind == b"moov").unwrap();
+	assert_eq!(moov.offset + 8 + moov.data.len(), data.len());
+	open_ended[moov.offset..moov.offset + 4].copy_from_slice(&[0; 4]);
+	let mut reader = Mp4Reader::new(Cursor::new(&open_ended[..])).unwrap();
+	assert_eq!((reader.length(), reader.packets()), (length, 150));
+	assert_eq!(&reader.read_packet().unwrap().unwrap().data, &packets[0]);
+
+	// a longer length than the packets hold is limited to them
+	let data = mux(&head, &packets, u64::MAX);
+	let reader = Mp4Reader::new(Cursor::new(&data[..])).unwrap();
+	assert_eq!(reader.length(), 960 * 150 - head.pre_skip as u64);
+}
+
+#[test]
+fn decode_trims() {
+	let (head, packets) = encode(1);
+	let length = 960 * 50 - head.pre_skip as u64 - 250;
+	let data = mux(&head, &packets, length);
+	for &rate in &[48000, 16000] {
+		let reader = Mp4Reader::new(Cursor::new(&data[..])).unwrap();
+		let mut decoder = Mp4Decoder::new(reader, rate).unwrap();
+		assert_eq!(decoder.channels(), 2);
+		let pcm = decoder.decode_all::<i16>().unwrap();
+		assert_eq!(pcm.len() as u64 / 2, length * rate as u64 / 48000);
+	}
+}
+
+#[test]
+fn seek() {
+	let (head, packets) = encode(6);
+	let length = 960 * 300 - head.pre_skip as u64;
+	let data = mux(&head, &packets, length);
+	let reader = Mp4Reader::new(Cursor::new(&data[..])).unwrap();
+	let mut decoder = Mp4Decoder::new(reader, 48000).unwrap();
+	let full = decoder.decode_all::<f32>().unwrap();
+	assert_eq!(full.len() as u64, 2 * length);
+
+	for &target in &[0, 1000, 100_000, 250_000, length - 10] {
+		decoder.seek(target).unwrap();
+		let rest = decoder.decode_all::<f32>().unwrap();
+		assert_eq!(rest.len() as u64, 2 * (length - target), "{}", target);
+		let offset = 2 * 9600;
+		if rest.len() > offset + 1920 {
+			let error: f32 = rest[offset..offset + 1920]
+				.iter()
+				.zip(&full[2 * target as usize + offset..])
+				.map(|(a, b)| (a - b).abs())
+				.sum();
+			assert!(error / 1920.0 < 0.005, "{} {}", target, error);
+		}
+	}
+}
+
+#[test]
+fn multistream() {
+	let mapping = [0, 4, 1, 2, 3, 5];
+	let mut encoder = MSEncoder::new(48000, 4, 2, &mapping, Application::Audio).unwrap();
+	let head = OpusHead::for_ms_encoder(&mut encoder, 1, 4, 2, &mapping).unwrap();
+	let input = vec![0.1f32; 960 * 6];
+	let packets: Vec<Vec<u8>> =
+		(0..10).map(|_| encoder.encode_vec_float(&input, 4000).unwrap()).collect();
+	let length = 960 * 10 - head.pre_skip as u64;
+	let data = mux(&head, &packets, length);
+
+	let reader = Mp4Reader::new(Cursor::new(&data[..])).unwrap();
+	assert_eq!(reader.head(), &head);
+	let mut decoder = Mp4Decoder::new(reader, 48000).unwrap();
+	assert_eq!(decoder.channels(), 6);
+	assert_eq!(decoder.decode_all::<f32>().unwrap().len() as u64, 6 * length);
+}
+
+#[test]
+fn malformed() {
+	let err = Mp4Reader::new(Cursor::new(&b"\0\0\0\x08free"[..])).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::Malformed);
+
+	let (head, packets) = encode(1);
+	let data = mux(&head, &packets, 0);
+	// the movie box is cut off
+	assert!(Mp4Reader::new(Cursor::new(&data[..data.len() - 10])).is_err());
+	// fragmented files are rejected
+	let mut fragmented = data.clone();
+	let pos = fragmented.windows(4).position(|w| w == b"mdat").unwrap();
+	fragmented[pos..pos + 4].copy_from_slice(b"moof");
+	let err = Mp4Reader::new(Cursor::new(&fragmented[..])).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::Malformed);
+	// a track without an Opus sample entry is not found
+	let mut other = data.clone();
+	let pos = other.windows(4).position(|w| w == b"dOps").unwrap();
+	other[pos - 36..pos - 32].copy_from_slice(b"mp4a");
+	assert!(Mp4Reader::new(Cursor::new(&other[..])).is_err());
+
+	// sample counts far beyond the media data are rejected without
+	// allocating for them
+	let mut fixed = data.clone();
+	let pos = fixed.windows(4).position(|w| w == b"stsz").unwrap();
+	fixed[pos + 8..pos + 12].copy_from_slice(&1u32.to_be_bytes());
+	fixed[pos + 12..pos + 16].copy_from_slice(&u32::MAX.to_be_bytes());
+	let err = Mp4Reader::new(Cursor::new(&fixed[..])).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::Malformed);
+	assert_eq!(err.context(), Some("samples exceed the media data"));
+	let mut runs = data.clone();
+	let pos = runs.windows(4).position(|w| w == b"stts").unwrap();
+	runs[pos + 12..pos + 16].copy_from_slice(&u32::MAX.to_be_bytes());
+	let err = Mp4Reader::new(Cursor::new(&runs[..])).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::Malformed);
+	assert_eq!(err.context(), Some("sample tables differ in length"));
+
+	let mut writer = Mp4Writer::new(Cursor::new(Vec::new()), &head).unwrap();
+	assert!(writer.write_packet(&[]).is_err());
+}
@@ -0,0 +1,290 @@
+//! Test Ogg Opus writing.
+
+extern crate opus;
+use opus::ogg::*;
+use opus::opus_head::OpusHead;
+use opus::*;
+
+#[derive(Debug)]
+struct Page {
+	flags: u8,
+	granule: u64,
+	serial: u32,
+	sequence: u32,
+	segments: Vec<u8>,
+	data: Vec<u8>,
+}
+
+fn crc(data: &[u8]) -> u32 {
+	let mut crc = 0u32;
+	for &b in data {
+		crc ^= (b as u32) << 24;
+		for _ in 0..8 {
+			crc = if crc & 0x8000_0000 != 0 { crc << 1 ^ 0x04C1_1DB7 } else { crc << 1 };
+		}
+	}
+	crc
+}
+
+fn pages(mut data: &[u8]) -> Vec<Page> {
+	let mut pages = Vec::new();
+	while !data.is_empty() {
+		assert_eq!(&data[..5], b"OggS\0");
+		let count = data[26] as usize;
+		let segments = data[27..27 + count].to_vec();
+		let len = 27 + count + segments.iter().map(|&s| s as usize).sum::<usize>();
+		let mut page = data[..len].to_vec();
+		let stored = u32::from_le_bytes([page[22], page[23], page[24], page[25]]);
+		page[22..26].copy_from_slice(&[0; 4]);
+		assert_eq!(crc(&page), stored);
+
+		let mut granule = [0; 8];
+		granule.copy_from_slice(&data[6..14]);
+		pages.push(Page {
+			flags: data[5],
+			granule: u64::from_le_bytes(granule),
+			serial: u32::from_le_bytes([data[14], data[15], data[16], data[17]]),
+			sequence: u32::from_le_bytes([data[18], data[19], data[20], data[21]]),
+			segments,
+			data: data[27 + count..len].to_vec(),
+		});
+		data = &data[len..];
+	}
+	pages
+}
+
+/// Split the pages back into packets.
+fn packets(pages: &[Page]) -> Vec<Vec<u8>> {
+	let mut packets = Vec::new();
+	let mut packet = Vec::new();
+	for page in pages {
+		assert_eq!(page.flags & 1 != 0, !packet.is_empty());
+		let mut pos = 0;
+		for &segment in &page.segments {
+			packet.extend_from_slice(&page.data[pos..pos + segment as usize]);
+			pos += segment as usize;
+			if segment < 255 {
+				packets.push(std::mem::take(&mut packet));
+			}
+		}
+	}
+	packets
+}
+
+fn encode(frames: usize) -> (OpusHead, Vec<Vec<u8>>) {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	encoder.set_bitrate(Bitrate::Bits(128000)).unwrap();
+	let head = OpusHead::for_encoder(&mut encoder).unwrap();
+	let frame: Vec<f32> = (0..1920).map(|i| ((i / 2) as f32 * 0.05).sin() * 0.3).collect();
+	let packets = (0..frames).map(|_| encoder.encode_vec_float(&frame, 4000).unwrap()).collect();
+	(head, packets)
+}
+
+#[test]
+fn tags() {
+	let mut tags = OpusTags::new();
+	assert_eq!(tags.vendor, version());
+	tags.add("TITLE", "Test");
+	tags.add("artist", "Somebody=Else");
+	let data = tags.to_bytes();
+	assert_eq!(&data[..8], b"OpusTags");
+	let parsed = OpusTags::parse(&data).unwrap();
+	assert_eq!(parsed, tags);
+	assert_eq!(parsed.get("title"), Some("Test"));
+	assert_eq!(parsed.get("ARTIST"), Some("Somebody=Else"));
+	assert_eq!(parsed.get("album"), None);
+
+	assert_eq!(OpusTags::parse(&data[..data.len() - 1]).unwrap_err().kind(), ErrorKind::Malformed);
+	assert!(OpusTags::parse(b"OpusHead\0\0\0\0\0\0\0\0").is_err());
+}
+
+#[test]
+fn stream() {
+	let (head, packets) = encode(150);
+	let mut tags = OpusTags::new();
+	tags.add("TITLE", "Test");
+	let mut writer = OggOpusWriter::new(Vec::new(), &head, &tags, 1234).unwrap();
+	for packet in &packets {
+		writer.write_packet(packet).unwrap();
+	}
+	assert_eq!(writer.samples(), 960 * 150);
+	let length = 960 * 150 - head.pre_skip as u64 - 300;
+	let data = writer.finish_with_length(length).unwrap();
+
+	let pages = pages(&data);
+	for (i, page) in pages.iter().enumerate() {
+		assert_eq!((page.serial, page.sequence), (1234, i as u32));
+	}
+	// the headers are on their own pages
+	assert_eq!((pages[0].flags, pages[0].granule), (2, 0));
+	assert_eq!(pages[0].data, head.to_bytes());
+	assert_eq!((pages[1].flags, pages[1].granule), (0, 0));
+	assert_eq!(OpusTags::parse(&pages[1].data).unwrap(), tags);
+
+	// pages end on packet boundaries, with the samples decoded so far
+	let audio = &pages[2..];
+	assert!(audio.len() > 3);
+	let mut decoded = 0;
+	for page in &audio[..audio.len() - 1] {
+		assert_eq!(page.flags, 0);
+		assert!(page.data.len() <= 4096 + 4000);
+		decoded += 960 * page.segments.iter().filter(|&&s| s < 255).count() as u64;
+		assert_eq!(page.granule, decoded);
+	}
+	// the last page trims the padding
+	let last = audio.last().unwrap();
+	assert_eq!(last.flags, 4);
+	assert_eq!(last.granule, head.pre_skip as u64 + length);
+	assert_eq!(self::packets(audio), packets);
+}
+
+#[test]
+fn continued_packets() {
+	// headers larger than a page are split across pages
+	let head = OpusHead::new(Channels::Mono, 312, 48000);
+	let mut tags = OpusTags::new();
+	tags.add("COMMENT", &"x".repeat(70_000));
+	let mut writer = OggOpusWriter::new(Vec::new(), &head, &tags, 1).unwrap();
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	let packet = encoder.encode_vec_float(&[0.0; 960], 4000).unwrap();
+	writer.write_packet(&packet).unwrap();
+	let data = writer.finish().unwrap();
+
+	let pages = pages(&data);
+	assert_eq!(pages.len(), 4);
+	assert_eq!(pages[1].segments.len(), 255);
+	// no packet ends on the first page of the tags
+	assert_eq!(pages[1].granule, u64::MAX);
+	assert_eq!((pages[2].flags, pages[2].granule), (1, 0));
+	let packets = packets(&pages);
+	assert_eq!(packets.len(), 3);
+	assert_eq!(OpusTags::parse(&packets[1]).unwrap(), tags);
+	assert_eq!((pages[3].flags, pages[3].granule), (4, 960));
+}
+
+#[test]
+fn empty() {
+	let (head, _) = encode(0);
+	let data =
+		OggOpusWriter::new(Vec::new(), &head, &OpusTags::new(), 7).unwrap().finish().unwrap();
+	let pages = pages(&data);
+	assert_eq!(pages.len(), 3);
+	assert_eq!((pages[2].flags, pages[2].granule), (4, 0));
+	assert!(pages[2].segments.is_empty());
+
+	let mut writer = OggOpusWriter::new(Vec::new(), &head, &OpusTags::new(), 7).unwrap();
+	assert!(writer.write_packet(&[]).is_err());
+}
+
+#[test]
+fn reader() {
+	let (head, packets) = encode(120);
+	let mut tags = OpusTags::new();
+	tags.add("TITLE", "Test");
+	let mut writer = OggOpusWriter::new(Vec::new(), &head, &tags, 99).unwrap();
+	for packet in &packets {
+		writer.write_packet(packet).unwrap();
+	}
+	let length = 960 * 120 - head.pre_skip as u64 - 500;
+	let data = writer.finish_with_length(length).unwrap();
+
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.head(), &head);
+	assert_eq!(reader.tags(), &tags);
+	assert_eq!(reader.serial(), 99);
+	let mut read = Vec::new();
+	let mut granule = 0;
+	while let Some(packet) = reader.read_packet().unwrap() {
+		if let Some(position) = packet.granule_position {
+			assert!(position > granule);
+			granule = position;
+		}
+		assert_eq!(packet.end_of_stream, read.len() + 1 == packets.len());
+		read.push(packet.data);
+	}
+	assert_eq!(read, packets);
+	assert_eq!(granule, head.pre_skip as u64 + length);
+	assert!(reader.read_packet().unwrap().is_none());
+}
+
+#[test]
+fn reader_pages() {
+	// packets spanning pages are reassembled
+	let head = OpusHead::new(Channels::Mono, 312, 48000);
+	let mut tags = OpusTags::new();
+	tags.add("COMMENT", &"x".repeat(70_000));
+	let mut writer = OggOpusWriter::new(Vec::new(), &head, &tags, 1).unwrap();
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	encoder.set_bitrate(Bitrate::Max).unwrap();
+	let noise: Vec<f32> =
+		(0..2880).map(|i| ((i * 7919 % 1000) as f32 / 500.0 - 1.0) * 0.5).collect();
+	let packet = encoder.encode_vec_float(&noise, 1275 * 3).unwrap();
+	writer.write_packet(&packet).unwrap();
+	let data = writer.finish().unwrap();
+	let mut reader = OggOpusReader::new(&data[..]).unwrap();
+	assert_eq!(reader.tags(), &tags);
+	assert_eq!(reader.read_packet().unwrap().unwrap().data, packet);
+
+	// pages of other streams are skipped
+	let other = OggOpusWriter::new(Vec::new(), &head, &OpusTags::new(), 2).unwrap();
+	let other = other.finish().unwrap();
+	let (ours, theirs) = (split(&data), split(&other));
+	let mut mixed = Vec::new();
+	for i in 0..ours.len().max(theirs.len()) {
+		for pages in &[&ours, &theirs] {
+			if let Some(page) = pages.get(i) {
+				mixed.extend_from_slice(page);
+			}
+		}
+	}
+	let mut reader = OggOpusReader::new(&mixed[..]).unwrap();
+	assert_eq!((reader.serial(), reader.tags()), (1, &tags));
+	let last = reader.read_packet().unwrap().unwrap();
+	assert_eq!((last.data, last.granule_position, last.end_of_stream), (packet, Some(2880), true));
+	assert!(reader.read_packet().unwrap().is_none());
+}
+
+/// Split a stream into its raw pages.
+fn split(mut data: &[u8]) -> Vec<&[u8]> {
+	let mut pages = Vec::new();
+	while !data.is_empty() {
+		let count = data[26] as usize;
+		let len = 27 + count + data[27..27 + count].iter().map(|&s| s as usize).sum::<usize>();
+		pages.push(&data[..len]);
+		data = &data[len..];
+	}
+	pages
+}
+
+#[test]
+fn reader_malformed() {
+	let (head, packets) = encode(10);
+	let mut writer = OggOpusWriter::new(Vec::new(), &head, &OpusTags::new(), 5).unwrap();
+	for packet in &packets {
+		writer.write_packet(packet).unwrap();
+	}
+	let data = writer.finish().unwrap();
+	let pages = split(&data);
+
+	// a corrupted page fails its checksum
+	let mut corrupt = data.clone();
+	let last = data.len() - 10;
+	corrupt[last] ^= 1;
+	let mut reader = OggOpusReader::new(&corrupt[..]).unwrap();
+	assert_eq!(reader.read_packet().unwrap_err().kind(), ErrorKind::Malformed);
+
+	// a truncated page is an error, but a stream cut between pages just ends
+	let cut = pages[0].len() + pages[1].len() + 10;
+	let mut reader = OggOpusReader::new(&data[..cut]).unwrap();
+	assert!(reader.read_packet().is_err());
+	let mut reader = OggOpusReader::new(&data[..cut - 10]).unwrap();
+	assert!(reader.read_packet().unwrap().is_none());
+
+	// the headers must be present
+	assert_eq!(
+		OggOpusReader::new(&data[..pages[0].len()]).unwrap_err().kind(),
+		ErrorKind::Malformed
+	);
+	assert_eq!(OggOpusReader::new(&b""[..]).unwrap_err().kind(), ErrorKind::Malformed);
+	assert!(OggOpusReader::new(&b"RIFF0000WAVE"[..]).is_err());
+}
@@ -0,0 +1,66 @@
+//! Test reading and writing the `opus_demo` bitstream format.
+
+extern crate opus;
+use opus::opus_demo::*;
+use opus::*;
+
+#[test]
+fn roundtrip() {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	let mut writer = DemoBitstreamWriter::new(Vec::new());
+	let mut packets = Vec::new();
+	for i in 0..5 {
+		if i == 2 {
+			writer.write_lost().unwrap();
+			continue;
+		}
+		let packet = encoder.encode_vec(&[0; 960], 4000).unwrap();
+		let range = encoder.get_final_range().unwrap();
+		writer.write_packet(&packet, range).unwrap();
+		packets.push((packet, range));
+	}
+	assert_eq!(writer.packets(), 5);
+	let bytes = writer.into_inner();
+	assert_eq!(&bytes[..4], &(packets[0].0.len() as u32).to_be_bytes());
+	assert_eq!(&bytes[4..8], &packets[0].1.to_be_bytes());
+
+	let mut reader = DemoBitstreamReader::new(&bytes[..]);
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let mut output = [0i16; 960];
+	let mut expected = packets.iter();
+	for packet in &mut reader {
+		let packet = packet.unwrap();
+		if packet.is_lost() {
+			decoder.decode(&[], &mut output, false).unwrap();
+			continue;
+		}
+		let &(ref data, range) = expected.next().unwrap();
+		assert_eq!(&packet.data, data);
+		decoder.decode(&packet.data, &mut output, false).unwrap();
+		assert_eq!(decoder.get_final_range().unwrap(), range);
+	}
+	assert_eq!(reader.packets(), 5);
+	assert!(expected.next().is_none());
+}
+
+#[test]
+fn truncated() {
+	let mut writer = DemoBitstreamWriter::new(Vec::new());
+	writer.write_packet(&[1, 2, 3], 42).unwrap();
+	let bytes = writer.into_inner();
+
+	for len in 1..bytes.len() {
+		let mut reader = DemoBitstreamReader::new(&bytes[..len]);
+		let err = reader.read_packet().unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::Io);
+	}
+	let mut reader = DemoBitstreamReader::new(&bytes[..]);
+	assert_eq!(
+		reader.read_packet().unwrap(),
+		Some(DemoPacket { data: vec![1, 2, 3], final_range: 42 })
+	);
+	assert_eq!(reader.read_packet().unwrap(), None);
+
+	let bad = [0xff, 0xff, 0xff, 0xff, 0, 0, 0, 0];
+	assert!(DemoBitstreamReader::new(&bad[..]).read_packet().is_err());
+}
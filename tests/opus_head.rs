@@ -0,0 +1,93 @@
+//! Test the `OpusHead` identification header.
+
+extern crate opus;
+use opus::opus_head::*;
+use opus::*;
+
+#[test]
+fn family_zero() {
+	let head = OpusHead::new(Channels::Stereo, 312, 44100);
+	let bytes = head.to_bytes();
+	assert_eq!(bytes.len(), 19);
+	assert_eq!(&bytes[..8], b"OpusHead");
+	assert_eq!(bytes[8], 1);
+	assert_eq!(bytes[9], 2);
+	assert_eq!(&bytes[10..12], &312u16.to_le_bytes());
+	assert_eq!(OpusHead::parse(&bytes).unwrap(), head);
+	assert_eq!((head.streams, head.coupled_streams, &head.mapping[..]), (1, 1, &[0, 1][..]));
+
+	let mut decoder = head.decoder(48000).unwrap();
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let packet = encoder.encode_vec_float(&[0.0; 1920], 4000).unwrap();
+	let mut output = [0.0; 1920];
+	assert_eq!(decoder.decode_float(&packet, &mut output, false).unwrap(), 960);
+}
+
+#[test]
+fn for_encoder() {
+	let mut encoder = Encoder::new(16000, Channels::Mono, Application::Voip).unwrap();
+	let lookahead = encoder.get_lookahead().unwrap() as u16;
+	let head = OpusHead::for_encoder(&mut encoder).unwrap();
+	assert_eq!(head.pre_skip, lookahead * 3);
+	assert_eq!(head.input_sample_rate, 16000);
+	assert_eq!(head.channels, 1);
+
+	let mapping = [0, 1, 2];
+	let mut encoder = MSEncoder::new(48000, 2, 1, &mapping, Application::Audio).unwrap();
+	let head = OpusHead::for_ms_encoder(&mut encoder, 255, 2, 1, &mapping).unwrap();
+	assert_eq!(head.channels, 3);
+	assert_eq!(head.pre_skip as i32, encoder.get_lookahead().unwrap());
+	assert!(OpusHead::for_ms_encoder(&mut encoder, 255, 2, 1, &[0, 1]).is_err());
+}
+
+#[test]
+fn multistream() {
+	let head = OpusHead::multistream(1, 4, 2, &[0, 4, 1, 2, 3, 5], 3840, 48000).unwrap();
+	let bytes = head.to_bytes();
+	assert_eq!(bytes.len(), 27);
+	let parsed = OpusHead::parse(&bytes).unwrap();
+	assert_eq!(parsed, head);
+	assert_eq!(parsed.decoder(48000).unwrap().get_sample_rate().unwrap(), 48000);
+
+	// trailing data is allowed
+	let mut extended = bytes.clone();
+	extended.extend_from_slice(&[1, 2, 3]);
+	assert_eq!(OpusHead::parse(&extended).unwrap(), head);
+
+	assert!(OpusHead::multistream(0, 1, 1, &[0, 1, 2], 0, 48000).is_err());
+	assert!(OpusHead::multistream(1, 2, 3, &[0, 1], 0, 48000).is_err());
+	assert!(OpusHead::multistream(1, 1, 0, &[0, 1], 0, 48000).is_err());
+	// silent channels are allowed
+	assert!(OpusHead::multistream(1, 1, 0, &[0, 255], 0, 48000).is_ok());
+}
+
+#[test]
+fn malformed() {
+	let bytes = OpusHead::multistream(1, 2, 1, &[0, 1, 2], 0, 48000).unwrap().to_bytes();
+	for len in 0..bytes.len() {
+		let err = OpusHead::parse(&bytes[..len]).unwrap_err();
+		assert_eq!(err.kind(), ErrorKind::Malformed);
+		assert_eq!(err.code(), ErrorCode::InvalidPacket);
+	}
+
+	let mut bad = bytes.clone();
+	bad[8] = 0x10;
+	assert!(OpusHead::parse(&bad).is_err());
+	// minor versions are compatible
+	bad[8] = 0x0F;
+	assert!(OpusHead::parse(&bad).is_ok());
+	bad[0] = b'o';
+	assert!(OpusHead::parse(&bad).is_err());
+	let mut bad = bytes;
+	bad[21] = 3;
+	assert!(OpusHead::parse(&bad).is_err());
+}
+
+#[test]
+fn output_gain() {
+	let mut head = OpusHead::new(Channels::Mono, 0, 48000);
+	assert_eq!(head.output_gain_factor(), 1.0);
+	head.output_gain = -6 * 256;
+	assert_eq!(head.output_gain_db(), -6.0);
+	assert!((head.output_gain_factor() - 0.501).abs() < 0.001);
+}
@@ -0,0 +1,306 @@
+//! Test reading and writing PCM files.
+
+extern crate opus;
+use opus::pcm::*;
+use opus::*;
+use std::io::Cursor;
+
+fn format(channels: u16, sample_format: SampleFormat) -> PcmFormat {
+	PcmFormat {
+		channels,
+		sample_rate: 48000,
+		sample_format,
+		channel_mask: None,
+	}
+}
+
+fn write<T: Copy + Sample>(format: &PcmFormat, samples: &[T]) -> Vec<u8> {
+	let mut writer = WavWriter::new(Cursor::new(Vec::new()), format).unwrap();
+	writer.write(samples).unwrap();
+	writer.finish().unwrap().into_inner()
+}
+
+#[test]
+fn wav_roundtrip() {
+	let input: Vec<i16> = (0..2000).map(|i| (i * 37 % 65536 - 32768) as i16).collect();
+	for &sample_format in &[
+		SampleFormat::I16,
+		SampleFormat::I24,
+		SampleFormat::I32,
+		SampleFormat::F32,
+		SampleFormat::F64,
+	] {
+		let data = write(&format(2, sample_format), &input);
+		assert_eq!(data.len(), data.len() / 2 * 2);
+		let mut reader = PcmReader::new(&data[..]).unwrap();
+		assert_eq!(reader.container(), Container::Wav);
+		// wider samples use an extensible header, which gives the channel mask
+		let mask = if sample_format == SampleFormat::I16 { None } else { Some(0x3) };
+		let expected = PcmFormat {
+			channel_mask: mask,
+			..format(2, sample_format)
+		};
+		assert_eq!(reader.format(), &expected);
+		assert_eq!(reader.frames(), Some(1000));
+		assert_eq!(reader.read_all::<i16>().unwrap(), input, "{:?}", sample_format);
+	}
+
+	// eight bits keep the top of each sample
+	let data = write(&format(1, SampleFormat::U8), &input[..999]);
+	assert_eq!(data.len() % 2, 0);
+	let output = PcmReader::new(&data[..]).unwrap().read_all::<i16>().unwrap();
+	assert_eq!(output.len(), 999);
+	for (a, b) in input.iter().zip(&output) {
+		assert!((a - b).abs() <= 128, "{} {}", a, b);
+	}
+
+	let input = [0.5f32, -0.25, 1.5, -1.5];
+	let data = write(&format(2, SampleFormat::I16), &input);
+	let output = PcmReader::new(&data[..]).unwrap().read_all::<f32>().unwrap();
+	assert_eq!(output, [0.5, -0.25, 32767.0 / 32768.0, -1.0]);
+}
+
+#[test]
+fn wav_headers() {
+	// mono 16-bit uses a plain header
+	let data = write(&format(1, SampleFormat::I16), &[0i16; 10]);
+	assert_eq!(&data[..4], b"RIFF");
+	assert_eq!(u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize, data.len() - 8);
+	let fmt = data.windows(4).position(|w| w == b"fmt ").unwrap();
+	assert_eq!(&data[fmt + 4..fmt + 10], &[16, 0, 0, 0, 1, 0]);
+
+	// surround uses an extensible header with the default mask
+	let data = write(&format(6, SampleFormat::I16), &[0i16; 12]);
+	let fmt = data.windows(4).position(|w| w == b"fmt ").unwrap();
+	assert_eq!(&data[fmt + 4..fmt + 10], &[40, 0, 0, 0, 0xFE, 0xFF]);
+	assert_eq!(&data[fmt + 28..fmt + 34], &[0x3F, 0, 0, 0, 1, 0]);
+	let reader = PcmReader::new(&data[..]).unwrap();
+	assert_eq!(reader.format().channel_mask, Some(0x3F));
+
+	// other chunks before the data are skipped, with their padding
+	let mut data = write(&format(1, SampleFormat::I16), &[1i16, 2, 3]);
+	let chunk = b"LIST\x03\0\0\0abc\0";
+	let at = data.windows(4).position(|w| w == b"data").unwrap();
+	data.splice(at..at, chunk.iter().cloned());
+	let mut reader = PcmReader::new(&data[..]).unwrap();
+	assert_eq!(reader.read_all::<i16>().unwrap(), [1, 2, 3]);
+}
+
+/// Build an RF64 file by hand, since the writer only uses it past 4 GB.
+#[test]
+fn wav_streaming() {
+	// a pipe can't seek back to fill in the sizes
+	let mut writer = WavWriter::streaming(Vec::new(), &format(1, SampleFormat::U8)).unwrap();
+	writer.write(&[0.5f32; 99]).unwrap();
+	writer.write(&[-0.5f32; 100]).unwrap();
+	let data = writer.finish_streaming().unwrap();
+	assert_eq!(&data[4..8], &[0xFF; 4]);
+	let mut reader = PcmReader::new(&data[..]).unwrap();
+	assert_eq!(reader.frames(), None);
+	let output = reader.read_all::<f32>().unwrap();
+	assert_eq!(output.len(), 199);
+	assert_eq!((output[98], output[99]), (0.5, -0.5));
+}
+
+#[test]
+fn rf64() {
+	let mut data = b"RF64\xFF\xFF\xFF\xFFWAVEds64\x1C\0\0\0".to_vec();
+	data.extend_from_slice(&0u64.to_le_bytes());
+	data.extend_from_slice(&6u64.to_le_bytes());
+	data.extend_from_slice(&3u64.to_le_bytes());
+	data.extend_from_slice(&0u32.to_le_bytes());
+	data.extend_from_slice(b"fmt \x10\0\0\0\x01\0\x01\0\x80\xBB\0\0\0\x77\x01\0\x02\0\x10\0");
+	data.extend_from_slice(b"data\xFF\xFF\xFF\xFF");
+	data.extend_from_slice(&[1, 0, 2, 0, 3, 0, 4, 0]);
+	let mut reader = PcmReader::new(&data[..]).unwrap();
+	assert_eq!(reader.container(), Container::Rf64);
+	assert_eq!(reader.frames(), Some(3));
+	// the size from the ds64 chunk ends the data
+	assert_eq!(reader.read_all::<i16>().unwrap(), [1, 2, 3]);
+}
+
+fn caf(desc: &[u8], chan: Option<&[u8]>, samples: &[u8], size: i64) -> Vec<u8> {
+	let mut data = b"caff\0\x01\0\0desc".to_vec();
+	data.extend_from_slice(&(desc.len() as i64).to_be_bytes());
+	data.extend_from_slice(desc);
+	if let Some(chan) = chan {
+		data.extend_from_slice(b"chan");
+		data.extend_from_slice(&(chan.len() as i64).to_be_bytes());
+		data.extend_from_slice(chan);
+	}
+	data.extend_from_slice(b"data");
+	data.extend_from_slice(&size.to_be_bytes());
+	data.extend_from_slice(&[0; 4]);
+	data.extend_from_slice(samples);
+	data
+}
+
+fn desc(sample_rate: f64, flags: u32, bytes: u32, channels: u32) -> Vec<u8> {
+	let mut desc = sample_rate.to_be_bytes().to_vec();
+	desc.extend_from_slice(b"lpcm");
+	for value in &[flags, bytes * channels, 1, channels, bytes * 8] {
+		desc.extend_from_slice(&value.to_be_bytes());
+	}
+	desc
+}
+
+#[test]
+fn caf_formats() {
+	// big-endian 16-bit integers, running to the end of the file
+	let data = caf(&desc(16000.0, 0, 2, 1), None, &[0x40, 0, 0xC0, 0], -1);
+	let mut reader = PcmReader::new(&data[..]).unwrap();
+	assert_eq!(reader.container(), Container::Caf);
+	assert_eq!(
+		reader.format(),
+		&PcmFormat {
+			sample_rate: 16000,
+			..format(1, SampleFormat::I16)
+		}
+	);
+	assert_eq!(reader.frames(), None);
+	assert_eq!(reader.read_all::<f32>().unwrap(), [0.5, -0.5]);
+
+	// little-endian floats with a channel bitmap
+	let mut samples = Vec::new();
+	for value in &[0.25f32, -0.75, 0.5, 0.125] {
+		samples.extend_from_slice(&value.to_le_bytes());
+	}
+	let chan = [0, 1, 0, 0, 0, 0, 0, 0x33, 0, 0, 0, 0];
+	let data = caf(&desc(48000.0, 3, 4, 4), Some(&chan), &samples, 20);
+	let mut reader = PcmReader::new(&data[..]).unwrap();
+	assert_eq!(reader.format().sample_format, SampleFormat::F32);
+	assert_eq!(reader.format().channel_mask, Some(0x33));
+	assert_eq!(reader.frames(), Some(1));
+	assert_eq!(reader.read_all::<f32>().unwrap(), [0.25, -0.75, 0.5, 0.125]);
+
+	// 24-bit big-endian integers
+	let data = caf(&desc(48000.0, 0, 3, 1), None, &[0xFF, 0xFF, 0xFF, 0x40, 0, 0], 10);
+	let output = PcmReader::new(&data[..]).unwrap().read_all::<f32>().unwrap();
+	assert_eq!(output, [-1.0 / 8_388_608.0, 0.5]);
+}
+
+#[test]
+fn layouts() {
+	let stereo = StreamLayout::for_format(&format(2, SampleFormat::I16)).unwrap();
+	assert_eq!((stereo.mapping_family, stereo.streams, stereo.coupled_streams), (0, 1, 1));
+
+	// 5.1 in WAV order is FL FR FC LFE BL BR
+	let surround = StreamLayout::for_format(&format(6, SampleFormat::I16)).unwrap();
+	assert_eq!((surround.mapping_family, surround.streams, surround.coupled_streams), (1, 4, 2));
+	assert_eq!(surround.mapping, [0, 4, 1, 2, 3, 5]);
+	assert_eq!(surround.order, [0, 2, 1, 4, 5, 3]);
+
+	// 5.1 with side speakers uses them as the rear pair
+	let side = PcmFormat {
+		channel_mask: Some(0x60F),
+		..format(6, SampleFormat::I16)
+	};
+	assert_eq!(StreamLayout::for_format(&side).unwrap().order, [0, 2, 1, 4, 5, 3]);
+
+	// 7.1 is FL FR FC LFE BL BR SL SR
+	let layout = StreamLayout::for_format(&format(8, SampleFormat::I16)).unwrap();
+	assert_eq!(layout.order, [0, 2, 1, 6, 7, 4, 5, 3]);
+	assert_eq!(layout.mapping, [0, 6, 1, 2, 3, 4, 5, 7]);
+
+	// masks which don't fit a Vorbis layout are coded separately
+	let odd = PcmFormat {
+		channel_mask: Some(0x7),
+		..format(4, SampleFormat::I16)
+	};
+	let layout = StreamLayout::for_format(&odd).unwrap();
+	assert_eq!((layout.mapping_family, layout.streams, layout.coupled_streams), (255, 4, 0));
+	assert_eq!(layout.order, [0, 1, 2, 3]);
+	let layout = StreamLayout::for_format(&format(12, SampleFormat::I16)).unwrap();
+	assert_eq!(layout.mapping_family, 255);
+	assert!(StreamLayout::for_format(&format(0, SampleFormat::I16)).is_err());
+
+	// a family can be forced where the channels allow it
+	let mono = StreamLayout::with_family(&format(1, SampleFormat::I16), 1).unwrap();
+	assert_eq!((mono.mapping_family, mono.streams, mono.coupled_streams), (1, 1, 0));
+	let layout = StreamLayout::with_family(&format(6, SampleFormat::I16), 255).unwrap();
+	assert_eq!((layout.streams, layout.coupled_streams), (6, 0));
+	assert!(StreamLayout::with_family(&format(3, SampleFormat::I16), 0).is_err());
+	assert!(StreamLayout::with_family(&odd, 1).is_err());
+	assert!(StreamLayout::with_family(&format(2, SampleFormat::I16), 2).is_err());
+}
+
+#[test]
+fn raw() {
+	let data = [0x00, 0x40, 0x00, 0xC0, 0x01];
+	let mut reader = PcmReader::raw(&data[..], &format(2, SampleFormat::I16), false).unwrap();
+	assert_eq!(reader.container(), Container::Raw);
+	assert_eq!(reader.frames(), None);
+	assert_eq!(reader.read_all::<f32>().unwrap(), [0.5, -0.5]);
+	assert!(PcmReader::raw(&data[..], &format(0, SampleFormat::I16), false).is_err());
+}
+
+#[test]
+fn encode_surround() {
+	// each channel holds its index
+	let frames: usize = 2000;
+	let input: Vec<f32> = (0..frames * 6).map(|i| (i % 6) as f32 / 10.0).collect();
+	let data = write(&format(6, SampleFormat::I24), &input);
+	let mut reader = PcmReader::new(&data[..]).unwrap();
+	let layout = StreamLayout::for_format(reader.format()).unwrap();
+	reader.reorder(&layout).unwrap();
+
+	let mut encoder = layout.encoder(48000, Application::Audio).unwrap();
+	let head = layout.head(&mut encoder).unwrap();
+	assert_eq!(head.mapping_family, 1);
+	let mut packets = Vec::new();
+	while let Some(packet) = reader.encode_packet::<f32>(&mut encoder, FrameSize::Ms20).unwrap() {
+		packets.push(packet);
+	}
+	assert_eq!(packets.len(), frames.div_ceil(960));
+
+	// the reordered channels come back in Vorbis order
+	let mut reader = PcmReader::new(&data[..]).unwrap();
+	reader.reorder(&layout).unwrap();
+	let mut frame = [0.0f32; 6];
+	assert_eq!(reader.read(&mut frame).unwrap(), 1);
+	let expected = [0.0, 0.2, 0.1, 0.4, 0.5, 0.3];
+	for (a, b) in frame.iter().zip(&expected) {
+		assert!((a - b).abs() < 1e-6, "{:?}", frame);
+	}
+
+	let mut reader = PcmReader::new(&data[..]).unwrap();
+	let mut stereo = StreamLayout::for_format(&format(2, SampleFormat::I16))
+		.unwrap()
+		.encoder(48000, Application::Audio)
+		.unwrap();
+	assert!(reader.encode_packet::<i16>(&mut stereo, FrameSize::Ms20).is_err());
+	assert!(reader
+		.reorder(&StreamLayout::for_format(&format(2, SampleFormat::I16)).unwrap())
+		.is_err());
+	let mut encoder = layout.encoder(48000, Application::Audio).unwrap();
+	assert!(reader.encode_packet::<i16>(&mut encoder, FrameSize::Arg).is_err());
+}
+
+#[test]
+fn malformed() {
+	let err = PcmReader::new(&b"OggS\0\0\0\0"[..]).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::Malformed);
+
+	let data = write(&format(2, SampleFormat::I16), &[0i16; 100]);
+	// cut off before the data chunk
+	assert_eq!(PcmReader::new(&data[..40]).unwrap_err().kind(), ErrorKind::Io);
+	// a truncated data chunk ends early, dropping the partial frame
+	let mut reader = PcmReader::new(&data[..data.len() - 3]).unwrap();
+	assert_eq!(reader.read_all::<i16>().unwrap().len(), 98);
+
+	// compressed formats are not supported
+	let mut adpcm = data.clone();
+	let fmt = adpcm.windows(4).position(|w| w == b"fmt ").unwrap();
+	adpcm[fmt + 8] = 2;
+	assert_eq!(PcmReader::new(&adpcm[..]).unwrap_err().kind(), ErrorKind::Malformed);
+
+	let mut aac = caf(&desc(48000.0, 0, 2, 1), None, &[], 4);
+	let at = aac.windows(4).position(|w| w == b"lpcm").unwrap();
+	aac[at..at + 4].copy_from_slice(b"aac ");
+	assert_eq!(PcmReader::new(&aac[..]).unwrap_err().kind(), ErrorKind::Malformed);
+
+	assert!(WavWriter::new(Cursor::new(Vec::new()), &format(0, SampleFormat::I16)).is_err());
+	let mut writer =
+		WavWriter::new(Cursor::new(Vec::new()), &format(2, SampleFormat::I16)).unwrap();
+	assert!(writer.write(&[0i16; 3]).is_err());
+}
@@ -0,0 +1,117 @@
+//! Test objective quality measurement.
+
+extern crate opus;
+use opus::quality::*;
+use opus::*;
+
+/// A chord of harmonics with a slow tremolo, so every segment has energy.
+fn tone(sample_rate: u32, channels: usize, seconds: f64) -> Vec<f32> {
+	let len = (sample_rate as f64 * seconds) as usize;
+	let mut output = Vec::with_capacity(len * channels);
+	for i in 0..len {
+		let t = i as f64 / sample_rate as f64;
+		let envelope = 0.6 + 0.4 * (2.0 * std::f64::consts::PI * 3.0 * t).sin();
+		for channel in 0..channels {
+			let mut value = 0.0;
+			for harmonic in 1..6 {
+				let freq = 220.0 * harmonic as f64 * (1.0 + 0.01 * channel as f64);
+				value += (2.0 * std::f64::consts::PI * freq * t).sin() / harmonic as f64;
+			}
+			output.push((0.2 * envelope * value) as f32);
+		}
+	}
+	output
+}
+
+#[test]
+fn identical() {
+	let input = tone(48000, 2, 0.2);
+	let scores = score(&input, &input, 2, 48000).unwrap();
+	assert_eq!(scores.snr, f64::INFINITY);
+	assert_eq!(scores.segmental_snr, 35.0);
+	assert_eq!(scores.log_spectral_distance, 0.0);
+	assert!(scores.perceptual > 99.0, "{:?}", scores);
+}
+
+#[test]
+fn degradation_lowers_scores() {
+	let input = tone(16000, 1, 0.5);
+	let slight: Vec<f32> = input.iter().map(|&s| s * 0.99).collect();
+	let heavy: Vec<f32> = input.iter().map(|&s| (s * 8.0).round() / 8.0).collect();
+	let slight = score(&input, &slight, 1, 16000).unwrap();
+	let heavy = score(&input, &heavy, 1, 16000).unwrap();
+	assert!((slight.snr - 40.0).abs() < 0.1, "{:?}", slight);
+	assert!(heavy.snr < slight.snr);
+	assert!(heavy.segmental_snr < slight.segmental_snr);
+	assert!(heavy.log_spectral_distance > slight.log_spectral_distance);
+	assert!(heavy.perceptual < slight.perceptual);
+}
+
+#[test]
+fn bad_arguments() {
+	let input = tone(48000, 2, 0.1);
+	assert!(score(&input, &input[..input.len() - 2], 2, 48000).is_err());
+	assert!(score(&input[..3], &input[..3], 2, 48000).is_err());
+	// too short for the perceptual score
+	assert!(perceptual(&input[..200], &input[..200], 2, 48000).is_err());
+	assert!(perceptual(&input, &input, 3, 48000).is_err());
+
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let mut decoder = Decoder::new(24000, Channels::Stereo).unwrap();
+	assert!(roundtrip(&mut encoder, &mut decoder, &input, FrameSize::Ms20).is_err());
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	assert!(roundtrip(&mut encoder, &mut decoder, &input, FrameSize::Ms20).is_err());
+}
+
+#[test]
+fn roundtrip_is_aligned() {
+	let input = tone(48000, 1, 0.5);
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	encoder.set_bitrate(Bitrate::Bits(64000)).unwrap();
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let result = roundtrip(&mut encoder, &mut decoder, &input, FrameSize::Ms20).unwrap();
+	assert_eq!(result.output.len(), input.len());
+	assert_eq!(result.bytes * 8, (result.bitrate * result.packets as f64 * 0.02).round() as usize);
+
+	// without the delay removed, the waveforms would not line up
+	let scores = score(&input, &result.output, 1, 48000).unwrap();
+	assert!(scores.snr > 10.0, "{:?}", scores);
+	assert!(scores.perceptual > 0.0, "{:?}", scores);
+}
+
+#[test]
+fn roundtrip_resampled() {
+	let input: Vec<i16> = tone(44100, 1, 0.3).into_iter().map(|s| (s * 32767.0) as i16).collect();
+	let mut encoder = Encoder::with_input_rate(44100, Channels::Mono, Application::Audio).unwrap();
+	encoder.set_bitrate(Bitrate::Bits(64000)).unwrap();
+	let mut decoder = Decoder::with_output_rate(44100, Channels::Mono).unwrap();
+	let result = roundtrip(&mut encoder, &mut decoder, &input, FrameSize::Ms20).unwrap();
+	assert_eq!(result.output.len(), input.len());
+	let reference: Vec<f32> = input.iter().map(|&s| s as f32 / 32768.0).collect();
+	let scores = score(&reference, &result.output, 1, 44100).unwrap();
+	assert!(scores.snr > 10.0, "{:?}", scores);
+}
+
+#[test]
+fn sweep() {
+	let input = tone(48000, 1, 0.3);
+	let sweep = Sweep {
+		application: Application::Audio,
+		bitrates: vec![Bitrate::Bits(12000), Bitrate::Bits(96000)],
+		complexities: vec![10],
+		frame_sizes: vec![FrameSize::Ms10, FrameSize::Ms20],
+	};
+	let report = sweep.run(&input, 48000, Channels::Mono).unwrap();
+	assert_eq!(report.rows.len(), 4);
+	let low = &report.rows[1];
+	let high = &report.rows[3];
+	assert_eq!((high.bitrate, high.frame_size), (Bitrate::Bits(96000), FrameSize::Ms20));
+	assert!(high.actual_bitrate > low.actual_bitrate);
+	assert!(high.scores.segmental_snr > low.scores.segmental_snr, "{}", report);
+	assert!(report.best().unwrap().bitrate == Bitrate::Bits(96000));
+
+	let table = report.to_string();
+	assert_eq!(table.lines().count(), 5);
+	assert!(table.lines().next().unwrap().contains("perceptual"));
+	assert!(table.lines().nth(4).unwrap().contains("96000"));
+}
@@ -0,0 +1,140 @@
+//! Test the adaptive bitrate controller against simulated network feedback.
+
+extern crate opus;
+use opus::rate::*;
+use opus::*;
+
+fn report(loss: f32) -> Feedback {
+	Feedback { loss, ..Feedback::default() }
+}
+
+#[test]
+fn ramp_and_backoff() {
+	let mut controller = RateController::new(RateControllerConfig::default()).unwrap();
+	assert_eq!(controller.decision().reason, RateReason::Initial);
+	assert_eq!(controller.decision().bitrate, 32000);
+
+	// increases wait for the holdoff
+	assert_eq!(controller.update(&report(0.0)).reason, RateReason::Hold);
+	assert_eq!(controller.update(&report(0.0)).reason, RateReason::Hold);
+	let decision = controller.update(&report(0.0));
+	assert_eq!(decision.reason, RateReason::Increase);
+	assert!(decision.bitrate > 32000 && decision.changed);
+
+	for _ in 0..100 {
+		controller.update(&report(0.0));
+	}
+	assert_eq!(controller.decision().bitrate, 128000);
+	assert_eq!(controller.decision().max_bandwidth, Bandwidth::Fullband);
+
+	let decision = controller.update(&report(0.3));
+	assert_eq!(decision.reason, RateReason::LossBackoff);
+	assert_eq!(decision.bitrate, 108800);
+	assert!(decision.inband_fec);
+	assert_eq!(decision.packet_loss_perc, 15);
+
+	for _ in 0..20 {
+		controller.update(&report(0.3));
+	}
+	let decision = *controller.decision();
+	assert_eq!(decision.bitrate, 6000);
+	assert_eq!(decision.max_bandwidth, Bandwidth::Narrowband);
+	assert_eq!(decision.frame_size, FrameSize::Ms60);
+}
+
+#[test]
+fn fec_hysteresis() {
+	let mut controller = RateController::new(RateControllerConfig::default()).unwrap();
+	controller.update(&report(0.05));
+	assert!(controller.decision().inband_fec);
+	// loss falls off slowly, and FEC stays on until it is well below the
+	// threshold which enabled it
+	let mut reports = 0;
+	while controller.decision().inband_fec {
+		controller.update(&report(0.0));
+		reports += 1;
+	}
+	assert!(reports > 5);
+	assert!(controller.smoothed_loss() < 0.01);
+}
+
+#[test]
+fn delay_and_available() {
+	let mut controller = RateController::new(RateControllerConfig::default()).unwrap();
+	controller.update(&Feedback { rtt_ms: Some(40), ..Feedback::default() });
+	let decision = controller.update(&Feedback { rtt_ms: Some(200), ..Feedback::default() });
+	assert_eq!(decision.reason, RateReason::DelayBackoff);
+	assert_eq!(decision.bitrate, 27200);
+
+	let decision = controller.update(&Feedback {
+		available_bitrate: Some(20000),
+		..Feedback::default()
+	});
+	assert_eq!(decision.reason, RateReason::BandwidthLimit);
+	assert_eq!(decision.bitrate, 18000);
+	assert_eq!(decision.max_bandwidth, Bandwidth::Wideband);
+
+	controller.reset();
+	assert_eq!(controller.decision().bitrate, 32000);
+}
+
+#[test]
+fn apply_to_encoder() {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	let config = RateControllerConfig {
+		start_bitrate: 12000,
+		..RateControllerConfig::default()
+	};
+	let mut controller = RateController::new(config).unwrap();
+	let decision = controller.apply(&mut encoder, &report(0.2)).unwrap();
+	assert_eq!(encoder.get_bitrate().unwrap(), Bitrate::Bits(decision.bitrate));
+	assert!(encoder.get_inband_fec().unwrap());
+	assert_eq!(encoder.get_packet_loss_perc().unwrap(), decision.packet_loss_perc);
+	assert_eq!(encoder.get_max_bandwidth().unwrap(), decision.max_bandwidth);
+	// the caller's input decides the frame size
+	assert_eq!(decision.frame_size, FrameSize::Ms40);
+	assert_eq!(encoder.get_expert_frame_duration().unwrap(), FrameSize::Arg);
+
+	let bad = RateControllerConfig {
+		min_bitrate: 64000,
+		..RateControllerConfig::default()
+	};
+	assert_eq!(RateController::new(bad).unwrap_err().context(), Some("start_bitrate"));
+	let bad = RateControllerConfig {
+		max_frame_size: FrameSize::Arg,
+		..RateControllerConfig::default()
+	};
+	assert_eq!(RateController::new(bad).unwrap_err().context(), Some("max_frame_size"));
+}
+
+#[test]
+fn buffered_frame_size_switch() {
+	let preset = Preset::Voip { narrowband_fallback: false };
+	let encoder = Encoder::from_preset(48000, Channels::Mono, preset).unwrap();
+	let mut buffered = BufferedEncoder::<i16>::new(encoder, preset.frame_size()).unwrap();
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let mut controller = RateController::new(RateControllerConfig::default()).unwrap();
+
+	// heavy loss drives the controller down to 60 ms frames, then a clean
+	// network brings it back up to 20 ms
+	let mut sizes = Vec::new();
+	let mut output = [0i16; 2880];
+	for step in 0..200 {
+		let loss = if step < 30 { 0.3 } else { 0.0 };
+		let decision = controller.apply_buffered(&mut buffered, &report(loss)).unwrap();
+		assert_eq!(buffered.frame_size(), decision.frame_size);
+
+		let mut packets = Vec::new();
+		buffered.push(&[0; 700], &mut packets).unwrap();
+		for packet in &packets {
+			let samples = packet::get_nb_samples(packet, 48000).unwrap();
+			assert_eq!(decoder.decode(packet, &mut output, false).unwrap(), samples);
+			if sizes.last() != Some(&samples) {
+				sizes.push(samples);
+			}
+		}
+	}
+	assert_eq!(sizes.first(), Some(&960));
+	assert!(sizes.contains(&2880), "{:?}", sizes);
+	assert_eq!(sizes.last(), Some(&960));
+}
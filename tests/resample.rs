@@ -0,0 +1,109 @@
+//! Test encoding and decoding at sample rates Opus does not support.
+
+extern crate opus;
+use opus::resample::Resampler;
+use opus::*;
+
+fn sine(rate: u32, freq: f64, offset: usize, len: usize) -> Vec<f32> {
+	(offset..offset + len)
+		.map(|i| (2.0 * std::f64::consts::PI * freq * i as f64 / rate as f64).sin() as f32 * 0.5)
+		.collect()
+}
+
+#[test]
+fn exact_ratio() {
+	let mut resampler = Resampler::new(44100, 48000, 2).unwrap();
+	for _ in 0..5 {
+		assert_eq!(resampler.process(&[0i16; 882 * 2]).len(), 960 * 2);
+	}
+	let mut resampler = Resampler::new(48000, 22050, 1).unwrap();
+	for _ in 0..5 {
+		assert_eq!(resampler.process(&[0f32; 960]).len(), 441);
+	}
+}
+
+#[test]
+fn preserves_sine() {
+	let mut resampler = Resampler::new(44100, 48000, 1).unwrap();
+	let mut output = Vec::new();
+	for block in 0..10 {
+		output.extend_from_slice(resampler.process(&sine(44100, 1000.0, block * 441, 441)));
+	}
+	// output sample k corresponds to input time k * 44100 / 48000 - delay
+	let delay = resampler.delay() as f64;
+	let error = output
+		.iter()
+		.enumerate()
+		.skip(100)
+		.map(|(k, &actual)| {
+			let t = (k as f64 * 44100.0 / 48000.0 - delay) / 44100.0;
+			let expected = (2.0 * std::f64::consts::PI * 1000.0 * t).sin() as f32 * 0.5;
+			(actual - expected).abs()
+		})
+		.fold(0.0, f32::max);
+	assert!(error < 0.01, "max error {}", error);
+}
+
+#[test]
+fn coprime_rates() {
+	// 48000 phases would need millions of coefficients; they are interpolated
+	let mut resampler = Resampler::new(44101, 48000, 1).unwrap();
+	let mut output = Vec::new();
+	for block in 0..10 {
+		let input = sine(44101, 1000.0, block * 4410, 4410);
+		output.extend_from_slice(resampler.process(&input));
+	}
+	assert!((output.len() as i64 - 44100 * 48000 / 44101).abs() <= 1, "{} samples", output.len());
+	let delay = resampler.delay() as f64;
+	let error = output
+		.iter()
+		.enumerate()
+		.skip(100)
+		.map(|(k, &actual)| {
+			let t = (k as f64 * 44101.0 / 48000.0 - delay) / 44101.0;
+			let expected = (2.0 * std::f64::consts::PI * 1000.0 * t).sin() as f32 * 0.5;
+			(actual - expected).abs()
+		})
+		.fold(0.0, f32::max);
+	assert!(error < 0.01, "max error {}", error);
+
+	assert!(Resampler::new(48000, 44101, 2).is_ok());
+	assert!(Resampler::new(u32::MAX, 8000, 1).is_err());
+}
+
+#[test]
+fn encode_decode_44100() {
+	let mut encoder = Encoder::with_input_rate(44100, Channels::Mono, Application::Audio).unwrap();
+	let mut native = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	assert!(encoder.get_lookahead().unwrap() > 0);
+	assert!(encoder.get_lookahead().unwrap() != native.get_lookahead().unwrap());
+	// the sample rate is that of the caller's PCM
+	assert_eq!(encoder.get_sample_rate().unwrap(), 44100);
+	encoder.reset_state().unwrap();
+
+	let mut decoder = Decoder::with_output_rate(44100, Channels::Mono).unwrap();
+	assert!(decoder.resampler_delay() > 0);
+	assert_eq!(decoder.get_sample_rate().unwrap(), 44100);
+	let mut output = [0i16; 882];
+	for block in 0..10 {
+		let input: Vec<i16> =
+			sine(44100, 440.0, block * 882, 882).iter().map(|&s| (s * 32767.0) as i16).collect();
+		let packet = encoder.encode_vec(&input, 4000).unwrap();
+		assert_eq!(decoder.get_nb_samples(&packet).unwrap(), 882);
+		assert_eq!(decoder.decode(&packet, &mut output, false).unwrap(), 882);
+	}
+
+	// packet loss is concealed for the requested duration
+	assert_eq!(decoder.decode(&[], &mut output, false).unwrap(), 882);
+}
+
+#[test]
+fn buffered_22050() {
+	let encoder = Encoder::with_input_rate(22050, Channels::Stereo, Application::Voip).unwrap();
+	let mut buffered = BufferedEncoder::<f32>::new(encoder, FrameSize::Ms20).unwrap();
+	let mut packets = Vec::new();
+	for _ in 0..4 {
+		buffered.push(&[0.0; 441 * 2], &mut packets).unwrap();
+	}
+	assert_eq!(packets.len(), 4);
+}
@@ -0,0 +1,121 @@
+//! Test the network impairment simulator.
+
+extern crate opus;
+use opus::simulate::*;
+use opus::*;
+
+fn packets(count: usize, fec: bool) -> Vec<Vec<u8>> {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	encoder.set_bitrate(Bitrate::Bits(24000)).unwrap();
+	encoder.set_inband_fec(fec).unwrap();
+	encoder.set_packet_loss_perc(if fec { 20 } else { 0 }).unwrap();
+	(0..count)
+		.map(|n| {
+			let frame: Vec<i16> =
+				(0..960).map(|i| (((n * 960 + i) as f32 * 0.03).sin() * 10000.0) as i16).collect();
+			encoder.encode_vec(&frame, 4000).unwrap()
+		})
+		.collect()
+}
+
+#[test]
+fn clean_network() {
+	let packets = packets(20, false);
+	let arrivals = transmit(&packets, &Impairments::default());
+	assert_eq!(arrivals.len(), 20);
+	assert!(arrivals.iter().enumerate().all(|(i, a)| a.seq == i && a.data == packets[i]));
+
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let sim: Simulation<i16> =
+		simulate(&mut decoder, &packets, &Impairments::default(), &Receiver::default()).unwrap();
+	assert_eq!(sim.pcm.len(), 20 * 960);
+	assert_eq!(sim.count(Action::Decoded), 20);
+	assert_eq!(sim.effective_loss(), 0.0);
+}
+
+#[test]
+fn loss_models() {
+	let packets = packets(400, false);
+	let bernoulli = Impairments {
+		loss: LossModel::Bernoulli { loss: 0.1 },
+		seed: 7,
+		..Impairments::default()
+	};
+	let delivered = transmit(&packets, &bernoulli).len();
+	assert!(delivered > 330 && delivered < 390, "{}", delivered);
+	// the same seed gives the same result
+	assert_eq!(transmit(&packets, &bernoulli), transmit(&packets, &bernoulli));
+	// and so does sending the packets one at a time
+	let mut network = Network::new(&bernoulli);
+	let sent: Vec<Arrival> = packets.iter().flat_map(|packet| network.send(packet)).collect();
+	assert_eq!(sent, transmit(&packets, &bernoulli));
+
+	let bursty = Impairments {
+		loss: LossModel::GilbertElliott {
+			good_to_bad: 0.05,
+			bad_to_good: 0.3,
+			loss_good: 0.0,
+			loss_bad: 1.0,
+		},
+		seed: 7,
+		..Impairments::default()
+	};
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let sim: Simulation<f32> =
+		simulate(&mut decoder, &packets, &bursty, &Receiver { fec: false, ..Receiver::default() })
+			.unwrap();
+	assert_eq!(sim.pcm.len(), 400 * 960);
+	let lost: Vec<bool> = sim.trace.iter().map(|entry| entry.lost).collect();
+	let bursts = lost.windows(2).filter(|w| w[0] && w[1]).count();
+	assert!(bursts > 0);
+	assert_eq!(sim.count(Action::Plc), lost.iter().filter(|&&l| l).count());
+}
+
+#[test]
+fn fec_recovery() {
+	let packets = packets(200, true);
+	let impairments = Impairments {
+		loss: LossModel::Bernoulli { loss: 0.15 },
+		seed: 3,
+		..Impairments::default()
+	};
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let sim: Simulation<i16> =
+		simulate(&mut decoder, &packets, &impairments, &Receiver::default()).unwrap();
+	assert_eq!(sim.pcm.len(), 200 * 960);
+	assert!(sim.count(Action::Fec) > 0);
+	for (i, entry) in sim.trace.iter().enumerate() {
+		assert_eq!(entry.samples, 960);
+		if entry.action == Action::Fec {
+			assert!(entry.lost);
+			assert!(!sim.trace[i + 1].lost);
+		}
+	}
+}
+
+#[test]
+fn reorder_duplicate_corrupt() {
+	let packets = packets(200, false);
+	let impairments = Impairments {
+		reorder: 0.2,
+		max_delay: 4,
+		duplicate: 0.1,
+		bit_error_rate: 0.001,
+		seed: 11,
+		..Impairments::default()
+	};
+	let arrivals = transmit(&packets, &impairments);
+	assert!(arrivals.len() > 200);
+	assert!(arrivals.windows(2).any(|w| w[0].seq > w[1].seq));
+	assert!(arrivals.iter().any(|a| a.corrupted));
+
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let receiver = Receiver { jitter_buffer: 1, fec: false };
+	let sim: Simulation<i16> = simulate(&mut decoder, &packets, &impairments, &receiver).unwrap();
+	// corruption may change the duration of a packet
+	assert_eq!(sim.pcm.len(), sim.trace.iter().map(|entry| entry.samples).sum::<usize>());
+	assert!(sim.trace.iter().any(|entry| entry.late && entry.action == Action::Plc));
+	assert!(sim.trace.iter().any(|entry| entry.duplicated));
+	assert!(sim.trace.iter().any(|entry| entry.reordered && !entry.late));
+	assert!(sim.trace.iter().any(|entry| entry.corrupted));
+}
@@ -0,0 +1,133 @@
+//! Test packet-domain speaker detection.
+
+extern crate opus;
+use opus::speaker::*;
+use opus::*;
+
+/// Encode `frames` frames of a speech-like signal at the given amplitude,
+/// with DTX so that silence is sent as DTX packets.
+fn talk(amplitude: f32, frames: usize) -> Vec<Vec<u8>> {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	encoder.set_dtx(true).unwrap();
+	encoder.set_bitrate(Bitrate::Bits(32000)).unwrap();
+	let mut seed = 1u32;
+	(0..frames)
+		.map(|frame| {
+			let pcm: Vec<f32> = (0..960)
+				.map(|i| {
+					let t = (frame * 960 + i) as f32 / 48000.0;
+					seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+					let noise = (seed >> 8) as f32 / (1 << 24) as f32 - 0.5;
+					// a buzzing voice with a varying pitch
+					let voice =
+						(t * (140.0 + 30.0 * (t * 3.0).sin()) * std::f32::consts::TAU).sin();
+					amplitude * (0.7 * voice + 0.3 * noise)
+				})
+				.collect();
+			encoder.encode_vec_float(&pcm, 4000).unwrap()
+		})
+		.collect()
+}
+
+#[test]
+fn estimates() {
+	// speech is active, while silence and DTX are not
+	let speech = talk(0.3, 30);
+	for packet in &speech[10..] {
+		let activity = estimate(packet, None).unwrap();
+		assert!(activity.voice && !activity.silent, "{:?}", activity);
+		assert!(activity.level > 0.2, "{:?}", activity);
+		assert_eq!(activity.duration, 960);
+	}
+	// the encoder codes 200 ms of silence as comfort noise before DTX starts
+	let silence = talk(0.0, 30);
+	for packet in &silence[..10] {
+		assert!(!estimate(packet, None).unwrap().voice);
+	}
+	for packet in &silence[10..] {
+		let activity = estimate(packet, None).unwrap();
+		assert!(activity.silent && !activity.voice, "{:?}", activity);
+		assert_eq!(activity.level, 0.0);
+	}
+	// CELT's silence frames, as in the stereo encoding test
+	assert!(estimate(&[252, 255, 254], None).unwrap().silent);
+	assert!(estimate(&[253, 255, 254, 255, 254], None).unwrap().silent);
+	assert!(estimate(&[], None).is_err());
+
+	// the audio level header takes precedence over the packet size
+	let loud = AudioLevel { level: 20, voice: None };
+	let quiet = AudioLevel { level: 60, voice: None };
+	assert_eq!(estimate(&speech[20], Some(loud)).unwrap().level, 1.0);
+	let activity = estimate(&speech[20], Some(quiet)).unwrap();
+	assert!((activity.level - 1.0 / 3.0).abs() < 1e-6 && activity.voice);
+	let silence = AudioLevel { level: 127, voice: None };
+	assert!(!estimate(&speech[20], Some(silence)).unwrap().voice);
+	// as does the voice activity flag
+	let unvoiced = AudioLevel { level: 20, voice: Some(false) };
+	assert!(!estimate(&speech[20], Some(unvoiced)).unwrap().voice);
+}
+
+#[test]
+fn audio_level() {
+	let level = AudioLevel::from_byte(0x80 | 42, true);
+	assert_eq!(level, AudioLevel { level: 42, voice: Some(true) });
+	assert_eq!(level.to_byte(), 0x80 | 42);
+	let level = AudioLevel::from_byte(0x80 | 42, false);
+	assert_eq!(level, AudioLevel { level: 42, voice: None });
+	assert_eq!(level.to_byte(), 42);
+}
+
+#[test]
+fn ranking() {
+	let speech = talk(0.3, 100);
+	let silence = talk(0.0, 100);
+	let mut detector = SpeakerDetector::default();
+	let levels = [30, 50, 127];
+	for i in 0..50 {
+		for (source, &level) in levels.iter().enumerate() {
+			let audio = AudioLevel { level, voice: None };
+			detector.update(source as u32, &speech[i], Some(audio)).unwrap();
+		}
+		detector.update(3, &silence[i], None).unwrap();
+	}
+	assert_eq!(detector.sources(), [0, 1, 2, 3]);
+	assert_eq!(detector.top(5), [0, 1]);
+	assert_eq!(detector.top(1), [0]);
+	assert!(detector.is_speaking(1) && !detector.is_speaking(2) && !detector.is_speaking(3));
+	assert!(detector.last_activity(3).unwrap().silent);
+
+	// a speaker who falls silent drops out after a pause, and the next one
+	// takes over as soon as they start
+	for i in 50..100 {
+		detector.update(0, &silence[i], None).unwrap();
+		detector.update(3, &speech[i], None).unwrap();
+		if i == 52 {
+			assert!(detector.is_speaking(0));
+			assert!(detector.is_speaking(3));
+		}
+	}
+	assert!(!detector.is_speaking(0), "{:?}", detector.score(0));
+	assert_eq!(detector.top(1), [3]);
+
+	assert!(detector.remove(3));
+	assert!(!detector.remove(3));
+	assert_eq!(detector.score(3), None);
+	assert_eq!(detector.top(5), [1]);
+}
+
+#[test]
+fn dtx_decay() {
+	// DTX packets arrive only every 400 ms, but decay as fast as silence
+	let speech = talk(0.3, 50);
+	let mut detector = SpeakerDetector::default();
+	for packet in &speech {
+		detector.update(7, packet, None).unwrap();
+	}
+	assert!(detector.is_speaking(7));
+	let dtx = [speech[0][0] & 0xFC];
+	for _ in 0..3 {
+		detector.update(7, &dtx, None).unwrap();
+	}
+	assert!(!detector.is_speaking(7), "{:?}", detector.score(7));
+	assert!(detector.top(3).is_empty());
+}
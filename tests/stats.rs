@@ -0,0 +1,145 @@
+//! Test the opt-in codec statistics.
+
+extern crate opus;
+use opus::packet::Mode;
+use opus::*;
+
+#[test]
+fn toc() {
+	let toc = packet::Toc::from_byte(0b1111_1100);
+	assert_eq!(toc.mode, Mode::Celt);
+	assert_eq!(toc.bandwidth, Bandwidth::Fullband);
+	assert_eq!(toc.frame_size, FrameSize::Ms20);
+	assert_eq!(toc.channels, Channels::Stereo);
+	assert_eq!(toc.code, 0);
+
+	let toc = packet::Toc::from_byte(0b0100_1011);
+	assert_eq!(toc.mode, Mode::Silk);
+	assert_eq!(toc.bandwidth, Bandwidth::Wideband);
+	assert_eq!(toc.frame_size, FrameSize::Ms20);
+	assert_eq!(toc.channels, Channels::Mono);
+	assert_eq!(toc.code, 3);
+
+	// the TOC decoding agrees with libopus for every value
+	for byte in 0..=255u8 {
+		let data = [byte, 0, 0, 0];
+		let toc = packet::Toc::from_byte(byte);
+		assert_eq!(Ok(toc.bandwidth), packet::get_bandwidth(&data).map_err(|e| e.code()));
+		assert_eq!(Ok(toc.channels), packet::get_nb_channels(&data).map_err(|e| e.code()));
+		assert_eq!(toc.frame_size.samples(48000), packet::get_samples_per_frame(&data, 48000).ok());
+	}
+	assert!(packet::get_toc(&[]).is_err());
+}
+
+#[test]
+fn encoder_stats() {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	assert!(encoder.stats().is_none());
+	encoder.enable_stats();
+	encoder.set_bitrate(Bitrate::Bits(16000)).unwrap();
+
+	let tone: Vec<i16> = (0..960).map(|i| ((i as f32 * 0.05).sin() * 8000.0) as i16).collect();
+	let mut total = 0;
+	for _ in 0..10 {
+		total += encoder.encode_vec(&tone, 4000).unwrap().len();
+	}
+	let stats = encoder.stats().unwrap();
+	assert_eq!(stats.packets, 10);
+	assert_eq!(stats.bytes, total as u64);
+	assert_eq!(stats.samples, 9600);
+	assert_eq!(stats.average_frame_duration_ms, 20.0);
+	assert_eq!(stats.silk_packets + stats.hybrid_packets + stats.celt_packets, 10);
+	assert!(stats.min_packet_size <= stats.max_packet_size);
+	assert!(stats.recent_bitrate > 0.0);
+	assert_eq!(stats.average_bitrate, total as f64 * 8.0 / 0.2);
+	assert_eq!(stats.final_range, encoder.get_final_range().unwrap());
+	assert!(stats.mode.is_some() && stats.bandwidth.is_some());
+
+	// silence eventually enters DTX
+	encoder.set_dtx(true).unwrap();
+	for _ in 0..50 {
+		encoder.encode_vec(&[0; 960], 4000).unwrap();
+	}
+	let stats = encoder.stats().unwrap();
+	assert!(stats.in_dtx);
+	assert!(stats.dtx_packets > 0);
+	assert_eq!(stats.last_packet_size, 1);
+
+	encoder.reset_stats();
+	assert_eq!(encoder.stats().unwrap().packets, 0);
+	encoder.disable_stats();
+	assert!(encoder.stats().is_none());
+}
+
+#[test]
+fn multistream_stats() {
+	let mut encoder = MSEncoder::new(48000, 2, 0, &[0, 1], Application::Audio).unwrap();
+	encoder.enable_stats();
+	let packet = encoder.encode_vec_float(&[0.0; 2 * 960], 4000).unwrap();
+	let stats = encoder.stats().unwrap();
+	assert_eq!(stats.packets, 1);
+	assert_eq!(stats.bytes, packet.len() as u64);
+	assert_eq!(stats.samples, 960);
+}
+
+#[test]
+fn decoder_stats() {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	encoder.set_inband_fec(true).unwrap();
+	encoder.set_packet_loss_perc(20).unwrap();
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	decoder.enable_stats();
+
+	let tone: Vec<i16> = (0..960).map(|i| ((i as f32 * 0.05).sin() * 8000.0) as i16).collect();
+	let mut output = [0i16; 960];
+	let mut bytes = 0;
+	for _ in 0..5 {
+		let packet = encoder.encode_vec(&tone, 4000).unwrap();
+		bytes += packet.len();
+		decoder.decode(&packet, &mut output, false).unwrap();
+	}
+	// one packet lost and concealed, one recovered from the next packet
+	decoder.decode(&[], &mut output, false).unwrap();
+	let next = encoder.encode_vec(&tone, 4000).unwrap();
+	decoder.decode(&next, &mut output, true).unwrap();
+	decoder.decode(&next, &mut output, false).unwrap();
+	bytes += next.len();
+	// a corrupt packet
+	assert!(decoder.decode(&[0xff, 0xff], &mut output, false).is_err());
+
+	let stats = decoder.stats().unwrap();
+	assert_eq!(stats.packets, 6);
+	assert_eq!(stats.bytes, bytes as u64);
+	assert_eq!(stats.plc_frames, 1);
+	assert_eq!(stats.fec_frames, 1);
+	assert_eq!(stats.samples, 8 * 960);
+	assert_eq!(stats.concealed_samples, 2 * 960);
+	assert_eq!(stats.concealed_duration().as_millis(), 40);
+	assert_eq!(stats.concealed_fraction(), 0.25);
+	assert_eq!(stats.errors.get(ErrorCode::InvalidPacket), 1);
+	assert_eq!(stats.errors.total(), 1);
+	assert_eq!(stats.channels, Some(Channels::Mono));
+
+	// switching from speech to a stereo music encoder
+	let mut music = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	music.set_bitrate(Bitrate::Bits(128000)).unwrap();
+	let packet = music.encode_vec(&[0; 2 * 960], 4000).unwrap();
+	decoder.decode(&packet, &mut output, false).unwrap();
+	let stats = decoder.stats().unwrap();
+	assert_eq!(stats.channel_changes, 1);
+	assert_eq!(stats.mode, Some(Mode::Celt));
+	assert_eq!(stats.mode_changes, 1);
+}
+
+#[test]
+fn resampled_decoder_stats() {
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Audio).unwrap();
+	let mut decoder = Decoder::with_output_rate(44100, Channels::Mono).unwrap();
+	decoder.enable_stats();
+	let mut output = [0i16; 882];
+	for _ in 0..3 {
+		let packet = encoder.encode_vec(&[0; 960], 4000).unwrap();
+		decoder.decode(&packet, &mut output, false).unwrap();
+	}
+	assert_eq!(decoder.stats().unwrap().samples, 3 * 960);
+}
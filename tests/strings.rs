@@ -0,0 +1,78 @@
+//! Test the string forms of the setting types.
+
+extern crate opus;
+#[cfg(feature = "serde")]
+extern crate serde_json;
+use opus::*;
+
+#[test]
+fn display_parse() {
+	assert_eq!(Bandwidth::Fullband.to_string(), "fullband");
+	assert_eq!(FrameSize::Ms2_5.to_string(), "2.5ms");
+	assert_eq!(Application::LowDelay.to_string(), "lowdelay");
+	assert_eq!(ErrorCode::BufferTooSmall.to_string(), "buffer_too_small");
+	assert_eq!(Bitrate::Max.to_string(), "max");
+	assert_eq!(Bitrate::Bits(64000).to_string(), "64000");
+
+	assert_eq!("FB".parse(), Ok(Bandwidth::Fullband));
+	assert_eq!("20".parse(), Ok(FrameSize::Ms20));
+	assert_eq!("stereo".parse(), Ok(Channels::Stereo));
+	assert_eq!("1".parse(), Ok(Channels::Mono));
+	assert_eq!("voice".parse(), Ok(Signal::Voice));
+	assert_eq!("64k".parse(), Ok(Bitrate::Bits(64000)));
+	assert_eq!("Auto".parse(), Ok(Bitrate::Auto));
+
+	let err = "loud".parse::<Signal>().unwrap_err();
+	assert_eq!(err.to_string(), "invalid signal: \"loud\"");
+	assert!("3".parse::<Channels>().is_err());
+	assert!("fast".parse::<Bitrate>().is_err());
+}
+
+#[test]
+fn all_variants_roundtrip() {
+	let sizes = [
+		FrameSize::Arg,
+		FrameSize::Ms2_5,
+		FrameSize::Ms5,
+		FrameSize::Ms10,
+		FrameSize::Ms20,
+		FrameSize::Ms40,
+		FrameSize::Ms60,
+		FrameSize::Ms80,
+		FrameSize::Ms100,
+		FrameSize::Ms120,
+	];
+	for &size in &sizes {
+		assert_eq!(size.as_str().parse(), Ok(size));
+	}
+	let bandwidths = [
+		Bandwidth::Auto,
+		Bandwidth::Narrowband,
+		Bandwidth::Mediumband,
+		Bandwidth::Wideband,
+		Bandwidth::Superwideband,
+		Bandwidth::Fullband,
+	];
+	for &bandwidth in &bandwidths {
+		assert_eq!(bandwidth.as_str().parse(), Ok(bandwidth));
+	}
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn serde_strings() {
+	assert_eq!(serde_json::to_string(&Bandwidth::Fullband).unwrap(), "\"fullband\"");
+	assert_eq!(serde_json::to_string(&FrameSize::Ms20).unwrap(), "\"20ms\"");
+	assert_eq!(serde_json::to_string(&Bitrate::Max).unwrap(), "\"max\"");
+	assert_eq!(serde_json::to_string(&Bitrate::Bits(32000)).unwrap(), "32000");
+	assert_eq!(serde_json::from_str::<Bitrate>("32000").unwrap(), Bitrate::Bits(32000));
+	assert_eq!(serde_json::from_str::<Bitrate>("\"96k\"").unwrap(), Bitrate::Bits(96000));
+	assert_eq!(serde_json::from_str::<Channels>("\"mono\"").unwrap(), Channels::Mono);
+	assert!(serde_json::from_str::<Signal>("\"loud\"").is_err());
+
+	let data = [0b1111_1001, 0, 1, 2, 3];
+	let packet = packet::parse(&data).unwrap();
+	let json = serde_json::to_value(&packet).unwrap();
+	assert_eq!(json["toc"], 0b1111_1001);
+	assert_eq!(json["frames"].as_array().unwrap().len(), packet.frames.len());
+}
@@ -163,3 +163,39 @@ fn repacketizer() {
 		assert_eq!(&out[..len], &[249, 255, 254, 71, 71]);
 	}
 }
+
+#[test]
+fn error_details() {
+	use opus::ErrorKind;
+	use std::error::Error as _;
+
+	let err =
+		opus::Encoder::new(44100, opus::Channels::Mono, opus::Application::Audio).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::UnsupportedSampleRate(44100));
+	assert_eq!(err.code(), opus::ErrorCode::BadArg);
+	assert_eq!(err.raw_code(), None);
+	assert_eq!(err.to_string(), "Encoder::new: unsupported sample rate 44100 Hz");
+
+	let mut encoder =
+		opus::Encoder::new(48000, opus::Channels::Mono, opus::Application::Audio).unwrap();
+	let err = encoder.set_bitrate(opus::Bitrate::Bits(-7)).unwrap_err();
+	assert_eq!(err.code(), opus::ErrorCode::BadArg);
+	assert_eq!(err.raw_code(), Some(-1));
+	assert_eq!(err.function(), "opus_encoder_ctl");
+	assert_eq!(err.context(), Some("OPUS_SET_BITRATE_REQUEST"));
+	assert_eq!(err.to_string(), "opus_encoder_ctl(OPUS_SET_BITRATE_REQUEST): invalid argument");
+	assert!(err.source().is_none());
+
+	let err = opus::packet::get_bandwidth(&[]).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::EmptyPacket);
+
+	let stereo = [0i16; 2 * MONO_20MS];
+	let frame = opus::Frame::interleaved(&stereo, opus::Channels::Stereo).unwrap();
+	let err = encoder.encode_frame(&frame, &mut [0; 256]).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::ChannelMismatch { expected: 1, actual: 2 });
+
+	let io = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "truncated");
+	let err = opus::Error::from(io);
+	assert_eq!(err.kind(), ErrorKind::Io);
+	assert_eq!(err.source().unwrap().to_string(), "truncated");
+}
@@ -0,0 +1,67 @@
+//! Test final range verification between encoders and decoders.
+
+extern crate opus;
+use opus::verify::*;
+use opus::*;
+
+fn noise(len: usize) -> Vec<f32> {
+	let mut state = 1u32;
+	(0..len)
+		.map(|_| {
+			state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+			(state >> 16) as f32 / 65536.0 - 0.5
+		})
+		.collect()
+}
+
+#[test]
+fn verifier() {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let mut decoder = Decoder::new(48000, Channels::Stereo).unwrap();
+	let mut verifier = RangeVerifier::new();
+	let input = noise(2 * 960);
+	let mut output = [0.0f32; 2 * 960];
+
+	let mut packets = Vec::new();
+	for _ in 0..3 {
+		packets.push(encoder.encode_vec_float(&input, 4000).unwrap());
+		verifier.record(encoder.get_final_range().unwrap());
+	}
+	assert_eq!(verifier.pending(), 3);
+
+	decoder.decode_float(&packets[0], &mut output, false).unwrap();
+	verifier.verify(decoder.get_final_range().unwrap()).unwrap();
+	// the second packet is lost
+	decoder.decode_float(&[], &mut output, false).unwrap();
+	verifier.skip().unwrap();
+	decoder.decode_float(&packets[2], &mut output, false).unwrap();
+	let range = decoder.get_final_range().unwrap();
+	verifier.record(0x1234);
+	verifier.verify(range).unwrap();
+	assert_eq!((verifier.verified(), verifier.skipped()), (2, 1));
+
+	let err = verifier.verify(range).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::RangeMismatch { packet: 3, expected: 0x1234, actual: range });
+	assert_eq!(err.code(), ErrorCode::InternalError);
+	assert!(verifier.verify(range).is_err());
+	assert!(verifier.skip().is_err());
+}
+
+#[test]
+fn roundtrip() {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let mut decoder = Decoder::new(48000, Channels::Stereo).unwrap();
+	let input = noise(2 * 48000 + 100);
+	let report = verify_roundtrip(&mut encoder, &mut decoder, &input, FrameSize::Ms20).unwrap();
+	assert!(report.is_conformant());
+	assert_eq!(report.packets, 51);
+
+	let mut encoder = Encoder::with_input_rate(44100, Channels::Mono, Application::Voip).unwrap();
+	let mut decoder = Decoder::new(48000, Channels::Mono).unwrap();
+	let input: Vec<i16> = noise(44100).iter().map(|&s| (s * 20000.0) as i16).collect();
+	let report = verify_roundtrip(&mut encoder, &mut decoder, &input, FrameSize::Ms10).unwrap();
+	assert!(report.is_conformant());
+	assert_eq!(report.packets, 100);
+
+	assert!(verify_roundtrip(&mut encoder, &mut decoder, &input, FrameSize::Ms2_5).is_err());
+}
@@ -0,0 +1,366 @@
+//! Test WebM muxing.
+
+extern crate opus;
+use opus::opus_head::OpusHead;
+use opus::webm::*;
+use opus::*;
+
+/// A parsed EBML element, enough to check the structure of the output.
+#[derive(Debug)]
+struct Element {
+	id: u32,
+	offset: usize,
+	data: Vec<u8>,
+	children: Vec<Element>,
+}
+
+impl Element {
+	fn find(&self, id: u32) -> Option<&Element> {
+		self.children.iter().find(|child| child.id == id)
+	}
+
+	fn all(&self, id: u32) -> Vec<&Element> {
+		self.children.iter().filter(|child| child.id == id).collect()
+	}
+
+	fn uint(&self) -> u64 {
+		self.data.iter().fold(0, |acc, &b| acc << 8 | b as u64)
+	}
+}
+
+const MASTERS: &[u32] = &[
+	0x1A45_DFA3,
+	0x1853_8067,
+	0x1549_A966,
+	0x1654_AE6B,
+	0xAE,
+	0xE1,
+	0x1F43_B675,
+	0xA0,
+	0x1C53_BB6B,
+	0xBB,
+	0xB7,
+];
+
+fn vint(data: &[u8], pos: &mut usize, keep_marker: bool) -> u64 {
+	let len = data[*pos].leading_zeros() as usize + 1;
+	let mut value = data[*pos] as u64;
+	if !keep_marker {
+		value &= 0xFF >> len;
+	}
+	for i in 1..len {
+		value = value << 8 | data[*pos + i] as u64;
+	}
+	*pos += len;
+	if !keep_marker && value == (1 << (7 * len)) - 1 {
+		u64::MAX
+	} else {
+		value
+	}
+}
+
+fn parse(data: &[u8], base: usize) -> Vec<Element> {
+	let mut elements = Vec::new();
+	let mut pos = 0;
+	while pos < data.len() {
+		let offset = base + pos;
+		let id = vint(data, &mut pos, true) as u32;
+		let size = vint(data, &mut pos, false);
+		let end = if size == u64::MAX { data.len() } else { pos + size as usize };
+		let body = &data[pos..end];
+		let children = if MASTERS.contains(&id) { parse(body, base + pos) } else { Vec::new() };
+		elements.push(Element { id, offset, data: body.to_vec(), children });
+		pos = end;
+	}
+	elements
+}
+
+fn encode(seconds: usize) -> (OpusHead, Vec<Vec<u8>>) {
+	let mut encoder = Encoder::new(48000, Channels::Stereo, Application::Audio).unwrap();
+	let head = OpusHead::for_encoder(&mut encoder).unwrap();
+	let frame: Vec<f32> = (0..1920).map(|i| ((i / 2) as f32 * 0.05).sin() * 0.3).collect();
+	let packets =
+		(0..seconds * 50).map(|_| encoder.encode_vec_float(&frame, 4000).unwrap()).collect();
+	(head, packets)
+}
+
+#[test]
+fn structure() {
+	let (head, packets) = encode(12);
+	let mut writer = WebmWriter::new(Vec::new(), &head).unwrap();
+	for packet in &packets {
+		writer.write_packet(packet).unwrap();
+	}
+	assert_eq!(writer.samples(), 960 * 600);
+	// trim the last 10 ms of the final frame
+	let length = 960 * 600 - head.pre_skip as u64 - 480;
+	let data = writer.finish_with_length(length).unwrap();
+
+	let top = parse(&data, 0);
+	assert_eq!(top.len(), 2);
+	let doc_type = top[0].find(0x4282).unwrap();
+	assert_eq!(doc_type.data, b"webm");
+
+	let segment = &top[1];
+	let segment_start = segment.offset + 12;
+	let track = segment.find(0x1654_AE6B).unwrap().find(0xAE).unwrap();
+	assert_eq!(track.find(0x86).unwrap().data, b"A_OPUS");
+	assert_eq!(track.find(0x63A2).unwrap().data, head.to_bytes());
+	assert_eq!(track.find(0x56AA).unwrap().uint(), head.pre_skip as u64 * 1_000_000 / 48);
+	assert_eq!(track.find(0x56BB).unwrap().uint(), 80_000_000);
+	assert_eq!(track.find(0xE1).unwrap().find(0x9F).unwrap().uint(), 2);
+
+	// 12 seconds in clusters of 5 seconds
+	let clusters = segment.all(0x1F43_B675);
+	assert_eq!(clusters.len(), 3);
+	let mut blocks = 0;
+	for (i, cluster) in clusters.iter().enumerate() {
+		assert_eq!(cluster.find(0xE7).unwrap().uint(), i as u64 * 5000);
+		blocks += cluster.all(0xA3).len() + cluster.all(0xA0).len();
+	}
+	assert_eq!(blocks, 600);
+
+	// the second block of each cluster is 20 ms in, and the data is intact
+	let block = &clusters[1].all(0xA3)[1].data;
+	assert_eq!(&block[..4], &[0x81, 0, 20, 0x80]);
+	assert_eq!(&block[4..], &packets[251][..]);
+
+	// only the final block has discard padding
+	let groups = clusters[2].all(0xA0);
+	assert_eq!(groups.len(), 1);
+	assert_eq!(groups[0].find(0xA1).unwrap().data[4..], packets[599][..]);
+	assert_eq!(groups[0].find(0x75A2).unwrap().uint(), 10_000_000);
+
+	// every cue points at its cluster
+	let cues = segment.find(0x1C53_BB6B).unwrap().all(0xBB);
+	assert_eq!(cues.len(), 3);
+	for (cue, cluster) in cues.iter().zip(&clusters) {
+		let time = cue.find(0xB3).unwrap().uint();
+		let position = cue.find(0xB7).unwrap().find(0xF1).unwrap().uint();
+		assert_eq!(time, cluster.find(0xE7).unwrap().uint());
+		assert_eq!(position as usize + segment_start, cluster.offset);
+	}
+}
+
+#[test]
+fn untrimmed() {
+	let (head, packets) = encode(1);
+	let mut writer = WebmWriter::new(Vec::new(), &head).unwrap();
+	for packet in &packets {
+		writer.write_packet(packet).unwrap();
+	}
+	let data = writer.finish().unwrap();
+	let top = parse(&data, 0);
+	let cluster = top[1].find(0x1F43_B675).unwrap();
+	assert_eq!(cluster.all(0xA3).len(), 50);
+	assert!(cluster.find(0xA0).is_none());
+
+	// an empty file still has its header and track
+	let data = WebmWriter::new(Vec::new(), &head).unwrap().finish().unwrap();
+	let top = parse(&data, 0);
+	assert!(top[1].find(0x1654_AE6B).is_some());
+	assert!(top[1].find(0x1F43_B675).is_none());
+
+	let mut writer = WebmWriter::new(Vec::new(), &head).unwrap();
+	assert!(writer.write_packet(&[]).is_err());
+}
+
+fn mux(head: &OpusHead, packets: &[Vec<u8>], length: u64) -> Vec<u8> {
+	let mut writer = WebmWriter::new(Vec::new(), head).unwrap();
+	for packet in packets {
+		writer.write_packet(packet).unwrap();
+	}
+	writer.finish_with_length(length).unwrap()
+}
+
+#[test]
+fn demux() {
+	let (head, packets) = encode(7);
+	let data = mux(&head, &packets, 960 * 350 - head.pre_skip as u64 - 100);
+	let mut reader = WebmReader::new(&data[..]).unwrap();
+	assert_eq!(reader.head(), &head);
+	assert_eq!(reader.codec_delay(), head.pre_skip as u64 * 1_000_000 / 48);
+	assert_eq!(reader.seek_pre_roll(), 80_000_000);
+	for (i, packet) in packets.iter().enumerate() {
+		let read = reader.read_packet().unwrap().unwrap();
+		assert_eq!(&read.data, packet);
+		assert_eq!(read.timestamp, i as u64 * 20_000_000);
+		let padding = if i == 349 { 100 * 1_000_000 / 48 } else { 0 };
+		assert_eq!(read.discard_padding, padding);
+	}
+	assert!(reader.read_packet().unwrap().is_none());
+	assert!(reader.read_packet().unwrap().is_none());
+}
+
+#[test]
+fn decode_trims() {
+	let (head, packets) = encode(1);
+	let length = 960 * 50 - head.pre_skip as u64 - 250;
+	let data = mux(&head, &packets, length);
+	for &rate in &[48000, 16000] {
+		let reader = WebmReader::new(&data[..]).unwrap();
+		let mut decoder = WebmDecoder::new(reader, rate).unwrap();
+		assert_eq!(decoder.channels(), 2);
+		let pcm = decoder.decode_all::<i16>().unwrap();
+		let expected = (length * rate as u64 + 24000) / 48000;
+		assert!((pcm.len() as u64 / 2).abs_diff(expected) <= 1, "{} {}", pcm.len(), expected);
+	}
+}
+
+#[test]
+fn seek() {
+	let (head, packets) = encode(12);
+	let length = 960 * 600 - head.pre_skip as u64;
+	let data = mux(&head, &packets, length);
+	let reader = WebmReader::new(std::io::Cursor::new(&data[..])).unwrap();
+	let mut decoder = WebmDecoder::new(reader, 48000).unwrap();
+	let full = decoder.decode_all::<f32>().unwrap();
+	assert_eq!(full.len() as u64, 2 * length);
+
+	// the cues are at the end, and found by skipping over the clusters
+	for &target in &[0, 1000, 300_000, 500_000, length - 10] {
+		decoder.seek(target).unwrap();
+		let rest = decoder.decode_all::<f32>().unwrap();
+		assert_eq!(rest.len() as u64, 2 * (length - target), "{}", target);
+		// the output converges on a continuous decode shortly after the
+		// pre-roll
+		let offset = 2 * 9600;
+		if rest.len() > offset + 1920 {
+			let error: f32 = rest[offset..offset + 1920]
+				.iter()
+				.zip(&full[2 * target as usize + offset..])
+				.map(|(a, b)| (a - b).abs())
+				.sum();
+			assert!(error / 1920.0 < 0.005, "{} {}", target, error);
+		}
+	}
+
+	// without cues, reading starts again from the first cluster
+	let cues = data.windows(4).rposition(|w| w == [0x1C, 0x53, 0xBB, 0x6B]).unwrap();
+	let reader = WebmReader::new(std::io::Cursor::new(&data[..cues])).unwrap();
+	let mut decoder = WebmDecoder::new(reader, 48000).unwrap();
+	decoder.seek(400_000).unwrap();
+	assert_eq!(decoder.decode_all::<f32>().unwrap().len() as u64, 2 * (length - 400_000));
+
+	let mut reader = WebmReader::new(std::io::Cursor::new(&data[..])).unwrap();
+	reader.seek(5_010_000_000).unwrap();
+	assert_eq!(reader.read_packet().unwrap().unwrap().timestamp, 5_000_000_000);
+}
+
+fn element(id: &[u8], body: &[u8]) -> Vec<u8> {
+	assert!(body.len() < 0x3FFF);
+	let mut data = id.to_vec();
+	data.extend_from_slice(&(0x4000 | body.len() as u16).to_be_bytes());
+	data.extend_from_slice(body);
+	data
+}
+
+/// The headers of a stream as written by browsers, with an unknown-size
+/// segment, and a second track before the Opus track.
+fn unknown_size_headers(head: &OpusHead, codec_delay: Option<u64>) -> Vec<u8> {
+	let mut data = element(&[0x1A, 0x45, 0xDF, 0xA3], &element(&[0x42, 0x82], b"webm"));
+	data.extend_from_slice(&[
+		0x18, 0x53, 0x80, 0x67, 0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+	]);
+	let mut entry = element(&[0xD7], &[1]);
+	entry.extend(element(&[0x86], b"A_OPUS"));
+	entry.extend(element(&[0x63, 0xA2], &head.to_bytes()));
+	if let Some(delay) = codec_delay {
+		entry.extend(element(&[0x56, 0xAA], &delay.to_be_bytes()));
+	}
+	let mut tracks = element(&[0xAE], &element(&[0xD7], &[2]));
+	tracks.extend(element(&[0xAE], &entry));
+	data.extend(element(&[0x16, 0x54, 0xAE, 0x6B], &tracks));
+	data
+}
+
+#[test]
+fn laced_unknown_size() {
+	// unknown-size clusters, and a block of three Xiph-laced packets
+	let mut encoder = Encoder::new(48000, Channels::Mono, Application::Voip).unwrap();
+	let packets: Vec<Vec<u8>> =
+		(0..4).map(|_| encoder.encode_vec_float(&[0.1; 960], 100).unwrap()).collect();
+	let head = OpusHead::new(Channels::Mono, 312, 48000);
+
+	let mut data = unknown_size_headers(&head, None);
+	data.extend_from_slice(&[0x1F, 0x43, 0xB6, 0x75, 0xFF]);
+	data.extend(element(&[0xE7], &[0]));
+	let mut block = vec![0x81, 0, 0, 0x82, 2];
+	block.push(packets[0].len() as u8);
+	block.push(packets[1].len() as u8);
+	for packet in &packets[..3] {
+		block.extend_from_slice(packet);
+	}
+	data.extend(element(&[0xA3], &block));
+	// a block for the other track is skipped
+	data.extend(element(&[0xA3], &[0x82, 0, 0, 0x80, 1, 2, 3]));
+	data.extend_from_slice(&[0x1F, 0x43, 0xB6, 0x75, 0xFF]);
+	data.extend(element(&[0xE7], &[60]));
+	let mut block = vec![0x81, 0, 0, 0x80];
+	block.extend_from_slice(&packets[3]);
+	data.extend(element(&[0xA3], &block));
+
+	let mut reader = WebmReader::new(&data[..]).unwrap();
+	// the codec delay defaults to the pre-skip
+	assert_eq!(reader.codec_delay(), 6_500_000);
+	for (i, packet) in packets.iter().enumerate() {
+		let read = reader.read_packet().unwrap().unwrap();
+		assert_eq!(&read.data, packet);
+		assert_eq!(read.timestamp, i as u64 * 20_000_000);
+	}
+	assert!(reader.read_packet().unwrap().is_none());
+}
+
+#[test]
+fn malformed() {
+	assert_eq!(WebmReader::new(&b"RIFF"[..]).unwrap_err().kind(), ErrorKind::Malformed);
+	let (head, packets) = encode(1);
+	let data = mux(&head, &packets, 0);
+	// the headers are cut off
+	assert!(WebmReader::new(&data[..100]).is_err());
+	// a block is cut off
+	let mut reader = WebmReader::new(&data[..data.len() / 2]).unwrap();
+	let err = loop {
+		match reader.read_packet() {
+			Ok(Some(_)) => {}
+			Ok(None) => panic!("truncation not detected"),
+			Err(err) => break err,
+		}
+	};
+	assert_eq!(err.kind(), ErrorKind::Io);
+
+	let mut matroska = data.clone();
+	let pos = matroska.windows(4).position(|w| w == b"webm").unwrap();
+	matroska[pos..pos + 4].copy_from_slice(b"mp4a");
+	assert!(WebmReader::new(&matroska[..]).is_err());
+
+	// a cluster timestamp which overflows when scaled to nanoseconds
+	let head = OpusHead::new(Channels::Mono, 312, 48000);
+	let mut data = unknown_size_headers(&head, None);
+	data.extend_from_slice(&[0x1F, 0x43, 0xB6, 0x75, 0xFF]);
+	data.extend(element(&[0xE7], &[0xFF; 8]));
+	data.extend(element(&[0xA3], &[0x81, 0, 0, 0x80, 0xF8, 0xFF, 0xFE]));
+	let err = WebmReader::new(&data[..]).unwrap().read_packet().unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::Malformed);
+	assert_eq!(err.context(), Some("timestamp overflow"));
+
+	// a codec delay longer than any pre-skip
+	let err = WebmReader::new(&unknown_size_headers(&head, Some(u64::MAX))[..]).unwrap_err();
+	assert_eq!(err.kind(), ErrorKind::Malformed);
+	assert_eq!(err.context(), Some("implausible codec delay"));
+
+	// discard padding of the largest signed value drops the whole packet
+	let mut data = unknown_size_headers(&head, Some(0));
+	data.extend_from_slice(&[0x1F, 0x43, 0xB6, 0x75, 0xFF]);
+	data.extend(element(&[0xE7], &[0]));
+	let mut group = element(&[0xA1], &[0x81, 0, 0, 0, 0xF8, 0xFF, 0xFE]);
+	group.extend(element(&[0x75, 0xA2], &i64::MAX.to_be_bytes()));
+	data.extend(element(&[0xA0], &group));
+	let reader = WebmReader::new(std::io::Cursor::new(&data[..])).unwrap();
+	let mut decoder = WebmDecoder::new(reader, 48000).unwrap();
+	assert_eq!(decoder.decode::<f32>(&mut Vec::new()).unwrap(), Some(0));
+
+	// seeking past the range of the timestamps
+	let err = decoder.seek(u64::MAX).unwrap_err();
+	assert_eq!(err.code(), ErrorCode::BadArg);
+}